@@ -1,7 +1,7 @@
 use std::process::{exit, Command};
 
 fn main() -> Result<(), std::io::Error> {
-    let ret = Command::new("buildsys").arg("build-variant").status()?;
+    let ret = Command::new("buildsys").arg("build-kit").status()?;
     if !ret.success() {
         exit(1);
     }
@@ -7,6 +7,7 @@ of its input arguments from environment variables.
 
 use buildsys::manifest::SupportedArch;
 use clap::{Parser, Subcommand};
+use serde::Serialize;
 use std::path::PathBuf;
 use url::Url;
 
@@ -14,19 +15,22 @@ use url::Url;
 /// variable changes. The build type is represented with bit flags so that we can easily list
 /// multiple build types for a single variable. See `[BuildType]` and `[rerun_for_envs]` below to
 /// see how this list is used.
-const REBUILD_VARS: [(&str, u8); 12] = [
-    ("BUILDSYS_ARCH", PACKAGE | VARIANT),
+const REBUILD_VARS: [(&str, u8); 15] = [
+    ("BUILDSYS_ARCH", PACKAGE | KIT | VARIANT),
+    ("BUILDSYS_COMPOSITES_DIR", VARIANT),
+    ("BUILDSYS_EXTERNAL_KITS_DIR", VARIANT),
+    ("BUILDSYS_KITS_DIR", KIT | VARIANT),
     ("BUILDSYS_NAME", VARIANT),
     ("BUILDSYS_OUTPUT_DIR", VARIANT),
     ("BUILDSYS_PACKAGES_DIR", PACKAGE),
     ("BUILDSYS_PRETTY_NAME", VARIANT),
-    ("BUILDSYS_ROOT_DIR", PACKAGE | VARIANT),
-    ("BUILDSYS_STATE_DIR", PACKAGE | VARIANT),
+    ("BUILDSYS_ROOT_DIR", PACKAGE | KIT | VARIANT),
+    ("BUILDSYS_STATE_DIR", PACKAGE | KIT | VARIANT),
     ("BUILDSYS_TIMESTAMP", VARIANT),
     ("BUILDSYS_VARIANT", VARIANT),
     ("BUILDSYS_VERSION_BUILD", VARIANT),
-    ("BUILDSYS_VERSION_IMAGE", VARIANT),
-    ("TLPRIVATE_SDK_IMAGE", PACKAGE | VARIANT),
+    ("BUILDSYS_VERSION_IMAGE", KIT | VARIANT),
+    ("TLPRIVATE_SDK_IMAGE", PACKAGE | KIT | VARIANT),
 ];
 
 /// A tool for building Bottlerocket images and artifacts.
@@ -37,8 +41,10 @@ pub(crate) struct Buildsys {
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Command {
     BuildPackage(Box<BuildPackageArgs>),
+    BuildKit(Box<BuildKitArgs>),
     BuildVariant(Box<BuildVariantArgs>),
 }
 
@@ -46,6 +52,7 @@ impl Command {
     pub(crate) fn build_type(&self) -> BuildType {
         match self {
             Command::BuildPackage(_) => BuildType::Package,
+            Command::BuildKit(_) => BuildType::Kit,
             Command::BuildVariant(_) => BuildType::Variant,
         }
     }
@@ -75,11 +82,34 @@ pub(crate) struct Common {
     #[arg(long, env = "CARGO_MANIFEST_DIR")]
     pub(crate) cargo_manifest_dir: PathBuf,
 
+    /// The cargo target directory of the build, if it is not the default one. It is not tracked
+    /// for changes, since it does not affect what is built.
+    #[arg(long, env = "CARGO_TARGET_DIR")]
+    pub(crate) cargo_target_dir: Option<PathBuf>,
+
     #[arg(long, env = "TLPRIVATE_SDK_IMAGE")]
     pub(crate) sdk_image: String,
 
     #[arg(long, env = "TWOLITER_TOOLS_DIR")]
     pub(crate) tools_dir: PathBuf,
+
+    /// Where to append build events as JSON lines, if anywhere. It is not tracked for changes,
+    /// since it does not affect what is built.
+    #[arg(long, env = "BUILDSYS_EVENTS_FILE")]
+    pub(crate) events_file: Option<PathBuf>,
+
+    /// The directory to write the output of each build to, in `<arch>/<name>.log`. The output is
+    /// printed instead when it is not set.
+    #[arg(long, env = "BUILDSYS_LOGS_DIR")]
+    pub(crate) logs_dir: Option<PathBuf>,
+
+    /// Whether to keep an image of a failed build for inspection. When a build step fails, the
+    /// stage that the failing step builds on is tagged with the build's tag: for a package, the
+    /// spec, sources and build dependencies before `rpmbuild` runs; for a variant, the package
+    /// repository before the image is made. Kits have no such stage. When the build succeeded
+    /// and copying the artifacts out failed, the built image and its container are kept instead.
+    #[arg(long, env = "BUILDSYS_KEEP_FAILED")]
+    pub(crate) keep_failed: bool,
 }
 
 /// Build RPMs from a spec file and sources.
@@ -122,6 +152,22 @@ pub(crate) struct BuildPackageArgs {
     pub(crate) common: Common,
 }
 
+/// Build a kit container image from RPMs.
+#[derive(Debug, Parser)]
+pub(crate) struct BuildKitArgs {
+    #[arg(long, env = "BUILDSYS_KITS_DIR")]
+    pub(crate) kits_dir: PathBuf,
+
+    #[arg(long, env = "BUILDSYS_VERSION_IMAGE")]
+    pub(crate) version_image: String,
+
+    #[arg(long, env = "CARGO_PKG_NAME")]
+    pub(crate) cargo_package_name: String,
+
+    #[command(flatten)]
+    pub(crate) common: Common,
+}
+
 /// Build filesystem and disk images from RPMs.
 #[derive(Debug, Parser)]
 pub(crate) struct BuildVariantArgs {
@@ -152,6 +198,15 @@ pub(crate) struct BuildVariantArgs {
     #[arg(long, env = "BUILDSYS_VERSION_IMAGE")]
     pub(crate) version_image: String,
 
+    #[arg(long, env = "BUILDSYS_KITS_DIR")]
+    pub(crate) kits_dir: PathBuf,
+
+    #[arg(long, env = "BUILDSYS_EXTERNAL_KITS_DIR")]
+    pub(crate) external_kits_dir: PathBuf,
+
+    #[arg(long, env = "BUILDSYS_COMPOSITES_DIR")]
+    pub(crate) composites_dir: PathBuf,
+
     #[command(flatten)]
     pub(crate) common: Common,
 }
@@ -174,10 +229,12 @@ pub(crate) fn rerun_for_envs(build_type: BuildType) {
 
 /// The thing that buildsys is building.
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum BuildType {
     Package = 0b00000001,
     Variant = 0b00000010,
+    Kit = 0b00000100,
 }
 
 impl BuildType {
@@ -190,6 +247,7 @@ impl BuildType {
 
 const PACKAGE: u8 = BuildType::Package as u8;
 const VARIANT: u8 = BuildType::Variant as u8;
+const KIT: u8 = BuildType::Kit as u8;
 
 #[test]
 fn build_type_includes_test() {
@@ -203,6 +261,7 @@ fn build_type_includes_test() {
     assert!(!BuildType::Variant.includes(PACKAGE));
     assert!(!BuildType::Variant.includes(32));
     assert!(!BuildType::Variant.includes(0));
+    assert!(!BuildType::Kit.includes(PACKAGE | VARIANT));
 }
 
 #[test]
@@ -220,3 +279,12 @@ fn test_sensitive_env_vars_package() {
     assert!(list.contains(&"BUILDSYS_PACKAGES_DIR"));
     assert!(!list.contains(&"BUILDSYS_VARIANT"));
 }
+
+#[test]
+fn test_sensitive_env_vars_kit() {
+    let list: Vec<&str> = sensitive_env_vars(BuildType::Kit).collect();
+    assert!(list.contains(&"BUILDSYS_ARCH"));
+    assert!(list.contains(&"BUILDSYS_KITS_DIR"));
+    assert!(!list.contains(&"BUILDSYS_PACKAGES_DIR"));
+    assert!(!list.contains(&"BUILDSYS_VARIANT"));
+}
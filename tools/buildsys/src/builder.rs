@@ -1,29 +1,38 @@
 /*!
-This module handles the calls to Docker needed to execute package and variant
-builds. The actual build steps and the expected parameters are defined in
+This module handles the calls to the container runtime needed to execute package,
+kit and variant builds. The actual build steps and the expected parameters are defined in
 the repository's top-level Dockerfile.
 
 */
+mod composite;
 pub(crate) mod error;
+mod partitions;
 
-use crate::args::{BuildPackageArgs, BuildType, BuildVariantArgs};
+use crate::args::{BuildKitArgs, BuildPackageArgs, BuildType, BuildVariantArgs, Common};
+use crate::events::{Event, Events};
+use crate::sbom::Sbom;
 use buildsys::manifest::{
-    ImageFeature, ImageFormat, ImageLayout, ManifestInfo, PartitionPlan, SupportedArch,
+    ImageFeature, ImageFormat, ImageLayout, ManifestInfo, PartitionPlan, SbomFormat, SupportedArch,
 };
+use container_runtime::{Build, ContainerRuntime, Runtime, Secret, SecretKind};
 use duct::cmd;
 use error::Result;
 use lazy_static::lazy_static;
 use nonzero_ext::nonzero;
+use partitions::PlannedPartition;
 use rand::Rng;
 use regex::Regex;
+use serde::Serialize;
 use sha2::{Digest, Sha512};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::HashSet;
 use std::env;
-use std::fs::{self, read_dir, File};
+use std::fs::{self, read_dir, File, OpenOptions};
+use std::io::Write;
 use std::num::NonZeroU16;
 use std::path::{Path, PathBuf};
 use std::process::Output;
+use std::time::Instant;
 use walkdir::{DirEntry, WalkDir};
 
 /*
@@ -128,10 +137,8 @@ struct PackageBuildArgs {
 }
 
 impl PackageBuildArgs {
-    fn build_args(&self) -> Vec<String> {
+    fn build_args(&self) -> Vec<(String, String)> {
         let mut args = Vec::new();
-        args.push("--network".into());
-        args.push("none".into());
         args.build_arg("PACKAGE", &self.package);
         args.build_arg("REPO", &self.publish_repo);
         args.build_arg("VARIANT", &self.variant);
@@ -147,18 +154,41 @@ impl PackageBuildArgs {
     }
 }
 
+struct KitBuildArgs {
+    kit: String,
+    /// The file names of the RPMs, relative to the packages directory, that belong in the kit.
+    rpms: String,
+    version_image: String,
+}
+
+impl KitBuildArgs {
+    fn build_args(&self) -> Vec<(String, String)> {
+        let mut args = Vec::new();
+        args.build_arg("KIT", &self.kit);
+        args.build_arg("RPMS", &self.rpms);
+        args.build_arg("VERSION_ID", &self.version_image);
+        args
+    }
+}
+
 struct VariantBuildArgs {
     data_image_publish_size_gib: i32,
     data_image_size_gib: String,
     image_features: HashSet<ImageFeature>,
     image_format: String,
+    image_layout: ImageLayout,
     kernel_parameters: String,
+    kits: String,
+    kits_composite: String,
     name: String,
     os_image_publish_size_gib: String,
     os_image_size_gib: String,
     packages: String,
     partition_plan: String,
+    partitions: Vec<PlannedPartition>,
     pretty_name: String,
+    sbom_formats: Vec<SbomFormat>,
+    timestamp: String,
     variant: String,
     variant_family: String,
     variant_flavor: String,
@@ -169,10 +199,8 @@ struct VariantBuildArgs {
 }
 
 impl VariantBuildArgs {
-    fn build_args(&self) -> Vec<String> {
+    fn build_args(&self) -> Vec<(String, String)> {
         let mut args = Vec::new();
-        args.push("--network".into());
-        args.push("host".into());
         args.build_arg(
             "DATA_IMAGE_PUBLISH_SIZE_GIB",
             self.data_image_publish_size_gib.to_string(),
@@ -180,11 +208,21 @@ impl VariantBuildArgs {
         args.build_arg("DATA_IMAGE_SIZE_GIB", &self.data_image_size_gib);
         args.build_arg("IMAGE_FORMAT", &self.image_format);
         args.build_arg("KERNEL_PARAMETERS", &self.kernel_parameters);
+        args.build_arg("KITS", &self.kits);
+        args.build_arg("KITS_COMPOSITE", &self.kits_composite);
         args.build_arg("IMAGE_NAME", &self.name);
+        args.build_arg(
+            "MAX_ROOTFS_SIZE",
+            self.image_layout
+                .max_rootfs_size
+                .map(|size| size.bytes().to_string())
+                .unwrap_or_default(),
+        );
         args.build_arg("OS_IMAGE_PUBLISH_SIZE_GIB", &self.os_image_publish_size_gib);
         args.build_arg("OS_IMAGE_SIZE_GIB", &self.os_image_size_gib);
         args.build_arg("PACKAGES", &self.packages);
         args.build_arg("PARTITION_PLAN", &self.partition_plan);
+        args.build_arg("PARTITION_LAYOUT", partitions::layout_arg(&self.partitions));
         args.build_arg("PRETTY_NAME", &self.pretty_name);
         args.build_arg("VARIANT", &self.variant);
         args.build_arg("VARIANT_FAMILY", &self.variant_family);
@@ -200,11 +238,66 @@ impl VariantBuildArgs {
 
         args
     }
+
+    /// Write the SBOM for the images that the build left in `build_dir`, next to them.
+    fn write_sbom(&self, build_dir: &Path, root_dir: &Path, arch: SupportedArch) -> Result<()> {
+        if self.sbom_formats.is_empty() {
+            return Ok(());
+        }
+        // These follow the names that `rpm2img` gives its output.
+        let version = format!("{}-{}", self.version_image, self.version_build);
+        let name = format!("{}-{}-{}-{}", self.name, self.variant, arch, version);
+        let dir = build_dir.join(&version);
+        Sbom::new(
+            &name,
+            &version,
+            &self.timestamp,
+            &dir.join(format!("{}-rpms.json", name)),
+            &root_dir.join("packages"),
+        )
+        .and_then(|sbom| sbom.write(&dir, &self.sbom_formats))
+        .context(error::SbomSnafu)?;
+        Ok(())
+    }
+
+    /// Record the parts of the variant manifest that shaped the images as `variant.json`, next to
+    /// them, so that images can be compared with the settings they were built with.
+    fn write_record(&self, build_dir: &Path) -> Result<()> {
+        let mut image_features = self.image_features.iter().copied().collect::<Vec<_>>();
+        image_features.sort();
+        let record = VariantRecord {
+            variant: &self.variant,
+            image_features,
+            kernel_parameters: self.kernel_parameters.split_whitespace().collect(),
+            image_layout: self.image_layout,
+            partitions: &self.partitions,
+        };
+        let path = build_dir
+            .join(format!("{}-{}", self.version_image, self.version_build))
+            .join("variant.json");
+        let json = serde_json::to_string_pretty(&record)
+            .context(error::VariantRecordSerializeSnafu { path: &path })?;
+        fs::write(&path, json).context(error::FileCreateSnafu { path: &path })?;
+        Ok(())
+    }
+}
+
+/// The contents of `variant.json`.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct VariantRecord<'a> {
+    variant: &'a str,
+    image_features: Vec<ImageFeature>,
+    kernel_parameters: Vec<&'a str>,
+    image_layout: ImageLayout,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    partitions: &'a [PlannedPartition],
 }
 
 #[allow(clippy::large_enum_variant)]
 enum TargetBuildArgs {
     Package(PackageBuildArgs),
+    Kit(KitBuildArgs),
     Variant(VariantBuildArgs),
 }
 
@@ -212,9 +305,36 @@ impl TargetBuildArgs {
     pub(crate) fn build_type(&self) -> BuildType {
         match self {
             TargetBuildArgs::Package(_) => BuildType::Package,
+            TargetBuildArgs::Kit(_) => BuildType::Kit,
             TargetBuildArgs::Variant(_) => BuildType::Variant,
         }
     }
+
+    /// The network that the build runs with.
+    fn network(&self) -> &'static str {
+        match self {
+            TargetBuildArgs::Package(_) | TargetBuildArgs::Kit(_) => "none",
+            TargetBuildArgs::Variant(_) => "host",
+        }
+    }
+
+    /// The stage to keep for inspection when a build step fails, if any. It is the stage that the
+    /// step which usually fails builds on, so it can be run again by hand from there.
+    fn inspect_target(&self) -> Option<&'static str> {
+        match self {
+            TargetBuildArgs::Package(_) => Some("rpmprep"),
+            TargetBuildArgs::Kit(_) => None,
+            TargetBuildArgs::Variant(_) => Some("repobuild"),
+        }
+    }
+
+    /// The directory in the final image that holds the artifacts to copy out.
+    fn output_dir(&self) -> &'static str {
+        match self {
+            TargetBuildArgs::Kit(_) => "/local",
+            TargetBuildArgs::Package(_) | TargetBuildArgs::Variant(_) => "/output",
+        }
+    }
 }
 
 pub(crate) struct DockerBuild {
@@ -228,7 +348,14 @@ pub(crate) struct DockerBuild {
     artifact_name: String,
     common_build_args: CommonBuildArgs,
     target_build_args: TargetBuildArgs,
-    secrets_args: Vec<String>,
+    secrets: Vec<Secret>,
+    /// An additional tag to keep for the image once the build is finished, if any.
+    image_tag: Option<String>,
+    events: Events,
+    /// The file to write the output of the build to, if any.
+    log: Option<PathBuf>,
+    /// Whether to keep an image of the build for inspection if it fails.
+    keep_failed: bool,
 }
 
 impl DockerBuild {
@@ -238,6 +365,13 @@ impl DockerBuild {
         manifest: &ManifestInfo,
         image_features: HashSet<ImageFeature>,
     ) -> Result<Self> {
+        let events = Events::new(
+            args.common.events_file.clone(),
+            BuildType::Package,
+            &args.cargo_package_name,
+            &args.common.arch.to_string(),
+        );
+        let log = log_path(&args.common, &args.cargo_package_name);
         let package = if let Some(name_override) = manifest.package_name() {
             name_override.clone()
         } else {
@@ -254,7 +388,7 @@ impl DockerBuild {
                     package = package,
                     arch = args.common.arch,
                 ),
-                &args.common.root_dir,
+                &args.common,
             ),
             root_dir: args.common.root_dir.clone(),
             artifacts_dir: args.packages_dir,
@@ -275,7 +409,63 @@ impl DockerBuild {
                 variant_platform: args.variant_platform,
                 variant_runtime: args.variant_runtime,
             }),
-            secrets_args: Vec::new(),
+            secrets: Vec::new(),
+            image_tag: None,
+            events,
+            log,
+            keep_failed: args.common.keep_failed,
+        })
+    }
+
+    /// Create a new `DockerBuild` that can build a kit image.
+    pub(crate) fn new_kit(args: BuildKitArgs, manifest: &ManifestInfo) -> Result<Self> {
+        let kit = args.cargo_package_name;
+        let arch = args.common.arch;
+        let packages = manifest
+            .kit_included_packages()
+            .cloned()
+            .unwrap_or_default();
+        let rpms = kit_rpms(&args.common.state_dir, arch, &kit, &packages)?;
+        let log = log_path(&args.common, &kit);
+        let keep_failed = args.common.keep_failed;
+
+        Ok(Self {
+            dockerfile: args.common.tools_dir.join("Dockerfile"),
+            context: args.common.root_dir.clone(),
+            target: "kit".to_string(),
+            tag: append_token(
+                format!("buildsys-kit-{kit}-{arch}", kit = kit, arch = arch),
+                &args.common,
+            ),
+            root_dir: args.common.root_dir.clone(),
+            artifacts_dir: args.kits_dir.join(arch.to_string()),
+            state_dir: args.common.state_dir,
+            artifact_name: kit.clone(),
+            common_build_args: CommonBuildArgs::new(
+                &args.common.root_dir,
+                args.common.sdk_image,
+                arch,
+            ),
+            target_build_args: TargetBuildArgs::Kit(KitBuildArgs {
+                kit: kit.clone(),
+                rpms: rpms.join(" "),
+                version_image: args.version_image.clone(),
+            }),
+            secrets: Vec::new(),
+            image_tag: Some(format!(
+                "{kit}-{arch}:v{version}",
+                kit = kit,
+                arch = arch,
+                version = args.version_image
+            )),
+            events: Events::new(
+                args.common.events_file,
+                BuildType::Kit,
+                &kit,
+                &arch.to_string(),
+            ),
+            log,
+            keep_failed,
         })
     }
 
@@ -292,6 +482,46 @@ impl DockerBuild {
         let (os_image_publish_size_gib, data_image_publish_size_gib) =
             image_layout.publish_image_sizes_gib();
 
+        // Check the custom partitions before anything is built, since rpm2img would only fail
+        // near the end of the build.
+        let partitions = match manifest.partitions() {
+            Some(partitions) => {
+                partitions::plan_partitions(partitions, &image_layout, args.common.arch)?
+            }
+            None => Vec::new(),
+        };
+
+        // Gather the included kits into a tree that the build can mount over the SDK's repos.
+        // Locally built kits take precedence over external kits with the same name.
+        let arch = args.common.arch.to_string();
+        let kits = manifest.included_kits().cloned().unwrap_or_default();
+        let composite_dir = args.composites_dir.join(&arch).join(&args.variant);
+        composite::create_composite(
+            &composite_dir,
+            &[
+                args.kits_dir.join(&arch),
+                args.external_kits_dir.join(&arch),
+            ],
+            &kits,
+        )?;
+        let kits_composite = composite_dir
+            .strip_prefix(&args.common.root_dir)
+            .context(error::StripPathPrefixSnafu {
+                path: &composite_dir,
+                prefix: &args.common.root_dir,
+            })?
+            .display()
+            .to_string();
+
+        let events = Events::new(
+            args.common.events_file.clone(),
+            BuildType::Variant,
+            &args.variant,
+            &arch,
+        );
+        let log = log_path(&args.common, &args.variant);
+        let keep_failed = args.common.keep_failed;
+
         Ok(Self {
             dockerfile: args.common.tools_dir.join("Dockerfile"),
             context: args.common.root_dir.clone(),
@@ -302,7 +532,7 @@ impl DockerBuild {
                     variant = args.variant,
                     arch = args.common.arch
                 ),
-                &args.common.root_dir,
+                &args.common,
             ),
             root_dir: args.common.root_dir.clone(),
             artifacts_dir: args.common.image_arch_variant_dir,
@@ -317,6 +547,7 @@ impl DockerBuild {
                 data_image_publish_size_gib,
                 data_image_size_gib: data_image_size_gib.to_string(),
                 image_features: manifest.image_features().unwrap_or_default(),
+                image_layout,
                 image_format: match manifest.image_format() {
                     Some(ImageFormat::Raw) | None => "raw",
                     Some(ImageFormat::Qcow2) => "qcow2",
@@ -328,6 +559,8 @@ impl DockerBuild {
                     .cloned()
                     .unwrap_or_default()
                     .join(" "),
+                kits: kits.join(" "),
+                kits_composite,
                 name: args.name,
                 os_image_publish_size_gib: os_image_publish_size_gib.to_string(),
                 os_image_size_gib: os_image_size_gib.to_string(),
@@ -341,7 +574,10 @@ impl DockerBuild {
                     PartitionPlan::Unified => "unified",
                 }
                 .to_string(),
+                partitions,
                 pretty_name: args.pretty_name,
+                sbom_formats: manifest.sbom_formats().cloned().unwrap_or_default(),
+                timestamp: args.common.timestamp,
                 variant: args.variant,
                 variant_family: args.variant_family,
                 variant_flavor: args.variant_flavor,
@@ -350,11 +586,45 @@ impl DockerBuild {
                 version_build: args.version_build,
                 version_image: args.version_image,
             }),
-            secrets_args: secrets_args()?,
+            secrets: secrets()?,
+            image_tag: None,
+            events,
+            log,
+            keep_failed,
         })
     }
 
+    /// Build the artifacts, reporting the start and end of the build as events.
     pub(crate) fn build(&self) -> Result<()> {
+        let subject = self.events.subject();
+        let start = Instant::now();
+        self.events.emit(&Event::BuildStarted { subject });
+        let result = self.start_log().and_then(|()| self.build_artifacts());
+        if let (Err(_), Some(log)) = (&result, &self.log) {
+            println!("The output of the build is in '{}'", log.display());
+        }
+        self.events.emit(&Event::BuildFinished {
+            subject,
+            success: result.is_ok(),
+            cached: false,
+            duration_secs: start.elapsed().as_secs_f64(),
+            log: self.log.as_deref(),
+        });
+        result
+    }
+
+    /// Create an empty log file for the build, replacing the one of the previous build.
+    fn start_log(&self) -> Result<()> {
+        if let Some(log) = &self.log {
+            if let Some(dir) = log.parent() {
+                fs::create_dir_all(dir).context(error::DirectoryCreateSnafu { path: dir })?;
+            }
+            File::create(log).context(error::FileCreateSnafu { path: log })?;
+        }
+        Ok(())
+    }
+
+    fn build_artifacts(&self) -> Result<()> {
         env::set_current_dir(&self.root_dir).context(error::DirectoryChangeSnafu {
             path: &self.root_dir,
         })?;
@@ -370,35 +640,38 @@ impl DockerBuild {
         // Clean up any previous outputs we have tracked.
         clean_build_files(&marker_dir, &self.artifacts_dir)?;
 
-        let mut build = format!(
-            "build {context} \
-            --target {target} \
-            --tag {tag} \
-            --file {dockerfile}",
-            context = self.context.display(),
-            dockerfile = self.dockerfile.display(),
-            target = self.target,
-            tag = self.tag,
-        )
-        .split_string();
-
-        build.extend(self.build_args());
-        build.extend(self.secrets_args.clone());
-
-        let create = format!("create --name {} {} true", self.tag, self.tag).split_string();
-        let cp = format!("cp {}:/output/. {}", self.tag, marker_dir.display()).split_string();
-        let rm = format!("rm --force {}", self.tag).split_string();
-        let rmi = format!("rmi --force {}", self.tag).split_string();
+        let runtime = Runtime::from_env()
+            .context(error::ContainerRuntimeSnafu)?
+            .commands();
+        let build = runtime.build(&Build {
+            context: self.context.clone(),
+            dockerfile: self.dockerfile.clone(),
+            tag: self.tag.clone(),
+            target: Some(self.target.clone()),
+            network: Some(self.target_build_args.network().to_string()),
+            build_args: self.build_args(),
+            secrets: self.secrets.clone(),
+        });
+        let create = runtime.create(&self.tag, &self.tag, &["true"]);
+        let cp = runtime.cp(
+            &self.tag,
+            &Path::new(self.target_build_args.output_dir()).join("."),
+            &marker_dir,
+        );
+        let rm = runtime.rm(&self.tag);
+        let rmi = runtime.rmi(&self.tag);
+        let log = self.log.as_deref();
 
         // Clean up the stopped container if it exists.
-        let _ = docker(&rm, Retry::No);
+        let _ = container(runtime, &rm, Retry::No, log);
 
         // Clean up the previous image if it exists.
-        let _ = docker(&rmi, Retry::No);
+        let _ = container(runtime, &rmi, Retry::No, log);
 
         // Build the image, which builds the artifacts we want.
         // Work around transient, known failure cases with Docker.
-        docker(
+        if let Err(e) = container(
+            runtime,
             &build,
             Retry::Yes {
                 attempts: DOCKER_BUILD_MAX_ATTEMPTS,
@@ -408,30 +681,95 @@ impl DockerBuild {
                     &*UNEXPECTED_EOF_ERROR,
                     &*CREATEREPO_C_READ_HEADER_ERROR,
                 ],
+                events: &self.events,
             },
-        )?;
+            log,
+        ) {
+            if self.keep_failed {
+                self.keep_inspect_stage(runtime, log);
+            }
+            return Err(e);
+        }
 
-        // Create a stopped container so we can copy artifacts out.
-        docker(&create, Retry::No)?;
+        let result = (|| {
+            // Create a stopped container so we can copy artifacts out.
+            container(runtime, &create, Retry::No, log)?;
 
-        // Copy artifacts into our output directory.
-        docker(&cp, Retry::No)?;
+            // Copy artifacts into our output directory.
+            container(runtime, &cp, Retry::No, log)?;
 
-        // Clean up our stopped container after copying artifacts out.
-        docker(&rm, Retry::No)?;
+            // Clean up our stopped container after copying artifacts out.
+            container(runtime, &rm, Retry::No, log)?;
 
-        // Clean up our image now that we're done.
-        docker(&rmi, Retry::No)?;
+            // Keep the image around under its public name before we remove our build tag.
+            if let Some(image_tag) = &self.image_tag {
+                container(runtime, &runtime.tag(&self.tag, image_tag), Retry::No, log)?;
+            }
+
+            // Clean up our image now that we're done.
+            container(runtime, &rmi, Retry::No, log)
+        })();
+        if let Err(e) = result {
+            if self.keep_failed {
+                println!(
+                    "Keeping the container and image '{}' of the failed build",
+                    self.tag
+                );
+            } else {
+                let _ = container(runtime, &rm, Retry::No, log);
+                let _ = container(runtime, &rmi, Retry::No, log);
+            }
+            return Err(e);
+        }
+
+        if let TargetBuildArgs::Variant(variant) = &self.target_build_args {
+            variant.write_sbom(&marker_dir, &self.root_dir, self.common_build_args.arch)?;
+            variant.write_record(&marker_dir)?;
+        }
 
         // Copy artifacts to the expected directory and write markers to track them.
-        copy_build_files(&marker_dir, &self.artifacts_dir)?;
+        for path in copy_build_files(&marker_dir, &self.artifacts_dir)? {
+            self.events.emit(&Event::ArtifactProduced {
+                subject: self.events.subject(),
+                path: &path,
+            });
+        }
 
         Ok(())
     }
 
-    fn build_args(&self) -> Vec<String> {
+    /// BuildKit does not keep anything of a failed build step, so build the stage that the failed
+    /// step builds on again, from the cache, and tag it for inspection. This is best effort, since
+    /// the step that failed may have been in that stage.
+    fn keep_inspect_stage(&self, runtime: &dyn ContainerRuntime, log: Option<&Path>) {
+        let Some(target) = self.target_build_args.inspect_target() else {
+            println!("There is no stage of the failed build to keep for inspection");
+            return;
+        };
+        let build = runtime.build(&Build {
+            context: self.context.clone(),
+            dockerfile: self.dockerfile.clone(),
+            tag: self.tag.clone(),
+            target: Some(target.to_string()),
+            network: Some(self.target_build_args.network().to_string()),
+            build_args: self.build_args(),
+            secrets: self.secrets.clone(),
+        });
+        match container(runtime, &build, Retry::No, log) {
+            Ok(_) => println!(
+                "Keeping the '{target}' stage of the failed build as image '{}'",
+                self.tag
+            ),
+            Err(_) => {
+                println!("The '{target}' stage of the failed build failed too; keeping nothing")
+            }
+        }
+    }
+
+    fn build_args(&self) -> Vec<(String, String)> {
         let mut args = match &self.target_build_args {
             TargetBuildArgs::Package(p) => p.build_args(),
+            TargetBuildArgs::Kit(k) => k.build_args(),
             TargetBuildArgs::Variant(v) => v.build_args(),
         };
         args.build_arg("ARCH", self.common_build_args.arch.to_string());
@@ -446,18 +784,37 @@ impl DockerBuild {
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
-/// Run `docker` with the specified arguments.
-fn docker(args: &[String], retry: Retry) -> Result<Output> {
+/// Run the container runtime with the specified arguments. The output is appended to `log` if
+/// there is one, and printed otherwise.
+fn container(
+    runtime: &dyn ContainerRuntime,
+    args: &[String],
+    retry: Retry,
+    log: Option<&Path>,
+) -> Result<Output> {
     let mut max_attempts: u16 = 1;
     let mut retry_messages: &[&Regex] = &[];
-    if let Retry::Yes { attempts, messages } = retry {
+    let mut retry_events = None;
+    if let Retry::Yes {
+        attempts,
+        messages,
+        events,
+    } = retry
+    {
         max_attempts = attempts.into();
         retry_messages = messages;
+        retry_events = Some(events);
     }
 
     let mut attempt = 1;
     loop {
-        let output = cmd("docker", args)
+        let mut command = cmd(runtime.program(), args);
+        if args.first().map(String::as_str) == Some("build") {
+            for (key, value) in runtime.build_env() {
+                command = command.env(key, value);
+            }
+        }
+        let output = command
             .stderr_to_stdout()
             .stdout_capture()
             .unchecked()
@@ -465,22 +822,52 @@ fn docker(args: &[String], retry: Retry) -> Result<Output> {
             .context(error::CommandStartSnafu)?;
 
         let stdout = String::from_utf8_lossy(&output.stdout);
-        println!("{}", &stdout);
+        match log {
+            Some(log) => append_log(log, runtime.program(), args, &stdout)?,
+            None => println!("{}", &stdout),
+        }
         if output.status.success() {
             return Ok(output);
         }
 
+        let reason = retry_messages.iter().find_map(|m| m.find(&stdout));
         ensure!(
-            retry_messages.iter().any(|m| m.is_match(&stdout)) && attempt < max_attempts,
-            error::DockerExecutionSnafu {
+            reason.is_some() && attempt < max_attempts,
+            error::ContainerExecutionSnafu {
+                program: runtime.program(),
                 args: &args.join(" ")
             }
         );
 
         attempt += 1;
+        if let (Some(events), Some(reason)) = (retry_events, reason) {
+            events.emit(&Event::BuildRetried {
+                subject: events.subject(),
+                attempt,
+                reason: reason.as_str(),
+            });
+        }
     }
 }
 
+/// Append the output of a command to the log of a build, after the command itself.
+fn append_log(log: &Path, program: &str, args: &[String], output: &str) -> Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .and_then(|mut file| write!(file, "+ {} {}\n{}\n", program, args.join(" "), output))
+        .context(error::FileWriteSnafu { path: log })
+}
+
+/// The log file of the build of `name`, if builds are logged.
+fn log_path(common: &Common, name: &str) -> Option<PathBuf> {
+    common.logs_dir.as_ref().map(|dir| {
+        dir.join(common.arch.to_string())
+            .join(format!("{}.log", name))
+    })
+}
+
 /// Allow the caller to configure retry behavior, since the command may fail
 /// for spurious reasons that should not be treated as an error.
 enum Retry<'a> {
@@ -488,6 +875,8 @@ enum Retry<'a> {
     Yes {
         attempts: NonZeroU16,
         messages: &'a [&'static Regex],
+        /// Where to report retries.
+        events: &'a Events,
     },
 }
 
@@ -496,7 +885,7 @@ enum Retry<'a> {
 /// Add secrets that might be needed for builds. Since most builds won't use
 /// them, they are not automatically tracked for changes. If necessary, builds
 /// can emit the relevant cargo directives for tracking in their build script.
-fn secrets_args() -> Result<Vec<String>> {
+fn secrets() -> Result<Vec<Secret>> {
     let mut args = Vec::new();
     let sbkeys_var = "BUILDSYS_SBKEYS_PROFILE_DIR";
     let sbkeys_dir = env::var(sbkeys_var).context(error::EnvironmentSnafu { var: sbkeys_var })?;
@@ -505,9 +894,9 @@ fn secrets_args() -> Result<Vec<String>> {
     for s in sbkeys {
         let s = s.context(error::DirectoryReadSnafu { path: &sbkeys_dir })?;
         args.build_secret(
-            "file",
-            &s.file_name().to_string_lossy(),
-            &s.path().to_string_lossy(),
+            SecretKind::File,
+            s.file_name().to_string_lossy(),
+            s.path().to_string_lossy(),
         );
     }
 
@@ -517,7 +906,7 @@ fn secrets_args() -> Result<Vec<String>> {
         "AWS_SESSION_TOKEN",
     ] {
         let id = format!("{}.env", var.to_lowercase().replace('_', "-"));
-        args.build_secret("env", &id, var);
+        args.build_secret(SecretKind::Env, &id, var);
     }
 
     Ok(args)
@@ -534,6 +923,7 @@ fn create_marker_dir(
 ) -> Result<PathBuf> {
     let prefix = match kind {
         BuildType::Package => "packages",
+        BuildType::Kit => "kits",
         BuildType::Variant => "variants",
     };
 
@@ -548,9 +938,55 @@ fn create_marker_dir(
 
 const MARKER_EXTENSION: &str = ".buildsys_marker";
 
-/// Copy build artifacts to the output directory.
+/// Find the RPMs that were built for each of the packages in a kit, using the marker files that
+/// were written when the package artifacts were copied into the packages directory.
+fn kit_rpms(
+    state_dir: &Path,
+    arch: SupportedArch,
+    kit: &str,
+    packages: &[String],
+) -> Result<Vec<String>> {
+    let mut rpms = Vec::new();
+    for package in packages {
+        let marker_dir = state_dir
+            .join(arch.to_string())
+            .join("packages")
+            .join(package);
+
+        let mut found = false;
+        let entries = read_dir(&marker_dir).into_iter().flatten();
+        for entry in entries {
+            let entry = entry.context(error::DirectoryReadSnafu { path: &marker_dir })?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(rpm) = file_name.strip_suffix(MARKER_EXTENSION) else {
+                continue;
+            };
+            found = true;
+            if rpm.ends_with(".rpm")
+                && !rpm.contains("-debuginfo-")
+                && !rpm.contains("-debugsource-")
+            {
+                rpms.push(rpm.to_string());
+            }
+        }
+
+        ensure!(
+            found,
+            error::KitPackageMissingSnafu {
+                kit,
+                package,
+                path: &marker_dir
+            }
+        );
+    }
+
+    rpms.sort();
+    Ok(rpms)
+}
+
+/// Copy build artifacts to the output directory, and return the paths they were copied to.
 /// Before we copy each file, we create a corresponding marker file to record its existence.
-fn copy_build_files<P>(build_dir: P, output_dir: P) -> Result<()>
+fn copy_build_files<P>(build_dir: P, output_dir: P) -> Result<Vec<PathBuf>>
 where
     P: AsRef<Path>,
 {
@@ -567,6 +1003,7 @@ where
         is_dir || is_not_marker || is_symlink
     }
 
+    let mut output_files = Vec::new();
     for artifact_file in find_files(&build_dir, has_artifacts) {
         let mut marker_file = artifact_file.clone().into_os_string();
         marker_file.push(MARKER_EXTENSION);
@@ -590,9 +1027,10 @@ where
             old_path: &artifact_file,
             new_path: &output_file,
         })?;
+        output_files.push(output_file);
     }
 
-    Ok(())
+    Ok(output_files)
 }
 
 /// Remove build artifacts from the output directory.
@@ -704,9 +1142,12 @@ fn token(p: impl AsRef<Path>) -> String {
     digest[..12].to_string()
 }
 
-/// Append the per-checkout suffix token to a Docker tag.
-fn append_token(tag: impl AsRef<str>, p: impl AsRef<Path>) -> String {
-    format!("{}-{}", tag.as_ref(), token(p))
+/// Append the per-checkout suffix token to a Docker tag. Builds that Twoliter runs at the same time
+/// in their own cargo target directories get their own tags, so that they do not replace each
+/// other's images.
+fn append_token(tag: impl AsRef<str>, common: &Common) -> String {
+    let dir = common.cargo_target_dir.as_ref().unwrap_or(&common.root_dir);
+    format!("{}-{}", tag.as_ref(), token(dir))
 }
 
 /// Helper trait for constructing buildkit --build-arg arguments.
@@ -717,52 +1158,34 @@ trait BuildArg {
         S2: AsRef<str>;
 }
 
-impl BuildArg for Vec<String> {
+impl BuildArg for Vec<(String, String)> {
     fn build_arg<S1, S2>(&mut self, key: S1, value: S2)
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
     {
-        self.push("--build-arg".to_string());
-        self.push(format!("{}={}", key.as_ref(), value.as_ref()));
+        self.push((key.as_ref().to_string(), value.as_ref().to_string()));
     }
 }
 
 /// Helper trait for constructing buildkit --secret arguments.
 trait BuildSecret {
-    fn build_secret<S>(&mut self, typ: S, id: S, src: S)
+    fn build_secret<S1, S2>(&mut self, kind: SecretKind, id: S1, src: S2)
     where
-        S: AsRef<str>;
+        S1: AsRef<str>,
+        S2: AsRef<str>;
 }
 
-impl BuildSecret for Vec<String> {
-    fn build_secret<S>(&mut self, typ: S, id: S, src: S)
+impl BuildSecret for Vec<Secret> {
+    fn build_secret<S1, S2>(&mut self, kind: SecretKind, id: S1, src: S2)
     where
-        S: AsRef<str>,
+        S1: AsRef<str>,
+        S2: AsRef<str>,
     {
-        self.push("--secret".to_string());
-        self.push(format!(
-            "type={},id={},src={}",
-            typ.as_ref(),
-            id.as_ref(),
-            src.as_ref()
-        ));
-    }
-}
-
-/// Helper trait for splitting a string on spaces into owned Strings.
-///
-/// If you need an element with internal spaces, you should handle that separately, for example
-/// with BuildArg.
-trait SplitString {
-    fn split_string(&self) -> Vec<String>;
-}
-
-impl<S> SplitString for S
-where
-    S: AsRef<str>,
-{
-    fn split_string(&self) -> Vec<String> {
-        self.as_ref().split(' ').map(String::from).collect()
+        self.push(Secret {
+            kind,
+            id: id.as_ref().to_string(),
+            src: src.as_ref().to_string(),
+        });
     }
 }
@@ -0,0 +1,251 @@
+/*!
+This module assembles the kits included in a variant into a single "composite" tree that is
+mounted into the variant build. The tree has the same layout as the `/local` directory of a kit
+image:
+
+```ignore
+etc/yum.repos.d/<kit>.repo
+kits/<kit>/repodata/...
+kits/<kit>/<package>.rpm
+```
+
+Each kit's repo file is rewritten with a dnf priority that follows the kit's position in the
+variant's `included-kits` list, so that a package found in more than one kit is resolved from the
+kit that was listed first.
+
+*/
+use super::error::{self, Result};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// dnf prefers repos with a lower priority value. The packages built in the local tree use a
+/// priority of 1, so the kits start after that, leaving room in between.
+const PRIORITY_STEP: usize = 10;
+
+/// Create the composite tree for `kits` in `composite_dir`, replacing any previous tree. Each kit
+/// is taken from the first of `kit_dirs` that contains it.
+pub(super) fn create_composite(
+    composite_dir: &Path,
+    kit_dirs: &[PathBuf],
+    kits: &[String],
+) -> Result<()> {
+    if composite_dir.exists() {
+        fs::remove_dir_all(composite_dir).context(error::DirectoryRemoveSnafu {
+            path: composite_dir,
+        })?;
+    }
+
+    let repos_dir = composite_dir.join("etc").join("yum.repos.d");
+    let packages_dir = composite_dir.join("kits");
+    for dir in [&repos_dir, &packages_dir] {
+        fs::create_dir_all(dir).context(error::DirectoryCreateSnafu { path: dir })?;
+    }
+
+    for (i, kit) in kits.iter().enumerate() {
+        // A kit listed twice would have two priorities, and would be linked over itself.
+        ensure!(
+            !kits[..i].contains(kit),
+            error::KitIncludedTwiceSnafu { kit }
+        );
+        let kit_dir = find_kit(kit_dirs, kit)?;
+        let kit_packages_dir = kit_dir.join("kits").join(kit);
+
+        // Changes to the kit's repository should trigger a rebuild of the variant.
+        println!(
+            "cargo:rerun-if-changed={}",
+            kit_packages_dir
+                .join("repodata")
+                .join("repomd.xml")
+                .display()
+        );
+
+        let repo_file = repo_file_path(&kit_dir, kit);
+        let repo =
+            fs::read_to_string(&repo_file).context(error::FileReadSnafu { path: &repo_file })?;
+        let repo = set_priority(&repo, (i + 1) * PRIORITY_STEP);
+        let composite_repo_file = repo_file_path(composite_dir, kit);
+        fs::write(&composite_repo_file, repo).context(error::FileCreateSnafu {
+            path: &composite_repo_file,
+        })?;
+
+        link_tree(&kit_packages_dir, &packages_dir.join(kit))?;
+    }
+
+    Ok(())
+}
+
+fn repo_file_path(dir: &Path, kit: &str) -> PathBuf {
+    dir.join("etc")
+        .join("yum.repos.d")
+        .join(format!("{}.repo", kit))
+}
+
+/// Find the first directory that holds both the repo file and the packages for `kit`.
+fn find_kit(kit_dirs: &[PathBuf], kit: &str) -> Result<PathBuf> {
+    kit_dirs
+        .iter()
+        .find(|dir| repo_file_path(dir, kit).is_file() && dir.join("kits").join(kit).is_dir())
+        .cloned()
+        .context(error::KitNotFoundSnafu {
+            kit,
+            searched: kit_dirs
+                .iter()
+                .map(|d| d.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        })
+}
+
+/// Set the priority of every repo in a yum repo file, replacing any priority it already had.
+fn set_priority(repo: &str, priority: usize) -> String {
+    let mut output = String::new();
+    for line in repo.lines() {
+        let is_priority = line
+            .split_once('=')
+            .map(|(key, _)| key.trim() == "priority")
+            .unwrap_or(false);
+        if is_priority {
+            continue;
+        }
+        output.push_str(line);
+        output.push('\n');
+        if line.trim_start().starts_with('[') {
+            output.push_str(&format!("priority={}\n", priority));
+        }
+    }
+    output
+}
+
+/// Recreate the directory tree at `src` under `dst`. Files are hard linked to avoid copying the
+/// packages, unless `src` and `dst` are on different filesystems.
+fn link_tree(src: &Path, dst: &Path) -> Result<()> {
+    for entry in WalkDir::new(src).follow_links(false) {
+        let entry = entry.context(error::DirectoryWalkSnafu)?;
+        let relative = entry
+            .path()
+            .strip_prefix(src)
+            .context(error::StripPathPrefixSnafu {
+                path: entry.path(),
+                prefix: src,
+            })?;
+        let target = dst.join(relative);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target).context(error::DirectoryCreateSnafu { path: &target })?;
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target).context(error::FileCopySnafu {
+                src: entry.path(),
+                dst: &target,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_set_priority() {
+    let repo = "[hello-kit]\nname=hello-kit\nbaseurl=file:///local/kits/hello-kit\n";
+    assert_eq!(
+        set_priority(repo, 10),
+        "[hello-kit]\npriority=10\nname=hello-kit\nbaseurl=file:///local/kits/hello-kit\n"
+    );
+}
+
+#[test]
+fn test_set_priority_replaces_existing() {
+    let repo = "[a]\npriority = 5\nname=a\n[b]\nname=b\npriority=1\n";
+    assert_eq!(
+        set_priority(repo, 20),
+        "[a]\npriority=20\nname=a\n[b]\npriority=20\nname=b\n"
+    );
+}
+
+/// Write a kit to `dir` with the given repo file and packages, as the kit build lays it out.
+#[cfg(test)]
+fn write_test_kit(dir: &Path, kit: &str, repo: &str, packages: &[(&str, &str)]) {
+    let kit_packages_dir = dir.join("kits").join(kit);
+    fs::create_dir_all(kit_packages_dir.join("repodata")).unwrap();
+    fs::create_dir_all(dir.join("etc").join("yum.repos.d")).unwrap();
+    fs::write(repo_file_path(dir, kit), repo).unwrap();
+    for (name, contents) in packages {
+        fs::write(kit_packages_dir.join(name), contents).unwrap();
+    }
+}
+
+#[cfg(test)]
+fn composite_priority(composite_dir: &Path, kit: &str) -> String {
+    fs::read_to_string(repo_file_path(composite_dir, kit))
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("priority="))
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn test_create_composite_duplicate_packages() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let local = tempdir.path().join("kits");
+    let external = tempdir.path().join("external-kits");
+    let composite_dir = tempdir.path().join("composite");
+    let package = "foo-1.0-1.x86_64.rpm";
+    write_test_kit(&local, "hello-kit", "[hello-kit]\n", &[(package, "hello")]);
+    write_test_kit(&external, "core-kit", "[core-kit]\n", &[(package, "core")]);
+    // A kit in an earlier directory hides one of the same name in a later directory.
+    write_test_kit(
+        &external,
+        "hello-kit",
+        "[hello-kit]\n",
+        &[(package, "other")],
+    );
+
+    create_composite(
+        &composite_dir,
+        &[local, external],
+        &["hello-kit".to_string(), "core-kit".to_string()],
+    )
+    .unwrap();
+
+    // Each kit keeps its own copy of the package, and dnf resolves it from the kit with the
+    // lower priority value, which was listed first.
+    let kit_package =
+        |kit: &str| fs::read_to_string(composite_dir.join("kits").join(kit).join(package)).unwrap();
+    assert_eq!(kit_package("hello-kit"), "hello");
+    assert_eq!(kit_package("core-kit"), "core");
+    assert_eq!(composite_priority(&composite_dir, "hello-kit"), "10");
+    assert_eq!(composite_priority(&composite_dir, "core-kit"), "20");
+}
+
+#[test]
+fn test_create_composite_priority_tie() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let kits_dir = tempdir.path().join("kits");
+    let composite_dir = tempdir.path().join("composite");
+    for kit in ["hello-kit", "core-kit"] {
+        write_test_kit(&kits_dir, kit, &format!("[{kit}]\npriority=1\n"), &[]);
+    }
+    let kit_dirs = [kits_dir];
+
+    // Kits that declare the same priority are ordered by the variant instead, either way round.
+    let kits = ["core-kit".to_string(), "hello-kit".to_string()];
+    create_composite(&composite_dir, &kit_dirs, &kits).unwrap();
+    assert_eq!(composite_priority(&composite_dir, "core-kit"), "10");
+    assert_eq!(composite_priority(&composite_dir, "hello-kit"), "20");
+    create_composite(
+        &composite_dir,
+        &kit_dirs,
+        &[kits[1].clone(), kits[0].clone()],
+    )
+    .unwrap();
+    assert_eq!(composite_priority(&composite_dir, "hello-kit"), "10");
+    assert_eq!(composite_priority(&composite_dir, "core-kit"), "20");
+
+    // A kit cannot tie with itself.
+    let kits = ["hello-kit".to_string(), "hello-kit".to_string()];
+    assert!(matches!(
+        create_composite(&composite_dir, &kit_dirs, &kits),
+        Err(error::Error::KitIncludedTwice { .. })
+    ));
+}
@@ -7,8 +7,13 @@ pub(crate) enum Error {
     #[snafu(display("Failed to start command: {}", source))]
     CommandStart { source: std::io::Error },
 
-    #[snafu(display("Failed to execute command: 'docker {}'", args))]
-    DockerExecution { args: String },
+    #[snafu(display("Failed to execute command: '{} {}'", program, args))]
+    ContainerExecution { program: String, args: String },
+
+    #[snafu(display("{}", source))]
+    ContainerRuntime {
+        source: container_runtime::error::Error,
+    },
 
     #[snafu(display("Failed to change directory to '{}': {}", path.display(), source))]
     DirectoryChange {
@@ -46,6 +51,22 @@ pub(crate) enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Invalid partitions in the variant manifest: {}", reason))]
+    InvalidPartitions { reason: String },
+
+    #[snafu(display("Failed to read file '{}': {}", path.display(), source))]
+    FileRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to copy file '{}' to '{}': {}", src.display(), dst.display(), source))]
+    FileCopy {
+        src: PathBuf,
+        dst: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to remove file '{}': {}", path.display(), source))]
     FileRemove {
         path: PathBuf,
@@ -59,6 +80,39 @@ pub(crate) enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Failed to write file '{}': {}", path.display(), source))]
+    FileWrite {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Kit '{}' includes package '{}', but no RPMs were found for it in '{}'",
+        kit,
+        package,
+        path.display()
+    ))]
+    KitPackageMissing {
+        kit: String,
+        package: String,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Unable to find kit '{}', looked in: {}", kit, searched))]
+    KitNotFound { kit: String, searched: String },
+
+    #[snafu(display("Kit '{}' is included more than once", kit))]
+    KitIncludedTwice { kit: String },
+
+    #[snafu(display("Failed to serialize the variant record '{}': {}", path.display(), source))]
+    VariantRecordSerialize {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to write the SBOM: {}", source))]
+    Sbom { source: crate::sbom::error::Error },
+
     #[snafu(display("Missing environment variable '{}'", var))]
     Environment {
         var: String,
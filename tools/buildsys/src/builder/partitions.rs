@@ -0,0 +1,629 @@
+/*!
+This module lays out the custom partitions of a variant in the "os" image, in place of the
+partitions that `partyplanner` plans for the variant's `partition-plan`. The layout is checked
+here, so that mistakes are reported before the build starts, and `rpm2img` is given the offset
+and size of each partition.
+
+`rpm2img` refers to Bottlerocket's own partitions by names such as `BOOT-A`, so those are found by
+their type GUIDs. Other partitions are named by their labels.
+
+*/
+use super::error::{self, Result};
+use buildsys::manifest::{
+    Bank, ImageLayout, Partition, PartitionFilesystem, PartitionPlan, PartitionSize, SupportedArch,
+};
+use serde::Serialize;
+use snafu::ensure;
+use std::collections::HashSet;
+
+/// The partitions start after the first MiB, and the last MiB holds the backup GPT.
+const GPT_MIB: u64 = 1;
+
+/// The space that the "split" partition plan keeps for the unused data partition in the "os"
+/// image.
+const SPLIT_DATA_A_MIB: u64 = 1;
+
+/// GPT partition names are at most 36 UTF-16 code units.
+const MAX_LABEL_LEN: usize = 36;
+
+const MIB: u64 = 1024 * 1024;
+
+/// The names that `rpm2img` knows Bottlerocket's own partitions by. Custom partitions are named by
+/// their labels, so these labels are taken.
+const RPM2IMG_NAMES: [&str; 14] = [
+    "BIOS",
+    "EFI-A",
+    "EFI-B",
+    "BOOT-A",
+    "BOOT-B",
+    "ROOT-A",
+    "ROOT-B",
+    "HASH-A",
+    "HASH-B",
+    "RESERVED-A",
+    "RESERVED-B",
+    "PRIVATE",
+    "DATA-A",
+    "DATA-B",
+];
+
+const BIOS_BOOT_TYPE: &str = "21686148-6449-6e6f-744e-656564454649";
+const EFI_SYSTEM_TYPE: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+const EFI_BACKUP_TYPE: &str = "b39ce39c-0a00-b4ab-2d11-f18f8237a21c";
+const BOOT_TYPE: &str = "6b636168-7420-6568-2070-6c616e657421";
+const ROOT_TYPE: &str = "5526016a-1a97-4ea4-b39a-b7c8c6ca4502";
+const HASH_TYPE: &str = "598f10af-c955-4456-6a99-7720068a6cea";
+const RESERVED_TYPE: &str = "0c5d99a5-d331-4147-baef-08e2b855bdc9";
+const PRIVATE_TYPE: &str = "440408bb-eb0b-4328-a6e5-a29038fad706";
+const DATA_TYPE: &str = "626f7474-6c65-6474-6861-726d61726b73";
+
+/// A partition of the "os" image where `rpm2img` should create it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PlannedPartition {
+    /// The name that `rpm2img` knows the partition by.
+    #[serde(skip)]
+    name: String,
+    label: String,
+    type_guid: String,
+    offset_mib: u64,
+    size_mib: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filesystem: Option<PartitionFilesystem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mount_point: Option<String>,
+}
+
+/// Lay out `partitions` in an "os" image of the size given by `layout`, followed by the data
+/// partition of the partition plan.
+pub(crate) fn plan_partitions(
+    partitions: &[Partition],
+    layout: &ImageLayout,
+    arch: SupportedArch,
+) -> Result<Vec<PlannedPartition>> {
+    let os_image_mib = u64::from(layout.os_image_size_gib.get()) * 1024;
+    let (data_a_mib, available_mib) = match layout.partition_plan {
+        PartitionPlan::Split => (
+            SPLIT_DATA_A_MIB,
+            os_image_mib - GPT_MIB * 2 - SPLIT_DATA_A_MIB,
+        ),
+        PartitionPlan::Unified => (
+            u64::from(layout.data_image_size_gib.get()) * 1024,
+            os_image_mib - GPT_MIB * 2,
+        ),
+    };
+
+    // Partitions outside the banks come before the banks if they are listed before them.
+    let first_banked = partitions
+        .iter()
+        .position(|partition| !partition.banks.is_empty())
+        .unwrap_or(partitions.len());
+    let mut ordered = Vec::new();
+    for partition in &partitions[..first_banked] {
+        ordered.push((partition, None));
+    }
+    for bank in [Bank::A, Bank::B] {
+        for partition in partitions
+            .iter()
+            .filter(|partition| partition.banks.contains(&bank))
+        {
+            ordered.push((partition, Some(bank)));
+        }
+    }
+    for partition in partitions[first_banked..]
+        .iter()
+        .filter(|partition| partition.banks.is_empty())
+    {
+        ordered.push((partition, None));
+    }
+
+    let mut planned = Vec::new();
+    let mut offset_mib = GPT_MIB;
+    for (partition, bank) in ordered {
+        let planned_partition = plan_partition(partition, bank, available_mib, offset_mib)?;
+        offset_mib += planned_partition.size_mib;
+        planned.push(planned_partition);
+    }
+    let used_mib = offset_mib - GPT_MIB;
+    ensure!(
+        used_mib <= available_mib,
+        error::InvalidPartitionsSnafu {
+            reason: format!(
+                "the partitions need {used_mib} MiB, but os-image-size-gib {} leaves {available_mib} \
+                MiB for them",
+                layout.os_image_size_gib
+            ),
+        }
+    );
+    check_partitions(&planned, arch)?;
+
+    planned.push(PlannedPartition {
+        name: "DATA-A".to_string(),
+        label: String::new(),
+        type_guid: DATA_TYPE.to_string(),
+        offset_mib,
+        size_mib: data_a_mib,
+        filesystem: None,
+        mount_point: None,
+    });
+    Ok(planned)
+}
+
+/// The layout as `rpm2img` reads it: a `name:label:type:offset:size:filesystem:mount-point` entry
+/// per partition, separated by spaces.
+pub(crate) fn layout_arg(partitions: &[PlannedPartition]) -> String {
+    partitions
+        .iter()
+        .map(|partition| {
+            format!(
+                "{}:{}:{}:{}:{}:{}:{}",
+                partition.name,
+                partition.label,
+                partition.type_guid,
+                partition.offset_mib,
+                partition.size_mib,
+                partition
+                    .filesystem
+                    .map(|filesystem| filesystem.to_string())
+                    .unwrap_or_default(),
+                partition.mount_point.as_deref().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn plan_partition(
+    partition: &Partition,
+    bank: Option<Bank>,
+    available_mib: u64,
+    offset_mib: u64,
+) -> Result<PlannedPartition> {
+    let invalid = |reason: String| {
+        error::InvalidPartitionsSnafu {
+            reason: format!("partition '{}' {reason}", partition.label),
+        }
+        .fail()
+    };
+
+    if partition.label.is_empty()
+        || partition.label.len() > MAX_LABEL_LEN - 2
+        || !partition
+            .label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return invalid(format!(
+            "needs a label of up to {} letters, digits, '-' and '_'",
+            MAX_LABEL_LEN - 2
+        ));
+    }
+    let type_guid = partition.type_guid.to_ascii_lowercase();
+    if !is_guid(&type_guid) {
+        return invalid(format!(
+            "has an invalid type GUID '{}'",
+            partition.type_guid
+        ));
+    }
+    let banks = partition.banks.iter().collect::<HashSet<_>>();
+    if banks.len() != partition.banks.len() {
+        return invalid("lists a bank more than once".to_string());
+    }
+
+    let bank_suffix = match bank {
+        Some(Bank::A) => "-A",
+        Some(Bank::B) => "-B",
+        None => "",
+    };
+    let label = if banks.len() == 2 {
+        format!("{}{bank_suffix}", partition.label)
+    } else {
+        partition.label.clone()
+    };
+    let name = match type_guid.as_str() {
+        BOOT_TYPE | ROOT_TYPE | HASH_TYPE | RESERVED_TYPE => {
+            if bank.is_none() {
+                return invalid("must be in a bank".to_string());
+            }
+            let role = match type_guid.as_str() {
+                BOOT_TYPE => "BOOT",
+                ROOT_TYPE => "ROOT",
+                HASH_TYPE => "HASH",
+                _ => "RESERVED",
+            };
+            format!("{role}{bank_suffix}")
+        }
+        BIOS_BOOT_TYPE | PRIVATE_TYPE => {
+            if bank.is_some() {
+                return invalid("cannot be in a bank".to_string());
+            }
+            match type_guid.as_str() {
+                BIOS_BOOT_TYPE => "BIOS",
+                _ => "PRIVATE",
+            }
+            .to_string()
+        }
+        EFI_SYSTEM_TYPE => "EFI-A".to_string(),
+        EFI_BACKUP_TYPE => "EFI-B".to_string(),
+        DATA_TYPE => {
+            return invalid(
+                "has the type of the data partition, which follows the partition plan".to_string(),
+            )
+        }
+        _ => label.clone(),
+    };
+    let is_custom = ![
+        BIOS_BOOT_TYPE,
+        EFI_SYSTEM_TYPE,
+        EFI_BACKUP_TYPE,
+        BOOT_TYPE,
+        ROOT_TYPE,
+        HASH_TYPE,
+        RESERVED_TYPE,
+        PRIVATE_TYPE,
+    ]
+    .contains(&type_guid.as_str());
+    if !is_custom {
+        // The OS and `rpm2img` find Bottlerocket's partitions by the labels that `partyplanner`
+        // gives them.
+        let expected = match name.as_str() {
+            "BIOS" => "BIOS-BOOT".to_string(),
+            "EFI-A" => "EFI-SYSTEM".to_string(),
+            "EFI-B" => "EFI-BACKUP".to_string(),
+            name => format!("BOTTLEROCKET-{name}"),
+        };
+        if label != expected {
+            return invalid(format!(
+                "has the type of Bottlerocket's {name} partition, which must be labeled \
+                '{expected}', not '{label}'"
+            ));
+        }
+    }
+    if is_custom && RPM2IMG_NAMES.contains(&label.as_str()) {
+        return invalid(format!(
+            "is labeled '{label}', which rpm2img uses as the name of one of Bottlerocket's \
+            partitions"
+        ));
+    }
+    if !is_custom && (partition.filesystem.is_some() || partition.mount_point.is_some()) {
+        return invalid(
+            "is one of Bottlerocket's partitions, so its filesystem and mount point cannot be set"
+                .to_string(),
+        );
+    }
+    if let Some(mount_point) = &partition.mount_point {
+        if partition.filesystem.is_none() || bank.is_some() {
+            return invalid(
+                "can only have a mount point if it has a filesystem and is not in a bank"
+                    .to_string(),
+            );
+        }
+        if mount_point == "/"
+            || !mount_point.starts_with('/')
+            || mount_point.ends_with('/')
+            || mount_point.contains("//")
+            || !mount_point
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '-' | '_'))
+        {
+            return invalid(format!(
+                "has mount point '{mount_point}', which is not an absolute path below '/' of \
+                letters, digits, '-' and '_'"
+            ));
+        }
+    }
+
+    let size_mib = match partition.size {
+        PartitionSize::Bytes(size) => {
+            if size.bytes() == 0 || size.bytes() % MIB != 0 {
+                return invalid(
+                    "must have a size that is a whole number of MiB, so that the partitions are \
+                    aligned to 1 MiB"
+                        .to_string(),
+                );
+            }
+            size.bytes() / MIB
+        }
+        PartitionSize::Percent(percent) => {
+            let size_mib = available_mib * u64::from(percent) / 100;
+            if size_mib == 0 {
+                return invalid(format!("would have no space with a size of {percent}%"));
+            }
+            size_mib
+        }
+    };
+
+    Ok(PlannedPartition {
+        name,
+        label,
+        type_guid,
+        offset_mib,
+        size_mib,
+        filesystem: partition.filesystem,
+        mount_point: partition.mount_point.clone(),
+    })
+}
+
+/// Check that the layout has the partitions that `rpm2img` and the OS need, in the order they
+/// need them.
+fn check_partitions(planned: &[PlannedPartition], arch: SupportedArch) -> Result<()> {
+    let mut names = HashSet::new();
+    let mut labels = HashSet::new();
+    for partition in planned {
+        ensure!(
+            names.insert(partition.name.as_str()),
+            error::InvalidPartitionsSnafu {
+                reason: format!("there is more than one {} partition", partition.name),
+            }
+        );
+        ensure!(
+            labels.insert(partition.label.as_str()),
+            error::InvalidPartitionsSnafu {
+                reason: format!(
+                    "there is more than one partition labeled '{}'",
+                    partition.label
+                ),
+            }
+        );
+    }
+
+    let mut required = vec![
+        "EFI-A", "BOOT-A", "ROOT-A", "HASH-A", "BOOT-B", "ROOT-B", "HASH-B", "PRIVATE",
+    ];
+    // GRUB is installed to the BIOS boot partition for legacy boot on x86_64.
+    if arch == SupportedArch::X86_64 {
+        required.push("BIOS");
+    }
+    for name in required {
+        ensure!(
+            names.contains(name),
+            error::InvalidPartitionsSnafu {
+                reason: format!("there is no {name} partition"),
+            }
+        );
+    }
+
+    // The kernel finds the root and hash partitions by their distance from the boot partition.
+    let position = |name: String| planned.iter().position(|partition| partition.name == name);
+    for bank in ["A", "B"] {
+        let boot = position(format!("BOOT-{bank}"));
+        ensure!(
+            boot.map(|boot| boot + 1) == position(format!("ROOT-{bank}"))
+                && boot.map(|boot| boot + 2) == position(format!("HASH-{bank}")),
+            error::InvalidPartitionsSnafu {
+                reason: format!(
+                    "the BOOT-{bank}, ROOT-{bank} and HASH-{bank} partitions must be next to \
+                    each other in that order"
+                ),
+            }
+        );
+    }
+    Ok(())
+}
+
+fn is_guid(guid: &str) -> bool {
+    let groups = guid.split('-').collect::<Vec<_>>();
+    groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+        && groups
+            .iter()
+            .all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+fn test_partition(label: &str, type_guid: &str, size: &str, banks: &[Bank]) -> Partition {
+    Partition {
+        label: label.to_string(),
+        type_guid: type_guid.to_string(),
+        size: PartitionSize::try_from(size.to_string()).unwrap(),
+        filesystem: None,
+        banks: banks.to_vec(),
+        mount_point: None,
+    }
+}
+
+/// The partitions that `partyplanner` lays out for a 2 GiB "os" image.
+#[cfg(test)]
+fn test_bottlerocket_partitions() -> Vec<Partition> {
+    vec![
+        test_partition("BIOS-BOOT", BIOS_BOOT_TYPE, "4 MiB", &[]),
+        test_partition("EFI-SYSTEM", EFI_SYSTEM_TYPE, "5 MiB", &[Bank::A]),
+        test_partition("EFI-BACKUP", EFI_BACKUP_TYPE, "5 MiB", &[Bank::B]),
+        test_partition(
+            "BOTTLEROCKET-BOOT",
+            BOOT_TYPE,
+            "40 MiB",
+            &[Bank::A, Bank::B],
+        ),
+        test_partition(
+            "BOTTLEROCKET-ROOT",
+            ROOT_TYPE,
+            "920 MiB",
+            &[Bank::A, Bank::B],
+        ),
+        test_partition(
+            "BOTTLEROCKET-HASH",
+            HASH_TYPE,
+            "10 MiB",
+            &[Bank::A, Bank::B],
+        ),
+        test_partition(
+            "BOTTLEROCKET-RESERVED",
+            RESERVED_TYPE,
+            "25 MiB",
+            &[Bank::A, Bank::B],
+        ),
+        test_partition("BOTTLEROCKET-PRIVATE", PRIVATE_TYPE, "41 MiB", &[]),
+    ]
+}
+
+#[cfg(test)]
+fn test_layout(partition_plan: &str) -> ImageLayout {
+    toml::from_str(&format!("partition-plan = \"{partition_plan}\"")).unwrap()
+}
+
+#[cfg(test)]
+fn invalid_reason(result: Result<Vec<PlannedPartition>>) -> String {
+    match result.unwrap_err() {
+        error::Error::InvalidPartitions { reason } => reason,
+        e => panic!("unexpected error: {e}"),
+    }
+}
+
+#[test]
+fn test_plan_like_partyplanner() {
+    let planned = plan_partitions(
+        &test_bottlerocket_partitions(),
+        &test_layout("split"),
+        SupportedArch::X86_64,
+    )
+    .unwrap();
+    let summary = planned
+        .iter()
+        .map(|p| (p.name.as_str(), p.label.as_str(), p.offset_mib, p.size_mib))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            ("BIOS", "BIOS-BOOT", 1, 4),
+            ("EFI-A", "EFI-SYSTEM", 5, 5),
+            ("BOOT-A", "BOTTLEROCKET-BOOT-A", 10, 40),
+            ("ROOT-A", "BOTTLEROCKET-ROOT-A", 50, 920),
+            ("HASH-A", "BOTTLEROCKET-HASH-A", 970, 10),
+            ("RESERVED-A", "BOTTLEROCKET-RESERVED-A", 980, 25),
+            ("EFI-B", "EFI-BACKUP", 1005, 5),
+            ("BOOT-B", "BOTTLEROCKET-BOOT-B", 1010, 40),
+            ("ROOT-B", "BOTTLEROCKET-ROOT-B", 1050, 920),
+            ("HASH-B", "BOTTLEROCKET-HASH-B", 1970, 10),
+            ("RESERVED-B", "BOTTLEROCKET-RESERVED-B", 1980, 25),
+            ("PRIVATE", "BOTTLEROCKET-PRIVATE", 2005, 41),
+            ("DATA-A", "", 2046, 1),
+        ]
+    );
+    assert!(layout_arg(&planned).starts_with(&format!(
+        "BIOS:BIOS-BOOT:{BIOS_BOOT_TYPE}:1:4:: EFI-A:EFI-SYSTEM:{EFI_SYSTEM_TYPE}:5:5:: "
+    )));
+}
+
+#[test]
+fn test_plan_custom_partitions() {
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[4].size = PartitionSize::Percent(40);
+    partitions.push(Partition {
+        filesystem: Some(PartitionFilesystem::Ext4),
+        mount_point: Some("/opt/scratch-space".to_string()),
+        ..test_partition(
+            "SCRATCH",
+            "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+            "64 MiB",
+            &[],
+        )
+    });
+    let planned =
+        plan_partitions(&partitions, &test_layout("unified"), SupportedArch::Aarch64).unwrap();
+    // 40% of the 2046 MiB that the unified plan leaves for the partitions.
+    assert_eq!(planned[3].size_mib, 818);
+    let scratch = &planned[12];
+    assert_eq!(scratch.name, "SCRATCH");
+    assert_eq!(scratch.type_guid, "0fc63daf-8483-4772-8e79-3d69d8477de4");
+    assert!(layout_arg(&planned).contains(":64:ext4:/opt/scratch-space DATA-A::"));
+    // The unified plan puts the whole data partition at the end of the "os" image.
+    assert_eq!(planned[13].size_mib, 1024);
+}
+
+#[test]
+fn test_plan_invalid_partitions() {
+    let arch = SupportedArch::X86_64;
+    let split = test_layout("split");
+
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[4].size = PartitionSize::Percent(50);
+    assert_eq!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)),
+        "the partitions need 2249 MiB, but os-image-size-gib 2 leaves 2045 MiB for them"
+    );
+
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[3].size = PartitionSize::try_from("1536 KiB".to_string()).unwrap();
+    assert!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)).contains("whole number of MiB")
+    );
+
+    let mut partitions = test_bottlerocket_partitions();
+    partitions.swap(4, 5);
+    assert_eq!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)),
+        "the BOOT-A, ROOT-A and HASH-A partitions must be next to each other in that order"
+    );
+
+    let mut partitions = test_bottlerocket_partitions();
+    partitions.remove(0);
+    assert_eq!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)),
+        "there is no BIOS partition"
+    );
+    assert!(plan_partitions(&partitions, &split, SupportedArch::Aarch64).is_ok());
+
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[7].banks = vec![Bank::A];
+    assert_eq!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)),
+        "partition 'BOTTLEROCKET-PRIVATE' cannot be in a bank"
+    );
+
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[4].mount_point = Some("/".to_string());
+    assert!(invalid_reason(plan_partitions(&partitions, &split, arch))
+        .starts_with("partition 'BOTTLEROCKET-ROOT' is one of Bottlerocket's partitions"));
+
+    // Custom partitions cannot take the names of Bottlerocket's partitions, in or out of a bank.
+    let custom_type = "0fc63daf-8483-4772-8e79-3d69d8477de4";
+    for (label, banks) in [
+        ("DATA-A", &[][..]),
+        ("PRIVATE", &[]),
+        ("EFI", &[Bank::A, Bank::B][..]),
+        ("BOOT", &[Bank::A, Bank::B]),
+    ] {
+        let mut partitions = test_bottlerocket_partitions();
+        partitions.push(test_partition(label, custom_type, "1 MiB", banks));
+        let reason = invalid_reason(plan_partitions(&partitions, &split, arch));
+        assert!(
+            reason.starts_with(&format!("partition '{label}' is labeled '{label}")),
+            "{reason}"
+        );
+        assert!(
+            reason.ends_with("which rpm2img uses as the name of one of Bottlerocket's partitions")
+        );
+    }
+    // The names are matched exactly, as rpm2img does.
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[4].size = PartitionSize::try_from("919 MiB".to_string()).unwrap();
+    partitions.push(test_partition("data-a", custom_type, "1 MiB", &[]));
+    assert!(plan_partitions(&partitions, &split, arch).is_ok());
+
+    // Bottlerocket's partitions keep the labels that the OS finds them by.
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[7].label = "PRIVATE-DATA".to_string();
+    assert_eq!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)),
+        "partition 'PRIVATE-DATA' has the type of Bottlerocket's PRIVATE partition, which must be \
+        labeled 'BOTTLEROCKET-PRIVATE', not 'PRIVATE-DATA'"
+    );
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[3].label = "BOOT".to_string();
+    assert_eq!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)),
+        "partition 'BOOT' has the type of Bottlerocket's BOOT-A partition, which must be labeled \
+        'BOTTLEROCKET-BOOT-A', not 'BOOT-A'"
+    );
+    let mut partitions = test_bottlerocket_partitions();
+    partitions[1].label = "EFI-BACKUP".to_string();
+    assert!(invalid_reason(plan_partitions(&partitions, &split, arch))
+        .ends_with("must be labeled 'EFI-SYSTEM', not 'EFI-BACKUP'"));
+
+    let mut partitions = test_bottlerocket_partitions();
+    partitions.push(test_partition("BIOS-BOOT", "not-a-guid", "1 MiB", &[]));
+    assert_eq!(
+        invalid_reason(plan_partitions(&partitions, &split, arch)),
+        "partition 'BIOS-BOOT' has an invalid type GUID 'not-a-guid'"
+    );
+}
@@ -0,0 +1,117 @@
+/*!
+Build events are written as JSON lines to the file named by `BUILDSYS_EVENTS_FILE`, so that the tool
+running the build can report its progress without parsing the build output. Builds run in parallel
+append to the same file, so each event is written with a single call.
+*/
+
+use crate::args::BuildType;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The package, kit or variant build that events are about.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Subject {
+    pub(crate) kind: BuildType,
+    pub(crate) name: String,
+    pub(crate) arch: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum Event<'a> {
+    BuildStarted {
+        #[serde(flatten)]
+        subject: &'a Subject,
+    },
+    /// The build failed with a known, transient error and is being tried again.
+    BuildRetried {
+        #[serde(flatten)]
+        subject: &'a Subject,
+        attempt: u16,
+        reason: &'a str,
+    },
+    ArtifactProduced {
+        #[serde(flatten)]
+        subject: &'a Subject,
+        path: &'a Path,
+    },
+    BuildFinished {
+        #[serde(flatten)]
+        subject: &'a Subject,
+        success: bool,
+        /// Whether the artifacts were up to date so nothing was built. Cargo does not run buildsys
+        /// at all in that case, so buildsys only reports builds that ran.
+        cached: bool,
+        duration_secs: f64,
+        /// The file with the output of the build, if it was written to one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        log: Option<&'a Path>,
+    },
+}
+
+/// Writes the events of one build.
+#[derive(Debug, Clone)]
+pub(crate) struct Events {
+    file: Option<PathBuf>,
+    subject: Subject,
+}
+
+impl Events {
+    pub(crate) fn new(file: Option<PathBuf>, kind: BuildType, name: &str, arch: &str) -> Self {
+        Self {
+            file,
+            subject: Subject {
+                kind,
+                name: name.to_string(),
+                arch: arch.to_string(),
+            },
+        }
+    }
+
+    pub(crate) fn subject(&self) -> &Subject {
+        &self.subject
+    }
+
+    /// Append `event` to the events file, if there is one. Events are only for reporting, so a
+    /// failure to write one is a warning rather than a build failure.
+    pub(crate) fn emit(&self, event: &Event) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if let Err(e) = append(file, event) {
+            println!(
+                "cargo:warning=Unable to write build event to '{}': {}",
+                file.display(),
+                e
+            );
+        }
+    }
+}
+
+fn append(file: &Path, event: &Event) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(event)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?
+        .write_all(&line)
+}
+
+#[test]
+fn test_event_json() {
+    let events = Events::new(None, BuildType::Package, "kernel-6_1", "x86_64");
+    let event = Event::BuildFinished {
+        subject: events.subject(),
+        success: true,
+        cached: false,
+        duration_secs: 1.5,
+        log: Some(Path::new("build/logs/x86_64/kernel-6_1.log")),
+    };
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        r#"{"event":"build-finished","kind":"package","name":"kernel-6_1","arch":"x86_64","success":true,"cached":false,"duration_secs":1.5,"log":"build/logs/x86_64/kernel-6_1.log"}"#
+    );
+}
@@ -1,5 +1,5 @@
 /*!
-This tool carries out a package or variant build using Docker.
+This tool carries out a package, kit or variant build using Docker.
 
 It is meant to be called by a Cargo build script. To keep those scripts simple,
 all of the configuration is taken from the environment, with the build type
@@ -11,11 +11,13 @@ The implementation is closely tied to the top-level Dockerfile.
 mod args;
 mod builder;
 mod cache;
+mod events;
 mod gomod;
 mod project;
+mod sbom;
 mod spec;
 
-use crate::args::{BuildPackageArgs, BuildVariantArgs, Buildsys, Command};
+use crate::args::{BuildKitArgs, BuildPackageArgs, BuildVariantArgs, Buildsys, Command};
 use crate::builder::DockerBuild;
 use buildsys::manifest::{BundleModule, ManifestInfo, SupportedArch};
 use cache::LookasideCache;
@@ -104,6 +106,7 @@ fn run(args: Buildsys) -> Result<()> {
     args::rerun_for_envs(args.command.build_type());
     match args.command {
         Command::BuildPackage(args) => build_package(*args),
+        Command::BuildKit(args) => build_kit(*args),
         Command::BuildVariant(args) => build_variant(*args),
     }
 }
@@ -237,6 +240,24 @@ fn build_package(args: BuildPackageArgs) -> Result<()> {
     Ok(())
 }
 
+fn build_kit(args: BuildKitArgs) -> Result<()> {
+    let manifest_file = "Cargo.toml";
+    println!("cargo:rerun-if-changed={}", manifest_file);
+
+    let manifest = ManifestInfo::new(args.common.cargo_manifest_dir.join(manifest_file))
+        .context(error::ManifestParseSnafu)?;
+
+    if manifest.kit_included_packages().is_some() {
+        DockerBuild::new_kit(args, &manifest)
+            .context(error::BuilderInstantiationSnafu)?
+            .build()
+            .context(error::BuildAttemptSnafu)?;
+    } else {
+        println!("cargo:warning=No included packages in manifest. Skipping kit build.");
+    }
+    Ok(())
+}
+
 fn build_variant(args: BuildVariantArgs) -> Result<()> {
     let manifest_file = "Cargo.toml";
     println!("cargo:rerun-if-changed={}", manifest_file);
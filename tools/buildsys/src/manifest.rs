@@ -124,6 +124,16 @@ to indicate a good URL for checking whether the software has had a new release.
 releases-url = "https://www.example.com/releases"
 ```
 
+## Metadata for kits
+
+`included-packages` is a list of packages that should be included in a kit.
+The RPMs built for these packages are collected into a yum repository, which
+is then distributed as a kit container image.
+```ignore
+[package.metadata.build-kit]
+included-packages = ["hello-agent", "hello-go"]
+```
+
 ## Metadata for variants
 
 `included-packages` is a list of packages that should be included in a variant.
@@ -132,6 +142,15 @@ releases-url = "https://www.example.com/releases"
 included-packages = ["release"]
 ```
 
+`included-kits` is a list of kits whose packages should be available to a
+variant. Kits are looked up among the locally built kits first, and then among
+the external kits. When a package is found in more than one kit, it is taken
+from the kit that appears first in the list.
+```ignore
+[package.metadata.build-variant]
+included-kits = ["hello-kit"]
+```
+
 `image-format` is the desired format for the built images.
 This can be `raw` (the default), `vmdk`, or `qcow2`.
 ```ignore
@@ -163,12 +182,82 @@ single "os" image volume. The hint will be ignored if the combined size of the
 `partition-plan` is the desired strategy for image partitioning.
 This can be `split` (the default) for "os" and "data" images backed by separate
 volumes, or `unified` to have "os" and "data" share the same volume.
+`max-rootfs-size` is the budget for the root filesystem, as a whole number of
+`KiB`, `MiB` or `GiB`. It is checked as soon as the packages are installed, and
+the variant build fails with a report of the largest packages when the root
+filesystem is larger. `/boot`, `/var/lib` and `/usr/share/licenses` are not
+counted, since they are not kept in the root filesystem. There is no budget by
+default.
 ```ignore
 [package.metadata.build-variant.image-layout]
 os-image-size-gib = 2
 data-image-size-gib = 1
 publish-image-size-hint-gib = 22
 partition-plan = "split"
+max-rootfs-size = "900 MiB"
+```
+
+`partition` replaces the partitions that `partition-plan` lays out in the "os"
+image with a custom list. Each partition has a GPT `label`, a `type-guid`, and a
+`size` that is either a whole number of `MiB` or `GiB`, or a percentage of the
+space in the "os" image. `banks` lists the banks the partition is in, `a` and
+`b`. A partition in both banks is created once per bank, with `-A` and `-B`
+added to its label. The partitions in a bank are laid out in the order they are
+listed, bank A before bank B. Partitions outside the banks that are listed
+before the first partition in a bank come before the banks, and the others
+come after them. The data partition still follows `partition-plan`.
+
+The partitions with Bottlerocket's type GUIDs are found by their type, and keep
+the labels that the OS finds them by, such as `BOTTLEROCKET-PRIVATE`, or
+`BOTTLEROCKET-BOOT` for the partitions in both banks. The list must have the boot, root and hash partitions in both banks, next to each
+other in that order, as well as the private partition and the EFI system
+partition. On `x86_64`, it must also have the BIOS boot partition. Other
+partitions can have a `filesystem`, `ext4` or `vfat`, which is created empty,
+and partitions outside the banks that have a filesystem can have a
+`mount-point` where the OS mounts them. Other partitions cannot be labeled with
+the names that the image build uses for Bottlerocket's partitions, such as
+`BOOT-A`, `EFI-B`, `PRIVATE` or `DATA-A`.
+```ignore
+[[package.metadata.build-variant.partition]]
+label = "BIOS-BOOT"
+type-guid = "21686148-6449-6e6f-744e-656564454649"
+size = "4 MiB"
+
+[[package.metadata.build-variant.partition]]
+label = "EFI-SYSTEM"
+type-guid = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b"
+size = "5 MiB"
+banks = ["a"]
+
+[[package.metadata.build-variant.partition]]
+label = "BOTTLEROCKET-BOOT"
+type-guid = "6b636168-7420-6568-2070-6c616e657421"
+size = "40 MiB"
+banks = ["a", "b"]
+
+[[package.metadata.build-variant.partition]]
+label = "BOTTLEROCKET-ROOT"
+type-guid = "5526016a-1a97-4ea4-b39a-b7c8c6ca4502"
+size = "40%"
+banks = ["a", "b"]
+
+[[package.metadata.build-variant.partition]]
+label = "BOTTLEROCKET-HASH"
+type-guid = "598f10af-c955-4456-6a99-7720068a6cea"
+size = "10 MiB"
+banks = ["a", "b"]
+
+[[package.metadata.build-variant.partition]]
+label = "BOTTLEROCKET-PRIVATE"
+type-guid = "440408bb-eb0b-4328-a6e5-a29038fad706"
+size = "41 MiB"
+
+[[package.metadata.build-variant.partition]]
+label = "SCRATCH"
+type-guid = "0fc63daf-8483-4772-8e79-3d69d8477de4"
+size = "64 MiB"
+filesystem = "ext4"
+mount-point = "/opt/scratch"
 ```
 
 `supported-arches` is the list of architectures the variant is able to run on.
@@ -179,6 +268,16 @@ If not specified, the variant can run on any of those architectures.
 supported-arches = ["x86_64"]
 ```
 
+`sbom-formats` is a list of the formats of the software bill of materials that is
+written next to the images. It lists the installed RPMs, along with the
+`external-files` and `source-groups` of the packages they were built from.
+The formats can be `spdx` (SPDX 2.3 JSON) and `cyclonedx` (CycloneDX 1.5 JSON).
+Both are written by default; an empty list turns the SBOM off.
+```ignore
+[package.metadata.build-variant]
+sbom-formats = ["spdx"]
+```
+
 `kernel-parameters` is a list of extra parameters to be added to the kernel command line.
 The given parameters are inserted at the start of the command line.
 ```ignore
@@ -311,6 +410,16 @@ impl ManifestInfo {
             .and_then(|b| b.included_packages.as_ref())
     }
 
+    /// Convenience method to return the list of included kits, in priority order.
+    pub fn included_kits(&self) -> Option<&Vec<String>> {
+        self.build_variant().and_then(|b| b.included_kits.as_ref())
+    }
+
+    /// Convenience method to return the list of packages included in a kit.
+    pub fn kit_included_packages(&self) -> Option<&Vec<String>> {
+        self.build_kit().and_then(|b| b.included_packages.as_ref())
+    }
+
     /// Convenience method to return the image format override, if any.
     pub fn image_format(&self) -> Option<&ImageFormat> {
         self.build_variant().and_then(|b| b.image_format.as_ref())
@@ -333,6 +442,23 @@ impl ManifestInfo {
             .and_then(|b| b.kernel_parameters.as_ref())
     }
 
+    /// Convenience method to return the SBOM formats for this variant.
+    pub fn sbom_formats(&self) -> Option<&Vec<SbomFormat>> {
+        self.build_variant().map(|b| &b.sbom_formats)
+    }
+
+    /// Convenience method to return the custom partitions for this variant, if any.
+    pub fn partitions(&self) -> Option<&Vec<Partition>> {
+        self.build_variant()
+            .map(|b| &b.partitions)
+            .filter(|partitions| !partitions.is_empty())
+    }
+
+    /// Convenience method to return the name of the Cargo package.
+    pub fn cargo_package_name(&self) -> Option<&String> {
+        self.package.name.as_ref()
+    }
+
     /// Convenience method to return the enabled image features for this variant.
     pub fn image_features(&self) -> Option<HashSet<ImageFeature>> {
         self.build_variant().and_then(|b| {
@@ -356,11 +482,19 @@ impl ManifestInfo {
             .as_ref()
             .and_then(|m| m.build_variant.as_ref())
     }
+
+    fn build_kit(&self) -> Option<&BuildKit> {
+        self.package
+            .metadata
+            .as_ref()
+            .and_then(|m| m.build_kit.as_ref())
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 struct Package {
+    name: Option<String>,
     metadata: Option<Metadata>,
 }
 
@@ -369,6 +503,7 @@ struct Package {
 struct Metadata {
     build_package: Option<BuildPackage>,
     build_variant: Option<BuildVariant>,
+    build_kit: Option<BuildKit>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -404,12 +539,29 @@ pub enum SensitivityType {
 #[serde(rename_all = "kebab-case")]
 pub struct BuildVariant {
     pub included_packages: Option<Vec<String>>,
+    pub included_kits: Option<Vec<String>>,
     pub image_format: Option<ImageFormat>,
     #[serde(default)]
     pub image_layout: ImageLayout,
     pub supported_arches: Option<HashSet<SupportedArch>>,
     pub kernel_parameters: Option<Vec<String>>,
     pub image_features: Option<HashMap<ImageFeature, bool>>,
+    #[serde(default = "BuildVariant::default_sbom_formats")]
+    pub sbom_formats: Vec<SbomFormat>,
+    #[serde(default, rename = "partition")]
+    pub partitions: Vec<Partition>,
+}
+
+impl BuildVariant {
+    fn default_sbom_formats() -> Vec<SbomFormat> {
+        vec![SbomFormat::Spdx, SbomFormat::Cyclonedx]
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildKit {
+    pub included_packages: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -420,17 +572,23 @@ pub enum ImageFormat {
     Vmdk,
 }
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
 /// Constrain specified image sizes to a plausible range, from 0 - 65535 GiB.
 pub struct ImageSize(u16);
 
+impl ImageSize {
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+}
+
 impl Display for ImageSize {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct ImageLayout {
     #[serde(default = "ImageLayout::default_os_image_size_gib")]
@@ -441,6 +599,8 @@ pub struct ImageLayout {
     publish_image_size_hint_gib: ImageSize,
     #[serde(default = "ImageLayout::default_partition_plan")]
     pub partition_plan: PartitionPlan,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rootfs_size: Option<ByteSize>,
 }
 
 /// These are the historical defaults for all variants, before we added support
@@ -499,17 +659,110 @@ impl Default for ImageLayout {
             data_image_size_gib: Self::default_data_image_size_gib(),
             publish_image_size_hint_gib: Self::default_publish_image_size_hint_gib(),
             partition_plan: Self::default_partition_plan(),
+            max_rootfs_size: None,
         }
     }
 }
 
-#[derive(Deserialize, Debug, Copy, Clone)]
+/// A size in bytes, written in the manifest with a binary unit, such as "900 MiB".
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "u64")]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl TryFrom<String> for ByteSize {
+    type Error = Error;
+    fn try_from(s: String) -> Result<Self> {
+        let trimmed = s.trim();
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let multiplier: u64 = match unit.trim_start() {
+            "KiB" => 1 << 10,
+            "MiB" => 1 << 20,
+            "GiB" => 1 << 30,
+            _ => return error::ParseByteSizeSnafu { what: s }.fail()?,
+        };
+        match number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(multiplier))
+        {
+            Some(bytes) => Ok(ByteSize(bytes)),
+            None => error::ParseByteSizeSnafu { what: s }.fail()?,
+        }
+    }
+}
+
+impl From<ByteSize> for u64 {
+    fn from(size: ByteSize) -> Self {
+        size.0
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum PartitionPlan {
     Split,
     Unified,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Partition {
+    pub label: String,
+    pub type_guid: String,
+    pub size: PartitionSize,
+    pub filesystem: Option<PartitionFilesystem>,
+    #[serde(default)]
+    pub banks: Vec<Bank>,
+    pub mount_point: Option<String>,
+}
+
+/// The size of a custom partition, written as a size such as "40 MiB", or as a percentage of the
+/// space in the image such as "40%".
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum PartitionSize {
+    Bytes(ByteSize),
+    Percent(u8),
+}
+
+impl TryFrom<String> for PartitionSize {
+    type Error = Error;
+    fn try_from(s: String) -> Result<Self> {
+        match s.trim().strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<u8>() {
+                Ok(percent) if (1..=100).contains(&percent) => Ok(PartitionSize::Percent(percent)),
+                _ => error::ParsePartitionSizeSnafu { what: s }.fail()?,
+            },
+            None => ByteSize::try_from(s).map(PartitionSize::Bytes),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionFilesystem {
+    Ext4,
+    Vfat,
+}
+
+serde_plain::derive_display_from_serialize!(PartitionFilesystem);
+
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Bank {
+    A,
+    B,
+}
+
 #[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum SupportedArch {
@@ -530,8 +783,8 @@ impl SupportedArch {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[serde(try_from = "String")]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", rename_all = "kebab-case")]
 pub enum ImageFeature {
     GrubSetPrivateVar,
     SystemdNetworkd,
@@ -569,6 +822,13 @@ impl fmt::Display for ImageFeature {
     }
 }
 
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    Spdx,
+    Cyclonedx,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum BundleModule {
@@ -586,3 +846,28 @@ pub struct ExternalFile {
     pub bundle_root_path: Option<PathBuf>,
     pub bundle_output_path: Option<PathBuf>,
 }
+
+#[test]
+fn test_byte_size() {
+    let parse = |s: &str| ByteSize::try_from(s.to_string()).map(|size| size.bytes());
+    assert_eq!(parse("900 MiB").unwrap(), 900 * 1024 * 1024);
+    assert_eq!(parse("2GiB").unwrap(), 2 * 1024 * 1024 * 1024);
+    assert_eq!(parse(" 512 KiB ").unwrap(), 512 * 1024);
+    assert!(parse("900").is_err());
+    assert!(parse("1.5 GiB").is_err());
+    assert!(parse("MiB").is_err());
+    assert!(parse("900 MB").is_err());
+}
+
+#[test]
+fn test_partition_size() {
+    let parse = |s: &str| PartitionSize::try_from(s.to_string());
+    assert_eq!(parse("40%").unwrap(), PartitionSize::Percent(40));
+    assert_eq!(
+        parse("40 MiB").unwrap(),
+        PartitionSize::Bytes(ByteSize(40 * 1024 * 1024))
+    );
+    assert!(parse("0%").is_err());
+    assert!(parse("101%").is_err());
+    assert!(parse("40").is_err());
+}
@@ -17,6 +17,19 @@ pub(super) enum Error {
     #[snafu(display("Failed to parse image feature '{}'", what))]
     ParseImageFeature { what: String },
 
+    #[snafu(display(
+        "Failed to parse size '{}'; expected a whole number of KiB, MiB or GiB, such as '900 MiB'",
+        what
+    ))]
+    ParseByteSize { what: String },
+
+    #[snafu(display(
+        "Failed to parse partition size '{}'; expected a size such as '40 MiB' or a percentage \
+        from 1% to 100%",
+        what
+    ))]
+    ParsePartitionSize { what: String },
+
     #[snafu(display("Invalid image size {}; must be between 1 and 1024", value))]
     InvalidImageSize { value: i32 },
 }
@@ -0,0 +1,921 @@
+/*!
+This module writes the software bill of materials (SBOM) for a variant image.
+
+The image build records the RPMs installed in the image. Each RPM is traced back to the package
+that it was built from through its source RPM, and the package manifest adds what the package was
+built from: the `external-files` with their URLs and hashes, and the `source-groups` of the
+project's own sources. RPMs from external kits have no local manifest, and are listed on their
+own.
+
+*/
+pub(crate) mod error;
+use error::Result;
+
+use buildsys::manifest::{ManifestInfo, SbomFormat};
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use snafu::{OptionExt, ResultExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The prefix that the OS adds to the names of the RPMs built from its packages.
+const RPM_PREFIX: &str = "bottlerocket-";
+
+/// An RPM installed in the image, as recorded by `rpm2img`.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct InstalledRpm {
+    pub(crate) name: String,
+    pub(crate) epoch: u64,
+    pub(crate) version: String,
+    pub(crate) release: String,
+    pub(crate) arch: String,
+    pub(crate) size: u64,
+    pub(crate) license: String,
+    pub(crate) url: String,
+    pub(crate) sourcerpm: String,
+}
+
+impl InstalledRpm {
+    /// Read the RPMs recorded in `path`.
+    pub(crate) fn read(path: &Path) -> Result<Vec<Self>> {
+        let data = fs::read_to_string(path).context(error::InstalledReadSnafu { path })?;
+        serde_json::from_str(&data).context(error::InstalledParseSnafu { path })
+    }
+
+    /// The version of the RPM, as `[epoch:]version-release`.
+    pub(crate) fn evr(&self) -> String {
+        match self.epoch {
+            0 => format!("{}-{}", self.version, self.release),
+            epoch => format!("{}:{}-{}", epoch, self.version, self.release),
+        }
+    }
+
+    /// The name of the package that the RPM was built from, which is the name of its spec file.
+    fn package(&self) -> Option<&str> {
+        let name = self.sourcerpm.strip_suffix(".src.rpm")?;
+        // Drop the version and the release.
+        let (name, _release) = name.rsplit_once('-')?;
+        let (name, _version) = name.rsplit_once('-')?;
+        Some(name.strip_prefix(RPM_PREFIX).unwrap_or(name))
+    }
+
+    fn purl(&self) -> String {
+        let mut purl = format!(
+            "pkg:rpm/bottlerocket/{}@{}-{}?arch={}",
+            self.name, self.version, self.release, self.arch
+        );
+        if self.epoch != 0 {
+            purl.push_str(&format!("&epoch={}", self.epoch));
+        }
+        purl
+    }
+
+    /// The license of the RPM, unless it did not declare one.
+    fn license(&self) -> Option<&str> {
+        match self.license.as_str() {
+            "" | "(none)" => None,
+            license => Some(license),
+        }
+    }
+
+    fn homepage(&self) -> Option<&str> {
+        match self.url.as_str() {
+            "" | "(none)" => None,
+            url => Some(url),
+        }
+    }
+}
+
+/// An upstream file that a package was built from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct ExternalFile {
+    name: String,
+    url: String,
+    sha512: String,
+}
+
+/// What a package was built from, according to its manifest.
+#[derive(Debug, Default)]
+struct PackageSources {
+    external_files: Vec<ExternalFile>,
+    source_groups: Vec<PathBuf>,
+}
+
+pub(crate) struct Sbom {
+    /// The name of the image, which the SBOM files are named after.
+    name: String,
+    version: String,
+    /// When the image was built, in RFC 3339 format.
+    created: String,
+    rpms: Vec<InstalledRpm>,
+    /// The sources of each package, by the name of its spec file.
+    sources: BTreeMap<String, PackageSources>,
+}
+
+impl Sbom {
+    /// Create the SBOM of the image `name`, from the RPMs recorded in `rpms_file` and the package
+    /// manifests in `packages_dir`. The `timestamp` of the build is in seconds since the epoch.
+    pub(crate) fn new(
+        name: impl Into<String>,
+        version: impl Into<String>,
+        timestamp: &str,
+        rpms_file: &Path,
+        packages_dir: &Path,
+    ) -> Result<Self> {
+        let created = timestamp
+            .parse()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .context(error::TimestampSnafu { timestamp })?
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string();
+
+        Ok(Self {
+            name: name.into(),
+            version: version.into(),
+            created,
+            rpms: InstalledRpm::read(rpms_file)?,
+            sources: package_sources(packages_dir)?,
+        })
+    }
+
+    /// Write the SBOM in each of `formats` to `dir`, and return the paths of the files.
+    pub(crate) fn write(&self, dir: &Path, formats: &[SbomFormat]) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for format in formats {
+            let (extension, document) = match format {
+                SbomFormat::Spdx => ("spdx.json", self.spdx()),
+                SbomFormat::Cyclonedx => ("cdx.json", self.cyclonedx()),
+            };
+            let path = dir.join(format!("{}.{}", self.name, extension));
+            let data = serde_json::to_string_pretty(&document)
+                .context(error::SbomSerializeSnafu { path: &path })?;
+            fs::write(&path, data).context(error::SbomWriteSnafu { path: &path })?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    fn sources(&self, rpm: &InstalledRpm) -> Option<&PackageSources> {
+        rpm.package().and_then(|package| self.sources.get(package))
+    }
+
+    /// The SBOM as an SPDX 2.3 document. The image contains the RPMs, and each RPM is generated
+    /// from the external files and source groups of its package.
+    fn spdx(&self) -> Value {
+        let image_id = "SPDXRef-Image";
+        let mut packages = vec![json!({
+            "SPDXID": image_id,
+            "name": self.name,
+            "versionInfo": self.version,
+            "primaryPackagePurpose": "OPERATING-SYSTEM",
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+        })];
+        let mut relationships = vec![relationship("SPDXRef-DOCUMENT", "DESCRIBES", image_id)];
+
+        // Packages often build several RPMs, so their sources are only listed once.
+        let mut listed = BTreeSet::new();
+        for rpm in &self.rpms {
+            let rpm_id = spdx_id("RPM", &rpm.name);
+            let mut package = json!({
+                "SPDXID": rpm_id,
+                "name": rpm.name,
+                "versionInfo": rpm.evr(),
+                "primaryPackagePurpose": "INSTALL",
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "licenseDeclared": rpm.license().unwrap_or("NOASSERTION"),
+                "sourceInfo": format!("built from {}", rpm.sourcerpm),
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": rpm.purl(),
+                }],
+            });
+            if let Some(homepage) = rpm.homepage() {
+                package["homepage"] = json!(homepage);
+            }
+            packages.push(package);
+            relationships.push(relationship(image_id, "CONTAINS", &rpm_id));
+
+            let Some(sources) = self.sources(rpm) else {
+                continue;
+            };
+            for file in &sources.external_files {
+                // Packages may have different files of the same name, so the ID includes the
+                // start of the file's hash.
+                let hash = file.sha512.get(..12).unwrap_or(&file.sha512);
+                let file_id = spdx_id("Source", &format!("{}-{}", file.name, hash));
+                if listed.insert(file_id.clone()) {
+                    packages.push(json!({
+                        "SPDXID": file_id,
+                        "name": file.name,
+                        "primaryPackagePurpose": "SOURCE",
+                        "downloadLocation": file.url,
+                        "filesAnalyzed": false,
+                        "checksums": [{
+                            "algorithm": "SHA512",
+                            "checksumValue": file.sha512,
+                        }],
+                    }));
+                }
+                relationships.push(relationship(&rpm_id, "GENERATED_FROM", &file_id));
+            }
+            for group in &sources.source_groups {
+                let group = group.display().to_string();
+                let group_id = spdx_id("SourceGroup", &group);
+                if listed.insert(group_id.clone()) {
+                    packages.push(json!({
+                        "SPDXID": group_id,
+                        "name": group,
+                        "primaryPackagePurpose": "SOURCE",
+                        "downloadLocation": "NOASSERTION",
+                        "filesAnalyzed": false,
+                        "comment": "A group of projects in the sources directory.",
+                    }));
+                }
+                relationships.push(relationship(&rpm_id, "GENERATED_FROM", &group_id));
+            }
+        }
+
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.name,
+            "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}", self.name, self.created),
+            "creationInfo": {
+                "created": self.created,
+                "creators": ["Tool: buildsys"],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+
+    /// The SBOM as a CycloneDX 1.5 document. The sources of each RPM are listed as its external
+    /// references and properties.
+    fn cyclonedx(&self) -> Value {
+        let components = self
+            .rpms
+            .iter()
+            .map(|rpm| {
+                let mut references = Vec::new();
+                if let Some(homepage) = rpm.homepage() {
+                    references.push(json!({"type": "website", "url": homepage}));
+                }
+                let mut properties = vec![
+                    json!({"name": "buildsys:sourcerpm", "value": rpm.sourcerpm}),
+                    json!({"name": "buildsys:installed-size", "value": rpm.size.to_string()}),
+                ];
+                if let Some(sources) = self.sources(rpm) {
+                    for file in &sources.external_files {
+                        references.push(json!({
+                            "type": "source-distribution",
+                            "url": file.url,
+                            "comment": file.name,
+                            "hashes": [{"alg": "SHA-512", "content": file.sha512}],
+                        }));
+                    }
+                    for group in &sources.source_groups {
+                        properties.push(json!({
+                            "name": "buildsys:source-group",
+                            "value": group.display().to_string(),
+                        }));
+                    }
+                }
+
+                let mut component = json!({
+                    "type": "library",
+                    "bom-ref": rpm.purl(),
+                    "name": rpm.name,
+                    "version": rpm.evr(),
+                    "purl": rpm.purl(),
+                    "externalReferences": references,
+                    "properties": properties,
+                });
+                if let Some(license) = rpm.license() {
+                    component["licenses"] = json!([{ "expression": license }]);
+                }
+                component
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": self.created,
+                "tools": {
+                    "components": [{"type": "application", "name": "buildsys"}],
+                },
+                "component": {
+                    "type": "operating-system",
+                    "bom-ref": self.name,
+                    "name": self.name,
+                    "version": self.version,
+                },
+            },
+            "components": components,
+        })
+    }
+}
+
+/// Find the sources of each of the packages in `packages_dir`, by the name of their spec file.
+fn package_sources(packages_dir: &Path) -> Result<BTreeMap<String, PackageSources>> {
+    let mut sources = BTreeMap::new();
+    // Projects without packages of their own only use RPMs from kits.
+    if !packages_dir.is_dir() {
+        return Ok(sources);
+    }
+
+    let entries =
+        fs::read_dir(packages_dir).context(error::DirectoryReadSnafu { path: packages_dir })?;
+    for entry in entries {
+        let entry = entry.context(error::DirectoryReadSnafu { path: packages_dir })?;
+        let path = entry.path().join("Cargo.toml");
+        if !path.is_file() {
+            continue;
+        }
+        let manifest =
+            ManifestInfo::new(&path).context(error::ManifestLoadSnafu { path: &path })?;
+
+        // The spec file is named after the package, unless the manifest overrides the name.
+        let name = match manifest.package_name().or(manifest.cargo_package_name()) {
+            Some(name) => name.clone(),
+            None => entry.file_name().to_string_lossy().to_string(),
+        };
+        let external_files = manifest
+            .external_files()
+            .into_iter()
+            .flatten()
+            .map(|f| ExternalFile {
+                name: f
+                    .path
+                    .as_ref()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|| url_file_name(&f.url)),
+                url: f.url.clone(),
+                sha512: f.sha512.clone(),
+            })
+            .collect();
+        let source_groups = manifest.source_groups().cloned().unwrap_or_default();
+        sources.insert(
+            name,
+            PackageSources {
+                external_files,
+                source_groups,
+            },
+        );
+    }
+    Ok(sources)
+}
+
+/// The last path segment of `url`, which is the file name that the lookaside cache uses when the
+/// manifest does not give one.
+fn url_file_name(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back().map(str::to_string))
+        })
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// An SPDX element ID, which may only contain letters, numbers, `.` and `-`.
+fn spdx_id(kind: &str, name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    format!("SPDXRef-{}-{}", kind, name)
+}
+
+fn relationship(element: &str, kind: &str, related: &str) -> Value {
+    json!({
+        "spdxElementId": element,
+        "relationshipType": kind,
+        "relatedSpdxElement": related,
+    })
+}
+
+#[test]
+fn test_sbom() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let packages_dir = tempdir.path().join("packages");
+    fs::create_dir_all(packages_dir.join("libfoo")).unwrap();
+    fs::write(
+        packages_dir.join("libfoo/Cargo.toml"),
+        r#"
+[package]
+name = "libfoo"
+version = "0.1.0"
+
+[package.metadata.build-package]
+source-groups = ["foo-agent"]
+
+[[package.metadata.build-package.external-files]]
+url = "https://example.com/releases/foo-1.2.tar.gz"
+sha512 = "abcdef"
+"#,
+    )
+    .unwrap();
+
+    let rpms_file = tempdir.path().join("rpms.json");
+    fs::write(
+        &rpms_file,
+        r#"[
+  {"name":"bottlerocket-libfoo","epoch":0,"version":"1.2","release":"1.1700000000.abcdef","arch":"x86_64","size":1024,"license":"MIT","url":"https://example.com","sourcerpm":"bottlerocket-libfoo-1.2-1.1700000000.abcdef.src.rpm"},
+  {"name":"bottlerocket-libfoo-bin","epoch":1,"version":"1.2","release":"1","arch":"x86_64","size":2048,"license":"(none)","url":"(none)","sourcerpm":"bottlerocket-libfoo-1.2-1.src.rpm"},
+  {"name":"bottlerocket-kitbar","epoch":0,"version":"3.0","release":"2","arch":"x86_64","size":4096,"license":"Apache-2.0","url":"","sourcerpm":"bottlerocket-kitbar-3.0-2.src.rpm"}
+]"#,
+    )
+    .unwrap();
+
+    let sbom = Sbom::new(
+        "bottlerocket-aws-dev-x86_64-1.20.0-abcdef",
+        "1.20.0-abcdef",
+        "1700000000",
+        &rpms_file,
+        &packages_dir,
+    )
+    .unwrap();
+    assert_eq!(sbom.created, "2023-11-14T22:13:20Z");
+    assert_eq!(sbom.rpms[1].evr(), "1:1.2-1");
+    assert_eq!(sbom.rpms[1].package(), Some("libfoo"));
+
+    let paths = sbom
+        .write(tempdir.path(), &[SbomFormat::Spdx, SbomFormat::Cyclonedx])
+        .unwrap();
+    assert_eq!(
+        paths,
+        [
+            tempdir
+                .path()
+                .join("bottlerocket-aws-dev-x86_64-1.20.0-abcdef.spdx.json"),
+            tempdir
+                .path()
+                .join("bottlerocket-aws-dev-x86_64-1.20.0-abcdef.cdx.json"),
+        ]
+    );
+
+    let spdx: Value = serde_json::from_str(&fs::read_to_string(&paths[0]).unwrap()).unwrap();
+    let packages = spdx["packages"].as_array().unwrap();
+    // The image, three RPMs, and the sources that two of them share.
+    assert_eq!(packages.len(), 6);
+    let source = packages
+        .iter()
+        .find(|p| p["SPDXID"] == "SPDXRef-Source-foo-1.2.tar.gz-abcdef")
+        .unwrap();
+    assert_eq!(
+        source["downloadLocation"],
+        "https://example.com/releases/foo-1.2.tar.gz"
+    );
+    assert_eq!(source["checksums"][0]["checksumValue"], "abcdef");
+    let generated_from = spdx["relationships"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|r| r["relationshipType"] == "GENERATED_FROM")
+        .count();
+    assert_eq!(generated_from, 4);
+
+    let cyclonedx: Value = serde_json::from_str(&fs::read_to_string(&paths[1]).unwrap()).unwrap();
+    let components = cyclonedx["components"].as_array().unwrap();
+    assert_eq!(components.len(), 3);
+    assert_eq!(
+        components[0]["externalReferences"][1]["hashes"][0]["content"],
+        "abcdef"
+    );
+    assert_eq!(components[0]["properties"][2]["value"], "foo-agent");
+    assert!(components[1].get("licenses").is_none());
+    assert_eq!(
+        components[2]["purl"],
+        "pkg:rpm/bottlerocket/bottlerocket-kitbar@3.0-2?arch=x86_64"
+    );
+}
+
+#[cfg(test)]
+fn installed_rpm(name: &str, epoch: u64, sourcerpm: &str) -> InstalledRpm {
+    InstalledRpm {
+        name: name.to_string(),
+        epoch,
+        version: "1.2".to_string(),
+        release: "1".to_string(),
+        arch: "aarch64".to_string(),
+        size: 1024,
+        license: "MIT OR Apache-2.0".to_string(),
+        url: "https://example.com/foo".to_string(),
+        sourcerpm: sourcerpm.to_string(),
+    }
+}
+
+#[test]
+fn test_installed_rpm_fields() {
+    let rpm = installed_rpm("bottlerocket-foo-bin", 0, "bottlerocket-foo-1.2-1.src.rpm");
+    assert_eq!(rpm.evr(), "1.2-1");
+    assert_eq!(rpm.package(), Some("foo"));
+    assert_eq!(
+        rpm.purl(),
+        "pkg:rpm/bottlerocket/bottlerocket-foo-bin@1.2-1?arch=aarch64"
+    );
+    assert_eq!(rpm.license(), Some("MIT OR Apache-2.0"));
+    assert_eq!(rpm.homepage(), Some("https://example.com/foo"));
+
+    // The epoch is part of the version and the purl, unless it is zero.
+    let rpm = installed_rpm("bottlerocket-foo", 2, "bottlerocket-foo-1.2-1.src.rpm");
+    assert_eq!(rpm.evr(), "2:1.2-1");
+    assert_eq!(
+        rpm.purl(),
+        "pkg:rpm/bottlerocket/bottlerocket-foo@1.2-1?arch=aarch64&epoch=2"
+    );
+
+    // Package names may contain dashes and dots, and RPMs from kits need not have the prefix.
+    for (sourcerpm, package) in [
+        (
+            "bottlerocket-kernel-6.1-6.1.72-1.1700000000.abcdef.src.rpm",
+            Some("kernel-6.1"),
+        ),
+        ("my-tool-0.3.0-2.src.rpm", Some("my-tool")),
+        ("bottlerocket-foo-1.2-1.rpm", None),
+        ("foo.src.rpm", None),
+        ("foo-1.2.src.rpm", None),
+        ("(none)", None),
+    ] {
+        let rpm = installed_rpm("foo", 0, sourcerpm);
+        assert_eq!(rpm.package(), package, "{sourcerpm}");
+    }
+
+    // rpm reports fields that a spec file leaves out as "(none)".
+    let mut rpm = installed_rpm("foo", 0, "foo-1.2-1.src.rpm");
+    rpm.license = "(none)".to_string();
+    rpm.url = String::new();
+    assert_eq!(rpm.license(), None);
+    assert_eq!(rpm.homepage(), None);
+}
+
+#[test]
+fn test_installed_rpm_read() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let path = tempdir.path().join("rpms.json");
+    assert!(matches!(
+        InstalledRpm::read(&path),
+        Err(error::Error::InstalledRead { .. })
+    ));
+
+    // Every field has to be recorded.
+    fs::write(&path, r#"[{"name":"foo","epoch":0,"version":"1.2"}]"#).unwrap();
+    assert!(matches!(
+        InstalledRpm::read(&path),
+        Err(error::Error::InstalledParse { .. })
+    ));
+
+    fs::write(
+        &path,
+        r#"[{"name":"foo","epoch":0,"version":"1.2","release":"1","arch":"noarch","size":0,"license":"MIT","url":"","sourcerpm":"foo-1.2-1.src.rpm"}]"#,
+    )
+    .unwrap();
+    let rpms = InstalledRpm::read(&path).unwrap();
+    assert_eq!(rpms.len(), 1);
+    assert_eq!(rpms[0].arch, "noarch");
+}
+
+#[test]
+fn test_package_sources() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let packages_dir = tempdir.path().join("packages");
+    // Projects without packages have no sources.
+    assert!(package_sources(&packages_dir).unwrap().is_empty());
+
+    for (dir, manifest) in [
+        (
+            "foo",
+            r#"
+[package]
+name = "foo"
+version = "0.1.0"
+
+[package.metadata.build-package]
+source-groups = ["foo-agent", "shared/foo-lib"]
+
+[[package.metadata.build-package.external-files]]
+url = "https://example.com/releases/foo-1.2.tar.gz?download=1"
+sha512 = "abcdef"
+
+[[package.metadata.build-package.external-files]]
+path = "foo-vendor.tar.gz"
+url = "https://example.com/vendor.tar.gz"
+sha512 = "012345"
+"#,
+        ),
+        (
+            "better_name",
+            r#"
+[package]
+name = "better_name"
+version = "0.1.0"
+
+[package.metadata.build-package]
+package-name = "better.name"
+"#,
+        ),
+    ] {
+        fs::create_dir_all(packages_dir.join(dir)).unwrap();
+        fs::write(packages_dir.join(dir).join("Cargo.toml"), manifest).unwrap();
+    }
+    // Directories without a manifest are not packages.
+    fs::create_dir_all(packages_dir.join("scratch")).unwrap();
+
+    let sources = package_sources(&packages_dir).unwrap();
+    assert_eq!(sources.keys().collect::<Vec<_>>(), ["better.name", "foo"]);
+    // A file is named after the last segment of its URL, unless the manifest gives its path.
+    assert_eq!(
+        sources["foo"].external_files,
+        [
+            ExternalFile {
+                name: "foo-1.2.tar.gz".to_string(),
+                url: "https://example.com/releases/foo-1.2.tar.gz?download=1".to_string(),
+                sha512: "abcdef".to_string(),
+            },
+            ExternalFile {
+                name: "foo-vendor.tar.gz".to_string(),
+                url: "https://example.com/vendor.tar.gz".to_string(),
+                sha512: "012345".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        sources["foo"].source_groups,
+        [PathBuf::from("foo-agent"), PathBuf::from("shared/foo-lib")]
+    );
+    assert!(sources["better.name"].external_files.is_empty());
+    assert!(sources["better.name"].source_groups.is_empty());
+
+    // A manifest that does not parse is an error rather than a package without sources.
+    fs::write(packages_dir.join("scratch/Cargo.toml"), "[package").unwrap();
+    assert!(matches!(
+        package_sources(&packages_dir),
+        Err(error::Error::ManifestLoad { .. })
+    ));
+}
+
+#[test]
+fn test_spdx_id() {
+    assert_eq!(
+        spdx_id("RPM", "bottlerocket-foo"),
+        "SPDXRef-RPM-bottlerocket-foo"
+    );
+    assert_eq!(
+        spdx_id("SourceGroup", "shared/foo_lib v2"),
+        "SPDXRef-SourceGroup-shared-foo-lib-v2"
+    );
+    assert_eq!(url_file_name("https://example.com/a/b.tar.xz"), "b.tar.xz");
+    assert_eq!(url_file_name("not a url"), "not a url");
+}
+
+/// An SBOM of two RPMs built from the package `foo`, and one from a kit.
+#[cfg(test)]
+fn test_sbom_document() -> Sbom {
+    let foo = PackageSources {
+        external_files: vec![ExternalFile {
+            name: "foo-1.2.tar.gz".to_string(),
+            url: "https://example.com/foo-1.2.tar.gz".to_string(),
+            sha512: "abcdef".to_string(),
+        }],
+        source_groups: vec![PathBuf::from("shared/foo-lib")],
+    };
+    let mut kit_rpm = installed_rpm("bottlerocket-bar", 0, "bottlerocket-bar-3.0-2.src.rpm");
+    kit_rpm.license = "(none)".to_string();
+    kit_rpm.url = "(none)".to_string();
+    Sbom {
+        name: "bottlerocket-aws-dev-aarch64-1.20.0".to_string(),
+        version: "1.20.0".to_string(),
+        created: "2023-11-14T22:13:20Z".to_string(),
+        rpms: vec![
+            installed_rpm("bottlerocket-foo", 0, "bottlerocket-foo-1.2-1.src.rpm"),
+            installed_rpm("bottlerocket-foo-bin", 1, "bottlerocket-foo-1.2-1.src.rpm"),
+            kit_rpm,
+        ],
+        sources: BTreeMap::from([("foo".to_string(), foo)]),
+    }
+}
+
+#[test]
+fn test_spdx() {
+    let spdx = test_sbom_document().spdx();
+    assert_eq!(spdx["spdxVersion"], "SPDX-2.3");
+    assert_eq!(spdx["SPDXID"], "SPDXRef-DOCUMENT");
+    assert_eq!(
+        spdx["documentNamespace"],
+        "https://spdx.org/spdxdocs/bottlerocket-aws-dev-aarch64-1.20.0-2023-11-14T22:13:20Z"
+    );
+    assert_eq!(spdx["creationInfo"]["created"], "2023-11-14T22:13:20Z");
+
+    let packages = spdx["packages"].as_array().unwrap();
+    let ids = packages
+        .iter()
+        .map(|p| p["SPDXID"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    // The sources that both RPMs of `foo` were built from are listed once.
+    assert_eq!(
+        ids,
+        [
+            "SPDXRef-Image",
+            "SPDXRef-RPM-bottlerocket-foo",
+            "SPDXRef-Source-foo-1.2.tar.gz-abcdef",
+            "SPDXRef-SourceGroup-shared-foo-lib",
+            "SPDXRef-RPM-bottlerocket-foo-bin",
+            "SPDXRef-RPM-bottlerocket-bar",
+        ]
+    );
+    assert_eq!(packages[0]["primaryPackagePurpose"], "OPERATING-SYSTEM");
+    assert_eq!(packages[0]["versionInfo"], "1.20.0");
+    assert_eq!(packages[1]["licenseDeclared"], "MIT OR Apache-2.0");
+    assert_eq!(packages[1]["homepage"], "https://example.com/foo");
+    assert_eq!(
+        packages[1]["externalRefs"][0]["referenceLocator"],
+        "pkg:rpm/bottlerocket/bottlerocket-foo@1.2-1?arch=aarch64"
+    );
+    assert_eq!(
+        packages[2]["downloadLocation"],
+        "https://example.com/foo-1.2.tar.gz"
+    );
+    assert_eq!(packages[2]["checksums"][0]["algorithm"], "SHA512");
+    assert_eq!(packages[3]["downloadLocation"], "NOASSERTION");
+    assert_eq!(packages[4]["versionInfo"], "1:1.2-1");
+    assert_eq!(packages[5]["licenseDeclared"], "NOASSERTION");
+    assert!(packages[5].get("homepage").is_none());
+
+    let relationships = spdx["relationships"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| {
+            format!(
+                "{} {} {}",
+                r["spdxElementId"].as_str().unwrap(),
+                r["relationshipType"].as_str().unwrap(),
+                r["relatedSpdxElement"].as_str().unwrap()
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relationships,
+        [
+            "SPDXRef-DOCUMENT DESCRIBES SPDXRef-Image",
+            "SPDXRef-Image CONTAINS SPDXRef-RPM-bottlerocket-foo",
+            "SPDXRef-RPM-bottlerocket-foo GENERATED_FROM SPDXRef-Source-foo-1.2.tar.gz-abcdef",
+            "SPDXRef-RPM-bottlerocket-foo GENERATED_FROM SPDXRef-SourceGroup-shared-foo-lib",
+            "SPDXRef-Image CONTAINS SPDXRef-RPM-bottlerocket-foo-bin",
+            "SPDXRef-RPM-bottlerocket-foo-bin GENERATED_FROM SPDXRef-Source-foo-1.2.tar.gz-abcdef",
+            "SPDXRef-RPM-bottlerocket-foo-bin GENERATED_FROM SPDXRef-SourceGroup-shared-foo-lib",
+            "SPDXRef-Image CONTAINS SPDXRef-RPM-bottlerocket-bar",
+        ]
+    );
+}
+
+#[test]
+fn test_spdx_same_file_name() {
+    let sources = |sha512: &str| PackageSources {
+        external_files: vec![ExternalFile {
+            name: "v1.0.tar.gz".to_string(),
+            url: "https://example.com/v1.0.tar.gz".to_string(),
+            sha512: sha512.to_string(),
+        }],
+        source_groups: Vec::new(),
+    };
+    let sbom = Sbom {
+        name: "bottlerocket-aws-dev-aarch64-1.20.0".to_string(),
+        version: "1.20.0".to_string(),
+        created: "2023-11-14T22:13:20Z".to_string(),
+        rpms: vec![
+            installed_rpm("bottlerocket-foo", 0, "bottlerocket-foo-1.2-1.src.rpm"),
+            installed_rpm("bottlerocket-bar", 0, "bottlerocket-bar-1.2-1.src.rpm"),
+        ],
+        sources: BTreeMap::from([
+            ("foo".to_string(), sources("0123456789abcdef0123")),
+            ("bar".to_string(), sources("fedcba9876543210fedc")),
+        ]),
+    };
+    let spdx = sbom.spdx();
+
+    // Files that have the same name but not the same contents are both listed.
+    let sources = spdx["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|p| p["primaryPackagePurpose"] == "SOURCE")
+        .map(|p| {
+            (
+                p["SPDXID"].as_str().unwrap(),
+                p["checksums"][0]["checksumValue"].as_str().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sources,
+        [
+            (
+                "SPDXRef-Source-v1.0.tar.gz-0123456789ab",
+                "0123456789abcdef0123"
+            ),
+            (
+                "SPDXRef-Source-v1.0.tar.gz-fedcba987654",
+                "fedcba9876543210fedc"
+            ),
+        ]
+    );
+    let relationships = spdx["relationships"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|r| r["relationshipType"] == "GENERATED_FROM")
+        .map(|r| {
+            format!(
+                "{} {}",
+                r["spdxElementId"].as_str().unwrap(),
+                r["relatedSpdxElement"].as_str().unwrap()
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relationships,
+        [
+            "SPDXRef-RPM-bottlerocket-foo SPDXRef-Source-v1.0.tar.gz-0123456789ab",
+            "SPDXRef-RPM-bottlerocket-bar SPDXRef-Source-v1.0.tar.gz-fedcba987654",
+        ]
+    );
+}
+
+#[test]
+fn test_cyclonedx() {
+    let cyclonedx = test_sbom_document().cyclonedx();
+    assert_eq!(cyclonedx["bomFormat"], "CycloneDX");
+    assert_eq!(cyclonedx["specVersion"], "1.5");
+    assert_eq!(cyclonedx["metadata"]["timestamp"], "2023-11-14T22:13:20Z");
+    assert_eq!(
+        cyclonedx["metadata"]["component"]["type"],
+        "operating-system"
+    );
+    assert_eq!(
+        cyclonedx["metadata"]["component"]["name"],
+        "bottlerocket-aws-dev-aarch64-1.20.0"
+    );
+
+    let components = cyclonedx["components"].as_array().unwrap();
+    assert_eq!(
+        components
+            .iter()
+            .map(|c| c["bom-ref"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        [
+            "pkg:rpm/bottlerocket/bottlerocket-foo@1.2-1?arch=aarch64",
+            "pkg:rpm/bottlerocket/bottlerocket-foo-bin@1.2-1?arch=aarch64&epoch=1",
+            "pkg:rpm/bottlerocket/bottlerocket-bar@1.2-1?arch=aarch64",
+        ]
+    );
+
+    let foo = &components[0];
+    assert_eq!(foo["type"], "library");
+    assert_eq!(foo["version"], "1.2-1");
+    assert_eq!(foo["purl"], foo["bom-ref"]);
+    assert_eq!(foo["licenses"][0]["expression"], "MIT OR Apache-2.0");
+    assert_eq!(
+        foo["externalReferences"],
+        json!([
+            {"type": "website", "url": "https://example.com/foo"},
+            {
+                "type": "source-distribution",
+                "url": "https://example.com/foo-1.2.tar.gz",
+                "comment": "foo-1.2.tar.gz",
+                "hashes": [{"alg": "SHA-512", "content": "abcdef"}],
+            },
+        ])
+    );
+    assert_eq!(
+        foo["properties"],
+        json!([
+            {"name": "buildsys:sourcerpm", "value": "bottlerocket-foo-1.2-1.src.rpm"},
+            {"name": "buildsys:installed-size", "value": "1024"},
+            {"name": "buildsys:source-group", "value": "shared/foo-lib"},
+        ])
+    );
+
+    // The RPM from a kit has no sources, license or homepage to list.
+    let bar = &components[2];
+    assert!(bar.get("licenses").is_none());
+    assert_eq!(bar["externalReferences"], json!([]));
+    assert_eq!(bar["properties"].as_array().unwrap().len(), 2);
+}
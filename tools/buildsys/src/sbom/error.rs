@@ -0,0 +1,40 @@
+use snafu::Snafu;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(super)))]
+pub(crate) enum Error {
+    #[snafu(display("Failed to read installed packages '{}': {}", path.display(), source))]
+    InstalledRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to parse installed packages '{}': {}", path.display(), source))]
+    InstalledParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to read directory '{}': {}", path.display(), source))]
+    DirectoryRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to load package manifest '{}': {}", path.display(), source))]
+    ManifestLoad {
+        path: PathBuf,
+        #[snafu(source(from(buildsys::manifest::Error, Box::new)))]
+        source: Box<buildsys::manifest::Error>,
+    },
+
+    #[snafu(display("Invalid build timestamp '{}'", timestamp))]
+    Timestamp { timestamp: String },
+
+    #[snafu(display("Failed to serialize SBOM '{}': {}", path.display(), source))]
+    SbomSerialize {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to write SBOM '{}': {}", path.display(), source))]
+    SbomWrite { path: PathBuf, source: io::Error },
+}
+
+pub(super) type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,408 @@
+/*!
+This library describes the container runtime commands that builds need, so that they can use either
+Docker or rootless Podman. It only constructs the arguments for each command; callers run the
+runtime's program with whichever process library they already use.
+
+The runtime is chosen with the `TWOLITER_CONTAINER_RUNTIME` environment variable, which Twoliter
+sets from the project's build settings.
+*/
+
+use error::Error;
+use snafu::ensure;
+use std::env;
+use std::fmt::{Debug, Display, Formatter};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The name of the environment variable that chooses the container runtime, e.g. `podman`. Docker
+/// is used when it is not set.
+pub const RUNTIME_ENV: &str = "TWOLITER_CONTAINER_RUNTIME";
+
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+pub mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility(pub(super)))]
+    pub enum Error {
+        #[snafu(display(
+            "Unknown container runtime '{}', expected one of: {}",
+            name,
+            super::Runtime::NAMES.join(", ")
+        ))]
+        UnknownRuntime { name: String },
+    }
+}
+
+/// The container runtimes that builds can use.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Runtime {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    const NAMES: [&'static str; 2] = ["docker", "podman"];
+
+    /// The runtime chosen by `TWOLITER_CONTAINER_RUNTIME`, or Docker if it is not set.
+    pub fn from_env() -> Result<Self> {
+        match env::var(RUNTIME_ENV) {
+            Ok(name) if !name.is_empty() => name.parse(),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// The commands of the runtime.
+    pub fn commands(&self) -> &'static dyn ContainerRuntime {
+        match self {
+            Runtime::Docker => &Docker,
+            Runtime::Podman => &Podman,
+        }
+    }
+}
+
+impl FromStr for Runtime {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        ensure!(
+            Self::NAMES.contains(&name),
+            error::UnknownRuntimeSnafu { name }
+        );
+        Ok(if name == "podman" {
+            Runtime::Podman
+        } else {
+            Runtime::Docker
+        })
+    }
+}
+
+impl Display for Runtime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.commands().program())
+    }
+}
+
+/// A container image build, e.g. `docker build`.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Build {
+    pub context: PathBuf,
+    pub dockerfile: PathBuf,
+    pub tag: String,
+    /// The stage of the Dockerfile to build, or the last stage if `None`.
+    pub target: Option<String>,
+    /// The network that `RUN` steps use, e.g. `none`.
+    pub network: Option<String>,
+    pub build_args: Vec<(String, String)>,
+    pub secrets: Vec<Secret>,
+}
+
+/// A secret that is available to `RUN --mount=type=secret` steps of a build without being stored in
+/// the image.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Secret {
+    pub kind: SecretKind,
+    pub id: String,
+    /// The path of the file or the name of the environment variable that holds the secret.
+    pub src: String,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SecretKind {
+    File,
+    Env,
+}
+
+/// A container run, e.g. `docker run`.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Run {
+    pub image: String,
+    /// Remove the container when it exits.
+    pub remove: bool,
+    /// Run an init process in the container that forwards signals and reaps zombies.
+    pub init: bool,
+    pub network: Option<String>,
+    /// The user to run as, e.g. `1000:1000`.
+    pub user: Option<String>,
+    pub group_add: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub mounts: Vec<Mount>,
+    pub workdir: Option<PathBuf>,
+    pub command: Vec<String>,
+}
+
+/// A bind mount of a host path into a container.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mount {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub readonly: bool,
+}
+
+/// The commands of a container runtime, as arguments to its program.
+pub trait ContainerRuntime: Debug + Send + Sync {
+    /// The name of the runtime's command line program.
+    fn program(&self) -> &'static str;
+
+    /// Options that this runtime needs for every `run`. Scripts that construct their own `run`
+    /// commands should add them too.
+    fn run_options(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Environment variables that `build` needs to be run with.
+    fn build_env(&self) -> Vec<(&'static str, &'static str)> {
+        Vec::new()
+    }
+
+    /// The socket that serves the runtime's Docker-compatible API, if it is local.
+    fn socket(&self) -> Option<PathBuf>;
+
+    /// Create a stopped container, e.g. to copy files out of an image.
+    fn create(&self, name: &str, image: &str, command: &[&str]) -> Vec<String> {
+        let mut args = strings(["create", "--name", name, image]);
+        args.extend(strings(command.iter().copied()));
+        args
+    }
+
+    /// Copy `src` from a container to `dest` on the host.
+    fn cp(&self, container: &str, src: &Path, dest: &Path) -> Vec<String> {
+        vec![
+            "cp".to_string(),
+            format!("{}:{}", container, src.display()),
+            dest.display().to_string(),
+        ]
+    }
+
+    fn build(&self, build: &Build) -> Vec<String> {
+        build_args(build, &[])
+    }
+
+    fn run(&self, run: &Run) -> Vec<String> {
+        run_args(run, &self.run_options())
+    }
+
+    fn stop(&self, container: &str) -> Vec<String> {
+        strings(["stop", container])
+    }
+
+    /// Remove a container, if it exists.
+    fn rm(&self, container: &str) -> Vec<String> {
+        strings(["rm", "--force", container])
+    }
+
+    /// Remove an image, if it exists.
+    fn rmi(&self, image: &str) -> Vec<String> {
+        strings(["rmi", "--force", image])
+    }
+
+    fn tag(&self, image: &str, tag: &str) -> Vec<String> {
+        strings(["tag", image, tag])
+    }
+
+    fn pull(&self, image: &str) -> Vec<String> {
+        strings(["pull", image])
+    }
+}
+
+/// The Docker CLI, with builds run by BuildKit.
+#[derive(Debug, Clone, Copy)]
+pub struct Docker;
+
+impl ContainerRuntime for Docker {
+    fn program(&self) -> &'static str {
+        "docker"
+    }
+
+    fn build_env(&self) -> Vec<(&'static str, &'static str)> {
+        vec![("DOCKER_BUILDKIT", "1")]
+    }
+
+    fn socket(&self) -> Option<PathBuf> {
+        match env::var("DOCKER_HOST") {
+            Ok(host) => host.strip_prefix("unix://").map(PathBuf::from),
+            Err(_) => Some(PathBuf::from("/var/run/docker.sock")),
+        }
+    }
+}
+
+/// Podman, which runs without a daemon and, when run by a user other than root, without root.
+#[derive(Debug, Clone, Copy)]
+pub struct Podman;
+
+impl ContainerRuntime for Podman {
+    fn program(&self) -> &'static str {
+        "podman"
+    }
+
+    fn run_options(&self) -> Vec<&'static str> {
+        // SELinux would otherwise stop containers from using bind mounts of the project.
+        let mut options = vec!["--security-opt", "label=disable"];
+        // Keep the user's ID in the container, so files written to bind mounts are owned by them.
+        if rootless() {
+            options.push("--userns=keep-id");
+        }
+        options
+    }
+
+    fn socket(&self) -> Option<PathBuf> {
+        if let Ok(host) = env::var("CONTAINER_HOST") {
+            return host.strip_prefix("unix://").map(PathBuf::from);
+        }
+        if !rootless() {
+            return Some(PathBuf::from("/run/podman/podman.sock"));
+        }
+        let runtime_dir = env::var("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(format!("/run/user/{}", uid().unwrap_or(0))));
+        Some(runtime_dir.join("podman").join("podman.sock"))
+    }
+
+    fn build(&self, build: &Build) -> Vec<String> {
+        build_args(build, &["--security-opt", "label=disable"])
+    }
+}
+
+fn build_args(build: &Build, options: &[&str]) -> Vec<String> {
+    let mut args = vec!["build".to_string(), build.context.display().to_string()];
+    args.extend(strings(options.iter().copied()));
+    if let Some(target) = &build.target {
+        args.extend(strings(["--target", target]));
+    }
+    args.extend(strings(["--tag", &build.tag]));
+    args.push("--file".to_string());
+    args.push(build.dockerfile.display().to_string());
+    if let Some(network) = &build.network {
+        args.extend(strings(["--network", network]));
+    }
+    for (key, value) in &build.build_args {
+        args.push("--build-arg".to_string());
+        args.push(format!("{key}={value}"));
+    }
+    for secret in &build.secrets {
+        let kind = match secret.kind {
+            SecretKind::File => "file",
+            SecretKind::Env => "env",
+        };
+        args.push("--secret".to_string());
+        args.push(format!("type={kind},id={},src={}", secret.id, secret.src));
+    }
+    args
+}
+
+fn run_args(run: &Run, options: &[&str]) -> Vec<String> {
+    let mut args = vec!["run".to_string()];
+    args.extend(strings(options.iter().copied()));
+    if run.remove {
+        args.push("--rm".to_string());
+    }
+    if run.init {
+        args.push("--init".to_string());
+    }
+    if let Some(network) = &run.network {
+        args.extend(strings(["--network", network]));
+    }
+    if let Some(user) = &run.user {
+        args.extend(strings(["--user", user]));
+    }
+    for group in &run.group_add {
+        args.extend(strings(["--group-add", group]));
+    }
+    for (key, value) in &run.env {
+        args.push("--env".to_string());
+        args.push(format!("{key}={value}"));
+    }
+    for mount in &run.mounts {
+        args.push("--mount".to_string());
+        args.push(format!(
+            "type=bind,source={},target={}{}",
+            mount.source.display(),
+            mount.target.display(),
+            if mount.readonly { ",readonly" } else { "" }
+        ));
+    }
+    if let Some(workdir) = &run.workdir {
+        args.push("--workdir".to_string());
+        args.push(workdir.display().to_string());
+    }
+    args.push(run.image.clone());
+    args.extend(run.command.iter().cloned());
+    args
+}
+
+fn strings<'a>(args: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    args.into_iter().map(str::to_string).collect()
+}
+
+/// The ID of the user running this process.
+fn uid() -> Option<u32> {
+    std::fs::metadata("/proc/self").ok().map(|m| m.uid())
+}
+
+fn rootless() -> bool {
+    uid() != Some(0)
+}
+
+#[test]
+fn test_runtime_names() {
+    assert_eq!("docker".parse::<Runtime>().unwrap(), Runtime::Docker);
+    assert_eq!("podman".parse::<Runtime>().unwrap(), Runtime::Podman);
+    assert!("containerd".parse::<Runtime>().is_err());
+    assert_eq!(Runtime::Podman.to_string(), "podman");
+}
+
+#[test]
+fn test_build_args() {
+    let build = Build {
+        context: PathBuf::from("/project"),
+        dockerfile: PathBuf::from("/project/build/tools/Dockerfile"),
+        tag: "buildsys-pkg-hello-x86_64".to_string(),
+        target: Some("package".to_string()),
+        network: Some("none".to_string()),
+        build_args: vec![("ARCH".to_string(), "x86_64".to_string())],
+        secrets: vec![Secret {
+            kind: SecretKind::Env,
+            id: "aws-access-key-id.env".to_string(),
+            src: "AWS_ACCESS_KEY_ID".to_string(),
+        }],
+    };
+    assert_eq!(
+        Docker.build(&build).join(" "),
+        "build /project --target package --tag buildsys-pkg-hello-x86_64 \
+         --file /project/build/tools/Dockerfile --network none --build-arg ARCH=x86_64 \
+         --secret type=env,id=aws-access-key-id.env,src=AWS_ACCESS_KEY_ID"
+    );
+    assert_eq!(
+        Podman.build(&build)[..4].join(" "),
+        "build /project --security-opt label=disable"
+    );
+}
+
+#[test]
+fn test_run_args() {
+    let run = Run {
+        image: "sdk:v0.1.0".to_string(),
+        remove: true,
+        network: Some("host".to_string()),
+        env: vec![("HOME".to_string(), "/tmp".to_string())],
+        mounts: vec![Mount {
+            source: PathBuf::from("/home/me/ova"),
+            target: PathBuf::from("/tmp/bottlerocket.ova"),
+            readonly: true,
+        }],
+        command: vec!["govc".to_string(), "about".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        Docker.run(&run).join(" "),
+        "run --rm --network host --env HOME=/tmp \
+         --mount type=bind,source=/home/me/ova,target=/tmp/bottlerocket.ova,readonly \
+         sdk:v0.1.0 govc about"
+    );
+    let podman = Podman.run(&run);
+    assert_eq!(podman[..3].join(" "), "run --security-opt label=disable");
+    assert_eq!(podman.last().map(String::as_str), Some("about"));
+}
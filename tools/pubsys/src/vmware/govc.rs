@@ -1,22 +1,23 @@
-//! The govc module handles the process of building and executing the calls to Docker in order to
-//! run specific `govc` commands.
+//! The govc module handles the process of building and executing the calls to the container
+//! runtime in order to run specific `govc` commands.
+use container_runtime::{Mount, Run, Runtime};
 use duct::cmd;
 use log::trace;
 use pubsys_config::vmware::{Datacenter, DatacenterCreds};
 use snafu::ResultExt;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Output;
 
 pub(crate) struct Govc {
-    env_config: Vec<String>,
+    env_config: Vec<(String, String)>,
 }
 
 impl Govc {
     const GOVC: &'static str = "govc";
 
     /// Make a new instance of `Govc`, creating all of the environment variables required to run
-    /// `govc` as Docker `--env` arguments
+    /// `govc` in a container
     pub(crate) fn new(dc: Datacenter, creds: DatacenterCreds) -> Self {
         let mut env_config = Vec::new();
         env_config.env_arg("GOVC_USERNAME", creds.username);
@@ -31,12 +32,12 @@ impl Govc {
         Self { env_config }
     }
 
-    /// Run `govc import.ova` using Docker.
+    /// Run `govc import.ova` in a container.
     ///
     /// Using the given name, OVA path, and import spec path, this function builds the `govc
     /// import.ova` command as it will be used in the container.  It also builds the necessary bind
-    /// mount arguments to mount the import spec and OVA into the container.  Finally, it calls
-    /// `govc` via `docker run` invocation using these arguments.
+    /// mounts of the import spec and OVA into the container.  Finally, it calls `govc` via the
+    /// container runtime's `run` using these arguments.
     pub(crate) fn upload_ova<S, P1, P2>(
         self,
         name: S,
@@ -57,22 +58,19 @@ impl Govc {
         let ova_container_path = "/tmp/bottlerocket.ova";
         let import_spec_container_path = "/tmp/import.spec";
 
-        //--mount type=bind,source="path/to/thing",target=/tmp/thing,readonly
-        let mount_config = &[
+        let mounts = vec![
             // Mount the import spec file
-            "--mount",
-            &format!(
-                "type=bind,source={},target={},readonly",
-                import_spec_host_path.display(),
-                import_spec_container_path
-            ),
+            Mount {
+                source: import_spec_host_path.to_path_buf(),
+                target: PathBuf::from(import_spec_container_path),
+                readonly: true,
+            },
             // Mount the OVA
-            "--mount",
-            &format!(
-                "type=bind,source={},target={},readonly",
-                ova_host_path.display(),
-                ova_container_path
-            ),
+            Mount {
+                source: ova_host_path.to_path_buf(),
+                target: PathBuf::from(ova_container_path),
+                readonly: true,
+            },
         ];
 
         // govc import.ova -options=/path/to/spec -name bottlerocket_vm_name /path/to/ova
@@ -85,37 +83,35 @@ impl Govc {
             ova_container_path,
         ];
 
-        let env_config: Vec<&str> = self.env_config.iter().map(|s| s.as_ref()).collect();
-
-        docker_run(&env_config, Some(mount_config), govc_cmd)
+        container_run(self.env_config, mounts, govc_cmd)
     }
 }
 
-/// Execute `docker run` using the SDK container with the specified environment, mount, and command
-/// arguments.
-///
-/// This builds the entire `docker run` command string using a list of Docker `--env FOO=BAR`
-/// strings, an optional list of `--mount` strings, and a list of strings meant to be the command
-/// to run in the container.
-// The arguments are `&[&str]` in an attempt to be as flexible as possible for the caller
-fn docker_run(docker_env: &[&str], mount: Option<&[&str]>, command: &[&str]) -> Result<Output> {
+/// Execute the container runtime's `run` using the SDK container with the specified environment,
+/// mounts, and command arguments.
+fn container_run(
+    container_env: Vec<(String, String)>,
+    mounts: Vec<Mount>,
+    command: &[&str],
+) -> Result<Output> {
     let sdk = env::var("TLPRIVATE_SDK_IMAGE").context(error::EnvironmentSnafu {
         var: "TLPRIVATE_SDK_IMAGE",
     })?;
     trace!("SDK image: {}", sdk);
-
-    let mut args = vec!["run"];
-    args.push("--net=host");
-    args.extend(docker_env);
-
-    if let Some(mount_cfg) = mount {
-        args.extend(mount_cfg)
-    }
-
-    args.push(&sdk);
-    args.extend(command);
-
-    let output = cmd("docker", args)
+    let runtime = Runtime::from_env()
+        .context(error::ContainerRuntimeSnafu)?
+        .commands();
+
+    let args = runtime.run(&Run {
+        image: sdk,
+        network: Some("host".to_string()),
+        env: container_env,
+        mounts,
+        command: command.iter().map(|arg| arg.to_string()).collect(),
+        ..Default::default()
+    });
+
+    let output = cmd(runtime.program(), args)
         .stderr_to_stdout()
         .stdout_capture()
         .unchecked()
@@ -127,13 +123,17 @@ fn docker_run(docker_env: &[&str], mount: Option<&[&str]>, command: &[&str]) ->
     if output.status.success() {
         Ok(output)
     } else {
-        error::DockerSnafu { output: stdout }.fail()
+        error::ContainerSnafu {
+            program: runtime.program(),
+            output: stdout,
+        }
+        .fail()
     }
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
-/// Helper trait for constructing Docker `--env` arguments
+/// Helper trait for collecting the environment of the container
 trait EnvArg {
     fn env_arg<S1, S2>(&mut self, key: S1, value: S2)
     where
@@ -141,14 +141,13 @@ trait EnvArg {
         S2: AsRef<str>;
 }
 
-impl EnvArg for Vec<String> {
+impl EnvArg for Vec<(String, String)> {
     fn env_arg<S1, S2>(&mut self, key: S1, value: S2)
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
     {
-        self.push("--env".to_string());
-        self.push(format!("{}={}", key.as_ref(), value.as_ref()))
+        self.push((key.as_ref().to_string(), value.as_ref().to_string()))
     }
 }
 
@@ -163,8 +162,13 @@ pub(crate) mod error {
         #[snafu(display("Failed to start command: {}", source))]
         CommandStart { source: std::io::Error },
 
-        #[snafu(display("Docker invocation failed: {}", output))]
-        Docker { output: String },
+        #[snafu(display("{} invocation failed: {}", program, output))]
+        Container { program: String, output: String },
+
+        #[snafu(display("{}", source))]
+        ContainerRuntime {
+            source: container_runtime::error::Error,
+        },
 
         #[snafu(display("Missing environment variable '{}'", var))]
         Environment {
@@ -10,12 +10,28 @@ Prepare and package embedded tools in a tarball to be included with Twoliter.
 use bytes::BufMut;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use std::{env, fs};
 
 const DATA_INPUT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/embedded");
 
+/// The binaries that Twoliter embeds, by the name they are installed as, and the cargo variables
+/// with their paths.
+const BINARIES: [(&str, &str); 6] = [
+    (
+        "bottlerocket-variant",
+        "CARGO_BIN_FILE_BUILDSYS_bottlerocket-variant",
+    ),
+    ("buildsys", "CARGO_BIN_FILE_BUILDSYS"),
+    ("pubsys", "CARGO_BIN_FILE_PUBSYS"),
+    ("pubsys-setup", "CARGO_BIN_FILE_PUBSYS_SETUP"),
+    ("testsys", "CARGO_BIN_FILE_TESTSYS"),
+    ("tuftool", "CARGO_BIN_FILE_TUFTOOL"),
+];
+
 fn main() {
     let paths = Paths::new();
     println!("cargo:rerun-if-changed={}", paths.data_input_dir.display());
@@ -34,6 +50,7 @@ fn main() {
     paths.copy_file("rpm2img");
     paths.copy_file("rpm2kmodkit");
     paths.copy_file("rpm2migrations");
+    paths.copy_file("rootfs-budget");
     paths.copy_file("metadata.spec");
 
     // Create tarball in memory.
@@ -55,6 +72,30 @@ fn main() {
         "Unable to write to file '{}'",
         paths.tar_gz.display()
     ));
+
+    // Hash everything that is embedded here, so that Twoliter does not have to each time it runs.
+    let mut sha256_rs = format!(
+        "const TAR_GZ_SHA256: &str = \"{}\";\n",
+        hex::encode(Sha256::digest(tar_gz_data))
+    );
+    sha256_rs.push_str("const BINARY_SHA256: [(&str, &str); 6] = [\n");
+    for (name, var) in BINARIES {
+        let path = env::var(var).expect(&format!("The cargo variable '{var}' is missing"));
+        let data = fs::read(&path).expect(&format!("Unable to read '{path}'"));
+        // Pass the path on, so that the binary that is embedded is the one that was hashed.
+        println!("cargo:rustc-env={var}={path}");
+        writeln!(
+            sha256_rs,
+            "    (\"{name}\", \"{}\"),",
+            hex::encode(Sha256::digest(data))
+        )
+        .unwrap();
+    }
+    sha256_rs.push_str("];\n");
+    fs::write(&paths.sha256_rs, sha256_rs).expect(&format!(
+        "Unable to write to file '{}'",
+        paths.sha256_rs.display()
+    ));
     println!("Done at {:?}", SystemTime::now());
 }
 
@@ -65,6 +106,8 @@ struct Paths {
     prep_dir: PathBuf,
     /// The path to tools.tar.gz
     tar_gz: PathBuf,
+    /// The path to the SHA-256 digests of tools.tar.gz and the embedded binaries, as Rust source.
+    sha256_rs: PathBuf,
 }
 
 impl Paths {
@@ -78,6 +121,7 @@ impl Paths {
             data_input_dir: PathBuf::from(DATA_INPUT_DIR),
             prep_dir: out_dir.join("tools"),
             tar_gz: out_dir.join("tools.tar.gz"),
+            sha256_rs: out_dir.join("sha256.rs"),
         }
     }
 
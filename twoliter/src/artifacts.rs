@@ -0,0 +1,244 @@
+//! The names and paths of the files that a variant build writes, mirroring the variables that
+//! `Makefile.toml` computes, so that commands which run after a build can find its output without
+//! going through `cargo make`.
+
+use crate::common::exec;
+use crate::project::Project;
+use anyhow::Result;
+use log::debug;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// The image name used when the project does not set one, matching `BUILDSYS_NAME`.
+const DEFAULT_IMAGE_NAME: &str = "bottlerocket";
+
+/// The build version used when the project is not in a git repository.
+const DEFAULT_VERSION_BUILD: &str = "00000000";
+
+/// The output of a variant build for one architecture.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct VariantArtifacts {
+    project_dir: PathBuf,
+    name: String,
+    variant: String,
+    arch: String,
+    version_image: String,
+    version_build: String,
+}
+
+impl VariantArtifacts {
+    /// The artifacts of `variant` for `arch` at the project's current version and commit.
+    pub(crate) async fn new(project: &Project, variant: &str, arch: &str) -> Result<Self> {
+        let name = project
+            .build_settings()
+            .resolve()?
+            .image_name
+            .unwrap_or_else(|| DEFAULT_IMAGE_NAME.to_string());
+        Ok(Self::with_version(
+            project.project_dir(),
+            name,
+            variant,
+            arch,
+            project.release_version(),
+            version_build(&project.project_dir()).await,
+        ))
+    }
+
+    fn with_version(
+        project_dir: impl Into<PathBuf>,
+        name: impl Into<String>,
+        variant: &str,
+        arch: &str,
+        version_image: &str,
+        version_build: impl Into<String>,
+    ) -> Self {
+        Self {
+            project_dir: project_dir.into(),
+            name: name.into(),
+            variant: variant.to_string(),
+            arch: arch.to_string(),
+            version_image: version_image.to_string(),
+            version_build: version_build.into(),
+        }
+    }
+
+    pub(crate) fn variant(&self) -> &str {
+        &self.variant
+    }
+
+    pub(crate) fn arch(&self) -> &str {
+        &self.arch
+    }
+
+    /// The release version of the image, e.g. `1.19.0`.
+    pub(crate) fn version_image(&self) -> &str {
+        &self.version_image
+    }
+
+    /// The short commit of the build, e.g. `0d47e9b1`.
+    pub(crate) fn version_build(&self) -> &str {
+        &self.version_build
+    }
+
+    /// `BUILDSYS_VERSION_FULL`, e.g. `1.19.0-0d47e9b1`.
+    pub(crate) fn version_full(&self) -> String {
+        format!("{}-{}", self.version_image, self.version_build)
+    }
+
+    /// `BUILDSYS_NAME_VARIANT`, e.g. `bottlerocket-aws-dev-x86_64`.
+    pub(crate) fn name_variant(&self) -> String {
+        format!("{}-{}-{}", self.name, self.variant, self.arch)
+    }
+
+    /// `BUILDSYS_NAME_VERSION`, e.g. `bottlerocket-1.19.0-0d47e9b1`.
+    pub(crate) fn name_version(&self) -> String {
+        format!("{}-{}", self.name, self.version_full())
+    }
+
+    /// `BUILDSYS_NAME_FULL`, e.g. `bottlerocket-aws-dev-x86_64-1.19.0-0d47e9b1`.
+    pub(crate) fn name_full(&self) -> String {
+        format!("{}-{}", self.name_variant(), self.version_full())
+    }
+
+    /// `BUILDSYS_NAME_FRIENDLY`, e.g. `bottlerocket-aws-dev-x86_64-v1.19.0`.
+    pub(crate) fn name_friendly(&self) -> String {
+        format!("{}-v{}", self.name_variant(), self.version_image)
+    }
+
+    /// The default name of registered AMIs and uploaded OVAs, e.g.
+    /// `bottlerocket-aws-dev-x86_64-v1.19.0-0d47e9b1`.
+    pub(crate) fn machine_image_name(&self) -> String {
+        format!("{}-{}", self.name_friendly(), self.version_build)
+    }
+
+    /// `BUILDSYS_OUTPUT_DIR`, which holds the builds of the variant for the architecture.
+    pub(crate) fn output_dir(&self) -> PathBuf {
+        self.project_dir
+            .join("build")
+            .join("images")
+            .join(format!("{}-{}", self.arch, self.variant))
+    }
+
+    /// `BUILDSYS_VARIANT_DIR`, which holds the images of this version and commit.
+    pub(crate) fn variant_dir(&self) -> PathBuf {
+        self.output_dir().join(self.version_full())
+    }
+
+    /// A file in the variant directory named after the full name, e.g. `image("-boot.ext4.lz4")`.
+    pub(crate) fn image(&self, suffix: &str) -> PathBuf {
+        self.variant_dir()
+            .join(format!("{}{}", self.name_full(), suffix))
+    }
+
+    /// A file in the variant directory named after the friendly name.
+    pub(crate) fn friendly_image(&self, suffix: &str) -> PathBuf {
+        self.variant_dir()
+            .join(format!("{}{}", self.name_friendly(), suffix))
+    }
+
+    /// The kmod kit archive for building out-of-tree kernel modules.
+    pub(crate) fn kmod_kit(&self) -> PathBuf {
+        self.variant_dir().join(format!(
+            "{}-{}-kmod-kit-v{}.tar.xz",
+            self.variant, self.arch, self.version_image
+        ))
+    }
+
+    /// The OVF template of the variant. Variants that have one are built into an OVA.
+    pub(crate) fn ovf_template(&self) -> PathBuf {
+        self.project_dir
+            .join("variants")
+            .join(&self.variant)
+            .join("template.ovf")
+    }
+
+    /// The IDs of the AMIs registered for this build, written by `pubsys ami`.
+    pub(crate) fn amis(&self) -> PathBuf {
+        self.image("-amis.json")
+    }
+
+    /// A link to the AMIs of the most recent build of the variant.
+    pub(crate) fn latest_amis(&self) -> PathBuf {
+        self.variant_dir()
+            .join(format!("{}-amis.json", self.name_variant()))
+    }
+
+    /// The SSM parameters published for this build, written by `pubsys ssm`.
+    pub(crate) fn ssm_parameters(&self) -> PathBuf {
+        self.image("-ssm-params.json")
+    }
+
+    /// The directory that `pubsys repo` writes the repository for this build to.
+    pub(crate) fn repo_dir(&self, repo: &str) -> PathBuf {
+        self.project_dir
+            .join("build")
+            .join("repos")
+            .join(repo)
+            .join(self.name_version())
+    }
+}
+
+/// The short commit of the project's git checkout, marked if the tree is dirty, as in
+/// `BUILDSYS_VERSION_BUILD`.
+async fn version_build(project_dir: &Path) -> String {
+    let output = exec(
+        Command::new("git")
+            .args(["describe", "--always", "--dirty", "--exclude", "*"])
+            .current_dir(project_dir),
+        true,
+    )
+    .await;
+    match output {
+        Ok(Some(version)) if !version.trim().is_empty() => version.trim().to_string(),
+        _ => {
+            debug!(
+                "Unable to describe the git commit of '{}', using '{DEFAULT_VERSION_BUILD}'",
+                project_dir.display()
+            );
+            DEFAULT_VERSION_BUILD.to_string()
+        }
+    }
+}
+
+#[test]
+fn test_variant_artifacts() {
+    let artifacts = VariantArtifacts::with_version(
+        "/project",
+        "bottlerocket",
+        "aws-dev",
+        "x86_64",
+        "1.19.0",
+        "0d47e9b1",
+    );
+    assert_eq!(artifacts.version_full(), "1.19.0-0d47e9b1");
+    assert_eq!(artifacts.name_variant(), "bottlerocket-aws-dev-x86_64");
+    assert_eq!(artifacts.name_version(), "bottlerocket-1.19.0-0d47e9b1");
+    assert_eq!(
+        artifacts.name_full(),
+        "bottlerocket-aws-dev-x86_64-1.19.0-0d47e9b1"
+    );
+    assert_eq!(
+        artifacts.machine_image_name(),
+        "bottlerocket-aws-dev-x86_64-v1.19.0-0d47e9b1"
+    );
+    assert_eq!(
+        artifacts.image("-boot.ext4.lz4"),
+        Path::new("/project/build/images/x86_64-aws-dev/1.19.0-0d47e9b1/bottlerocket-aws-dev-x86_64-1.19.0-0d47e9b1-boot.ext4.lz4")
+    );
+    assert_eq!(
+        artifacts.friendly_image(".ova"),
+        Path::new("/project/build/images/x86_64-aws-dev/1.19.0-0d47e9b1/bottlerocket-aws-dev-x86_64-v1.19.0.ova")
+    );
+    assert_eq!(
+        artifacts.kmod_kit(),
+        Path::new("/project/build/images/x86_64-aws-dev/1.19.0-0d47e9b1/aws-dev-x86_64-kmod-kit-v1.19.0.tar.xz")
+    );
+    assert_eq!(
+        artifacts.latest_amis(),
+        Path::new("/project/build/images/x86_64-aws-dev/1.19.0-0d47e9b1/bottlerocket-aws-dev-x86_64-amis.json")
+    );
+    assert_eq!(
+        artifacts.repo_dir("default"),
+        Path::new("/project/build/repos/default/bottlerocket-1.19.0-0d47e9b1")
+    );
+}
@@ -1,14 +1,20 @@
-use crate::common::exec_log;
-use crate::docker::ImageUri;
-use crate::project::Project;
-use anyhow::{bail, Result};
-use log::trace;
-use std::path::PathBuf;
+use crate::common::{exec_log, fs};
+use crate::docker::{make_image, runtime, ImageUri};
+use crate::project::{Project, SETTINGS_ENV_VARS};
+use anyhow::{bail, ensure, Context, Result};
+use container_runtime::{Mount, Run, Runtime, RUNTIME_ENV};
+use log::{trace, warn};
+use nix::unistd::{getgid, getuid};
+use std::collections::BTreeSet;
+use std::env;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use tokio::process::Command;
 
 /// A struct used to invoke `cargo make` tasks with `twoliter`'s `Makefile.toml`.
 /// ```rust
-/// # use crate::project::Project;
+/// # use crate::project::{Project, SETTINGS_ENV_VARS};
 /// # use crate::test::data_dir;
 /// # use self::CargoMake;
 /// # let project_path = data_dir().join("Twoliter-1.toml");
@@ -43,8 +49,12 @@ use tokio::process::Command;
 /// ```
 #[derive(Debug, Clone, Default)]
 pub struct CargoMake {
+    sdk: Option<String>,
     makefile_path: Option<PathBuf>,
     project_dir: Option<PathBuf>,
+    in_container: bool,
+    mounts: Vec<PathBuf>,
+    output_to_stderr: bool,
     args: Vec<String>,
 }
 
@@ -53,7 +63,21 @@ impl CargoMake {
     /// definition in `Twoliter.toml`.
     pub(crate) fn new(project: &Project) -> Result<Self> {
         let sdk = require_sdk(project)?;
-        Ok(Self::default().env("TLPRIVATE_SDK_IMAGE", sdk))
+        Ok(Self::with_sdk(sdk))
+    }
+
+    /// Create a new `cargo make` command that uses the given SDK image, for example one that is
+    /// pinned by digest in `Twoliter.lock`.
+    pub(crate) fn with_sdk<S>(sdk: S) -> Self
+    where
+        S: Into<String>,
+    {
+        let sdk = sdk.into();
+        Self {
+            sdk: Some(sdk.clone()),
+            ..Self::default()
+        }
+        .env("TLPRIVATE_SDK_IMAGE", sdk)
     }
 
     /// Specify the path to the `Makefile.toml` for the `cargo make` command
@@ -74,6 +98,32 @@ impl CargoMake {
         self
     }
 
+    /// Run `cargo make` in a container made from the SDK instead of on the host, so that nothing but
+    /// docker needs to be installed. The container talks to the host's docker daemon through its
+    /// socket, and runs as the current user so that the files it creates belong to them.
+    pub(crate) fn in_container(mut self, in_container: bool) -> Self {
+        self.in_container = in_container;
+        self
+    }
+
+    /// Mount a directory that `cargo make` needs in the container, in addition to the project
+    /// directory. Directories are mounted at the same path as on the host, so that paths passed to
+    /// `cargo make`, and the paths that it passes on to the host's docker daemon, work unchanged.
+    pub(crate) fn mount<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.mounts.push(path.into());
+        self
+    }
+
+    /// Write the output of `cargo make` to stderr instead of stdout, so that stdout only has the
+    /// messages that Twoliter writes, such as build events.
+    pub(crate) fn output_to_stderr(mut self, output_to_stderr: bool) -> Self {
+        self.output_to_stderr = output_to_stderr;
+        self
+    }
+
     /// Specify environment variables that should be applied for this comand
     pub(crate) fn env<S1, S2>(mut self, key: S1, value: S2) -> Self
     where
@@ -113,8 +163,17 @@ impl CargoMake {
         S2: Into<String>,
         I: IntoIterator<Item = S2>,
     {
+        let (mut command, runtime) = if self.in_container {
+            // The container has the docker CLI, which talks to the host's daemon.
+            (self.container_command().await?, Runtime::Docker)
+        } else {
+            (Command::new("cargo"), runtime())
+        };
+        if self.output_to_stderr {
+            command.stdout(std::io::stderr());
+        }
         exec_log(
-            Command::new("cargo")
+            command
                 .arg("make")
                 .arg("--disable-check-for-updates")
                 .args(
@@ -128,12 +187,116 @@ impl CargoMake {
                         .flat_map(|path| vec!["--cwd".to_string(), path.display().to_string()]),
                 )
                 .args(build_system_env_vars()?)
+                .arg(format!("-e={RUNTIME_ENV}={runtime}"))
+                .arg(format!(
+                    "-e=TLPRIVATE_CONTAINER_RUN_OPTIONS={}",
+                    runtime.commands().run_options().join(" ")
+                ))
                 .args(&self.args)
                 .arg(task.into())
                 .args(args.into_iter().map(Into::into)),
         )
         .await
     }
+
+    /// A `docker run` command that runs `cargo` in the `cargo make` container, to which the
+    /// `cargo make` arguments are added.
+    async fn container_command(&self) -> Result<Command> {
+        ensure!(
+            runtime() == Runtime::Docker,
+            "Unable to run cargo make in a container with container runtime '{}', only docker is \
+            supported. Run it on the host instead",
+            runtime()
+        );
+        let sdk = self
+            .sdk
+            .as_deref()
+            .context("Unable to run cargo make in a container without an SDK")?;
+        let image = make_image(sdk).await?;
+        let docker = runtime().commands();
+        let socket = docker.socket().context(
+            "Unable to run cargo make in a container because DOCKER_HOST is not a unix socket",
+        )?;
+        let socket_gid = fs::metadata(&socket)
+            .await
+            .context("Unable to find the docker socket, is docker running?")?
+            .gid();
+
+        let mut mounts = vec![Mount {
+            source: socket,
+            target: PathBuf::from(DEFAULT_DOCKER_SOCKET),
+            readonly: false,
+        }];
+        for path in self.project_dir.iter().chain(&self.mounts) {
+            let target = env::current_dir()
+                .context("Unable to get the current directory")?
+                .join(path);
+            check_mount(&target)?;
+            let source = fs::canonicalize(path).await?;
+            mounts.push(Mount {
+                source,
+                target,
+                readonly: false,
+            });
+        }
+        let args = docker.run(&Run {
+            image,
+            remove: true,
+            init: true,
+            network: Some("host".to_string()),
+            user: Some(format!("{}:{}", getuid(), getgid())),
+            group_add: vec![socket_gid.to_string()],
+            // The current user probably does not exist in the container and has no home there.
+            env: vec![("HOME".to_string(), "/tmp".to_string())],
+            mounts,
+            workdir: self.project_dir.clone(),
+            command: vec!["cargo".to_string()],
+        });
+        let mut command = Command::new(docker.program());
+        command.args(args);
+        Ok(command)
+    }
+}
+
+/// Where the docker socket is mounted in the `cargo make` container.
+const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Directories that the `cargo make` container needs for itself. A project in one of them cannot
+/// be mounted at the same path in the container without breaking it.
+const FORBIDDEN_MOUNTS: [&str; 13] = [
+    "/bin",
+    "/boot",
+    "/dev",
+    "/etc",
+    "/lib",
+    "/lib64",
+    "/proc",
+    "/run",
+    "/sbin",
+    "/sys",
+    "/usr",
+    "/var/lib/docker",
+    "/var/run",
+];
+
+/// Make sure that `path` can be mounted at the same path in the `cargo make` container.
+fn check_mount(path: &Path) -> Result<()> {
+    ensure!(
+        path != Path::new("/"),
+        "Unable to run cargo make in a container for '/', move the project to its own directory"
+    );
+    if let Some(forbidden) = FORBIDDEN_MOUNTS
+        .iter()
+        .find(|forbidden| path.starts_with(forbidden))
+    {
+        bail!(
+            "Unable to run cargo make in a container for '{}', because it is in '{forbidden}', \
+            which the container needs for itself. Move the project somewhere else, such as your \
+            home directory",
+            path.display()
+        )
+    }
+    Ok(())
 }
 
 fn require_sdk(project: &Project) -> Result<ImageUri> {
@@ -148,6 +311,17 @@ fn require_sdk(project: &Project) -> Result<ImageUri> {
 fn build_system_env_vars() -> Result<Vec<String>> {
     let mut args = Vec::new();
     for (key, val) in std::env::vars() {
+        // Build settings are resolved from Twoliter.toml and the environment, and passed
+        // explicitly by the commands that use them.
+        if SETTINGS_ENV_VARS.contains(&key.as_str()) {
+            continue;
+        }
+        if key.starts_with("BUILDSYS_") && !known_buildsys_vars().contains(&key) {
+            warn!(
+                "The environment variable '{key}' is not used by Twoliter's build, check it for \
+                typos"
+            );
+        }
         if is_build_system_env(key.as_str()) {
             trace!("Passing env var {} to cargo make", key);
             args.push("-e".to_string());
@@ -189,6 +363,50 @@ const ENV_VARS: [&str; 23] = [
     "no_proxy",
 ];
 
+/// Twoliter's `Makefile.toml`. Its `[env]` table defines the `BUILDSYS_` environment variables
+/// that can be set to change what it does.
+const MAKEFILE_TOML: &str = include_str!("../embedded/Makefile.toml");
+
+/// The `BUILDSYS_` environment variables that the build uses but `Makefile.toml` does not define,
+/// because Twoliter sets them or only `buildsys` reads them.
+const OTHER_BUILDSYS_VARS: [&str; 6] = [
+    "BUILDSYS_EVENTS_FILE",
+    "BUILDSYS_VARIANT_FAMILY",
+    "BUILDSYS_VARIANT_FLAVOR",
+    "BUILDSYS_VARIANT_PLATFORM",
+    "BUILDSYS_VARIANT_RUNTIME",
+    "BUILDSYS_VERSION_IMAGE",
+];
+
+static KNOWN_BUILDSYS_VARS: OnceLock<BTreeSet<String>> = OnceLock::new();
+
+/// The `BUILDSYS_` environment variables that the build uses, other than the ones for build
+/// settings. They are taken from the `[env]` table of `Makefile.toml`, including its profiles, so
+/// that the list follows the Makefile.
+fn known_buildsys_vars() -> &'static BTreeSet<String> {
+    KNOWN_BUILDSYS_VARS.get_or_init(|| {
+        let makefile: toml::Table =
+            toml::from_str(MAKEFILE_TOML).expect("The embedded Makefile.toml is not valid TOML");
+        let mut vars = BTreeSet::new();
+        let env = makefile.get("env").and_then(toml::Value::as_table);
+        for (key, value) in env.into_iter().flatten() {
+            match value.as_table() {
+                // A profile, such as `[env.development]`, or a variable set with a table like
+                // `{ script = [...] }`.
+                Some(profile) if !key.starts_with("BUILDSYS_") => {
+                    vars.extend(profile.keys().cloned());
+                }
+                _ => {
+                    vars.insert(key.clone());
+                }
+            }
+        }
+        vars.retain(|var| var.starts_with("BUILDSYS_"));
+        vars.extend(OTHER_BUILDSYS_VARS.map(str::to_string));
+        vars
+    })
+}
+
 const DISALLOWED_SDK_VARS: [&str; 3] = [
     "BUILDSYS_SDK_NAME",
     "BUILDSYS_SDK_VERSION",
@@ -238,8 +456,55 @@ fn test_is_build_system_env() {
     assert!(!is_build_system_env("COLORTERM"));
 }
 
+#[test]
+fn test_check_mount() {
+    assert!(check_mount(Path::new("/home/user/my-project")).is_ok());
+    assert!(check_mount(Path::new("/usr-projects/my-project")).is_ok());
+    assert!(check_mount(Path::new("/")).is_err());
+    assert!(check_mount(Path::new("/usr/src/my-project")).is_err());
+    assert!(check_mount(Path::new("/etc/docker/my-project")).is_err());
+}
+
 #[test]
 fn test_check_for_disallowed_var() {
     assert!(check_for_disallowed_var("BUILDSYS_REGISTRY").is_err());
     assert!(check_for_disallowed_var("BUILDSYS_PRETTY_NAME").is_ok());
 }
+
+#[test]
+fn test_known_buildsys_vars() {
+    let known = known_buildsys_vars();
+    for var in [
+        "BUILDSYS_ATTRIBUTIONS_DIR",
+        "BUILDSYS_KMOD_KIT",
+        "BUILDSYS_LOOKASIDE_CACHE",
+        "BUILDSYS_SBKEYS_PROFILE_DIR",
+        "BUILDSYS_TIMESTAMP",
+        "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
+        "BUILDSYS_VERSION_BUILD",
+        "BUILDSYS_EVENTS_FILE",
+    ] {
+        assert!(known.contains(var), "{var} is not known");
+    }
+    assert!(!known.contains("BUILDSYS_VERISON_BUILD"));
+
+    // Every variable that the Makefile uses is known, outside of comments.
+    for line in MAKEFILE_TOML
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+    {
+        let mut rest = line;
+        while let Some(start) = rest.find("BUILDSYS_") {
+            let var = &rest[start..];
+            let end = var
+                .find(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+                .unwrap_or(var.len());
+            assert!(
+                known.contains(&var[..end]),
+                "{} is used by Makefile.toml but not known",
+                &var[..end]
+            );
+            rest = &var[end..];
+        }
+    }
+}
@@ -1,13 +1,21 @@
 use super::build_clean::BuildClean;
+use crate::artifacts::VariantArtifacts;
 use crate::cargo_make::CargoMake;
 use crate::common::fs;
 use crate::docker::DockerContainer;
-use crate::project;
-use crate::tools::install_tools;
-use anyhow::{Context, Result};
+use crate::events::{self, BuildEvents, Event, FailedBuild, MessageFormat};
+use crate::kit;
+use crate::lock::Lock;
+use crate::project::{self, Project};
+use crate::tools::{install_tools, ToolOverrides};
+use anyhow::{ensure, Context, Result};
 use clap::Parser;
-use log::debug;
+use futures::stream::{self, StreamExt};
+use log::{debug, error, info, warn};
+use std::future::Future;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
 use tempfile::TempDir;
 
 #[derive(Debug, Parser)]
@@ -25,28 +33,65 @@ impl BuildCommand {
     }
 }
 
-/// Build a Bottlerocket variant image.
+/// Build Bottlerocket variant images.
 #[derive(Debug, Parser)]
 pub(crate) struct BuildVariant {
     /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
     #[clap(long = "project-path")]
     project_path: Option<PathBuf>,
 
-    /// The architecture to build for.
-    #[clap(long = "arch", default_value = "x86_64")]
-    arch: String,
+    /// The architectures to build for, separated by commas, e.g. x86_64,aarch64.
+    #[clap(long = "arch", default_value = "x86_64", value_delimiter = ',')]
+    arch: Vec<String>,
 
-    /// The variant to build.
-    variant: String,
+    /// The variants to build.
+    #[clap(required_unless_present = "all")]
+    variants: Vec<String>,
+
+    /// Build every variant in the project's variants directory.
+    #[clap(long = "all", conflicts_with = "variants")]
+    all: bool,
+
+    /// The most variant builds to run at once, across all of the variants and architectures.
+    /// Builds that run at the same time use their own cargo target directories, so packages that
+    /// they share may be built once for each of them. Defaults to the number of architectures.
+    #[clap(long = "jobs", short = 'j')]
+    jobs: Option<usize>,
 
     /// The URL to the lookaside cache where sources are stored to avoid pulling them from upstream.
-    /// Defaults to https://cache.bottlerocket.aws
+    /// Overrides the lookaside-cache build setting in Twoliter.toml. Defaults to
+    /// https://cache.bottlerocket.aws
+    #[clap(long = "lookaside-cache")]
     lookaside_cache: Option<String>,
 
     /// If sources are not found in the lookaside cache, this flag will cause buildsys to pull them
-    /// from the upstream URL found in a package's `Cargo.toml`.
+    /// from the upstream URL found in a package's `Cargo.toml`. Overrides the
+    /// upstream-source-fallback build setting in Twoliter.toml.
     #[clap(long = "upstream-source-fallback")]
     upstream_source_fallback: bool,
+
+    /// Require `Twoliter.lock` to be up to date. The build fails instead of resolving the SDK or
+    /// kits again and changing the lock.
+    #[clap(long = "locked")]
+    locked: bool,
+
+    /// Run cargo make in a container instead of on the host, so that it does not need to be
+    /// installed. The project must not be in a directory that the container needs for itself, such
+    /// as `/usr`.
+    #[clap(long = "in-container")]
+    in_container: bool,
+
+    /// How to report the progress of the build. `json` writes build events to stdout, one JSON
+    /// object per line, and everything else to stderr.
+    #[clap(long = "message-format", value_enum, default_value_t)]
+    message_format: MessageFormat,
+
+    /// Keep an image of a failed package or variant build for inspection. A failed package build
+    /// keeps its spec, sources and build dependencies as they were before `rpmbuild` ran, and a
+    /// failed variant build keeps its package repository as it was before the image was made,
+    /// under the `buildsys-pkg-*` or `buildsys-var-*` tag that buildsys prints.
+    #[clap(long = "keep-failed")]
+    keep_failed: bool,
 }
 
 impl BuildVariant {
@@ -54,25 +99,52 @@ impl BuildVariant {
         let project = project::load_or_find_project(self.project_path.clone()).await?;
         let token = project.token();
         let toolsdir = project.project_dir().join("build/tools");
-        install_tools(&toolsdir).await?;
+        let _tools = install_tools(
+            &toolsdir,
+            &ToolOverrides::resolve(project.tool_overrides())?,
+        )
+        .await?;
         let makefile_path = toolsdir.join("Makefile.toml");
+
+        let variants = if self.all {
+            project.find_variants().await?
+        } else {
+            self.variants.clone()
+        };
+        ensure!(!variants.is_empty(), "There are no variants to build");
+        let mut arches = Vec::new();
+        for arch in &self.arch {
+            if !arches.contains(arch) {
+                arches.push(arch.clone());
+            }
+        }
+        let jobs = self.jobs.unwrap_or(arches.len());
+        ensure!(jobs > 0, "--jobs must be at least 1");
+
+        // Use the SDK and kits pinned in the lock, and put the kits where buildsys will look for
+        // them.
+        let mut lock = None;
+        for arch in &arches {
+            lock = Some(Lock::load_or_create(&project, arch, self.locked).await?);
+        }
+        let lock = lock.context("No architecture was given")?;
+        let sdk_image = lock.sdk_image().await?;
+        for arch in &arches {
+            let external_kits_dir = project
+                .project_dir()
+                .join("build")
+                .join("external-kits")
+                .join(arch);
+            kit::extract_kits(&project, &lock.kit_images(arch).await?, &external_kits_dir).await?;
+        }
+
         // A temporary directory in the `build` directory
         let build_temp_dir = TempDir::new_in(project.project_dir())
             .context("Unable to create a tempdir for Twoliter's build")?;
         let packages_dir = build_temp_dir.path().join("sdk_rpms");
         fs::create_dir_all(&packages_dir).await?;
 
-        let sdk_container = DockerContainer::new(
-            format!("sdk-{}", token),
-            project
-                .sdk()
-                .context(format!(
-                    "No SDK defined in {}",
-                    project.filepath().display(),
-                ))?
-                .uri(),
-        )
-        .await?;
+        let sdk_container = DockerContainer::new(format!("sdk-{}", token), &sdk_image).await?;
         sdk_container
             .cp_out(Path::new("twoliter/alpha/build/rpms"), &packages_dir)
             .await?;
@@ -118,29 +190,61 @@ impl BuildVariant {
             created_files.push(models_dir)
         }
 
-        let mut optional_envs = Vec::new();
-
+        // Command line arguments take precedence over the build settings in Twoliter.toml and the
+        // environment.
+        let mut settings = project.build_settings().resolve()?;
         if let Some(lookaside_cache) = &self.lookaside_cache {
-            optional_envs.push(("BUILDSYS_LOOKASIDE_CACHE", lookaside_cache))
+            settings.lookaside_cache = Some(lookaside_cache.clone());
+        }
+        if self.upstream_source_fallback {
+            settings.upstream_source_fallback = Some(true);
         }
+        settings.validate()?;
 
-        // Hold the result of the cargo make call so we can clean up the project directory first.
-        let res = CargoMake::new(&project)?
+        let mut cargo_make = CargoMake::with_sdk(&sdk_image)
+            .in_container(self.in_container)
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
-            .env("BUILDSYS_ARCH", &self.arch)
-            .env("BUILDSYS_VARIANT", &self.variant)
             .env("BUILDSYS_SBKEYS_DIR", sbkeys_dir.display().to_string())
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
             .env("GO_MODULES", project.find_go_modules().await?.join(" "))
             .env(
-                "BUILDSYS_UPSTREAM_SOURCE_FALLBACK",
-                self.upstream_source_fallback.to_string(),
+                "BUILDSYS_RELEASE_CONFIG_PATH",
+                project.release_config().await?.display().to_string(),
             )
-            .envs(optional_envs.into_iter())
+            .envs(settings.env_vars().into_iter())
             .makefile(makefile_path)
-            .project_dir(project.project_dir())
-            .exec("build")
-            .await;
+            .project_dir(project.project_dir());
+
+        if self.keep_failed {
+            cargo_make = cargo_make.env("BUILDSYS_KEEP_FAILED", "true");
+        }
+
+        // buildsys writes its events to a file in the project directory, which is mounted in the
+        // cargo make container if there is one.
+        let events = BuildEvents::new(build_temp_dir.path().join("events.jsonl"));
+        cargo_make = cargo_make.env("BUILDSYS_EVENTS_FILE", events.path().display().to_string());
+        let json = self.message_format == MessageFormat::Json;
+        let forwarder = json.then(|| events.forward());
+
+        // Hold the result of the builds so we can clean up the project directory first.
+        let res = build_all(
+            &project,
+            cargo_make.output_to_stderr(json),
+            &variants,
+            &arches,
+            jobs,
+            json.then_some(&events),
+        )
+        .await;
+        if let Some(forwarder) = forwarder {
+            forwarder.finish().await;
+        }
+        if !json {
+            match events.failed().await {
+                Ok(failed) => print!("{}", failure_summary(&project.project_dir(), &failed).await),
+                Err(e) => warn!("Unable to find the builds that failed: {e:#}"),
+            }
+        }
 
         // Clean up all of the files we created
         for file_name in created_files {
@@ -155,3 +259,369 @@ impl BuildVariant {
         res
     }
 }
+
+/// The outcome of building one variant for one architecture.
+struct BuildResult {
+    variant: String,
+    arch: String,
+    result: Result<PathBuf>,
+}
+
+/// Build each of `variants` for each of `arches`, running up to `jobs` builds at once, and print a
+/// summary of the results, unless the results are reported as build `events`. Builds keep
+/// going when one of them fails.
+async fn build_all(
+    project: &Project,
+    cargo_make: CargoMake,
+    variants: &[String],
+    arches: &[String],
+    jobs: usize,
+    events: Option<&BuildEvents>,
+) -> Result<()> {
+    let first = (variants[0].as_str(), arches[0].as_str());
+    if variants.len() * arches.len() == 1 {
+        let (variant, arch) = first;
+        let cargo_make = cargo_make.env("BUILDSYS_ARCH", arch);
+        return build_one(project, &cargo_make, variant, arch, events)
+            .await
+            .map(|_| ());
+    }
+
+    // Fetch and generate what the builds share once, so that they do not race to do it.
+    cargo_make
+        .clone()
+        .env("BUILDSYS_VARIANT", first.0)
+        .env("BUILDSYS_ARCH", first.1)
+        .exec("prepare-build")
+        .await
+        .context("Unable to prepare the build")?;
+    let cargo_make = cargo_make.env("TLPRIVATE_PREPARED", "true");
+
+    // Each variant and architecture is built on its own, and builds that run at the same time get
+    // their own cargo target directories so that cargo does not make them wait for each other.
+    let builds = variants
+        .iter()
+        .flat_map(|variant| arches.iter().map(move |arch| (variant, arch)))
+        .collect::<Vec<_>>();
+    let mut results = run_jobs(builds, jobs, |slot, (variant, arch)| {
+        let mut cargo_make = cargo_make.clone().env("BUILDSYS_ARCH", arch);
+        if let Some(target_dir) = job_target_dir(project, arch, slot) {
+            cargo_make = cargo_make.env(
+                "TLPRIVATE_CARGO_TARGET_DIR",
+                target_dir.display().to_string(),
+            );
+        }
+        async move {
+            info!("Building '{variant}' for '{arch}'");
+            let result = build_one(project, &cargo_make, variant, arch, events).await;
+            if let Err(e) = &result {
+                error!("Unable to build '{variant}' for '{arch}': {e:?}");
+            }
+            BuildResult {
+                variant: variant.clone(),
+                arch: arch.clone(),
+                result,
+            }
+        }
+    })
+    .await;
+
+    results.sort_by(|a, b| (&a.variant, &a.arch).cmp(&(&b.variant, &b.arch)));
+    if events.is_none() {
+        println!("{}", summary(&project.project_dir(), &results));
+    }
+    let failed = results.iter().filter(|r| r.result.is_err()).count();
+    ensure!(failed == 0, "{failed} of {} builds failed", results.len());
+    Ok(())
+}
+
+/// Run `job` for each of `items`, with at most `jobs` of them running at once. Each running job is
+/// given a slot below `jobs` that no other running job has. The results are in the order that the
+/// jobs finished.
+async fn run_jobs<T, R, F, Fut>(items: Vec<T>, jobs: usize, job: F) -> Vec<R>
+where
+    F: Fn(usize, T) -> Fut,
+    Fut: Future<Output = R>,
+{
+    let free_slots = Mutex::new((0..jobs).rev().collect::<Vec<_>>());
+    stream::iter(items)
+        .map(|item| {
+            let free_slots = &free_slots;
+            let job = &job;
+            async move {
+                // No more than `jobs` run at once, so there is always a free slot.
+                let slot = free_slots
+                    .lock()
+                    .expect("job slots lock poisoned")
+                    .pop()
+                    .expect("no free job slot");
+                let result = job(slot, item).await;
+                free_slots
+                    .lock()
+                    .expect("job slots lock poisoned")
+                    .push(slot);
+                result
+            }
+        })
+        .buffer_unordered(jobs)
+        .collect()
+        .await
+}
+
+/// The cargo target directory for builds of `arch` in job `slot`. The first slot uses the one that
+/// builds use when they run on their own, and `None` leaves it to `cargo make`.
+fn job_target_dir(project: &Project, arch: &str, slot: usize) -> Option<PathBuf> {
+    (slot > 0).then(|| {
+        project
+            .project_dir()
+            .join("variants")
+            .join("target")
+            .join(format!("{arch}-job{slot}"))
+    })
+}
+
+/// Build one variant and return the directory of its images. With build `events`, the start and
+/// end of the build are reported, along with the packages and kits that were already up to date.
+async fn build_one(
+    project: &Project,
+    cargo_make: &CargoMake,
+    variant: &str,
+    arch: &str,
+    events: Option<&BuildEvents>,
+) -> Result<PathBuf> {
+    // Earlier builds wrote to the same events file, so only the events after this point are the
+    // ones of this build.
+    let since = match events {
+        Some(events) => {
+            events::emit(&Event::VariantBuildStarted { variant, arch });
+            Some(events.end().await)
+        }
+        None => None,
+    };
+    let start = Instant::now();
+    let result = build_variant(project, cargo_make, variant, arch).await;
+    if let Some((events, since)) = events.zip(since) {
+        if result.is_ok() {
+            let reported = match since {
+                Ok(since) => report_cached(project, events, since, variant, arch).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = reported {
+                warn!("Unable to report the cached builds of '{variant}' for '{arch}': {e:#}");
+            }
+        }
+        events::emit(&Event::VariantBuildFinished {
+            variant,
+            arch,
+            success: result.is_ok(),
+            duration_secs: start.elapsed().as_secs_f64(),
+            image_dir: result.as_deref().ok(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        });
+    }
+    result
+}
+
+async fn build_variant(
+    project: &Project,
+    cargo_make: &CargoMake,
+    variant: &str,
+    arch: &str,
+) -> Result<PathBuf> {
+    cargo_make
+        .clone()
+        .env("BUILDSYS_VARIANT", variant)
+        .exec("build")
+        .await?;
+    Ok(VariantArtifacts::new(project, variant, arch)
+        .await?
+        .variant_dir())
+}
+
+/// Report the packages and kits that `variant` needs which buildsys did not build for `arch`,
+/// because cargo found them up to date. Only the builds that started after the offset `since` in
+/// the events file count, since builds of other variants write to the same file.
+async fn report_cached(
+    project: &Project,
+    events: &BuildEvents,
+    since: usize,
+    variant: &str,
+    arch: &str,
+) -> Result<()> {
+    let project_dir = project.project_dir();
+    let cargo_lock = fs::read_to_string(project_dir.join("variants").join("Cargo.lock")).await?;
+    let started = events.started(arch, since).await?;
+    for name in events::workspace_dependencies(&cargo_lock, variant)?.difference(&started) {
+        let kind = if project_dir.join("kits").join(name).is_dir() {
+            "kit"
+        } else {
+            "package"
+        };
+        events::emit(&Event::BuildFinished {
+            kind,
+            name,
+            arch,
+            success: true,
+            cached: true,
+            duration_secs: 0.0,
+        });
+    }
+    Ok(())
+}
+
+/// The most lines of a failed build's output to show.
+const FAILURE_LOG_LINES: usize = 30;
+
+/// The end of the output of each failed build, so that the reason it failed is shown without
+/// searching through the output of all of the builds.
+async fn failure_summary(project_dir: &Path, failed: &[FailedBuild]) -> String {
+    let mut summary = String::new();
+    for build in failed {
+        let what = format!("{} '{}' for '{}'", build.kind, build.name, build.arch);
+        let Some(log) = &build.log else {
+            summary.push_str(&format!("\nThe build of {what} failed\n"));
+            continue;
+        };
+        let shown = log.strip_prefix(project_dir).unwrap_or(log).display();
+        match fs::read_to_string(log).await {
+            Ok(output) => {
+                summary.push_str(&format!(
+                    "\nThe build of {what} failed, the end of its output in '{shown}':\n"
+                ));
+                for line in tail(&output, FAILURE_LOG_LINES) {
+                    summary.push_str(&format!("    {line}\n"));
+                }
+            }
+            Err(e) => summary.push_str(&format!(
+                "\nThe build of {what} failed, and its output in '{shown}' is unreadable: {e}\n"
+            )),
+        }
+    }
+    summary
+}
+
+/// The last `n` lines of `text`, without trailing empty lines.
+fn tail(text: &str, n: usize) -> Vec<&str> {
+    let lines = text.trim_end().lines().collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(n)..].to_vec()
+}
+
+/// A table of the build results, with the image directory of each successful build relative to
+/// the project directory.
+fn summary(project_dir: &Path, results: &[BuildResult]) -> String {
+    let rows = results
+        .iter()
+        .map(|r| {
+            let (status, image) = match &r.result {
+                Ok(dir) => (
+                    "ok",
+                    dir.strip_prefix(project_dir)
+                        .unwrap_or(dir)
+                        .display()
+                        .to_string(),
+                ),
+                Err(_) => ("failed", "-".to_string()),
+            };
+            [r.variant.clone(), r.arch.clone(), status.to_string(), image]
+        })
+        .collect::<Vec<_>>();
+    let header = ["VARIANT", "ARCH", "RESULT", "IMAGE"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_summary() {
+    let results = vec![
+        BuildResult {
+            variant: "aws-dev".to_string(),
+            arch: "x86_64".to_string(),
+            result: Ok(PathBuf::from(
+                "/project/build/images/x86_64-aws-dev/1.0.0-abcdef01",
+            )),
+        },
+        BuildResult {
+            variant: "metal-dev".to_string(),
+            arch: "aarch64".to_string(),
+            result: Err(anyhow::anyhow!("boom")),
+        },
+    ];
+    assert_eq!(
+        summary(Path::new("/project"), &results),
+        "VARIANT    ARCH     RESULT  IMAGE\n\
+         aws-dev    x86_64   ok      build/images/x86_64-aws-dev/1.0.0-abcdef01\n\
+         metal-dev  aarch64  failed  -"
+    );
+}
+
+#[tokio::test]
+async fn test_run_jobs() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let builds = ["aws-dev", "metal-dev", "vmware-dev"]
+        .iter()
+        .flat_map(|variant| ["x86_64", "aarch64"].map(move |arch| (*variant, arch)))
+        .collect::<Vec<_>>();
+    let running = Mutex::new(Vec::new());
+    let most_running = AtomicUsize::new(0);
+    let results = run_jobs(builds.clone(), 4, |slot, build| {
+        let running = &running;
+        let most_running = &most_running;
+        async move {
+            {
+                let mut running = running.lock().unwrap();
+                // No two builds that run at once share a slot.
+                assert!(!running.contains(&slot), "slot {slot} is in use");
+                running.push(slot);
+                most_running.fetch_max(running.len(), Ordering::SeqCst);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            running.lock().unwrap().retain(|s| *s != slot);
+            (slot, build)
+        }
+    })
+    .await;
+
+    // Every variant of every architecture is built once, several at a time, within the limit.
+    assert_eq!(most_running.load(Ordering::SeqCst), 4);
+    let mut built = results.iter().map(|(_, build)| *build).collect::<Vec<_>>();
+    built.sort();
+    let mut expected = builds;
+    expected.sort();
+    assert_eq!(built, expected);
+    assert!(results.iter().all(|(slot, _)| *slot < 4));
+
+    // With one job, the builds run one after another in the first slot.
+    let results = run_jobs(
+        vec!["aws-dev", "metal-dev"],
+        1,
+        |slot, variant| async move { (slot, variant) },
+    )
+    .await;
+    assert_eq!(results, [(0, "aws-dev"), (0, "metal-dev")]);
+}
+
+#[test]
+fn test_tail() {
+    assert_eq!(tail("a\nb\nc\n\n", 2), ["b", "c"]);
+    assert_eq!(tail("a\nb", 5), ["a", "b"]);
+    assert!(tail("", 5).is_empty());
+}
@@ -16,7 +16,11 @@ impl BuildClean {
     pub(super) async fn run(&self) -> Result<()> {
         let project = project::load_or_find_project(self.project_path.clone()).await?;
         let toolsdir = project.project_dir().join("build/tools");
-        tools::install_tools(&toolsdir).await?;
+        let _tools = tools::install_tools(
+            &toolsdir,
+            &tools::ToolOverrides::resolve(project.tool_overrides())?,
+        )
+        .await?;
         let makefile_path = toolsdir.join("Makefile.toml");
 
         CargoMake::new(&project)?
@@ -1,6 +1,9 @@
-use crate::tools::install_tools;
+use super::doctor::Doctor;
+use crate::project;
+use crate::tools::{install_tools, ToolOverrides};
 use anyhow::Result;
 use clap::Parser;
+use log::info;
 use std::env;
 use std::path::PathBuf;
 use uuid::Uuid;
@@ -14,12 +17,14 @@ pub(crate) struct Debug {
 #[derive(Debug, Clone, Parser)]
 pub(crate) enum DebugAction {
     CheckTools(CheckToolArgs),
+    Doctor(Doctor),
 }
 
 impl DebugAction {
     pub(crate) async fn run(&self) -> Result<()> {
         match self {
             DebugAction::CheckTools(c) => c.run().await,
+            DebugAction::Doctor(d) => d.run().await,
         }
     }
 }
@@ -34,6 +39,11 @@ pub(crate) struct CheckToolArgs {
     /// be created if it does not exist. Outputs the name of the directory to stdout.
     #[clap(long)]
     install_dir: Option<PathBuf>,
+
+    /// Path to Twoliter.toml, whose tool overrides will be installed. If not specified, only the
+    /// overrides from `TWOLITER_TOOL_OVERRIDE_<NAME>` environment variables are installed.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
 }
 
 fn unique_name() -> String {
@@ -48,7 +58,24 @@ impl CheckToolArgs {
             .install_dir
             .clone()
             .unwrap_or_else(|| env::temp_dir().join(unique_name()));
-        install_tools(&dir).await?;
+        let configured = match &self.project_path {
+            Some(project_path) => project::load_or_find_project(Some(project_path.clone()))
+                .await?
+                .tool_overrides()
+                .clone(),
+            None => Default::default(),
+        };
+        let tools = install_tools(&dir, &ToolOverrides::resolve(&configured)?).await?;
+        for (name, binary) in tools.binaries() {
+            match binary.override_path() {
+                Some(path) => info!(
+                    "{name}: overridden by '{}' (sha256 {})",
+                    path.display(),
+                    binary.sha256()
+                ),
+                None => info!("{name}: embedded (sha256 {})", binary.sha256()),
+            }
+        }
         println!("{}", dir.display());
         Ok(())
     }
@@ -0,0 +1,404 @@
+use super::inspect::OutputFormat;
+use crate::image::{FileKind, OsImage, VariantRecord, ROOT_PARTITION};
+use crate::packages::{self, human_size, InstalledPackage, PackageDiff};
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use tokio::task::spawn_blocking;
+
+/// Compare two variant images: their packages, the files in their root filesystems, their kernel
+/// parameters and their image features. Works offline on the raw or qcow2 images in
+/// `build/images`, without mounting them.
+#[derive(Debug, Parser)]
+pub(crate) struct DiffImage {
+    /// How to print the differences.
+    #[clap(long = "output", value_enum, default_value_t)]
+    output: OutputFormat,
+
+    /// The older image, or the directory of the build that made it, such as
+    /// `build/images/x86_64-aws-dev/1.0.0-abcdef`.
+    old: PathBuf,
+
+    /// The newer image, or the directory of the build that made it.
+    new: PathBuf,
+}
+
+impl DiffImage {
+    pub(super) async fn run(&self) -> Result<()> {
+        let old = self.old.clone();
+        let new = self.new.clone();
+        let diff = spawn_blocking(move || -> Result<ImageDiff> {
+            let old = ImageSummary::read(&old)?;
+            let new = ImageSummary::read(&new)?;
+            Ok(ImageDiff::new(&old, &new))
+        })
+        .await
+        .context("Unable to run and join async task for comparing the images")??;
+        match self.output {
+            OutputFormat::Human => print!("{diff}"),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&diff)
+                    .context("Unable to serialize the image differences")?
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// A file in the root filesystem, as far as comparing images goes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct FileSummary {
+    kind: FileKind,
+    mode: u16,
+    /// The size of a regular file, or zero.
+    size: u64,
+    /// The SHA-256 of a regular file, or the target of a symlink.
+    content: Option<String>,
+}
+
+/// What is compared of an image.
+#[derive(Debug, Clone, Default)]
+struct ImageSummary {
+    files: BTreeMap<String, FileSummary>,
+    kernel_parameters: Vec<String>,
+    /// The image features from `variant.json`, if the build wrote one.
+    image_features: Option<Vec<String>>,
+    /// The packages from `packages.json`, if the build wrote one.
+    packages: Option<BTreeMap<String, InstalledPackage>>,
+}
+
+impl ImageSummary {
+    fn read(path: &Path) -> Result<Self> {
+        let image = OsImage::open(path)?;
+        let root = image.filesystem(ROOT_PARTITION)?;
+        let mut files = BTreeMap::new();
+        for node in root.walk()? {
+            let (size, content) = match node.kind {
+                FileKind::File => (
+                    node.size,
+                    Some(hex::encode(Sha256::digest(root.read_node(&node)?))),
+                ),
+                FileKind::Symlink => (0, node.target.clone()),
+                FileKind::Directory | FileKind::Other => (0, None),
+            };
+            files.insert(
+                node.path,
+                FileSummary {
+                    kind: node.kind,
+                    mode: node.mode,
+                    size,
+                    content,
+                },
+            );
+        }
+
+        let packages_json = image.dir().join("packages.json");
+        let packages = if packages_json.is_file() {
+            // This runs on a blocking thread, so the file is read without the async runtime.
+            let json = std::fs::read_to_string(&packages_json)
+                .with_context(|| format!("Unable to read '{}'", packages_json.display()))?;
+            Some(packages::parse(&json, &packages_json)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            files,
+            kernel_parameters: image.grub_config()?.kernel_parameters,
+            image_features: VariantRecord::load(image.dir())?.map(|record| record.image_features),
+            packages,
+        })
+    }
+}
+
+/// A file that was added, removed or changed.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct FileChange {
+    path: String,
+    kind: FileKind,
+    /// What changed about a file in both images: `kind`, `mode` or `content`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    changes: Vec<&'static str>,
+    size_delta: i64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct FileDiff {
+    added: Vec<FileChange>,
+    removed: Vec<FileChange>,
+    changed: Vec<FileChange>,
+    /// The change in the total size of the regular files, in bytes.
+    size_delta: i64,
+}
+
+impl FileDiff {
+    fn new(old: &BTreeMap<String, FileSummary>, new: &BTreeMap<String, FileSummary>) -> Self {
+        let mut diff = Self::default();
+        for (path, old_file) in old {
+            let Some(new_file) = new.get(path) else {
+                diff.removed.push(FileChange {
+                    path: path.clone(),
+                    kind: old_file.kind,
+                    changes: Vec::new(),
+                    size_delta: -(old_file.size as i64),
+                });
+                continue;
+            };
+            let changes = [
+                ("kind", old_file.kind != new_file.kind),
+                ("mode", old_file.mode != new_file.mode),
+                ("content", old_file.content != new_file.content),
+            ]
+            .into_iter()
+            .filter_map(|(change, changed)| changed.then_some(change))
+            .collect::<Vec<_>>();
+            if !changes.is_empty() {
+                diff.changed.push(FileChange {
+                    path: path.clone(),
+                    kind: new_file.kind,
+                    changes,
+                    size_delta: new_file.size as i64 - old_file.size as i64,
+                });
+            }
+        }
+        for (path, new_file) in new {
+            if !old.contains_key(path) {
+                diff.added.push(FileChange {
+                    path: path.clone(),
+                    kind: new_file.kind,
+                    changes: Vec::new(),
+                    size_delta: new_file.size as i64,
+                });
+            }
+        }
+        let total = |files: &BTreeMap<String, FileSummary>| -> i64 {
+            files.values().map(|file| file.size as i64).sum()
+        };
+        diff.size_delta = total(new) - total(old);
+        diff
+    }
+}
+
+/// The items of a list that were added or removed, ignoring their order.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+struct ListDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl ListDiff {
+    fn new(old: &[String], new: &[String]) -> Self {
+        let old_set = old.iter().collect::<BTreeSet<_>>();
+        let new_set = new.iter().collect::<BTreeSet<_>>();
+        Self {
+            added: new_set
+                .difference(&old_set)
+                .map(|s| s.to_string())
+                .collect(),
+            removed: old_set
+                .difference(&new_set)
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// The differences between two images. The packages and image features are left out when the
+/// build output of either image does not describe them.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ImageDiff {
+    packages: Option<PackageDiff>,
+    files: FileDiff,
+    kernel_parameters: ListDiff,
+    image_features: Option<ListDiff>,
+}
+
+impl ImageDiff {
+    fn new(old: &ImageSummary, new: &ImageSummary) -> Self {
+        Self {
+            packages: match (&old.packages, &new.packages) {
+                (Some(old), Some(new)) => Some(PackageDiff::new(old, new)),
+                _ => None,
+            },
+            files: FileDiff::new(&old.files, &new.files),
+            kernel_parameters: ListDiff::new(&old.kernel_parameters, &new.kernel_parameters),
+            image_features: match (&old.image_features, &new.image_features) {
+                (Some(old), Some(new)) => Some(ListDiff::new(old, new)),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Display for ImageDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Packages:")?;
+        match &self.packages {
+            Some(packages) => {
+                for line in packages.to_string().lines() {
+                    writeln!(f, "  {line}")?;
+                }
+            }
+            None => writeln!(
+                f,
+                "  Unknown, the build output of an image has no packages.json"
+            )?,
+        }
+
+        writeln!(f, "Files in the root filesystem:")?;
+        let files = &self.files;
+        for (sign, changes) in [
+            ('+', &files.added),
+            ('-', &files.removed),
+            ('~', &files.changed),
+        ] {
+            for change in changes {
+                write!(f, "  {sign} {}", change.path)?;
+                if !change.changes.is_empty() {
+                    write!(f, " [{}]", change.changes.join(", "))?;
+                }
+                if change.kind == FileKind::File {
+                    write!(f, " ({})", human_size(change.size_delta))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        writeln!(
+            f,
+            "  {} added, {} removed, {} changed, total size {}",
+            files.added.len(),
+            files.removed.len(),
+            files.changed.len(),
+            human_size(files.size_delta)
+        )?;
+
+        writeln!(f, "Kernel parameters:")?;
+        write_list_diff(f, &self.kernel_parameters)?;
+
+        writeln!(f, "Image features:")?;
+        match &self.image_features {
+            Some(image_features) => write_list_diff(f, image_features),
+            None => writeln!(
+                f,
+                "  Unknown, the build output of an image has no variant.json"
+            ),
+        }
+    }
+}
+
+fn write_list_diff(f: &mut Formatter<'_>, diff: &ListDiff) -> std::fmt::Result {
+    if diff.added.is_empty() && diff.removed.is_empty() {
+        return writeln!(f, "  No changes");
+    }
+    for item in &diff.added {
+        writeln!(f, "  + {item}")?;
+    }
+    for item in &diff.removed {
+        writeln!(f, "  - {item}")?;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_diff_image() {
+    use crate::image::{test_image, TestNode};
+
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let write_build = |name: &str, image: Vec<u8>, features: Option<&str>| {
+        let dir = tempdir.path().join(name);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("bottlerocket-aws-dev-x86_64.img"), image).unwrap();
+        if let Some(features) = features {
+            std::fs::write(
+                dir.join("variant.json"),
+                format!(
+                    r#"{{
+                        "variant": "aws-dev",
+                        "image-features": {features},
+                        "kernel-parameters": [],
+                        "image-layout": {{
+                            "os-image-size-gib": 2,
+                            "data-image-size-gib": 1,
+                            "publish-image-size-hint-gib": 22,
+                            "partition-plan": "split"
+                        }}
+                    }}"#
+                ),
+            )
+            .unwrap();
+        }
+        dir
+    };
+    let old = write_build(
+        "old",
+        test_image(
+            "linux ($root)/vmlinuz console=tty0 quiet\n",
+            &[
+                ("etc", TestNode::Directory),
+                ("etc/os-release", TestNode::File(b"VERSION_ID=1.0.0\n")),
+                ("etc/motd", TestNode::File(b"hello\n")),
+                ("usr", TestNode::Symlink("x86_64/usr")),
+            ],
+        ),
+        Some(r#"["grub-set-private-var"]"#),
+    );
+    let new = write_build(
+        "new",
+        test_image(
+            "linux ($root)/vmlinuz console=tty0 console=ttyS0\n",
+            &[
+                ("etc", TestNode::Directory),
+                ("etc/os-release", TestNode::File(b"VERSION_ID=1.1.0\n")),
+                ("etc/issue", TestNode::File(&[b'x'; 2048])),
+                ("usr", TestNode::Symlink("aarch64/usr")),
+            ],
+        ),
+        Some(r#"["grub-set-private-var", "fips"]"#),
+    );
+
+    let diff = spawn_blocking(move || {
+        ImageDiff::new(
+            &ImageSummary::read(&old).unwrap(),
+            &ImageSummary::read(&new).unwrap(),
+        )
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        diff.to_string(),
+        "Packages:\n\
+        \x20 Unknown, the build output of an image has no packages.json\n\
+        Files in the root filesystem:\n\
+        \x20 + /etc/issue (+2.0 KiB)\n\
+        \x20 - /etc/motd (-6 B)\n\
+        \x20 ~ /etc/os-release [content] (+0 B)\n\
+        \x20 ~ /usr [content]\n\
+        \x20 1 added, 1 removed, 2 changed, total size +2.0 KiB\n\
+        Kernel parameters:\n\
+        \x20 + console=ttyS0\n\
+        \x20 - quiet\n\
+        Image features:\n\
+        \x20 + fips\n"
+    );
+    assert!(diff.packages.is_none());
+}
+
+#[test]
+fn test_list_diff() {
+    let list = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let diff = ListDiff::new(&list(&["a", "b", "c"]), &list(&["c", "b", "d", "d"]));
+    assert_eq!(diff.added, ["d"]);
+    assert_eq!(diff.removed, ["a"]);
+    assert_eq!(
+        ListDiff::new(&list(&["a"]), &list(&["a"])),
+        ListDiff::default()
+    );
+}
@@ -0,0 +1,727 @@
+//! `twoliter debug doctor` checks that the host can run builds and that the project is ready to be
+//! built, so that common problems are found before a long build fails part way through.
+
+use crate::common::{exec, fs};
+use crate::docker::{image_id, list_tags, runtime};
+use crate::project::{self, Project};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use container_runtime::Runtime;
+use nix::sys::statvfs::statvfs;
+use nix::unistd::{access, AccessFlags};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Builds need room for the SDK, the package RPMs and several images per variant.
+const DISK_SPACE_WARN_GIB: u64 = 50;
+const DISK_SPACE_FAIL_GIB: u64 = 10;
+
+/// The oldest Docker release whose BuildKit has the fixes for moby/buildkit#1090 and
+/// moby/buildkit#1468. buildsys retries around those bugs, but builds are slower and flakier.
+const MIN_DOCKER_VERSION: (u64, u64) = (20, 10);
+
+/// The default location of the Docker daemon's socket.
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Check the host and the project for problems that would stop a build.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct Doctor {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent, and only check the host
+    /// if there is no project.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The architecture that the variants will be built for.
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// Write the results of the checks to this file as JSON.
+    #[clap(long = "report")]
+    report: Option<PathBuf>,
+}
+
+impl Doctor {
+    pub(crate) async fn run(&self) -> Result<()> {
+        let project = match &self.project_path {
+            Some(path) => Some(project::load_or_find_project(Some(path.clone())).await?),
+            None => project::load_or_find_project(None).await.ok(),
+        };
+
+        let mut report = Report::default();
+        match runtime() {
+            Runtime::Docker => {
+                report.push(Scope::Host, "docker", check_docker().await);
+                report.push(Scope::Host, "buildx", check_buildx().await);
+                report.push(
+                    Scope::Host,
+                    "docker-permissions",
+                    check_docker_permissions(),
+                );
+            }
+            Runtime::Podman => report.push(Scope::Host, "podman", check_podman().await),
+        }
+        report.push(Scope::Host, "cgroups", check_cgroups());
+        let build_dir = match &project {
+            Some(project) => project.project_dir().join("build"),
+            None => std::env::current_dir().context("Unable to get the current directory")?,
+        };
+        report.push(Scope::Host, "disk-space", check_disk_space(&build_dir));
+
+        match &project {
+            Some(project) => {
+                let project_dir = project.project_dir();
+                report.push(Scope::Project, "sdk", check_sdk(project).await);
+                report.push(Scope::Project, "sbkeys", check_sbkeys(&project_dir).await);
+                report.push(
+                    Scope::Project,
+                    "release-toml",
+                    check_release_toml(&project_dir),
+                );
+                report.push(
+                    Scope::Project,
+                    "cargo-lock",
+                    check_cargo_locks(&project_dir).await,
+                );
+                report.push(
+                    Scope::Project,
+                    "supported-arches",
+                    check_supported_arches(project, &self.arch).await,
+                );
+            }
+            None => report.push(
+                Scope::Project,
+                "project",
+                Outcome::warn("No Twoliter.toml was found, so only the host was checked"),
+            ),
+        }
+
+        print!("{report}");
+        if let Some(path) = &self.report {
+            let json = serde_json::to_string_pretty(&report)
+                .context("Unable to serialize the doctor report")?;
+            fs::write(path, json).await?;
+        }
+        let failed = report.count(Status::Fail);
+        if failed > 0 {
+            bail!("{failed} of {} checks failed", report.checks.len());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        f.pad(status)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Scope {
+    Host,
+    Project,
+}
+
+/// The result of one check.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct Outcome {
+    status: Status,
+    message: String,
+}
+
+impl Outcome {
+    fn pass(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Pass,
+            message: message.into(),
+        }
+    }
+
+    fn warn(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            message: message.into(),
+        }
+    }
+
+    fn fail(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Check {
+    scope: Scope,
+    name: &'static str,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, scope: Scope, name: &'static str, outcome: Outcome) {
+        self.checks.push(Check {
+            scope,
+            name,
+            outcome,
+        });
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.outcome.status == status)
+            .count()
+    }
+}
+
+/// One line per check, e.g. `PASS  host/docker: Docker 24.0.5 is running`.
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let scope = match check.scope {
+                Scope::Host => "host",
+                Scope::Project => "project",
+            };
+            writeln!(
+                f,
+                "{:<4}  {scope}/{}: {}",
+                check.outcome.status, check.name, check.outcome.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a command quietly and return its trimmed output, or the last line of its error, which is
+/// the end of the command's stderr if it ran.
+async fn output(cmd: &mut Command) -> std::result::Result<String, String> {
+    match exec(cmd, true).await {
+        Ok(output) => Ok(output.unwrap_or_default().trim().to_string()),
+        Err(e) => Err(format!("{e:#}")
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default()
+            .to_string()),
+    }
+}
+
+/// The major and minor version at the start of a version string such as `20.10.25+dfsg1` or
+/// `v0.11.2`.
+fn major_minor(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.trim_start_matches('v').split(['.', '-', '+']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+async fn check_docker() -> Outcome {
+    let version =
+        match output(Command::new("docker").args(["version", "--format", "{{.Server.Version}}"]))
+            .await
+        {
+            Ok(version) => version,
+            Err(e) => return Outcome::fail(format!("Unable to reach the Docker daemon: {e}")),
+        };
+    match major_minor(&version) {
+        Some(major_minor) if major_minor >= MIN_DOCKER_VERSION => {
+            Outcome::pass(format!("Docker {version} is running"))
+        }
+        Some(_) => Outcome::warn(format!(
+            "Docker {version} is running, but its BuildKit has bugs that make parallel builds \
+             fail, upgrade to Docker {}.{} or newer",
+            MIN_DOCKER_VERSION.0, MIN_DOCKER_VERSION.1
+        )),
+        None => Outcome::warn(format!(
+            "Docker is running, but its version '{version}' is not understood"
+        )),
+    }
+}
+
+async fn check_podman() -> Outcome {
+    // Podman has no daemon, so this only shows that it is installed and can read its storage.
+    match output(Command::new("podman").args(["version", "--format", "{{.Client.Version}}"])).await
+    {
+        Ok(version) => Outcome::pass(format!("Podman {version} is installed")),
+        Err(e) => Outcome::fail(format!("Unable to run Podman: {e}")),
+    }
+}
+
+async fn check_buildx() -> Outcome {
+    // e.g. `github.com/docker/buildx v0.11.2 9872040`
+    match output(Command::new("docker").args(["buildx", "version"])).await {
+        Ok(version) => match version.split_whitespace().nth(1) {
+            Some(version) => Outcome::pass(format!("buildx {version} is installed")),
+            None => Outcome::pass(format!("buildx is installed: {version}")),
+        },
+        Err(e) => Outcome::fail(format!(
+            "Builds use BuildKit, which needs the Docker buildx plugin: {e}"
+        )),
+    }
+}
+
+fn check_docker_permissions() -> Outcome {
+    let socket = match std::env::var("DOCKER_HOST") {
+        Ok(host) => match host.strip_prefix("unix://") {
+            Some(socket) => PathBuf::from(socket),
+            None => return Outcome::pass(format!("Docker is used through '{host}'")),
+        },
+        Err(_) => PathBuf::from(DOCKER_SOCKET),
+    };
+    if !socket.exists() {
+        return Outcome::fail(format!(
+            "The Docker socket '{}' does not exist",
+            socket.display()
+        ));
+    }
+    match access(&socket, AccessFlags::R_OK | AccessFlags::W_OK) {
+        Ok(()) => Outcome::pass(format!(
+            "The Docker socket '{}' is usable",
+            socket.display()
+        )),
+        Err(e) => Outcome::fail(format!(
+            "The current user is not allowed to use the Docker socket '{}', e.g. add them to the \
+             docker group: {e}",
+            socket.display()
+        )),
+    }
+}
+
+fn check_cgroups() -> Outcome {
+    let root = Path::new("/sys/fs/cgroup");
+    if root.join("cgroup.controllers").is_file() {
+        Outcome::pass("The unified cgroup v2 hierarchy is mounted")
+    } else if root.join("memory").is_dir() {
+        Outcome::pass("The cgroup v1 hierarchy is mounted")
+    } else {
+        Outcome::fail(format!(
+            "No cgroup hierarchy is mounted at '{}', so Docker cannot run containers",
+            root.display()
+        ))
+    }
+}
+
+fn check_disk_space(dir: &Path) -> Outcome {
+    // The build directory is created by the first build, so check the closest directory that
+    // exists.
+    let dir = dir
+        .ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(Path::new("/"));
+    let stat = match statvfs(dir) {
+        Ok(stat) => stat,
+        Err(e) => {
+            return Outcome::warn(format!(
+                "Unable to get the free space under '{}': {e}",
+                dir.display()
+            ))
+        }
+    };
+    #[allow(clippy::useless_conversion)]
+    let free = u64::from(stat.blocks_available()) * u64::from(stat.fragment_size());
+    disk_space_outcome(dir, free)
+}
+
+fn disk_space_outcome(dir: &Path, free: u64) -> Outcome {
+    let free_gib = free / (1 << 30);
+    let message = format!("{free_gib} GiB are free under '{}'", dir.display());
+    if free_gib < DISK_SPACE_FAIL_GIB {
+        Outcome::fail(message)
+    } else if free_gib < DISK_SPACE_WARN_GIB {
+        Outcome::warn(format!(
+            "{message}, builds usually need at least {DISK_SPACE_WARN_GIB} GiB"
+        ))
+    } else {
+        Outcome::pass(message)
+    }
+}
+
+async fn check_sdk(project: &Project) -> Outcome {
+    let Some(sdk) = project.sdk() else {
+        return Outcome::fail("Twoliter.toml does not name an SDK");
+    };
+    if let Ok(Some(_)) = image_id(&sdk).await {
+        return Outcome::pass(format!("The SDK '{sdk}' has already been pulled"));
+    }
+    match list_tags(&sdk).await {
+        Ok(tags) if tags.contains(&sdk.tag) => {
+            Outcome::pass(format!("The SDK '{sdk}' can be pulled"))
+        }
+        Ok(_) => Outcome::fail(format!(
+            "The SDK '{sdk}' does not exist, the registry has no tag '{}'",
+            sdk.tag
+        )),
+        Err(e) => Outcome::fail(format!("Unable to find the SDK '{sdk}': {e}")),
+    }
+}
+
+async fn check_sbkeys(project_dir: &Path) -> Outcome {
+    let sbkeys_dir = project_dir.join("sbkeys");
+    if !sbkeys_dir.is_dir() {
+        return Outcome::warn(format!(
+            "There is no '{}', local Secure Boot keys will be generated for the build",
+            sbkeys_dir.display()
+        ));
+    }
+    if sbkeys_dir.join("generate-local-sbkeys").is_file() || sbkeys_dir.join("local").is_dir() {
+        return Outcome::pass(format!(
+            "Secure Boot keys are in '{}'",
+            sbkeys_dir.display()
+        ));
+    }
+    let mut profiles = Vec::new();
+    if let Ok(mut read_dir) = tokio::fs::read_dir(&sbkeys_dir).await {
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            if entry.path().is_dir() {
+                profiles.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    if profiles.is_empty() {
+        Outcome::fail(format!(
+            "'{}' has no Secure Boot key profiles, remove it to have local keys generated",
+            sbkeys_dir.display()
+        ))
+    } else {
+        profiles.sort();
+        Outcome::pass(format!(
+            "Secure Boot key profiles in '{}': {}",
+            sbkeys_dir.display(),
+            profiles.join(", ")
+        ))
+    }
+}
+
+fn check_release_toml(project_dir: &Path) -> Outcome {
+    let path = project_dir.join("Release.toml");
+    if path.is_file() {
+        Outcome::warn(format!(
+            "'{}' is deprecated, run `twoliter migrate` and remove it",
+            path.display()
+        ))
+    } else {
+        Outcome::pass("There is no Release.toml")
+    }
+}
+
+/// Check that the `Cargo.lock` of each workspace in the project is up to date with its members.
+/// Builds run cargo with `--locked`, which fails if a lock file would have to change.
+async fn check_cargo_locks(project_dir: &Path) -> Outcome {
+    let mut workspaces = vec![project_dir.to_path_buf()];
+    if let Ok(mut read_dir) = tokio::fs::read_dir(project_dir).await {
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            if entry.file_name() != "build" && entry.path().is_dir() {
+                workspaces.push(entry.path());
+            }
+        }
+    }
+    workspaces.sort();
+
+    let mut problems = Vec::new();
+    let mut checked = 0;
+    for workspace in workspaces {
+        match cargo_lock_problems(&workspace).await {
+            Ok(None) => {}
+            Ok(Some(found)) => {
+                checked += 1;
+                problems.extend(found);
+            }
+            Err(e) => problems.push(format!("{e:#}")),
+        }
+    }
+    if problems.is_empty() {
+        Outcome::pass(format!("{checked} Cargo.lock files are up to date"))
+    } else {
+        Outcome::warn(problems.join("; "))
+    }
+}
+
+/// The problems with the lock file of the workspace in `dir`, or `None` if `dir` is not a
+/// workspace.
+async fn cargo_lock_problems(dir: &Path) -> Result<Option<Vec<String>>> {
+    let manifest_path = dir.join("Cargo.toml");
+    if !manifest_path.is_file() {
+        return Ok(None);
+    }
+    let manifest: toml::Table = toml::from_str(&fs::read_to_string(&manifest_path).await?)
+        .context(format!("Unable to parse '{}'", manifest_path.display()))?;
+    let Some(workspace) = manifest.get("workspace") else {
+        return Ok(None);
+    };
+    let workspace_version = workspace
+        .get("package")
+        .and_then(|package| package.get("version"))
+        .and_then(|version| version.as_str());
+
+    let lock_path = dir.join("Cargo.lock");
+    if !lock_path.is_file() {
+        return Ok(Some(vec![format!("'{}' is missing", lock_path.display())]));
+    }
+    let lock: toml::Table = toml::from_str(&fs::read_to_string(&lock_path).await?)
+        .context(format!("Unable to parse '{}'", lock_path.display()))?;
+    // Path dependencies are the locked packages without a source.
+    let locked = lock
+        .get("package")
+        .and_then(|packages| packages.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|package| package.get("source").is_none())
+        .filter_map(|package| {
+            Some((
+                package.get("name")?.as_str()?,
+                package.get("version")?.as_str()?,
+            ))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut problems = Vec::new();
+    for member in workspace_members(dir, workspace).await {
+        if member.join("Cargo.lock").is_file() {
+            problems.push(format!(
+                "'{}' is ignored because '{}' is a workspace member, remove it",
+                member.join("Cargo.lock").display(),
+                member.display()
+            ));
+        }
+        let member_manifest_path = member.join("Cargo.toml");
+        let Ok(member_manifest) = fs::read_to_string(&member_manifest_path).await else {
+            continue;
+        };
+        let member_manifest: toml::Table = toml::from_str(&member_manifest).context(format!(
+            "Unable to parse '{}'",
+            member_manifest_path.display()
+        ))?;
+        let Some(package) = member_manifest.get("package") else {
+            continue;
+        };
+        let Some(name) = package.get("name").and_then(|name| name.as_str()) else {
+            continue;
+        };
+        // A version of `{ workspace = true }` is inherited from the workspace.
+        let version = match package.get("version") {
+            Some(toml::Value::String(version)) => Some(version.as_str()),
+            Some(_) => workspace_version,
+            None => Some("0.0.0"),
+        };
+        match (locked.get(name), version) {
+            (None, _) => problems.push(format!("'{}' is missing '{name}'", lock_path.display())),
+            (Some(locked), Some(version)) if *locked != version => problems.push(format!(
+                "'{}' has '{name}' {locked} but its Cargo.toml has {version}",
+                lock_path.display()
+            )),
+            _ => {}
+        }
+    }
+    Ok(Some(problems))
+}
+
+/// The directories of the members of a workspace, including those matched by a trailing `*`.
+async fn workspace_members(dir: &Path, workspace: &toml::Value) -> Vec<PathBuf> {
+    let patterns = workspace
+        .get("members")
+        .and_then(|members| members.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|member| member.as_str());
+    let mut members = Vec::new();
+    for pattern in patterns {
+        let Some(pattern) = pattern.strip_suffix('*') else {
+            members.push(dir.join(pattern));
+            continue;
+        };
+        let (parent, prefix) = pattern.rsplit_once('/').unwrap_or(("", pattern));
+        if let Ok(mut read_dir) = tokio::fs::read_dir(dir.join(parent)).await {
+            while let Ok(Some(entry)) = read_dir.next_entry().await {
+                let matches = entry.file_name().to_string_lossy().starts_with(prefix);
+                if matches && entry.path().join("Cargo.toml").is_file() {
+                    members.push(entry.path());
+                }
+            }
+        }
+    }
+    members.sort();
+    members
+}
+
+async fn check_supported_arches(project: &Project, arch: &str) -> Outcome {
+    let variants = match project.find_variants().await {
+        Ok(variants) => variants,
+        Err(e) => return Outcome::warn(format!("Unable to find the variants: {e:#}")),
+    };
+    let mut unsupported = Vec::new();
+    for variant in &variants {
+        let path = project
+            .project_dir()
+            .join("variants")
+            .join(variant)
+            .join("Cargo.toml");
+        let manifest = match fs::read_to_string(&path).await {
+            Ok(manifest) => manifest,
+            Err(e) => return Outcome::fail(format!("{e:#}")),
+        };
+        match supports_arch(&manifest, arch) {
+            Ok(true) => {}
+            Ok(false) => unsupported.push(variant.as_str()),
+            Err(e) => return Outcome::fail(format!("Unable to parse '{}': {e:#}", path.display())),
+        }
+    }
+    if unsupported.is_empty() {
+        Outcome::pass(format!(
+            "All {} variants can be built for {arch}",
+            variants.len()
+        ))
+    } else {
+        Outcome::warn(format!(
+            "These variants do not list {arch} in their supported-arches: {}",
+            unsupported.join(", ")
+        ))
+    }
+}
+
+/// Whether a variant's `Cargo.toml` allows it to be built for `arch`. Variants that do not list
+/// their `supported-arches` can be built for any architecture.
+fn supports_arch(manifest: &str, arch: &str) -> Result<bool> {
+    let manifest: toml::Table = toml::from_str(manifest)?;
+    let supported_arches = manifest
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("build-variant"))
+        .and_then(|build_variant| build_variant.get("supported-arches"));
+    Ok(
+        match supported_arches.and_then(|arches| arches.as_array()) {
+            Some(arches) => arches.iter().any(|a| a.as_str() == Some(arch)),
+            None => true,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_major_minor() {
+        assert_eq!(major_minor("24.0.5"), Some((24, 0)));
+        assert_eq!(major_minor("20.10.25+dfsg1"), Some((20, 10)));
+        assert_eq!(major_minor("v0.11.2"), Some((0, 11)));
+        assert_eq!(major_minor("dev"), None);
+    }
+
+    #[test]
+    fn test_disk_space_outcome() {
+        let dir = Path::new("/build");
+        assert_eq!(disk_space_outcome(dir, 100 << 30).status, Status::Pass);
+        assert_eq!(disk_space_outcome(dir, 20 << 30).status, Status::Warn);
+        assert_eq!(disk_space_outcome(dir, 5 << 30).status, Status::Fail);
+    }
+
+    #[test]
+    fn test_supports_arch() {
+        let manifest = r#"
+            [package]
+            name = "my-variant"
+
+            [package.metadata.build-variant]
+            supported-arches = ["x86_64"]
+        "#;
+        assert!(supports_arch(manifest, "x86_64").unwrap());
+        assert!(!supports_arch(manifest, "aarch64").unwrap());
+        assert!(supports_arch("[package]\nname = \"my-variant\"\n", "aarch64").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cargo_lock_problems() {
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let workspace = tempdir.path();
+        fs::create_dir_all(workspace.join("hello")).await.unwrap();
+        fs::write(
+            workspace.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"*\"]\n",
+        )
+        .await
+        .unwrap();
+        fs::write(
+            workspace.join("hello/Cargo.toml"),
+            "[package]\nname = \"hello\"\nversion = \"0.2.0\"\n",
+        )
+        .await
+        .unwrap();
+        fs::write(
+            workspace.join("Cargo.lock"),
+            "version = 3\n\n[[package]]\nname = \"hello\"\nversion = \"0.1.0\"\n",
+        )
+        .await
+        .unwrap();
+        let problems = cargo_lock_problems(workspace).await.unwrap().unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("'hello' 0.1.0"));
+
+        // Members are found by a trailing glob, and a lock file that matches them is up to date.
+        fs::write(
+            workspace.join("Cargo.lock"),
+            "version = 3\n\n[[package]]\nname = \"hello\"\nversion = \"0.2.0\"\n",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            cargo_lock_problems(workspace).await.unwrap(),
+            Some(Vec::new())
+        );
+
+        // Directories that are not workspaces are skipped.
+        assert_eq!(
+            cargo_lock_problems(&workspace.join("hello")).await.unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = Report::default();
+        report.push(
+            Scope::Host,
+            "docker",
+            Outcome::pass("Docker 24.0.5 is running"),
+        );
+        report.push(Scope::Project, "sbkeys", Outcome::warn("No keys"));
+        assert_eq!(
+            report.to_string(),
+            "PASS  host/docker: Docker 24.0.5 is running\nWARN  project/sbkeys: No keys\n"
+        );
+        assert_eq!(report.count(Status::Warn), 1);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json["checks"][1],
+            serde_json::json!({
+                "scope": "project",
+                "name": "sbkeys",
+                "status": "warn",
+                "message": "No keys",
+            })
+        );
+    }
+}
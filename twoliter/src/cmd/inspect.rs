@@ -0,0 +1,347 @@
+use crate::image::{
+    DmVerity, ImageLayout, OsImage, Partition, PlannedPartition, VariantRecord,
+    DM_VERITY_ROOT_VARIABLE, HASH_PARTITION,
+};
+use crate::packages::{self, PackageDiff};
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use tokio::task::spawn_blocking;
+
+#[derive(Debug, Parser)]
+pub(crate) enum InspectCommand {
+    Image(InspectImage),
+    Packages(InspectPackages),
+}
+
+impl InspectCommand {
+    pub(crate) async fn run(self) -> Result<()> {
+        match self {
+            InspectCommand::Image(command) => command.run().await,
+            InspectCommand::Packages(command) => command.run().await,
+        }
+    }
+}
+
+/// How to print what was inspected.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// A summary for people to read.
+    #[default]
+    Human,
+    /// A JSON document.
+    Json,
+}
+
+/// Compare the packages installed in two variant images, as listed in the `packages.json` files
+/// that the variant build writes next to the images.
+#[derive(Debug, Parser)]
+pub(crate) struct InspectPackages {
+    /// How to print the differences.
+    #[clap(long = "output", value_enum, default_value_t)]
+    output: OutputFormat,
+
+    /// The `packages.json` of the older image.
+    old: PathBuf,
+
+    /// The `packages.json` of the newer image.
+    new: PathBuf,
+}
+
+impl InspectPackages {
+    pub(super) async fn run(&self) -> Result<()> {
+        let old = packages::load(&self.old).await?;
+        let new = packages::load(&self.new).await?;
+        let diff = PackageDiff::new(&old, &new);
+        match self.output {
+            OutputFormat::Human => print!("{diff}"),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&diff)
+                    .context("Unable to serialize the package differences")?
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Summarize a variant image: its partitions, kernel command line, dm-verity root hash and
+/// os-release. The image is read as it is, without mounting it, and is checked against the image
+/// layout of the variant when its build recorded one.
+#[derive(Debug, Parser)]
+pub(crate) struct InspectImage {
+    /// How to print the summary.
+    #[clap(long = "output", value_enum, default_value_t)]
+    output: OutputFormat,
+
+    /// The image, or the directory of the build that made it, such as
+    /// `build/images/x86_64-aws-dev/latest`.
+    path: PathBuf,
+}
+
+impl InspectImage {
+    pub(super) async fn run(&self) -> Result<()> {
+        let path = self.path.clone();
+        let report = spawn_blocking(move || ImageReport::new(&path))
+            .await
+            .context("Unable to run and join async task for inspecting the image")??;
+        match self.output {
+            OutputFormat::Human => print!("{report}"),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&report)
+                    .context("Unable to serialize the image summary")?
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// The dm-verity table of the root filesystem, and whether its root hash matches the hash tree.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct VerityReport {
+    #[serde(flatten)]
+    table: DmVerity,
+    /// Unknown when the image has no hash partition.
+    root_hash_matches: Option<bool>,
+}
+
+/// The fields of os-release that identify a build.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct OsRelease {
+    version_id: Option<String>,
+    build_id: Option<String>,
+    variant_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ImageReport {
+    path: PathBuf,
+    size: u64,
+    partitions: Vec<Partition>,
+    kernel_parameters: Vec<String>,
+    dm_verity: Option<VerityReport>,
+    os_release: OsRelease,
+    /// The image layout of the variant, if its build recorded it in `variant.json`.
+    image_layout: Option<ImageLayout>,
+    /// The partitions that the variant lists instead of following its partition plan.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant_partitions: Option<Vec<PlannedPartition>>,
+    /// How the image differs from what the variant build should have made.
+    mismatches: Vec<String>,
+}
+
+impl ImageReport {
+    fn new(path: &Path) -> Result<Self> {
+        let image = OsImage::open(path)?;
+        let grub_config = image.grub_config()?;
+        let dm_verity = match grub_config.variables.get(DM_VERITY_ROOT_VARIABLE) {
+            Some(table) => {
+                let table = DmVerity::parse(table).context(format!(
+                    "Unable to parse the dm-verity table of '{}'",
+                    image.path().display()
+                ))?;
+                let root_hash_matches = match image.gpt().partition(HASH_PARTITION) {
+                    Some(_) => Some(table.verify_root_hash(&image.partition(HASH_PARTITION)?)?),
+                    None => None,
+                };
+                Some(VerityReport {
+                    table,
+                    root_hash_matches,
+                })
+            }
+            None => None,
+        };
+
+        let mut os_release = image.os_release()?;
+        let os_release = OsRelease {
+            version_id: os_release.remove("VERSION_ID"),
+            build_id: os_release.remove("BUILD_ID"),
+            variant_id: os_release.remove("VARIANT_ID"),
+        };
+
+        let record = VariantRecord::load(image.dir())?;
+        let mut mismatches = Vec::new();
+        if let Some(record) = &record {
+            mismatches = record.mismatches(image.size(), image.gpt());
+            if let Some(variant_id) = &os_release.variant_id {
+                if *variant_id != record.variant {
+                    mismatches.push(format!(
+                        "The image is of variant '{variant_id}', but variant.json is for '{}'",
+                        record.variant
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            path: image.path().to_path_buf(),
+            size: image.size(),
+            partitions: image.gpt().partitions.clone(),
+            kernel_parameters: grub_config.kernel_parameters,
+            dm_verity,
+            os_release,
+            image_layout: record.as_ref().map(|record| record.image_layout),
+            variant_partitions: record.and_then(|record| record.partitions),
+            mismatches,
+        })
+    }
+}
+
+impl Display for ImageReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Image: {}", self.path.display())?;
+        writeln!(f, "Size: {}", bytes(self.size))?;
+
+        writeln!(f, "Partitions:")?;
+        for partition in &self.partitions {
+            writeln!(
+                f,
+                "  {:>2}  {:>10}  {:>10}  {}",
+                partition.number,
+                partition.first_lba,
+                bytes(partition.size()),
+                partition.name
+            )?;
+        }
+
+        writeln!(f, "Kernel parameters:")?;
+        writeln!(f, "  {}", self.kernel_parameters.join(" "))?;
+
+        writeln!(f, "dm-verity:")?;
+        match &self.dm_verity {
+            Some(verity) => {
+                writeln!(f, "  Root hash: {}", verity.table.root_hash)?;
+                writeln!(f, "  Salt: {}", verity.table.salt)?;
+                writeln!(
+                    f,
+                    "  Data: {} blocks of {} bytes",
+                    verity.table.data_blocks, verity.table.data_block_size
+                )?;
+                match verity.root_hash_matches {
+                    Some(true) => writeln!(f, "  The root hash matches the hash tree")?,
+                    Some(false) => writeln!(f, "  The root hash does NOT match the hash tree")?,
+                    None => writeln!(f, "  The image has no {HASH_PARTITION} partition")?,
+                }
+            }
+            None => writeln!(f, "  The kernel command line sets up no dm-verity table")?,
+        }
+
+        writeln!(f, "os-release:")?;
+        for (name, value) in [
+            ("VERSION_ID", &self.os_release.version_id),
+            ("BUILD_ID", &self.os_release.build_id),
+            ("VARIANT_ID", &self.os_release.variant_id),
+        ] {
+            writeln!(f, "  {name}={}", value.as_deref().unwrap_or("(unset)"))?;
+        }
+
+        writeln!(f, "Image layout:")?;
+        let Some(layout) = &self.image_layout else {
+            return writeln!(
+                f,
+                "  Unknown, the build output of the image has no variant.json"
+            );
+        };
+        writeln!(
+            f,
+            "  os-image-size-gib {}, data-image-size-gib {}, partition-plan {}",
+            layout.os_image_size_gib, layout.data_image_size_gib, layout.partition_plan
+        )?;
+        if let Some(partitions) = &self.variant_partitions {
+            writeln!(
+                f,
+                "  The variant lists its own {} partitions instead",
+                partitions.len()
+            )?;
+        }
+        if self.mismatches.is_empty() {
+            writeln!(f, "  The image matches the variant")?;
+        }
+        for mismatch in &self.mismatches {
+            writeln!(f, "  ! {mismatch}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a size in MiB when it is a whole number of them, which partition sizes usually are.
+fn bytes(size: u64) -> String {
+    const MIB: u64 = 1024 * 1024;
+    match size % MIB {
+        0 => format!("{} MiB", size / MIB),
+        _ => format!("{size} B"),
+    }
+}
+
+#[tokio::test]
+async fn test_inspect_image() {
+    use crate::image::{test_image, TestNode};
+
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let dir = tempdir.path();
+    std::fs::write(
+        dir.join("bottlerocket-aws-dev-x86_64.img"),
+        test_image(
+            "set dm_verity_root=\"root,,,ro,0 16 verity 1 PARTUUID=$boot_uuid/PARTNROFF=1 \
+            PARTUUID=$boot_uuid/PARTNROFF=2 4096 4096 2 1 sha256 abcd 1234 1 ignore_zero_blocks\"\n\
+            linux ($root)/vmlinuz console=tty0 dm-mod.create=\"$dm_verity_root\"\n",
+            &[
+                ("etc", TestNode::Directory),
+                (
+                    "etc/os-release",
+                    TestNode::File(b"VARIANT_ID=aws-k8s-1.30\nVERSION_ID=1.0.0\n"),
+                ),
+            ],
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("variant.json"),
+        r#"{
+            "variant": "aws-dev",
+            "image-features": [],
+            "kernel-parameters": [],
+            "image-layout": {
+                "os-image-size-gib": 2,
+                "data-image-size-gib": 1,
+                "publish-image-size-hint-gib": 22,
+                "partition-plan": "split"
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let path = dir.to_path_buf();
+    let report = spawn_blocking(move || ImageReport::new(&path))
+        .await
+        .unwrap()
+        .unwrap();
+    let verity = report.dm_verity.as_ref().unwrap();
+    assert_eq!(verity.table.root_hash, "abcd");
+    assert_eq!(verity.root_hash_matches, None);
+    assert_eq!(report.os_release.version_id.as_deref(), Some("1.0.0"));
+    assert_eq!(report.os_release.build_id, None);
+    assert_eq!(
+        report.kernel_parameters,
+        ["console=tty0", "dm-mod.create=$dm_verity_root"]
+    );
+    // The test image has only a boot and a root partition, and is for a different variant.
+    assert_eq!(report.mismatches.len(), 10);
+    assert!(report.mismatches[0]
+        .ends_with("but os-image-size-gib 2 with the split partition plan makes it 2048 MiB"));
+    assert_eq!(
+        report.mismatches.last().unwrap(),
+        "The image is of variant 'aws-k8s-1.30', but variant.json is for 'aws-dev'"
+    );
+
+    let human = report.to_string();
+    assert!(human.contains("  Root hash: abcd\n"));
+    assert!(human.contains("  VERSION_ID=1.0.0\n  BUILD_ID=(unset)\n"));
+    assert!(human.contains("  ! The image has 2 partitions, but the split partition plan has 13\n"));
+}
@@ -0,0 +1,281 @@
+use crate::cargo_make::CargoMake;
+use crate::common::fs;
+use crate::licenses::{self, AllowList};
+use crate::lock::Lock;
+use crate::project;
+use crate::tools::{install_tools, ToolOverrides};
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::info;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use tokio::task::spawn_blocking;
+
+/// Generate the license attribution bundle of a variant, in `build/attributions`. The licenses of
+/// the variant's packages and of the Rust sources must be allowed by `sources/deny.toml`. The
+/// sources of the packages must have been fetched, such as by building the variant.
+#[derive(Debug, Parser)]
+pub(crate) struct Licenses {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The architecture of the SDK that scans the sources.
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// A license to allow for packages in addition to the ones that `sources/deny.toml` allows,
+    /// as an SPDX identifier. Can be given more than once.
+    #[clap(long = "allow")]
+    allow: Vec<String>,
+
+    /// The variant to generate the attribution bundle for.
+    variant: String,
+}
+
+impl Licenses {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let project_dir = project.project_dir();
+        ensure!(
+            project_dir.join("variants").join(&self.variant).is_dir(),
+            "There is no variant '{}' in '{}'",
+            self.variant,
+            project_dir.join("variants").display()
+        );
+
+        let deny_toml = project_dir.join("sources").join("deny.toml");
+        let mut allow_list = if deny_toml.is_file() {
+            AllowList::from_deny_toml(&fs::read_to_string(&deny_toml).await?)
+                .context(format!("Unable to load '{}'", deny_toml.display()))?
+        } else {
+            AllowList::default()
+        };
+        for license in &self.allow {
+            allow_list.allow(license);
+        }
+
+        let packages = licenses::variant_packages(&project_dir, &self.variant).await?;
+        let disallowed = licenses::check_licenses(&packages, &allow_list)?;
+        ensure!(
+            disallowed.is_empty(),
+            "Packages of '{}' use licenses that are not allowed by '{}' or --allow:\n{}",
+            self.variant,
+            deny_toml.display(),
+            disallowed
+                .iter()
+                .map(|(package, licenses)| format!(
+                    "    {package}: {}",
+                    licenses.iter().cloned().collect::<Vec<_>>().join(", ")
+                ))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+
+        let packages_dir = project_dir.join("packages");
+        for package in &packages {
+            for archive in &package.archives {
+                ensure!(
+                    packages_dir.join(&archive.path).is_file(),
+                    "Unable to find '{}' for package '{}'. Build the variant first to fetch it.",
+                    archive.path.display(),
+                    package.name
+                );
+            }
+            for file in &package.other_files {
+                info!(
+                    "Not scanning '{}' of package '{}' for license files, since it is not a \
+                    tarball or a source RPM",
+                    file.display(),
+                    package.name
+                );
+            }
+        }
+
+        let toolsdir = project_dir.join("build/tools");
+        let _tools = install_tools(
+            &toolsdir,
+            &ToolOverrides::resolve(project.tool_overrides())?,
+        )
+        .await?;
+        let makefile_path = toolsdir.join("Makefile.toml");
+        let cargo_make = match Lock::load_current(&project).await? {
+            Some(lock) => CargoMake::with_sdk(lock.sdk_image().await?),
+            None => CargoMake::new(&project)?,
+        };
+
+        // The SDK container writes the license texts to a directory in the project, which Twoliter
+        // then moves into the bundle.
+        let scan_dir = TempDir::new_in(&project_dir)
+            .context("Unable to create a tempdir for the license scan")?;
+        fs::write(
+            scan_dir.path().join("archives.txt"),
+            licenses::archive_list(&packages),
+        )
+        .await?;
+        let scan_rust = packages.iter().any(|package| package.uses_sources);
+        cargo_make
+            .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
+            .env("BUILDSYS_ARCH", &self.arch)
+            .env("BUILDSYS_VARIANT", &self.variant)
+            .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+            .env(
+                "BUILDSYS_RELEASE_CONFIG_PATH",
+                project.release_config().await?.display().to_string(),
+            )
+            .env("GO_MODULES", project.find_go_modules().await?.join(" "))
+            .env(
+                "TLPRIVATE_LICENSES_DIR",
+                scan_dir.path().display().to_string(),
+            )
+            .env("TLPRIVATE_LICENSES_SCAN_RUST", scan_rust.to_string())
+            .envs(project.build_settings().resolve()?.env_vars().into_iter())
+            .makefile(makefile_path)
+            .project_dir(&project_dir)
+            .exec("scan-licenses")
+            .await?;
+
+        let attributions_dir = project_dir.join("build").join("attributions");
+        let bundle_dir = attributions_dir.join(&self.variant);
+        fs::remove_dir_all(&bundle_dir).await?;
+        fs::create_dir_all(&bundle_dir).await?;
+        for section in ["rust", "go", "packages"] {
+            let scanned = scan_dir.path().join(section);
+            if scanned.is_dir() {
+                fs::rename(&scanned, bundle_dir.join(section)).await?;
+            }
+        }
+        let document = licenses::attribution(&self.variant, &packages, &bundle_dir).await?;
+        fs::write(bundle_dir.join("attribution.txt"), document).await?;
+
+        let tarball = attributions_dir.join(format!("{}.tar.gz", self.variant));
+        write_tarball(&bundle_dir, &tarball, &self.variant).await?;
+        info!(
+            "Wrote the attributions for '{}' to '{}'",
+            self.variant,
+            tarball.display()
+        );
+        Ok(())
+    }
+}
+
+/// Archive the bundle in `dir` as `tarball`, under the directory `name`.
+async fn write_tarball(dir: &Path, tarball: &Path, name: &str) -> Result<()> {
+    let dir = dir.to_path_buf();
+    let tarball = tarball.to_path_buf();
+    let name = name.to_string();
+    spawn_blocking(move || {
+        let file = std::fs::File::create(&tarball)
+            .context(format!("Unable to create '{}'", tarball.display()))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        builder
+            .append_dir_all(&name, &dir)
+            .and_then(|()| builder.into_inner())
+            .and_then(|encoder| encoder.finish())
+            .context(format!("Unable to write '{}'", tarball.display()))?;
+        Ok(())
+    })
+    .await
+    .context("Unable to run and join async task for writing the attributions")?
+}
+
+/// Write a project with the variant `aws-dev`, which includes the package `hello` with the given
+/// `License` tag. The external file of `hello` has not been fetched.
+#[cfg(test)]
+fn test_project(dir: &Path, license: &str, deny_toml: &str) -> PathBuf {
+    let files = [
+        (
+            "Twoliter.toml",
+            "schema-version = 1\nrelease-version = \"1.0.0\"\n\n[sdk]\nregistry = \"a.com/b\"\n\
+            repo = \"my-bottlerocket-sdk\"\ntag = \"v1.2.3\"\n"
+                .to_string(),
+        ),
+        ("sources/deny.toml", deny_toml.to_string()),
+        (
+            "variants/Cargo.lock",
+            "version = 3\n\n[[package]]\nname = \"aws-dev\"\nversion = \"0.1.0\"\n\
+            dependencies = [\"hello\"]\n\n[[package]]\nname = \"hello\"\nversion = \"0.1.0\"\n"
+                .to_string(),
+        ),
+        ("variants/aws-dev/Cargo.toml", String::new()),
+        (
+            "packages/hello/Cargo.toml",
+            "[package]\nname = \"hello\"\nversion = \"0.1.0\"\n\n\
+            [[package.metadata.build-package.external-files]]\n\
+            url = \"https://example.com/hello-1.0.tar.gz\"\nsha512 = \"abcdef\"\n"
+                .to_string(),
+        ),
+        (
+            "packages/hello/hello.spec",
+            format!("Name: %{{_cross_os}}hello\nVersion: 1.0\nLicense: {license}\n"),
+        ),
+    ];
+    for (path, contents) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    dir.join("Twoliter.toml")
+}
+
+#[cfg(test)]
+fn test_licenses(project_path: PathBuf, allow: &[&str]) -> Licenses {
+    Licenses {
+        project_path: Some(project_path),
+        arch: "x86_64".to_string(),
+        allow: allow.iter().map(|license| license.to_string()).collect(),
+        variant: "aws-dev".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_licenses_disallowed() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let project_path = test_project(
+        tempdir.path(),
+        "GPL-3.0-only WITH GCC-exception-3.1 AND MIT",
+        "[licenses]\nallow = [\"MIT\"]\n",
+    );
+
+    let error = test_licenses(project_path.clone(), &[])
+        .run()
+        .await
+        .unwrap_err();
+    let message = format!("{error:#}");
+    assert!(
+        message.starts_with("Packages of 'aws-dev' use licenses that are not allowed by"),
+        "{message}"
+    );
+    assert!(message.ends_with("\n    hello: GPL-3.0-only"), "{message}");
+    assert!(!tempdir.path().join("build/attributions").exists());
+
+    // A license that is allowed with --allow gets as far as looking for the package's sources.
+    let error = test_licenses(project_path, &["GPL-3.0-only"])
+        .run()
+        .await
+        .unwrap_err();
+    assert!(format!("{error:#}").starts_with("Unable to find 'hello/hello-1.0.tar.gz'"));
+}
+
+#[tokio::test]
+async fn test_licenses_missing_archive() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let project_path = test_project(tempdir.path(), "MIT", "[licenses]\nallow = [\"MIT\"]\n");
+
+    let error = test_licenses(project_path.clone(), &[])
+        .run()
+        .await
+        .unwrap_err();
+    assert_eq!(
+        format!("{error:#}"),
+        "Unable to find 'hello/hello-1.0.tar.gz' for package 'hello'. Build the variant first to \
+        fetch it."
+    );
+
+    let mut licenses = test_licenses(project_path, &[]);
+    licenses.variant = "metal-dev".to_string();
+    let error = licenses.run().await.unwrap_err();
+    assert!(format!("{error:#}").starts_with("There is no variant 'metal-dev' in"));
+}
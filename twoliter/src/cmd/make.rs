@@ -1,6 +1,8 @@
 use crate::cargo_make::CargoMake;
+use crate::common::fs;
+use crate::lock::Lock;
 use crate::project::{self};
-use crate::tools::install_tools;
+use crate::tools::{install_tools, ToolOverrides};
 use anyhow::Result;
 use clap::Parser;
 use std::path::PathBuf;
@@ -25,6 +27,12 @@ pub(crate) struct Make {
     #[clap(long, env = "BUILDSYS_ARCH")]
     arch: String,
 
+    /// Run cargo make in a container instead of on the host, so that it does not need to be
+    /// installed. The project must not be in a directory that the container needs for itself, such
+    /// as `/usr`.
+    #[clap(long = "in-container")]
+    in_container: bool,
+
     /// Cargo make task. E.g. the word "build" if we want to execute `cargo make build`.
     makefile_task: String,
 
@@ -37,12 +45,32 @@ impl Make {
     pub(super) async fn run(&self) -> Result<()> {
         let project = project::load_or_find_project(self.project_path.clone()).await?;
         let toolsdir = project.project_dir().join("build/tools");
-        install_tools(&toolsdir).await?;
+        let _tools = install_tools(
+            &toolsdir,
+            &ToolOverrides::resolve(project.tool_overrides())?,
+        )
+        .await?;
         let makefile_path = toolsdir.join("Makefile.toml");
-        CargoMake::new(&project)?
+        // Builds must use the pinned SDK if the project has a lock file.
+        let cargo_make = match Lock::load_current(&project).await? {
+            Some(lock) => CargoMake::with_sdk(lock.sdk_image().await?),
+            None => CargoMake::new(&project)?,
+        };
+        if self.in_container {
+            // Docker would create a missing directory to mount, but it would belong to root.
+            fs::create_dir_all(&self.cargo_home).await?;
+        }
+        cargo_make
+            .in_container(self.in_container)
+            .mount(&self.cargo_home)
             .env("CARGO_HOME", self.cargo_home.display().to_string())
+            .envs(project.build_settings().resolve()?.env_vars().into_iter())
             .env("TWOLITER_TOOLS_DIR", toolsdir.display().to_string())
             .env("BUILDSYS_VERSION_IMAGE", project.release_version())
+            .env(
+                "BUILDSYS_RELEASE_CONFIG_PATH",
+                project.release_config().await?.display().to_string(),
+            )
             .makefile(makefile_path)
             .project_dir(project.project_dir())
             .exec_with_args(&self.makefile_task, self.additional_args.clone())
@@ -0,0 +1,337 @@
+use crate::common::fs;
+use crate::project::{self, sdk_version, vendor_name, CURRENT_SCHEMA_VERSION};
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::info;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use toml_edit::{value, DocumentMut, Item, Key, Table, TableLike, Value};
+
+/// Migrate Twoliter.toml to the newest schema version, keeping its comments and formatting. The
+/// migrations in the deprecated Release.toml are moved into Twoliter.toml, and Release.toml is
+/// removed.
+#[derive(Debug, Parser)]
+pub(crate) struct Migrate {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The name of the project. Defaults to the name of the project directory.
+    #[clap(long = "project-name")]
+    project_name: Option<String>,
+
+    /// Print the migrated Twoliter.toml instead of writing it.
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+}
+
+impl Migrate {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let path = project.filepath();
+        if project.schema_version() == CURRENT_SCHEMA_VERSION {
+            info!(
+                "'{}' already uses schema version {CURRENT_SCHEMA_VERSION}",
+                path.display()
+            );
+            return Ok(());
+        }
+
+        let release_toml = project.project_dir().join("Release.toml");
+        let release = if release_toml.is_file() {
+            Some(fs::read_to_string(&release_toml).await?)
+        } else {
+            None
+        };
+        let project_name = self.project_name.clone().unwrap_or_else(|| project.name());
+        let migrated = migrate_v1(
+            &fs::read_to_string(&path).await?,
+            &project_name,
+            release.as_deref(),
+        )?;
+        project::check_project_file(&migrated)
+            .context("The migrated project file is not valid, this is a bug in Twoliter")?;
+
+        if self.dry_run {
+            println!("{migrated}");
+            return Ok(());
+        }
+        fs::write(&path, migrated).await?;
+        if release.is_some() {
+            fs::remove_file(&release_toml).await?;
+        }
+        info!(
+            "Migrated '{}' to schema version {CURRENT_SCHEMA_VERSION}",
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Migrate a schema version 1 project file to schema version 2:
+/// - `project-name` is added after `schema-version`.
+/// - The registries of the SDK and kits become `[vendor.<name>]` tables, which the SDK and kits
+///   refer to by name.
+/// - The SDK is referred to by name and version, like kits, instead of by repository and tag.
+/// - The `[migrations]` table of `release`, the contents of `Release.toml`, is moved over.
+fn migrate_v1(data: &str, project_name: &str, release: Option<&str>) -> Result<String> {
+    let mut doc: DocumentMut = data.parse().context("Unable to parse the project file")?;
+    let mut vendors = Vendors::default();
+
+    if let Some(sdk) = doc.get_mut("sdk") {
+        let sdk = sdk.as_table_like_mut().context("The SDK is not a table")?;
+        rewrite_keys(sdk, |key, item| {
+            Ok(match key.get().to_string().as_str() {
+                "registry" => (rename(&key, "vendor"), vendors.vendor_of(&item)?),
+                "repo" => (rename(&key, "name"), item),
+                "tag" => {
+                    let tag = item.as_str().context("The SDK tag is not a string")?;
+                    let version = sdk_version(tag)?.to_string();
+                    (rename(&key, "version"), replace_value(&item, version))
+                }
+                _ => (key, item),
+            })
+        })?;
+    }
+    if let Some(kits) = doc.get_mut("kit") {
+        let kits = kits
+            .as_array_of_tables_mut()
+            .context("The kits are not an array of tables")?;
+        for kit in kits.iter_mut() {
+            rewrite_keys(kit, |key, item| {
+                Ok(match key.get().to_string().as_str() {
+                    "registry" => (rename(&key, "vendor"), vendors.vendor_of(&item)?),
+                    "repo" => (rename(&key, "name"), item),
+                    _ => (key, item),
+                })
+            })?;
+        }
+    }
+
+    // The vendor tables go right before the first table that uses them.
+    let position = ["sdk", "kit"]
+        .iter()
+        .filter_map(|key| doc.get(key))
+        .find_map(|item| match item {
+            Item::Table(table) => table.position(),
+            Item::ArrayOfTables(tables) => tables.get(0).and_then(Table::position),
+            _ => None,
+        });
+    let root = doc.as_table_mut();
+    let mut entries = Vec::new();
+    let mut vendor_table = Some(vendors.into_table(position));
+    for (key, item) in take_entries(root) {
+        let name = key.get().to_string();
+        if name == "sdk" || name == "kit" {
+            entries.extend(vendor_table.take().map(|table| (Key::new("vendor"), table)));
+        }
+        if name == "schema-version" {
+            entries.push((
+                key.clone(),
+                replace_value(&item, CURRENT_SCHEMA_VERSION as i64),
+            ));
+            entries.push((Key::new("project-name"), value(project_name)));
+        } else {
+            entries.push((key, item));
+        }
+    }
+    entries.extend(vendor_table.map(|table| (Key::new("vendor"), table)));
+    for (key, item) in entries {
+        root.insert_formatted(&key, item);
+    }
+
+    if let Some(release) = release {
+        let mut release: DocumentMut = release.parse().context("Unable to parse Release.toml")?;
+        if let Some(mut migrations) = release.remove("migrations") {
+            if let Item::Table(table) = &mut migrations {
+                // Positions in Release.toml mean nothing in Twoliter.toml, put it at the end.
+                table.set_position(usize::MAX);
+            }
+            root.insert("migrations", migrations);
+        }
+    }
+    Ok(doc.to_string())
+}
+
+/// The vendors found while migrating, named after their registries.
+#[derive(Debug, Default)]
+struct Vendors {
+    by_registry: BTreeMap<String, String>,
+}
+
+impl Vendors {
+    /// Returns the name of the vendor for the registry `item`, adding a vendor for it if needed.
+    fn vendor_of(&mut self, item: &Item) -> Result<Item> {
+        let registry = item.as_str().context("The registry is not a string")?;
+        let name = match self.by_registry.get(registry) {
+            Some(name) => name.clone(),
+            None => {
+                let base = vendor_name(registry);
+                let mut name = base.clone();
+                let mut n = 1;
+                while self.by_registry.values().any(|taken| *taken == name) {
+                    n += 1;
+                    name = format!("{base}-{n}");
+                }
+                self.by_registry.insert(registry.to_string(), name.clone());
+                name
+            }
+        };
+        Ok(replace_value(item, name))
+    }
+
+    /// The `[vendor.<name>]` tables, placed at `position` in the document.
+    fn into_table(self, position: Option<usize>) -> Item {
+        let mut vendors = Table::new();
+        vendors.set_implicit(true);
+        for (registry, name) in self.by_registry {
+            let mut vendor = Table::new();
+            vendor.insert("registry", value(registry));
+            if let Some(position) = position {
+                vendor.set_position(position);
+            }
+            vendors.insert(&name, Item::Table(vendor));
+        }
+        Item::Table(vendors)
+    }
+}
+
+/// Rewrite each key and value of `table`, keeping their order.
+fn rewrite_keys<F>(table: &mut dyn TableLike, mut rewrite: F) -> Result<()>
+where
+    F: FnMut(Key, Item) -> Result<(Key, Item)>,
+{
+    for (key, item) in take_entries(table) {
+        let (key, item) = rewrite(key, item)?;
+        table.entry_format(&key).or_insert(item);
+    }
+    Ok(())
+}
+
+/// Remove and return the entries of `table`, keys with their formatting, in order.
+fn take_entries(table: &mut dyn TableLike) -> Vec<(Key, Item)> {
+    let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+    let entries = keys
+        .iter()
+        .filter_map(|key| table.get_key_value(key))
+        .map(|(key, item)| (key.clone(), item.clone()))
+        .collect();
+    table.clear();
+    entries
+}
+
+/// A key named `name` with the comments and whitespace of `key`.
+fn rename(key: &Key, name: &str) -> Key {
+    Key::new(name).with_leaf_decor(key.leaf_decor().clone())
+}
+
+/// A new value for `item`, keeping its comments and whitespace.
+fn replace_value(item: &Item, new: impl Into<Value>) -> Item {
+    let mut new = new.into();
+    if let Some(old) = item.as_value() {
+        *new.decor_mut() = old.decor().clone();
+    }
+    Item::Value(new)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const V1: &str = r#"# My project
+schema-version = 1
+release-version = "1.0.0" # bumped for each release
+
+# The SDK to build with
+[sdk]
+registry = "public.ecr.aws/bottlerocket"
+repo = "bottlerocket-sdk"
+tag = "v0.50.0" # the newest
+
+[[kit]]
+registry = "public.ecr.aws/bottlerocket"
+repo = "bottlerocket-core-kit"
+version = "1.15.1"
+
+[[kit]]
+repo = "my-kit"
+version = "0.1.0"
+"#;
+
+    const RELEASE: &str = r#"version = "1.0.0"
+
+# Migrations for each release
+[migrations]
+"(0.9.0, 1.0.0)" = ["migrate_v1.0.0_my-migration.lz4"]
+"#;
+
+    #[test]
+    fn migrate_project() {
+        let migrated = migrate_v1(V1, "my-project", Some(RELEASE)).unwrap();
+        assert_eq!(
+            migrated,
+            r#"# My project
+schema-version = 2
+project-name = "my-project"
+release-version = "1.0.0" # bumped for each release
+
+[vendor.bottlerocket]
+registry = "public.ecr.aws/bottlerocket"
+
+# The SDK to build with
+[sdk]
+vendor = "bottlerocket"
+name = "bottlerocket-sdk"
+version = "0.50.0" # the newest
+
+[[kit]]
+vendor = "bottlerocket"
+name = "bottlerocket-core-kit"
+version = "1.15.1"
+
+[[kit]]
+name = "my-kit"
+version = "0.1.0"
+
+# Migrations for each release
+[migrations]
+"(0.9.0, 1.0.0)" = ["migrate_v1.0.0_my-migration.lz4"]
+"#
+        );
+        project::check_project_file(&migrated).unwrap();
+    }
+
+    #[test]
+    fn migrate_vendor_names() {
+        let data = r#"schema-version = 1
+release-version = "1.0.0"
+
+[sdk]
+registry = "a.com/bottlerocket"
+repo = "bottlerocket-sdk"
+tag = "v0.50.0"
+
+[[kit]]
+registry = "b.com/bottlerocket"
+repo = "my-kit"
+version = "0.1.0"
+"#;
+        let migrated = migrate_v1(data, "my-project", None).unwrap();
+        let migrated: toml::Table = toml::from_str(&migrated).unwrap();
+        assert_eq!(migrated["sdk"]["vendor"].as_str(), Some("bottlerocket"));
+        assert_eq!(
+            migrated["kit"][0]["vendor"].as_str(),
+            Some("bottlerocket-2")
+        );
+        assert_eq!(
+            migrated["vendor"]["bottlerocket-2"]["registry"].as_str(),
+            Some("b.com/bottlerocket")
+        );
+    }
+
+    #[test]
+    fn migrate_bad_sdk_tag() {
+        let data = V1.replace("v0.50.0", "latest");
+        assert!(migrate_v1(&data, "my-project", None).is_err());
+    }
+}
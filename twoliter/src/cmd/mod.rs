@@ -1,11 +1,29 @@
 mod build;
 mod build_clean;
 mod debug;
+mod diff_image;
+mod doctor;
+mod inspect;
+mod licenses;
 mod make;
+mod migrate;
+mod new;
+mod publish;
+mod testsys;
+mod update;
 
 use self::build::BuildCommand;
 use crate::cmd::debug::DebugAction;
+use crate::cmd::diff_image::DiffImage;
+use crate::cmd::inspect::InspectCommand;
+use crate::cmd::licenses::Licenses;
 use crate::cmd::make::Make;
+use crate::cmd::migrate::Migrate;
+use crate::cmd::new::NewCommand;
+use crate::cmd::publish::PublishCommand;
+use crate::cmd::testsys::TestCommand;
+use crate::cmd::update::Update;
+use crate::tools;
 use anyhow::Result;
 use clap::Parser;
 use env_logger::Builder;
@@ -23,6 +41,11 @@ pub(crate) struct Args {
     #[clap(long = "log-level")]
     pub(crate) log_level: Option<LevelFilter>,
 
+    /// Reinstall the tools in the project's build directory even if they are up to date. Twoliter
+    /// normally only reinstalls them when they differ from the ones it embeds.
+    #[clap(long = "force-reinstall")]
+    pub(crate) force_reinstall: bool,
+
     #[clap(subcommand)]
     pub(crate) subcommand: Subcommand,
 }
@@ -33,8 +56,36 @@ pub(crate) enum Subcommand {
     #[clap(subcommand)]
     Build(BuildCommand),
 
+    /// Compare two variant images, such as the packages, files and kernel parameters they have.
+    DiffImage(DiffImage),
+
+    /// Inspect the output of a variant build, such as the packages installed in its images.
+    #[clap(subcommand)]
+    Inspect(InspectCommand),
+
+    /// Generate the license attribution bundle of a variant, and check its licenses.
+    Licenses(Licenses),
+
     Make(Make),
 
+    /// Migrate Twoliter.toml to the newest schema version.
+    Migrate(Migrate),
+
+    /// Create a new project, or add something new to an existing project.
+    #[clap(subcommand)]
+    New(NewCommand),
+
+    /// Publish a variant build, such as to a TUF repository, an AMI or a VMware datacenter.
+    #[clap(subcommand)]
+    Publish(PublishCommand),
+
+    /// Run and watch tests of a variant build in a testsys cluster.
+    #[clap(subcommand)]
+    Test(TestCommand),
+
+    /// Update the SDK and kits in Twoliter.lock to the newest versions allowed by Twoliter.toml.
+    Update(Update),
+
     /// Commands that are used for checking and troubleshooting Twoliter's internals.
     #[clap(subcommand)]
     Debug(DebugAction),
@@ -42,9 +93,20 @@ pub(crate) enum Subcommand {
 
 /// Entrypoint for the `twoliter` command line program.
 pub(super) async fn run(args: Args) -> Result<()> {
+    if args.force_reinstall {
+        tools::force_reinstall();
+    }
     match args.subcommand {
         Subcommand::Build(build_command) => build_command.run().await,
+        Subcommand::DiffImage(diff_args) => diff_args.run().await,
+        Subcommand::Inspect(inspect_command) => inspect_command.run().await,
+        Subcommand::Licenses(licenses_args) => licenses_args.run().await,
         Subcommand::Make(make_args) => make_args.run().await,
+        Subcommand::Migrate(migrate_args) => migrate_args.run().await,
+        Subcommand::New(new_command) => new_command.run().await,
+        Subcommand::Publish(publish_command) => publish_command.run().await,
+        Subcommand::Test(test_command) => test_command.run().await,
+        Subcommand::Update(update_args) => update_args.run().await,
         Subcommand::Debug(debug_action) => debug_action.run().await,
     }
 }
@@ -0,0 +1,94 @@
+use super::{
+    check_crate_name, path_dependencies, render, templates, toml_list, write_files, KITS, PACKAGES,
+};
+use crate::project;
+use anyhow::Result;
+use clap::Parser;
+use log::info;
+use std::path::{Path, PathBuf};
+
+/// Add a new kit to an existing project.
+#[derive(Debug, Parser)]
+pub(crate) struct NewKit {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The name of the kit.
+    #[clap(long)]
+    name: String,
+
+    /// The packages to include in the kit.
+    #[clap(long, value_delimiter = ',')]
+    packages: Vec<String>,
+}
+
+impl NewKit {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let project_dir = project.project_dir();
+        check_crate_name(&self.name)?;
+        for package in &self.packages {
+            PACKAGES.check_crate(&project_dir, package)?;
+        }
+
+        let mut files = KITS.missing_shim_files(&project_dir);
+        files.push((
+            Path::new(KITS.dir).join(&self.name).join("Cargo.toml"),
+            render(
+                templates::KIT_MANIFEST,
+                &[
+                    ("name", &self.name),
+                    ("packages", &toml_list(&self.packages)),
+                    (
+                        "build_dependencies",
+                        &path_dependencies(PACKAGES.dir, &self.packages),
+                    ),
+                ],
+            ),
+        ));
+        write_files(&project_dir, &files).await?;
+        KITS.add_member(&project_dir, &self.name).await?;
+
+        info!("Created kit '{}'", self.name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::{new_project, read_toml};
+    use super::*;
+
+    #[tokio::test]
+    async fn new_kit() {
+        let project = new_project().await;
+        let dir = project.path();
+        NewKit::try_parse_from([
+            "kit",
+            "--project-path",
+            dir.join("Twoliter.toml").to_str().unwrap(),
+            "--name",
+            "my-kit",
+            "--packages",
+            "hello-agent",
+        ])
+        .unwrap()
+        .run()
+        .await
+        .unwrap();
+
+        let manifest = read_toml(dir, "kits/my-kit/Cargo.toml").await;
+        assert_eq!(
+            manifest["package"]["metadata"]["build-kit"]["included-packages"]
+                .as_array()
+                .unwrap()[0]
+                .as_str(),
+            Some("hello-agent")
+        );
+        assert_eq!(
+            manifest["build-dependencies"]["hello-agent"]["path"].as_str(),
+            Some("../../packages/hello-agent")
+        );
+    }
+}
@@ -0,0 +1,315 @@
+mod kit;
+mod package;
+mod project;
+mod templates;
+mod variant;
+
+use self::kit::NewKit;
+use self::package::NewPackage;
+use self::project::NewProject;
+use self::variant::NewVariant;
+use crate::common::{exec_log, fs};
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use log::debug;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use toml_edit::DocumentMut;
+
+/// Create a new project, or add something new to an existing project.
+#[derive(Debug, Parser)]
+pub(crate) enum NewCommand {
+    Project(NewProject),
+    Package(NewPackage),
+    Kit(NewKit),
+    Variant(NewVariant),
+}
+
+impl NewCommand {
+    pub(crate) async fn run(self) -> Result<()> {
+        match self {
+            NewCommand::Project(command) => command.run().await,
+            NewCommand::Package(command) => command.run().await,
+            NewCommand::Kit(command) => command.run().await,
+            NewCommand::Variant(command) => command.run().await,
+        }
+    }
+}
+
+/// One of the Cargo workspaces in a project whose crates are built by buildsys.
+#[derive(Debug, Clone, Copy)]
+struct Workspace {
+    /// The workspace directory, relative to the project directory.
+    dir: &'static str,
+    /// The buildsys command that the workspace's `build.rs` calls.
+    command: &'static str,
+    /// What the crates in the workspace are called in its empty `lib.rs`.
+    kind: &'static str,
+}
+
+const PACKAGES: Workspace = Workspace {
+    dir: "packages",
+    command: "build-package",
+    kind: "package",
+};
+
+const KITS: Workspace = Workspace {
+    dir: "kits",
+    command: "build-kit",
+    kind: "kit",
+};
+
+const VARIANTS: Workspace = Workspace {
+    dir: "variants",
+    command: "build-variant",
+    kind: "variant",
+};
+
+impl Workspace {
+    /// The `build.rs` and empty `lib.rs` shared by the crates in the workspace.
+    fn shim_files(&self) -> Vec<(PathBuf, String)> {
+        let dir = Path::new(self.dir);
+        vec![
+            (
+                dir.join("build.rs"),
+                render(templates::BUILD_RS, &[("command", self.command)]),
+            ),
+            (
+                dir.join(format!("{}.rs", self.dir)),
+                render(templates::EMPTY_LIB, &[("kind", self.kind)]),
+            ),
+        ]
+    }
+
+    /// The shim files that do not exist yet in `project_dir`, e.g. when adding the first kit to a
+    /// project that does not have any.
+    fn missing_shim_files(&self, project_dir: &Path) -> Vec<(PathBuf, String)> {
+        self.shim_files()
+            .into_iter()
+            .filter(|(path, _)| !project_dir.join(path).exists())
+            .collect()
+    }
+
+    /// Make sure that `name` is a crate in this workspace.
+    fn check_crate(&self, project_dir: &Path, name: &str) -> Result<()> {
+        let manifest = project_dir.join(self.dir).join(name).join("Cargo.toml");
+        ensure!(
+            manifest.is_file(),
+            "There is no {} named '{name}', '{}' does not exist",
+            self.kind,
+            manifest.display()
+        );
+        Ok(())
+    }
+
+    /// Add `member` to the members of the workspace's `Cargo.toml`, keeping the rest of the file as
+    /// it is. Projects are not required to make every directory a Cargo workspace, so there is
+    /// nothing to do when there is no workspace `Cargo.toml`.
+    async fn add_member(&self, project_dir: &Path, member: &str) -> Result<()> {
+        let workspace_dir = project_dir.join(self.dir);
+        let path = workspace_dir.join("Cargo.toml");
+        if !path.is_file() {
+            debug!(
+                "There is no '{}', not adding '{member}' to a workspace",
+                path.display()
+            );
+            return Ok(());
+        }
+        let mut manifest: DocumentMut = fs::read_to_string(&path)
+            .await?
+            .parse()
+            .context(format!("Unable to parse '{}'", path.display()))?;
+        let members = manifest
+            .get_mut("workspace")
+            .and_then(|workspace| workspace.get_mut("members"))
+            .and_then(|members| members.as_array_mut())
+            .context(format!(
+                "'{}' does not have a list of workspace members",
+                path.display()
+            ))?;
+        if !members.iter().any(|m| m.as_str() == Some(member)) {
+            members.push(member);
+        }
+        fs::write(&path, manifest.to_string()).await?;
+
+        // The embedded Makefile fetches with `--locked`, so the lock file must list the new member.
+        if workspace_dir.join("Cargo.lock").is_file() {
+            update_lockfile(&workspace_dir).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Replace each `{{key}}` in `template` with its value.
+fn render(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |rendered, (key, value)| {
+            rendered.replace(&format!("{{{{{key}}}}}"), value)
+        })
+}
+
+/// Written in place of an empty list of dependencies in a package's `Cargo.toml`.
+const NO_DEPENDENCIES: &str = "# None\n";
+
+/// Format names as the items of a TOML array, e.g. `"a", "b"`.
+fn toml_list<S: AsRef<str>>(names: &[S]) -> String {
+    names
+        .iter()
+        .map(|name| format!("\"{}\"", name.as_ref()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Format `build-dependencies` entries for crates in a sibling workspace, e.g.
+/// `hello-agent = { path = "../../packages/hello-agent" }`.
+fn path_dependencies<S: AsRef<str>>(workspace: &str, names: &[S]) -> String {
+    names
+        .iter()
+        .map(|name| {
+            let name = name.as_ref();
+            format!("{name} = {{ path = \"../../{workspace}/{name}\" }}\n")
+        })
+        .collect()
+}
+
+/// Cargo package names cannot contain `.`, which variant names such as `aws-k8s-1.28` do, so the
+/// package for a variant uses `_` instead.
+fn variant_package_name(variant: &str) -> String {
+    variant.replace('.', "_")
+}
+
+/// Make sure that `name` can be used as the name of a package or kit crate and its directory.
+fn check_crate_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "'{name}' is not a valid name, use only letters, numbers, '-' and '_'"
+    );
+    Ok(())
+}
+
+/// Write each of `files`, relative to `dir`, refusing to overwrite anything.
+async fn write_files(dir: &Path, files: &[(PathBuf, String)]) -> Result<()> {
+    for (path, _) in files {
+        let path = dir.join(path);
+        ensure!(!path.exists(), "'{}' already exists", path.display());
+    }
+    for (path, contents) in files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, contents).await?;
+    }
+    Ok(())
+}
+
+/// The embedded Makefile fetches with `--locked`, so each workspace needs a `Cargo.lock`.
+async fn generate_lockfile(workspace: &Path) -> Result<()> {
+    exec_log(
+        Command::new("cargo")
+            .arg("generate-lockfile")
+            .arg("--offline")
+            .arg("--manifest-path")
+            .arg(workspace.join("Cargo.toml")),
+    )
+    .await
+    .context(format!(
+        "Unable to generate Cargo.lock for '{}'",
+        workspace.display()
+    ))
+}
+
+/// Add the workspace members to the workspace's existing `Cargo.lock`.
+async fn update_lockfile(workspace: &Path) -> Result<()> {
+    exec_log(
+        Command::new("cargo")
+            .arg("update")
+            .arg("--workspace")
+            .arg("--offline")
+            .arg("--manifest-path")
+            .arg(workspace.join("Cargo.toml")),
+    )
+    .await
+    .context(format!(
+        "Unable to update Cargo.lock for '{}'",
+        workspace.display()
+    ))
+}
+
+/// Format the dependencies of a package, or a placeholder when there are none.
+fn package_dependencies<S: AsRef<str>>(names: &[S]) -> String {
+    if names.is_empty() {
+        NO_DEPENDENCIES.to_string()
+    } else {
+        path_dependencies(PACKAGES.dir, names)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Create a new project in a temporary directory, as `twoliter new project` would, but without
+    /// generating lock files.
+    pub(super) async fn new_project() -> TempDir {
+        let tempdir = TempDir::new().unwrap();
+        let files = project::project_files(
+            "test-project",
+            "aws-dev",
+            &"example.com/bottlerocket-sdk:v0.50.0".parse().unwrap(),
+        )
+        .unwrap();
+        write_files(tempdir.path(), &files).await.unwrap();
+        tempdir
+    }
+
+    /// Read a TOML file from the project.
+    pub(super) async fn read_toml(dir: &Path, path: &str) -> toml::Table {
+        toml::from_str(&fs::read_to_string(dir.join(path)).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn add_member_keeps_comments() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("kits/Cargo.toml");
+        fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+        fs::write(&path, "[workspace]\n# The kits\nmembers = [\"a-kit\"]\n")
+            .await
+            .unwrap();
+        KITS.add_member(tempdir.path(), "b-kit").await.unwrap();
+        KITS.add_member(tempdir.path(), "b-kit").await.unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).await.unwrap(),
+            "[workspace]\n# The kits\nmembers = [\"a-kit\", \"b-kit\"]\n"
+        );
+    }
+}
+
+#[test]
+fn test_render() {
+    assert_eq!(
+        render("{{a}} and {{b}}, {{a}}", &[("a", "x"), ("b", "y")]),
+        "x and y, x"
+    );
+}
+
+#[test]
+fn test_lists() {
+    assert_eq!(toml_list(&["a", "b"]), r#""a", "b""#);
+    assert_eq!(
+        path_dependencies("packages", &["a"]),
+        "a = { path = \"../../packages/a\" }\n"
+    );
+}
+
+#[test]
+fn test_check_crate_name() {
+    assert!(check_crate_name("hello-agent_2").is_ok());
+    assert!(check_crate_name("").is_err());
+    assert!(check_crate_name("../hello").is_err());
+}
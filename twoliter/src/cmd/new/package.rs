@@ -0,0 +1,148 @@
+use super::{check_crate_name, package_dependencies, render, templates, write_files, PACKAGES};
+use crate::project;
+use anyhow::Result;
+use clap::Parser;
+use log::info;
+use std::path::{Path, PathBuf};
+
+/// Add a new package to an existing project, with a spec file to fill in.
+#[derive(Debug, Parser)]
+pub(crate) struct NewPackage {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The name of the package.
+    #[clap(long)]
+    name: String,
+
+    /// Packages that are needed to build this package (RPM BuildRequires).
+    #[clap(long = "build-requires", value_delimiter = ',')]
+    build_requires: Vec<String>,
+
+    /// Packages that are needed to run this package (RPM Requires).
+    #[clap(long, value_delimiter = ',')]
+    requires: Vec<String>,
+}
+
+impl NewPackage {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let project_dir = project.project_dir();
+        check_crate_name(&self.name)?;
+        for package in self.build_requires.iter().chain(&self.requires) {
+            PACKAGES.check_crate(&project_dir, package)?;
+        }
+
+        let mut files = PACKAGES.missing_shim_files(&project_dir);
+        files.extend(self.package_files());
+        write_files(&project_dir, &files).await?;
+        PACKAGES.add_member(&project_dir, &self.name).await?;
+
+        info!(
+            "Created package '{}'. Fill in '{}/{}/{}.spec' to build it",
+            self.name, PACKAGES.dir, self.name, self.name
+        );
+        Ok(())
+    }
+
+    fn package_files(&self) -> Vec<(PathBuf, String)> {
+        let dir = Path::new(PACKAGES.dir).join(&self.name);
+        let requires: String = self
+            .build_requires
+            .iter()
+            .map(|package| format!("BuildRequires: %{{_cross_os}}{package}\n"))
+            .chain(
+                self.requires
+                    .iter()
+                    .map(|package| format!("Requires: %{{_cross_os}}{package}\n")),
+            )
+            .collect();
+        vec![
+            (
+                dir.join("Cargo.toml"),
+                render(
+                    templates::PACKAGE_MANIFEST,
+                    &[
+                        ("name", &self.name),
+                        ("metadata", ""),
+                        (
+                            "build_dependencies",
+                            &package_dependencies(&self.build_requires),
+                        ),
+                        ("dependencies", &package_dependencies(&self.requires)),
+                    ],
+                ),
+            ),
+            (
+                dir.join(format!("{}.spec", self.name)),
+                render(
+                    templates::PACKAGE_SPEC,
+                    &[("name", &self.name), ("requires", &requires)],
+                ),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::{new_project, read_toml};
+    use super::*;
+    use crate::common::fs;
+
+    #[tokio::test]
+    async fn new_package() {
+        let project = new_project().await;
+        let dir = project.path();
+        NewPackage::try_parse_from([
+            "package",
+            "--project-path",
+            dir.join("Twoliter.toml").to_str().unwrap(),
+            "--name",
+            "my-package",
+            "--build-requires",
+            "hello-agent",
+        ])
+        .unwrap()
+        .run()
+        .await
+        .unwrap();
+
+        let manifest = read_toml(dir, "packages/my-package/Cargo.toml").await;
+        assert_eq!(manifest["package"]["build"].as_str(), Some("../build.rs"));
+        assert_eq!(
+            manifest["build-dependencies"]["hello-agent"]["path"].as_str(),
+            Some("../../packages/hello-agent")
+        );
+        let spec = fs::read_to_string(dir.join("packages/my-package/my-package.spec"))
+            .await
+            .unwrap();
+        assert!(spec.contains("BuildRequires: %{_cross_os}hello-agent\n"));
+
+        let workspace = read_toml(dir, "packages/Cargo.toml").await;
+        assert_eq!(
+            workspace["workspace"]["members"].as_array().unwrap().len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn new_package_missing_dependency() {
+        let project = new_project().await;
+        let result = NewPackage::try_parse_from([
+            "package",
+            "--project-path",
+            project.path().join("Twoliter.toml").to_str().unwrap(),
+            "--name",
+            "my-package",
+            "--requires",
+            "no-such-package",
+        ])
+        .unwrap()
+        .run()
+        .await;
+        assert!(result.is_err());
+        assert!(!project.path().join("packages/my-package").exists());
+    }
+}
@@ -0,0 +1,279 @@
+use super::templates;
+use super::{
+    check_crate_name, generate_lockfile, package_dependencies, path_dependencies, render,
+    toml_list, variant_package_name, write_files, KITS, PACKAGES, VARIANTS,
+};
+use crate::docker::ImageUri;
+use crate::project::{sdk_version, vendor_name, ImageDependency};
+use anyhow::{ensure, Context, Result};
+use bottlerocket_variant::Variant;
+use clap::Parser;
+use log::info;
+use std::path::{Path, PathBuf};
+
+/// The SDK used by new projects unless another is given.
+const DEFAULT_SDK: &str = "public.ecr.aws/bottlerocket/bottlerocket-sdk:v0.50.0";
+
+/// The sample package, which is also the only package in the sample kit.
+const SAMPLE_PACKAGE: &str = "hello-agent";
+
+/// The sample kit that the sample variant is built from.
+const SAMPLE_KIT: &str = "hello-kit";
+
+/// The Cargo workspaces of a project.
+const WORKSPACES: [&str; 4] = ["packages", "sources", "kits", "variants"];
+
+/// Create a new Twoliter project with a sample package, kit and variant.
+#[derive(Debug, Parser)]
+pub(crate) struct NewProject {
+    /// The name of the project.
+    #[clap(long)]
+    name: String,
+
+    /// The name of the sample variant, e.g. aws-dev. Variant names are made of a platform, a
+    /// runtime, and optionally a version and a flavor, separated by '-'.
+    #[clap(long = "variant-name")]
+    variant_name: String,
+
+    /// The directory to create the project in. Defaults to a directory named after the project.
+    #[clap(long)]
+    path: Option<PathBuf>,
+
+    /// The Bottlerocket SDK container image to build with.
+    #[clap(long, default_value = DEFAULT_SDK)]
+    sdk: ImageUri,
+}
+
+impl NewProject {
+    pub(super) async fn run(&self) -> Result<()> {
+        Variant::new(&self.variant_name).context(format!(
+            "'{}' is not a valid variant name",
+            self.variant_name
+        ))?;
+        check_crate_name(&variant_package_name(&self.variant_name))?;
+        let dir = self
+            .path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(&self.name));
+        ensure!(
+            !dir.exists()
+                || dir
+                    .read_dir()
+                    .map(|mut d| d.next().is_none())
+                    .unwrap_or(false),
+            "'{}' already exists and is not empty",
+            dir.display()
+        );
+
+        write_files(
+            &dir,
+            &project_files(&self.name, &self.variant_name, &self.sdk)?,
+        )
+        .await?;
+        for workspace in WORKSPACES {
+            generate_lockfile(&dir.join(workspace)).await?;
+        }
+
+        info!(
+            "Created project '{}' in '{}'. Build it with `twoliter build variant {}`",
+            self.name,
+            dir.display(),
+            self.variant_name
+        );
+        Ok(())
+    }
+}
+
+/// The files of a new project, relative to the project directory.
+pub(super) fn project_files(
+    name: &str,
+    variant: &str,
+    sdk: &ImageUri,
+) -> Result<Vec<(PathBuf, String)>> {
+    // The SDK is declared by name and version, and its registry by a vendor.
+    let vendor = sdk.registry.as_deref().map(vendor_name);
+    let vendor_table = match (&vendor, &sdk.registry) {
+        (Some(vendor), Some(registry)) => {
+            format!("\n[vendor.{vendor}]\nregistry = \"{registry}\"\n")
+        }
+        _ => String::new(),
+    };
+    let sdk = toml::to_string(&ImageDependency {
+        name: sdk.repo.clone(),
+        version: sdk_version(&sdk.tag)?,
+        vendor,
+    })
+    .context("Unable to serialize the SDK")?;
+    let variant_package = variant_package_name(variant);
+    let package_metadata = format!(
+        "variant-sensitive = false\nsource-groups = [{}]\n",
+        toml_list(&[SAMPLE_PACKAGE])
+    );
+
+    let mut files = vec![
+        (".gitignore", templates::GITIGNORE.to_string()),
+        (".dockerignore", templates::DOCKERIGNORE.to_string()),
+        (
+            "README.md",
+            render(
+                templates::README,
+                &[("project", name), ("variant", variant)],
+            ),
+        ),
+        (
+            "Twoliter.toml",
+            render(
+                templates::TWOLITER_TOML,
+                &[("project", name), ("vendor", &vendor_table), ("sdk", &sdk)],
+            ),
+        ),
+        // Packages
+        (
+            "packages/Cargo.toml",
+            workspace_manifest(templates::WORKSPACE_MANIFEST, &[SAMPLE_PACKAGE]),
+        ),
+        (
+            "packages/hello-agent/Cargo.toml",
+            render(
+                templates::PACKAGE_MANIFEST,
+                &[
+                    ("name", SAMPLE_PACKAGE),
+                    ("metadata", &package_metadata),
+                    ("build_dependencies", &package_dependencies::<&str>(&[])),
+                    ("dependencies", &package_dependencies::<&str>(&[])),
+                ],
+            ),
+        ),
+        (
+            "packages/hello-agent/hello-agent.spec",
+            templates::HELLO_AGENT_SPEC.to_string(),
+        ),
+        (
+            "packages/hello-agent/hello-agent.service",
+            templates::HELLO_AGENT_SERVICE.to_string(),
+        ),
+        (
+            "packages/hello-agent/hello-agent.timer",
+            templates::HELLO_AGENT_TIMER.to_string(),
+        ),
+        // Sources
+        (
+            "sources/Cargo.toml",
+            workspace_manifest(templates::WORKSPACE_MANIFEST, &[SAMPLE_PACKAGE]),
+        ),
+        (
+            "sources/hello-agent/Cargo.toml",
+            templates::HELLO_AGENT_SOURCE_MANIFEST.to_string(),
+        ),
+        (
+            "sources/hello-agent/build.rs",
+            templates::HELLO_AGENT_SOURCE_BUILD_RS.to_string(),
+        ),
+        (
+            "sources/hello-agent/src/main.rs",
+            templates::HELLO_AGENT_MAIN_RS.to_string(),
+        ),
+        // Kits
+        (
+            "kits/Cargo.toml",
+            workspace_manifest(templates::WORKSPACE_MANIFEST, &[SAMPLE_KIT]),
+        ),
+        (
+            "kits/hello-kit/Cargo.toml",
+            render(
+                templates::KIT_MANIFEST,
+                &[
+                    ("name", SAMPLE_KIT),
+                    ("packages", &toml_list(&[SAMPLE_PACKAGE])),
+                    (
+                        "build_dependencies",
+                        &path_dependencies(PACKAGES.dir, &[SAMPLE_PACKAGE]),
+                    ),
+                ],
+            ),
+        ),
+        // Variants
+        (
+            "variants/Cargo.toml",
+            workspace_manifest(templates::VARIANTS_WORKSPACE_MANIFEST, &[variant]),
+        ),
+    ]
+    .into_iter()
+    .map(|(path, contents)| (PathBuf::from(path), contents))
+    .collect::<Vec<_>>();
+
+    files.push((
+        Path::new("variants").join(variant).join("Cargo.toml"),
+        render(
+            templates::VARIANT_MANIFEST,
+            &[
+                ("name", &variant_package),
+                ("packages", &toml_list(&[SAMPLE_PACKAGE])),
+                ("kits", &toml_list(&[SAMPLE_KIT])),
+                (
+                    "build_dependencies",
+                    &path_dependencies(KITS.dir, &[SAMPLE_KIT]),
+                ),
+            ],
+        ),
+    ));
+
+    // Each workspace has a build.rs shim that calls buildsys, and an empty lib.rs.
+    for workspace in [PACKAGES, KITS, VARIANTS] {
+        files.extend(workspace.shim_files());
+    }
+
+    Ok(files)
+}
+
+/// Render a workspace `Cargo.toml` with the given members.
+fn workspace_manifest<S: AsRef<str>>(template: &str, members: &[S]) -> String {
+    render(template, &[("members", &toml_list(members))])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::fs;
+    use crate::project::Project;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn new_project_files() {
+        let tempdir = TempDir::new().unwrap();
+        let dir = tempdir.path();
+        let sdk: ImageUri = DEFAULT_SDK.parse().unwrap();
+        let files = project_files("my-project", "aws-dev-1.0", &sdk).unwrap();
+        write_files(dir, &files).await.unwrap();
+
+        let project = Project::load(dir.join("Twoliter.toml")).await.unwrap();
+        assert_eq!(project.sdk(), Some(sdk));
+
+        // Every manifest should be valid TOML.
+        for (path, contents) in &files {
+            if path.extension().and_then(|e| e.to_str()) == Some("toml") {
+                let _: toml::Table = toml::from_str(contents)
+                    .unwrap_or_else(|e| panic!("'{}' is invalid: {e}", path.display()));
+            }
+        }
+
+        let variant: toml::Table = toml::from_str(
+            &fs::read_to_string(dir.join("variants/aws-dev-1.0/Cargo.toml"))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(variant["package"]["name"].as_str(), Some("aws-dev-1_0"));
+        assert_eq!(variant["package"]["build"].as_str(), Some("../build.rs"));
+        assert_eq!(
+            variant["build-dependencies"]["hello-kit"]["path"].as_str(),
+            Some("../../kits/hello-kit")
+        );
+
+        let build_rs = fs::read_to_string(dir.join("kits/build.rs")).await.unwrap();
+        assert!(build_rs.contains(r#".arg("build-kit")"#));
+
+        // Nothing is overwritten.
+        assert!(write_files(dir, &files).await.is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! Templates for the files generated by `twoliter new`. Placeholders such as `{{variant}}` are
+//! replaced by [`render`](super::render).
+
+/// `.gitignore` for a new project.
+pub(super) const GITIGNORE: &str = r##"/build/
+**/target/
+/.cargo/
+/.gomodcache/
+/keys/
+/roles/
+/sbkeys/
+Test.toml
+testsys.kubeconfig
+Infra.toml
+"##;
+
+/// `.dockerignore` for a new project.
+pub(super) const DOCKERIGNORE: &str = r##"/.git
+/.gomodcache
+/build/*
+!/build/rpms/
+/build/rpms/*
+!/build/rpms/*.rpm
+/build/rpms/*-debuginfo-*.rpm
+/build/rpms/*-debugsource-*.rpm
+!/build/composites/
+**/target/*
+/sbkeys
+"##;
+
+/// `README.md` for a new project.
+pub(super) const README: &str = r##"# {{project}}
+
+A Bottlerocket project built with Twoliter.
+
+* `packages` holds the RPM packages, one Cargo crate per package.
+* `sources` holds the source code for first-party packages such as `hello-agent`.
+* `kits` holds the kits, which group packages into a repository that variants can use.
+* `variants` holds the variants, which are the Bottlerocket images built from packages and kits.
+
+To build the `{{variant}}` variant:
+
+```shell
+twoliter build variant {{variant}} --arch x86_64
+```
+"##;
+
+/// `Twoliter.toml` for a new project.
+pub(super) const TWOLITER_TOML: &str = r##"schema-version = 2
+project-name = "{{project}}"
+release-version = "0.1.0"
+{{vendor}}
+[sdk]
+{{sdk}}"##;
+
+/// The `Cargo.toml` of the `packages`, `kits` and `sources` workspaces.
+pub(super) const WORKSPACE_MANIFEST: &str = r##"[workspace]
+resolver = "1"
+members = [{{members}}]
+"##;
+
+/// The `Cargo.toml` of the `variants` workspace.
+pub(super) const VARIANTS_WORKSPACE_MANIFEST: &str = r##"[workspace]
+resolver = "1"
+members = [{{members}}]
+
+[profile.dev]
+debug = false
+opt-level = 'z'
+
+[profile.dev.build-override]
+opt-level = 'z'
+"##;
+
+/// The `build.rs` shared by every crate in a workspace, which calls `buildsys {{command}}`.
+pub(super) const BUILD_RS: &str = r##"use std::process::{exit, Command};
+
+fn main() -> Result<(), std::io::Error> {
+    let ret = Command::new("buildsys").arg("{{command}}").status()?;
+    if !ret.success() {
+        exit(1);
+    }
+    Ok(())
+}
+"##;
+
+/// The empty `lib.rs` shared by every crate in a workspace.
+pub(super) const EMPTY_LIB: &str = r##"/*!
+
+This is an intentionally empty file that all of the {{kind}} `Cargo.toml` files can point to as their
+`lib.rs`. The build system uses `build.rs` to invoke `buildsys` but Cargo needs something to compile
+so we give it an empty `lib.rs` file.
+
+!*/
+"##;
+
+/// The `Cargo.toml` of a package.
+pub(super) const PACKAGE_MANIFEST: &str = r##"[package]
+name = "{{name}}"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+
+[package.metadata.build-package]
+{{metadata}}
+[lib]
+path = "../packages.rs"
+
+# RPM BuildRequires
+[build-dependencies]
+{{build_dependencies}}
+# RPM Requires
+[dependencies]
+{{dependencies}}"##;
+
+/// The spec file of a new package, to be filled in.
+pub(super) const PACKAGE_SPEC: &str = r##"%global _cross_first_party 1
+%undefine _debugsource_packages
+
+Name: %{_cross_os}{{name}}
+Version: 0.0
+Release: 0%{?dist}
+Summary: {{name}}
+License: Apache-2.0 OR MIT
+URL: https://github.com/bottlerocket-os/bottlerocket
+
+# sources < 100: misc
+
+BuildRequires: %{_cross_os}glibc-devel
+{{requires}}
+%description
+%{summary}.
+
+%prep
+
+%build
+
+%install
+
+%files
+"##;
+
+/// The spec file of the sample `hello-agent` package.
+pub(super) const HELLO_AGENT_SPEC: &str = r##"%global _cross_first_party 1
+%undefine _debugsource_packages
+
+Name: %{_cross_os}hello-agent
+Version: 0.0
+Release: 0%{?dist}
+Summary: Hello-agent
+License: Apache-2.0 OR MIT
+URL: https://github.com/bottlerocket-os/bottlerocket
+
+# sources < 100: misc
+
+# 1xx sources: systemd units
+Source103: hello-agent.service
+Source104: hello-agent.timer
+
+BuildRequires: %{_cross_os}glibc-devel
+
+%description
+%{summary}.
+
+%prep
+%setup -T -c
+%cargo_prep
+
+%build
+mkdir bin
+
+%cargo_build_static --manifest-path %{_builddir}/sources/Cargo.toml \
+    -p hello-agent
+
+%install
+install -d %{buildroot}%{_cross_bindir}
+install -p -m 0755 ${HOME}/.cache/.static/%{__cargo_target_static}/release/hello-agent %{buildroot}%{_cross_bindir}
+
+install -d %{buildroot}%{_cross_unitdir}
+install -p -m 0644 \
+  %{S:103} %{S:104} \
+  %{buildroot}%{_cross_unitdir}
+
+%files
+%{_cross_bindir}/hello-agent
+%{_cross_unitdir}/hello-agent.service
+%{_cross_unitdir}/hello-agent.timer
+"##;
+
+/// The systemd service of the sample `hello-agent` package.
+pub(super) const HELLO_AGENT_SERVICE: &str = r##"[Unit]
+Description=Send a hello-agent Ping
+
+[Service]
+Type=oneshot
+RemainAfterExit=false
+StandardError=journal+console
+ExecStart=/usr/bin/hello-agent
+TimeoutStartSec=30s
+"##;
+
+/// The systemd timer of the sample `hello-agent` package.
+pub(super) const HELLO_AGENT_TIMER: &str = r##"[Unit]
+Description=Scheduled Hello-Agent Pings
+
+[Timer]
+# Don't run missed executions
+Persistent=false
+# Run 5 seconds after startup
+OnStartupSec=5
+# Run every 5 sec thereafter
+OnUnitActiveSec=5
+# Don't fire at exactly the same second across machines started together.
+RandomizedDelaySec=1
+# We don't want to extend the startup report too long after the requested time.
+AccuracySec=1
+# File describing job to execute
+Unit=hello-agent.service
+
+[Install]
+WantedBy=timers.target
+"##;
+
+/// The `Cargo.toml` of the sample `hello-agent` source crate.
+pub(super) const HELLO_AGENT_SOURCE_MANIFEST: &str = r##"[package]
+name = "hello-agent"
+version = "0.1.0"
+edition = "2021"
+publish = false
+"##;
+
+/// The `build.rs` of the sample `hello-agent` source crate.
+pub(super) const HELLO_AGENT_SOURCE_BUILD_RS: &str = r##"fn main() {}
+"##;
+
+/// The `main.rs` of the sample `hello-agent` source crate.
+pub(super) const HELLO_AGENT_MAIN_RS: &str = r##"use std::process;
+
+fn main() -> ! {
+    println!("Hello from hello-agent");
+    process::exit(0)
+}
+"##;
+
+/// The `Cargo.toml` of a kit.
+pub(super) const KIT_MANIFEST: &str = r##"[package]
+name = "{{name}}"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+# Don't rebuild crate just because of changes to README.
+exclude = ["README.md"]
+
+[package.metadata.build-kit]
+included-packages = [{{packages}}]
+
+[lib]
+path = "../kits.rs"
+
+[build-dependencies]
+{{build_dependencies}}"##;
+
+/// The `Cargo.toml` of a variant.
+pub(super) const VARIANT_MANIFEST: &str = r##"[package]
+name = "{{name}}"
+version = "0.1.0"
+edition = "2021"
+publish = false
+build = "../build.rs"
+# Don't rebuild crate just because of changes to README.
+exclude = ["README.md"]
+
+[package.metadata.build-variant.image-features]
+grub-set-private-var = true
+unified-cgroup-hierarchy = true
+uefi-secure-boot = true
+xfs-data-partition = true
+systemd-networkd = true
+
+[package.metadata.build-variant]
+included-packages = [{{packages}}]
+included-kits = [{{kits}}]
+kernel-parameters = []
+
+[lib]
+path = "../variants.rs"
+
+[build-dependencies]
+{{build_dependencies}}"##;
@@ -0,0 +1,192 @@
+use super::{
+    check_crate_name, path_dependencies, render, templates, toml_list, variant_package_name,
+    write_files, KITS, PACKAGES, VARIANTS,
+};
+use crate::common::fs;
+use crate::project::{self, Project};
+use anyhow::{ensure, Context, Result};
+use bottlerocket_variant::Variant;
+use clap::Parser;
+use log::info;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+/// Add a new variant to an existing project, either from scratch or as a copy of another variant.
+#[derive(Debug, Parser)]
+pub(crate) struct NewVariant {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The name of the variant, e.g. aws-dev.
+    #[clap(long)]
+    name: String,
+
+    /// The packages to include in the variant.
+    #[clap(long, value_delimiter = ',')]
+    packages: Vec<String>,
+
+    /// The kits to build the variant from. These can be kits in the project, or external kits
+    /// declared in Twoliter.toml.
+    #[clap(long, value_delimiter = ',')]
+    kits: Vec<String>,
+
+    /// An existing variant in the project to copy the metadata of.
+    #[clap(long, conflicts_with_all = ["packages", "kits"])]
+    from: Option<String>,
+}
+
+impl NewVariant {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let project_dir = project.project_dir();
+        Variant::new(&self.name).context(format!("'{}' is not a valid variant name", self.name))?;
+        let package_name = variant_package_name(&self.name);
+        check_crate_name(&package_name)?;
+
+        let manifest = match &self.from {
+            Some(from) => copy_manifest(&project_dir, from, &package_name).await?,
+            None => self.manifest(&project, &package_name)?,
+        };
+        let mut files = VARIANTS.missing_shim_files(&project_dir);
+        files.push((
+            Path::new(VARIANTS.dir).join(&self.name).join("Cargo.toml"),
+            manifest,
+        ));
+        write_files(&project_dir, &files).await?;
+        VARIANTS.add_member(&project_dir, &self.name).await?;
+
+        info!(
+            "Created variant '{}'. Build it with `twoliter build variant {}`",
+            self.name, self.name
+        );
+        Ok(())
+    }
+
+    /// Render the `Cargo.toml` of a variant built from the given packages and kits. Packages and
+    /// kits in the project are build dependencies of the variant, external kits are not.
+    fn manifest(&self, project: &Project, package_name: &str) -> Result<String> {
+        let project_dir = project.project_dir();
+        for package in &self.packages {
+            PACKAGES.check_crate(&project_dir, package)?;
+        }
+        let mut local_kits = Vec::new();
+        for kit in &self.kits {
+            if KITS.check_crate(&project_dir, kit).is_ok() {
+                local_kits.push(kit.as_str());
+            } else {
+                ensure!(
+                    project.kits().iter().any(|external| &external.repo == kit),
+                    "There is no kit named '{kit}' in '{}' or in the kits of '{}'",
+                    project_dir.join(KITS.dir).display(),
+                    project.filepath().display()
+                );
+            }
+        }
+
+        let build_dependencies = path_dependencies(PACKAGES.dir, &self.packages)
+            + &path_dependencies(KITS.dir, &local_kits);
+        Ok(render(
+            templates::VARIANT_MANIFEST,
+            &[
+                ("name", package_name),
+                ("packages", &toml_list(&self.packages)),
+                ("kits", &toml_list(&self.kits)),
+                ("build_dependencies", &build_dependencies),
+            ],
+        ))
+    }
+}
+
+/// Copy the `Cargo.toml` of the variant `from`, with the package renamed to `package_name`.
+async fn copy_manifest(project_dir: &Path, from: &str, package_name: &str) -> Result<String> {
+    let path = project_dir.join(VARIANTS.dir).join(from).join("Cargo.toml");
+    ensure!(
+        path.is_file(),
+        "There is no variant named '{from}', '{}' does not exist",
+        path.display()
+    );
+    let mut manifest: DocumentMut = fs::read_to_string(&path)
+        .await?
+        .parse()
+        .context(format!("Unable to parse '{}'", path.display()))?;
+    let package = manifest
+        .get_mut("package")
+        .and_then(|package| package.as_table_like_mut())
+        .context(format!("'{}' does not have a [package]", path.display()))?;
+    package.insert("name", toml_edit::value(package_name));
+    Ok(manifest.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::{new_project, read_toml};
+    use super::*;
+
+    async fn new_variant(dir: &Path, args: &[&str]) -> Result<()> {
+        let project_path = dir.join("Twoliter.toml");
+        let mut all_args = vec!["variant", "--project-path", project_path.to_str().unwrap()];
+        all_args.extend(args);
+        NewVariant::try_parse_from(all_args).unwrap().run().await
+    }
+
+    #[tokio::test]
+    async fn new_variant_from_kits() {
+        let project = new_project().await;
+        let dir = project.path();
+        new_variant(
+            dir,
+            &[
+                "--name",
+                "metal-dev",
+                "--packages",
+                "hello-agent",
+                "--kits",
+                "hello-kit",
+            ],
+        )
+        .await
+        .unwrap();
+
+        let manifest = read_toml(dir, "variants/metal-dev/Cargo.toml").await;
+        assert_eq!(manifest["package"]["name"].as_str(), Some("metal-dev"));
+        let build_dependencies = manifest["build-dependencies"].as_table().unwrap();
+        assert!(build_dependencies.contains_key("hello-agent"));
+        assert!(build_dependencies.contains_key("hello-kit"));
+
+        let workspace = read_toml(dir, "variants/Cargo.toml").await;
+        assert!(workspace["workspace"]["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|member| member.as_str() == Some("metal-dev")));
+
+        // Unknown kits are an error.
+        assert!(
+            new_variant(dir, &["--name", "vmware-dev", "--kits", "no-such-kit"])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn new_variant_from_existing() {
+        let project = new_project().await;
+        let dir = project.path();
+        new_variant(dir, &["--name", "aws-dev-1.1", "--from", "aws-dev"])
+            .await
+            .unwrap();
+
+        let original = read_toml(dir, "variants/aws-dev/Cargo.toml").await;
+        let mut copied = read_toml(dir, "variants/aws-dev-1.1/Cargo.toml").await;
+        assert_eq!(copied["package"]["name"].as_str(), Some("aws-dev-1_1"));
+        copied["package"]["name"] = original["package"]["name"].clone();
+        assert_eq!(copied, original);
+
+        assert!(
+            new_variant(dir, &["--name", "aws-dev-2", "--from", "no-such-variant"])
+                .await
+                .is_err()
+        );
+    }
+}
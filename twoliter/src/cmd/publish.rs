@@ -0,0 +1,548 @@
+use crate::artifacts::VariantArtifacts;
+use crate::common::{exec_log, fs};
+use crate::project::{self, Project};
+use crate::tools::{install_tools, InstalledTools, ToolOverrides};
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use log::info;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+use tempfile::TempDir;
+use tokio::process::Command;
+
+#[derive(Debug, Parser)]
+pub(crate) enum PublishCommand {
+    Repo(PublishRepo),
+    Ami(PublishAmi),
+    Ssm(PublishSsm),
+    Ova(PublishOva),
+}
+
+impl PublishCommand {
+    pub(crate) async fn run(self) -> Result<()> {
+        match self {
+            PublishCommand::Repo(command) => command.run().await,
+            PublishCommand::Ami(command) => command.run().await,
+            PublishCommand::Ssm(command) => command.run().await,
+            PublishCommand::Ova(command) => command.run().await,
+        }
+    }
+}
+
+/// The arguments that choose the build to publish, and where to publish it.
+#[derive(Debug, Parser)]
+pub(crate) struct PublishArgs {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// The architecture of the build to publish.
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// The variant to publish. It must already be built at the project's current version and
+    /// commit.
+    variant: String,
+
+    /// Path to Infra.toml, which describes the infrastructure to publish to. Defaults to Infra.toml
+    /// in the project directory.
+    #[clap(long = "infra-config-path")]
+    infra_config_path: Option<PathBuf>,
+}
+
+/// Build a TUF repository that serves the variant's images as updates.
+#[derive(Debug, Parser)]
+pub(crate) struct PublishRepo {
+    #[clap(flatten)]
+    args: PublishArgs,
+
+    /// The repository in Infra.toml to build.
+    #[clap(long = "repo", default_value = "default")]
+    repo: String,
+
+    /// Path to the policy for when repository metadata expires. Defaults to
+    /// tools/pubsys/policies/repo-expiration/2w-2w-1w.toml in the project directory.
+    #[clap(long = "expiration-policy-path")]
+    expiration_policy_path: Option<PathBuf>,
+
+    /// Path to the policy for the waves that the update is rolled out in. Defaults to
+    /// sources/updater/waves/default-waves.toml in the project directory.
+    #[clap(long = "wave-policy-path")]
+    wave_policy_path: Option<PathBuf>,
+
+    /// When update waves and metadata expiration start, as an RFC3339 date or an offset like
+    /// "in 2 hours". Defaults to now.
+    #[clap(long = "release-start-time")]
+    release_start_time: Option<String>,
+
+    /// Path to the root role of the repository. Defaults to roles/<repo>.root.json in the project
+    /// directory, which is created if it does not exist.
+    #[clap(long = "root-role-path")]
+    root_role_path: Option<PathBuf>,
+
+    /// Path to the key that signs the repository when Infra.toml does not name one. Defaults to
+    /// keys/<repo>.pem in the project directory, which is created if it does not exist.
+    #[clap(long = "default-key-path")]
+    default_key_path: Option<PathBuf>,
+}
+
+impl PublishRepo {
+    pub(super) async fn run(&self) -> Result<()> {
+        let publisher = Publisher::new(&self.args).await?;
+        let artifacts = &publisher.artifacts;
+        let root_role_path = publisher.path(
+            &self.root_role_path,
+            &format!("roles/{}.root.json", self.repo),
+        );
+        let default_key_path =
+            publisher.path(&self.default_key_path, &format!("keys/{}.pem", self.repo));
+
+        // Create the root role and signing key for the repository if they do not exist yet.
+        exec_log(
+            Command::new(publisher.toolsdir.join("pubsys-setup"))
+                .arg("--log-level")
+                .arg(log_level())
+                .arg("--infra-config-path")
+                .arg(&publisher.infra_config_path)
+                .arg("--root-role-path")
+                .arg(&root_role_path)
+                .arg("--default-key-path")
+                .arg(&default_key_path)
+                .arg("--repo")
+                .arg(&self.repo),
+        )
+        .await
+        .context("Unable to set up the repository's root role and signing key")?;
+
+        let boot_image = artifacts.image("-boot.ext4.lz4");
+        let root_image = artifacts.image("-root.ext4.lz4");
+        let hash_image = artifacts.image("-root.verity.lz4");
+        for image in [&boot_image, &root_image, &hash_image] {
+            publisher.require(image, "twoliter build variant")?;
+        }
+
+        // Every migration of the build is copied into the repository.
+        let migrations_dir =
+            TempDir::new().context("Unable to create a tempdir for the migrations")?;
+        let migrations = artifacts.image("-migrations.tar");
+        File::open(&migrations)
+            .and_then(|file| Archive::new(file).unpack(migrations_dir.path()))
+            .context(format!(
+                "Unable to extract the migrations from '{}'",
+                migrations.display()
+            ))?;
+        let mut copy_targets = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(migrations_dir.path())
+            .await
+            .context("Unable to read the extracted migrations")?;
+        while let Some(entry) = read_dir
+            .next_entry()
+            .await
+            .context("Unable to read the extracted migrations")?
+        {
+            copy_targets.push(entry.path());
+        }
+        copy_targets.sort();
+
+        // The kmod kit is included to ease building out-of-tree kernel modules for the release, as
+        // are the disk images under both their full and friendly names.
+        let mut link_targets = vec![artifacts.kmod_kit()];
+        for suffix in [".img.lz4", "-data.img.lz4"] {
+            if is_nonempty(&artifacts.image(suffix)) {
+                link_targets.push(artifacts.image(suffix));
+                link_targets.push(artifacts.friendly_image(suffix));
+            }
+        }
+        if is_nonempty(&artifacts.ovf_template()) {
+            let ova = artifacts.friendly_image(".ova");
+            publisher.require(&ova, "twoliter build variant")?;
+            link_targets.push(ova);
+        }
+
+        let outdir = artifacts.repo_dir(&self.repo);
+        let mut pubsys = publisher.pubsys("repo");
+        pubsys
+            .arg("--repo")
+            .arg(&self.repo)
+            .arg("--arch")
+            .arg(artifacts.arch())
+            .arg("--version")
+            .arg(artifacts.version_image())
+            .arg("--variant")
+            .arg(artifacts.variant())
+            .arg("--boot-image")
+            .arg(&boot_image)
+            .arg("--root-image")
+            .arg(&root_image)
+            .arg("--hash-image")
+            .arg(&hash_image);
+        for target in &link_targets {
+            pubsys.arg("--link-target").arg(target);
+        }
+        for target in &copy_targets {
+            pubsys.arg("--copy-target").arg(target);
+        }
+        pubsys
+            .arg("--repo-expiration-policy-path")
+            .arg(publisher.path(
+                &self.expiration_policy_path,
+                "tools/pubsys/policies/repo-expiration/2w-2w-1w.toml",
+            ))
+            .arg("--release-config-path")
+            .arg(publisher.project.release_config().await?)
+            .arg("--wave-policy-path")
+            .arg(publisher.path(
+                &self.wave_policy_path,
+                "sources/updater/waves/default-waves.toml",
+            ))
+            .arg("--root-role-path")
+            .arg(&root_role_path)
+            .arg("--default-key-path")
+            .arg(&default_key_path)
+            .arg("--outdir")
+            .arg(&outdir);
+        if let Some(release_start_time) = &self.release_start_time {
+            pubsys.arg("--release-start-time").arg(release_start_time);
+        }
+        exec_log(&mut pubsys).await?;
+
+        fs::replace_symlink(artifacts.name_version(), outdir.with_file_name("latest")).await?;
+        info!("Built repository '{}' in '{}'", self.repo, outdir.display());
+        Ok(())
+    }
+}
+
+/// Register the variant's images as an AMI in the regions configured in Infra.toml.
+#[derive(Debug, Parser)]
+pub(crate) struct PublishAmi {
+    #[clap(flatten)]
+    args: PublishArgs,
+
+    /// The name of the AMI. Defaults to a name made from the image name, variant, architecture,
+    /// version and commit.
+    #[clap(long = "name")]
+    name: Option<String>,
+
+    /// The description of the AMI. Defaults to its name.
+    #[clap(long = "description")]
+    description: Option<String>,
+
+    /// The regions to register the AMI in, separated by commas. The first is used as the base for
+    /// copying. Defaults to the regions in Infra.toml.
+    #[clap(long = "regions", value_delimiter = ',')]
+    regions: Vec<String>,
+
+    /// The Secure Boot key profile in the sbkeys directory that holds the UEFI data.
+    #[clap(long = "sbkeys-profile", default_value = "local")]
+    sbkeys_profile: String,
+
+    /// Don't display progress bars while uploading snapshots.
+    #[clap(long = "no-progress")]
+    no_progress: bool,
+}
+
+impl PublishAmi {
+    pub(super) async fn run(&self) -> Result<()> {
+        let publisher = Publisher::new(&self.args).await?;
+        let artifacts = &publisher.artifacts;
+        let os_image = artifacts.image(".img.lz4");
+        publisher.require(&os_image, "twoliter build variant")?;
+
+        // pubsys needs the images uncompressed. Only variants with the split disk format have a
+        // data image.
+        let os_image = unlz4(&os_image).await?;
+        let data_image = artifacts.image("-data.img.lz4");
+        let data_image = if is_nonempty(&data_image) {
+            Some(unlz4(&data_image).await?)
+        } else {
+            None
+        };
+
+        let name = self
+            .name
+            .clone()
+            .unwrap_or_else(|| artifacts.machine_image_name());
+        let ami_output = artifacts.amis();
+        let mut pubsys = publisher.pubsys("ami");
+        pubsys.arg("--os-image").arg(&os_image);
+        if let Some(data_image) = &data_image {
+            pubsys.arg("--data-image").arg(data_image);
+        }
+        pubsys
+            .arg("--variant-manifest")
+            .arg(
+                publisher
+                    .project
+                    .project_dir()
+                    .join("variants")
+                    .join(artifacts.variant())
+                    .join("Cargo.toml"),
+            )
+            .arg("--uefi-data")
+            .arg(
+                publisher
+                    .project
+                    .project_dir()
+                    .join("sbkeys")
+                    .join(&self.sbkeys_profile)
+                    .join("efi-vars.aws"),
+            )
+            .arg("--arch")
+            .arg(artifacts.arch())
+            .arg("--name")
+            .arg(&name)
+            .arg("--description")
+            .arg(self.description.as_ref().unwrap_or(&name))
+            .arg("--ami-output")
+            .arg(&ami_output);
+        if self.no_progress {
+            pubsys.arg("--no-progress");
+        }
+        if !self.regions.is_empty() {
+            pubsys.arg("--regions").arg(self.regions.join(","));
+        }
+        exec_log(&mut pubsys).await?;
+
+        fs::replace_symlink(
+            ami_output
+                .file_name()
+                .context("AMI output has no file name")?,
+            artifacts.latest_amis(),
+        )
+        .await?;
+        info!("Wrote the registered AMIs to '{}'", ami_output.display());
+        Ok(())
+    }
+}
+
+/// Publish SSM parameters that point to the AMIs registered by `twoliter publish ami`.
+#[derive(Debug, Parser)]
+pub(crate) struct PublishSsm {
+    #[clap(flatten)]
+    args: PublishArgs,
+
+    /// Path to the templates of the parameters to publish. Defaults to
+    /// tools/pubsys/policies/ssm/defaults.toml in the project directory.
+    #[clap(long = "template-path")]
+    template_path: Option<PathBuf>,
+
+    /// The regions to publish parameters in, separated by commas. Defaults to the regions in
+    /// Infra.toml.
+    #[clap(long = "regions", value_delimiter = ',')]
+    regions: Vec<String>,
+
+    /// Overwrite parameters that already exist.
+    #[clap(long = "allow-clobber")]
+    allow_clobber: bool,
+}
+
+impl PublishSsm {
+    pub(super) async fn run(&self) -> Result<()> {
+        let publisher = Publisher::new(&self.args).await?;
+        let artifacts = &publisher.artifacts;
+        let ami_input = artifacts.amis();
+        publisher.require(&ami_input, "twoliter publish ami")?;
+
+        let ssm_output = artifacts.ssm_parameters();
+        let mut pubsys = publisher.pubsys("ssm");
+        pubsys
+            .arg("--ami-input")
+            .arg(&ami_input)
+            .arg("--arch")
+            .arg(artifacts.arch())
+            .arg("--variant")
+            .arg(artifacts.variant())
+            .arg("--version")
+            .arg(artifacts.version_full())
+            .arg("--template-path")
+            .arg(publisher.path(
+                &self.template_path,
+                "tools/pubsys/policies/ssm/defaults.toml",
+            ))
+            .arg("--ssm-parameter-output")
+            .arg(&ssm_output);
+        if !self.regions.is_empty() {
+            pubsys.arg("--regions").arg(self.regions.join(","));
+        }
+        if self.allow_clobber {
+            pubsys.arg("--allow-clobber");
+        }
+        exec_log(&mut pubsys).await?;
+
+        info!(
+            "Wrote the published parameters to '{}'",
+            ssm_output.display()
+        );
+        Ok(())
+    }
+}
+
+/// Upload the variant's OVA to the VMware datacenters configured in Infra.toml.
+#[derive(Debug, Parser)]
+pub(crate) struct PublishOva {
+    #[clap(flatten)]
+    args: PublishArgs,
+
+    /// The name of the VM. Defaults to a name made from the image name, variant, architecture,
+    /// version and commit.
+    #[clap(long = "name")]
+    name: Option<String>,
+
+    /// Path to the import spec template. Defaults to
+    /// tools/pubsys/support/vmware/import_spec.template in the project directory.
+    #[clap(long = "import-spec-path")]
+    import_spec_path: Option<PathBuf>,
+
+    /// The datacenters to upload the OVA to, separated by commas. Defaults to the datacenters in
+    /// Infra.toml.
+    #[clap(long = "datacenters", value_delimiter = ',')]
+    datacenters: Vec<String>,
+
+    /// Mark the uploaded VM as a template.
+    #[clap(long = "mark-as-template")]
+    mark_as_template: bool,
+}
+
+impl PublishOva {
+    pub(super) async fn run(&self) -> Result<()> {
+        let publisher = Publisher::new(&self.args).await?;
+        let artifacts = &publisher.artifacts;
+        let ova = artifacts.image(".ova");
+        publisher.require(&ova, "twoliter build variant")?;
+
+        let mut pubsys = publisher.pubsys("upload-ova");
+        pubsys
+            .arg("--ova")
+            .arg(&ova)
+            .arg("--spec")
+            .arg(publisher.path(
+                &self.import_spec_path,
+                "tools/pubsys/support/vmware/import_spec.template",
+            ))
+            .arg("--name")
+            .arg(
+                self.name
+                    .clone()
+                    .unwrap_or_else(|| artifacts.machine_image_name()),
+            );
+        if self.mark_as_template {
+            pubsys.arg("--mark-as-template");
+        }
+        if !self.datacenters.is_empty() {
+            pubsys.arg("--datacenters").arg(self.datacenters.join(","));
+        }
+        exec_log(&mut pubsys).await
+    }
+}
+
+/// What every publish command needs: the project, the build being published, and the tools.
+struct Publisher {
+    project: Project,
+    artifacts: VariantArtifacts,
+    toolsdir: PathBuf,
+    _tools: InstalledTools,
+    infra_config_path: PathBuf,
+}
+
+impl Publisher {
+    async fn new(args: &PublishArgs) -> Result<Self> {
+        let project = project::load_or_find_project(args.project_path.clone()).await?;
+        let toolsdir = project.project_dir().join("build/tools");
+        let tools = install_tools(
+            &toolsdir,
+            &ToolOverrides::resolve(project.tool_overrides())?,
+        )
+        .await?;
+        let artifacts = VariantArtifacts::new(&project, &args.variant, &args.arch).await?;
+        let infra_config_path = args
+            .infra_config_path
+            .clone()
+            .unwrap_or_else(|| project.project_dir().join("Infra.toml"));
+        Ok(Self {
+            project,
+            artifacts,
+            toolsdir,
+            _tools: tools,
+            infra_config_path,
+        })
+    }
+
+    /// A `pubsys` command with the arguments that all of its subcommands take.
+    fn pubsys(&self, subcommand: &str) -> Command {
+        let mut command = Command::new(self.toolsdir.join("pubsys"));
+        command
+            .arg("--log-level")
+            .arg(log_level())
+            .arg("--infra-config-path")
+            .arg(&self.infra_config_path)
+            .arg(subcommand);
+        command
+    }
+
+    /// The path given on the command line, or else `default` in the project directory.
+    fn path(&self, path: &Option<PathBuf>, default: &str) -> PathBuf {
+        path.clone()
+            .unwrap_or_else(|| self.project.project_dir().join(default))
+    }
+
+    /// Fail with a hint to run `command` if the build output at `path` does not exist.
+    fn require(&self, path: &Path, command: &str) -> Result<()> {
+        ensure!(
+            is_nonempty(path),
+            "'{}' doesn't exist for the current version/commit - {} - please run `{command}`",
+            path.display(),
+            self.artifacts.version_full()
+        );
+        Ok(())
+    }
+}
+
+/// The log level for the tools, which follows Twoliter's own.
+fn log_level() -> String {
+    log::max_level().to_string()
+}
+
+fn is_nonempty(path: &Path) -> bool {
+    path.metadata().map(|m| m.len() > 0).unwrap_or(false)
+}
+
+/// Decompress an lz4 image next to itself and return the path of the decompressed image.
+async fn unlz4(path: &Path) -> Result<PathBuf> {
+    let decompressed = path.with_extension("");
+    exec_log(Command::new("lz4").arg("-df").arg(path).arg(&decompressed))
+        .await
+        .context(format!("Unable to decompress '{}'", path.display()))?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cmd::{Args, Subcommand};
+
+    #[test]
+    fn parse_publish_ami() {
+        let args = Args::try_parse_from([
+            "twoliter",
+            "publish",
+            "ami",
+            "--arch",
+            "aarch64",
+            "--regions",
+            "us-west-2,us-east-1",
+            "aws-dev",
+        ])
+        .unwrap();
+        let Subcommand::Publish(PublishCommand::Ami(ami)) = args.subcommand else {
+            panic!("Expected `publish ami`, got {:?}", args.subcommand);
+        };
+        assert_eq!(ami.args.variant, "aws-dev");
+        assert_eq!(ami.args.arch, "aarch64");
+        assert_eq!(ami.regions, ["us-west-2", "us-east-1"]);
+        assert_eq!(ami.sbkeys_profile, "local");
+
+        // The variant is required.
+        assert!(Args::try_parse_from(["twoliter", "publish", "repo"]).is_err());
+    }
+}
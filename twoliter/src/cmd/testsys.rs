@@ -0,0 +1,312 @@
+use crate::artifacts::VariantArtifacts;
+use crate::common::{exec, exec_log};
+use crate::project::{self, Project};
+use crate::tools::{install_tools, InstalledTools, ToolOverrides};
+use anyhow::{bail, Result};
+use clap::Parser;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+#[derive(Debug, Parser)]
+pub(crate) enum TestCommand {
+    Run(TestRun),
+    Status(TestStatus),
+    Logs(TestLogs),
+}
+
+impl TestCommand {
+    pub(crate) async fn run(self) -> Result<()> {
+        match self {
+            TestCommand::Run(command) => command.run().await,
+            TestCommand::Status(command) => command.run().await,
+            TestCommand::Logs(command) => command.run().await,
+        }
+    }
+}
+
+/// The arguments that choose the project and the testsys cluster.
+#[derive(Debug, Parser)]
+pub(crate) struct TestsysArgs {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// Path to the kubeconfig of the testsys cluster. Defaults to testsys.kubeconfig in the tests
+    /// directory or the project directory.
+    #[clap(long = "kubeconfig", env = "TESTSYS_KUBECONFIG")]
+    kubeconfig: Option<PathBuf>,
+}
+
+/// Run tests of a variant build in the testsys cluster.
+#[derive(Debug, Parser)]
+pub(crate) struct TestRun {
+    #[clap(flatten)]
+    args: TestsysArgs,
+
+    /// The architecture of the build to test.
+    #[clap(long = "arch", default_value = "x86_64")]
+    arch: String,
+
+    /// The variant to test. It must already be built at the project's current version and commit.
+    variant: String,
+
+    /// The type of test to run, e.g. `quick`, `conformance` or `migration`.
+    #[clap(long = "test", default_value = "quick")]
+    test: String,
+
+    /// Path to Infra.toml. Defaults to Infra.toml in the project directory.
+    #[clap(long = "infra-config-path")]
+    infra_config_path: Option<PathBuf>,
+
+    /// Path to Test.toml. Defaults to Test.toml in the tests directory or the project directory.
+    #[clap(long = "test-config-path")]
+    test_config_path: Option<PathBuf>,
+
+    /// The repository in Infra.toml to use for upgrade and downgrade tests.
+    #[clap(long = "repo")]
+    repo: Option<String>,
+
+    /// The name of the secret in the testsys cluster that holds AWS credentials for the tests.
+    #[clap(long = "aws-secret")]
+    aws_secret: Option<String>,
+
+    /// More arguments for `testsys run`, given after `--`.
+    #[clap(last = true)]
+    testsys_args: Vec<String>,
+}
+
+impl TestRun {
+    pub(super) async fn run(&self) -> Result<()> {
+        let (project, _tools, mut testsys) = self.args.testsys().await?;
+        let artifacts = VariantArtifacts::new(&project, &self.variant, &self.arch).await?;
+        let project_dir = project.project_dir();
+        let tests_dir = project_dir.join("tests");
+        let test_config_path = match &self.test_config_path {
+            Some(path) => path.clone(),
+            None => find_one(&[project_dir.join("Test.toml"), tests_dir.join("Test.toml")])?
+                .unwrap_or_else(|| project_dir.join("Test.toml")),
+        };
+
+        testsys
+            .arg("run")
+            .arg(&self.test)
+            .arg("--arch")
+            .arg(artifacts.arch())
+            .arg("--variant")
+            .arg(artifacts.variant())
+            .arg("--infra-config-path")
+            .arg(
+                self.infra_config_path
+                    .clone()
+                    .unwrap_or_else(|| project_dir.join("Infra.toml")),
+            )
+            .arg("--test-config-path")
+            .arg(test_config_path)
+            .arg("--tests-directory")
+            .arg(&tests_dir)
+            .arg("--build-id")
+            .arg(artifacts.version_build())
+            .arg("--migration-target-version")
+            .arg(artifacts.version_image())
+            .arg("--ova-name")
+            .arg(format!("{}.ova", artifacts.name_friendly()))
+            .arg("--image-name")
+            .arg(artifacts.name_full());
+        // The AMI is chosen from the ones registered by `twoliter publish ami`, if any.
+        let amis = artifacts.amis();
+        if amis.metadata().map(|m| m.len() > 0).unwrap_or(false) {
+            testsys.arg("--ami-input").arg(amis);
+        }
+        if let Some(repo) = &self.repo {
+            testsys.arg("--repo").arg(repo);
+        }
+        if let Some(aws_secret) = &self.aws_secret {
+            testsys
+                .arg("--secret")
+                .arg(format!("awsCredentials={aws_secret}"));
+        }
+        testsys.args(&self.testsys_args);
+        exec_log(&mut testsys).await
+    }
+}
+
+/// Show the status of the tests and resources in the testsys cluster.
+#[derive(Debug, Parser)]
+pub(crate) struct TestStatus {
+    #[clap(flatten)]
+    args: TestsysArgs,
+
+    /// Only show tests of this architecture.
+    #[clap(long = "arch")]
+    arch: Option<String>,
+
+    /// Only show tests of this variant.
+    #[clap(long = "variant")]
+    variant: Option<String>,
+
+    /// The format of the status: `json`, `narrow` or `wide`.
+    #[clap(long = "output")]
+    output: Option<String>,
+
+    /// Only show tests, not resources.
+    #[clap(long = "test")]
+    test: bool,
+
+    /// Only show tests that passed.
+    #[clap(long = "passed", conflicts_with_all = ["failed", "running"])]
+    passed: bool,
+
+    /// Only show tests that failed.
+    #[clap(long = "failed", conflicts_with_all = ["passed", "running"])]
+    failed: bool,
+
+    /// Only show tests and resources that haven't finished.
+    #[clap(long = "running", conflicts_with_all = ["passed", "failed"])]
+    running: bool,
+}
+
+impl TestStatus {
+    pub(super) async fn run(&self) -> Result<()> {
+        let (_, _tools, mut testsys) = self.args.testsys().await?;
+        testsys.arg("status");
+        for (flag, value) in [
+            ("--arch", &self.arch),
+            ("--variant", &self.variant),
+            ("--output", &self.output),
+        ] {
+            if let Some(value) = value {
+                testsys.arg(flag).arg(value);
+            }
+        }
+        for (flag, set) in [
+            ("--test", self.test),
+            ("--passed", self.passed),
+            ("--failed", self.failed),
+            ("--running", self.running),
+        ] {
+            if set {
+                testsys.arg(flag);
+            }
+        }
+        // The status is what was asked for, so it is shown at any log level.
+        exec(&mut testsys, false).await?;
+        Ok(())
+    }
+}
+
+/// Show the logs of a test or resource in the testsys cluster.
+#[derive(Debug, Parser)]
+pub(crate) struct TestLogs {
+    #[clap(flatten)]
+    args: TestsysArgs,
+
+    /// The name of the test to show the logs of.
+    #[clap(
+        long = "test",
+        conflicts_with = "resource",
+        required_unless_present = "resource"
+    )]
+    test: Option<String>,
+
+    /// The name of the resource to show the logs of.
+    #[clap(long = "resource", requires = "state")]
+    resource: Option<String>,
+
+    /// The state of the resource to show the logs of: `Creation` or `Destruction`.
+    #[clap(long = "state", conflicts_with = "test")]
+    state: Option<String>,
+
+    /// Keep streaming the logs as they are written.
+    #[clap(long = "follow", short = 'f')]
+    follow: bool,
+}
+
+impl TestLogs {
+    pub(super) async fn run(&self) -> Result<()> {
+        let (_, _tools, mut testsys) = self.args.testsys().await?;
+        testsys.arg("logs");
+        for (flag, value) in [
+            ("--test", &self.test),
+            ("--resource", &self.resource),
+            ("--state", &self.state),
+        ] {
+            if let Some(value) = value {
+                testsys.arg(flag).arg(value);
+            }
+        }
+        if self.follow {
+            testsys.arg("--follow");
+        }
+        exec(&mut testsys, false).await?;
+        Ok(())
+    }
+}
+
+impl TestsysArgs {
+    /// Load the project, install the tools, and return a `testsys` command for its cluster. The tools
+    /// stay installed while the returned `InstalledTools` is held.
+    async fn testsys(&self) -> Result<(Project, InstalledTools, Command)> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let project_dir = project.project_dir();
+        let toolsdir = project_dir.join("build/tools");
+        let tools = install_tools(
+            &toolsdir,
+            &ToolOverrides::resolve(project.tool_overrides())?,
+        )
+        .await?;
+
+        let mut testsys = Command::new(toolsdir.join("testsys"));
+        testsys.arg("--log-level").arg(log::max_level().to_string());
+        let kubeconfig = match &self.kubeconfig {
+            Some(kubeconfig) => Some(kubeconfig.clone()),
+            None => find_one(&[
+                project_dir.join("tests").join("testsys.kubeconfig"),
+                project_dir.join("testsys.kubeconfig"),
+            ])?,
+        };
+        if let Some(kubeconfig) = kubeconfig {
+            testsys.arg("--kubeconfig").arg(kubeconfig);
+        }
+        Ok((project, tools, testsys))
+    }
+}
+
+/// Find the one file of `candidates` that exists, if any. It is an error for more than one to
+/// exist, since it would be unclear which is meant.
+fn find_one(candidates: &[PathBuf]) -> Result<Option<PathBuf>> {
+    let found = candidates
+        .iter()
+        .filter(|path| path.metadata().map(|m| m.len() > 0).unwrap_or(false))
+        .collect::<Vec<_>>();
+    match found.as_slice() {
+        [] => Ok(None),
+        [path] => Ok(Some(path.to_path_buf())),
+        _ => bail!(
+            "Found more than one of {}, remove all but one or choose one with its argument",
+            found
+                .iter()
+                .map(|path| format!("'{}'", path.display()))
+                .collect::<Vec<_>>()
+                .join(" and ")
+        ),
+    }
+}
+
+#[test]
+fn test_find_one() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let a = tempdir.path().join("a");
+    let b = tempdir.path().join("b");
+    let candidates = [a.clone(), b.clone()];
+    assert_eq!(find_one(&candidates).unwrap(), None);
+
+    std::fs::write(&b, "b").unwrap();
+    assert_eq!(find_one(&candidates).unwrap().as_deref(), Some(b.as_path()));
+
+    // Empty files are ignored.
+    std::fs::write(&a, "").unwrap();
+    assert_eq!(find_one(&candidates).unwrap().as_deref(), Some(b.as_path()));
+
+    std::fs::write(&a, "a").unwrap();
+    assert!(find_one(&candidates).is_err());
+}
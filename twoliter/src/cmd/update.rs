@@ -0,0 +1,615 @@
+use crate::docker::{self, ImageUri};
+use crate::kit::{self, DockerKitMetadataSource, KitDependency, KitMetadataSource, ResolvedKits};
+use crate::lock::{self, Lock, LOCK_FILE};
+use crate::project;
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use log::{debug, info};
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+/// The architecture used to resolve kits when there is no lock file to say which architectures the
+/// project is built for.
+const DEFAULT_ARCH: &str = "x86_64";
+
+/// Update the SDK and kits in Twoliter.lock to the newest versions allowed by Twoliter.toml. All
+/// kits must be built with the same SDK, and must agree on the versions of the kits they share.
+#[derive(Debug, Parser)]
+pub(crate) struct Update {
+    /// Path to Twoliter.toml. Will search for Twoliter.toml when absent.
+    #[clap(long = "project-path")]
+    project_path: Option<PathBuf>,
+
+    /// Only update this kit. The SDK and the other kits keep their locked versions.
+    #[clap(long = "package")]
+    package: Option<String>,
+
+    /// Show what would be updated without changing Twoliter.lock.
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+}
+
+impl Update {
+    pub(super) async fn run(&self) -> Result<()> {
+        let project = project::load_or_find_project(self.project_path.clone()).await?;
+        let declared_sdk = project.sdk().context(format!(
+            "No SDK defined in {}",
+            project.filepath().display(),
+        ))?;
+        let path = Lock::path(&project);
+        // A stale lock is fine, updating it is how it becomes current again.
+        let current = if path.is_file() {
+            Some(Lock::load(&path).await?)
+        } else {
+            None
+        };
+        let arches: Vec<String> = match current.as_ref().map(Lock::arches) {
+            Some(arches) if !arches.is_empty() => arches.into_iter().collect(),
+            _ => vec![DEFAULT_ARCH.to_string()],
+        };
+
+        if let Some(package) = &self.package {
+            ensure!(
+                project.kits().iter().any(|kit| &kit.repo == package),
+                "The kit '{package}' is not declared in Twoliter.toml"
+            );
+        }
+
+        // Start from the locked versions wherever they still satisfy Twoliter.toml.
+        let locked_sdk = current
+            .as_ref()
+            .map(Lock::sdk)
+            .filter(|sdk| lock::sdk_satisfies(&declared_sdk, sdk));
+        let starting: Vec<KitDependency> = project
+            .kits()
+            .iter()
+            .map(|declared| {
+                current
+                    .iter()
+                    .flat_map(Lock::kits)
+                    .find(|locked| {
+                        locked.repo == declared.repo
+                            && locked.registry == declared.registry
+                            && lock::compatible_versions(&declared.version).matches(&locked.version)
+                    })
+                    .unwrap_or(declared)
+                    .clone()
+            })
+            .collect();
+
+        // Kits are never moved to a version older than the one they start from.
+        let mut candidates = Vec::new();
+        for kit in &starting {
+            let versions = match &self.package {
+                Some(package) if package != &kit.repo => vec![kit.version.clone()],
+                _ => kit_versions(kit, &arches)
+                    .await?
+                    .into_iter()
+                    .filter(|version| version >= &kit.version)
+                    .collect(),
+            };
+            ensure!(
+                !versions.is_empty(),
+                "Unable to find a version of kit '{}' for {} that satisfies Twoliter.toml",
+                kit.repo,
+                arches.join(", ")
+            );
+            candidates.push(versions);
+        }
+        let sdk_constraint = SdkConstraint {
+            declared: declared_sdk.clone(),
+            exact_tag: match (&self.package, locked_sdk) {
+                (Some(_), Some(locked)) => Some(locked.tag.clone()),
+                _ => None,
+            },
+        };
+
+        let source = DockerKitMetadataSource::new(&project);
+        let (kits, resolved) =
+            select_versions(&source, &starting, &candidates, &sdk_constraint, &arches[0])
+                .await
+                .context("Unable to update the kits")?;
+        let sdk = match resolved.sdk.as_deref() {
+            Some(kit_sdk) => {
+                let tag = kit::kit_sdk_tag(kit_sdk, &declared_sdk, &arches[0])
+                    .context(format!("Unexpected kit SDK '{kit_sdk}'"))?;
+                ImageUri {
+                    tag: tag.to_string(),
+                    ..declared_sdk.clone()
+                }
+            }
+            None => match &sdk_constraint.exact_tag {
+                Some(tag) => ImageUri {
+                    tag: tag.clone(),
+                    ..declared_sdk.clone()
+                },
+                None => newest_sdk(&declared_sdk).await?,
+            },
+        };
+
+        let before = current.as_ref().map(versions).unwrap_or_default();
+        let mut after = BTreeMap::new();
+        after.insert(sdk.repo.clone(), sdk.tag.clone());
+        for kit in resolved.kits.values() {
+            after.insert(
+                kit.dependency.repo.clone(),
+                kit.dependency.version.to_string(),
+            );
+        }
+        println!("{}", format_table(&before, &after));
+
+        if self.dry_run {
+            info!("Not writing '{}' because of --dry-run", path.display());
+            return Ok(());
+        }
+        let lock = Lock::resolve(&project, sdk, &kits, &arches).await?;
+        lock.save(&path).await?;
+        info!("Updated '{LOCK_FILE}'");
+        Ok(())
+    }
+}
+
+/// The SDK versions that the kits may be built with.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct SdkConstraint {
+    /// The SDK declared in Twoliter.toml.
+    declared: ImageUri,
+    /// When set, the kits must be built with the SDK that has exactly this tag. Otherwise any SDK
+    /// that satisfies the declared SDK will do.
+    exact_tag: Option<String>,
+}
+
+impl SdkConstraint {
+    fn allows(&self, kit_sdk: &str, arch: &str) -> bool {
+        let Some(tag) = kit::kit_sdk_tag(kit_sdk, &self.declared, arch) else {
+            return false;
+        };
+        match &self.exact_tag {
+            Some(exact) => tag == exact,
+            None => lock::sdk_satisfies(
+                &self.declared,
+                &ImageUri {
+                    tag: tag.to_string(),
+                    ..self.declared.clone()
+                },
+            ),
+        }
+    }
+}
+
+/// Choose versions for the kits from `candidates`, which are sorted newest first, such that the
+/// kits resolve together. Combinations are tried in order of preference, from the newest version of
+/// the first kit down, skipping any combination whose leading kits already fail to resolve.
+async fn select_versions<S>(
+    source: &S,
+    kits: &[KitDependency],
+    candidates: &[Vec<Version>],
+    sdk: &SdkConstraint,
+    arch: &str,
+) -> Result<(Vec<KitDependency>, ResolvedKits)>
+where
+    S: KitMetadataSource,
+{
+    let mut index = vec![0; kits.len()];
+    let mut depth = 0;
+    let mut resolved = ResolvedKits::default();
+    while depth < kits.len() {
+        if index[depth] == candidates[depth].len() {
+            ensure!(
+                depth > 0,
+                "Unable to find versions of the kits that work together"
+            );
+            index[depth] = 0;
+            depth -= 1;
+            index[depth] += 1;
+            continue;
+        }
+        let trial = chosen(kits, candidates, &index[..=depth]);
+        match try_resolve(source, &trial, sdk, arch).await {
+            Ok(trial_resolved) => {
+                resolved = trial_resolved;
+                depth += 1;
+            }
+            Err(e) => {
+                debug!("Kits {} do not work together: {e}", list(&trial));
+                index[depth] += 1;
+            }
+        }
+    }
+    Ok((chosen(kits, candidates, &index), resolved))
+}
+
+/// The first `index.len()` kits at the versions chosen by `index`.
+fn chosen(
+    kits: &[KitDependency],
+    candidates: &[Vec<Version>],
+    index: &[usize],
+) -> Vec<KitDependency> {
+    index
+        .iter()
+        .enumerate()
+        .map(|(i, &j)| KitDependency {
+            version: candidates[i][j].clone(),
+            ..kits[i].clone()
+        })
+        .collect()
+}
+
+fn list(kits: &[KitDependency]) -> String {
+    kits.iter()
+        .map(|kit| format!("'{kit}'"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Resolve `kits` and make sure that they were built with an SDK allowed by `sdk`.
+async fn try_resolve<S>(
+    source: &S,
+    kits: &[KitDependency],
+    sdk: &SdkConstraint,
+    arch: &str,
+) -> Result<ResolvedKits>
+where
+    S: KitMetadataSource,
+{
+    let resolved = ResolvedKits::resolve(source, kits, arch).await?;
+    if let Some(kit_sdk) = &resolved.sdk {
+        ensure!(
+            sdk.allows(kit_sdk, arch),
+            "The kits were built with SDK '{kit_sdk}', which does not satisfy '{}'",
+            sdk.declared
+        );
+    }
+    Ok(resolved)
+}
+
+/// The versions of `kit` that are published for all of `arches` and satisfy its version in
+/// Twoliter.toml, newest first.
+async fn kit_versions(kit: &KitDependency, arches: &[String]) -> Result<Vec<Version>> {
+    let mut common: Option<BTreeSet<Version>> = None;
+    for arch in arches {
+        let tags = docker::list_tags(&kit.image(arch)).await?;
+        let versions: BTreeSet<Version> =
+            compatible_tags(&tags, &kit.version).into_iter().collect();
+        common = Some(match common {
+            None => versions,
+            Some(common) => common.intersection(&versions).cloned().collect(),
+        });
+    }
+    Ok(common.unwrap_or_default().into_iter().rev().collect())
+}
+
+/// The newest tag of the SDK repository that satisfies the SDK in Twoliter.toml.
+async fn newest_sdk(declared: &ImageUri) -> Result<ImageUri> {
+    let Ok(version) = kit::parse_version_tag(&declared.tag) else {
+        // A tag like `latest` cannot be updated.
+        return Ok(declared.clone());
+    };
+    let tags = docker::list_tags(declared).await?;
+    let Some(newest) = compatible_tags(&tags, &version).into_iter().next() else {
+        bail!("Unable to find a tag for SDK '{declared}' in its registry");
+    };
+    Ok(ImageUri {
+        tag: format!("v{newest}"),
+        ..declared.clone()
+    })
+}
+
+/// The versions in `tags` that satisfy `minimum`, newest first. Kit `-metadata` tags and pre-release
+/// versions are skipped, unless `minimum` is itself a pre-release.
+fn compatible_tags(tags: &[String], minimum: &Version) -> Vec<Version> {
+    let requirement = lock::compatible_versions(minimum);
+    let mut versions: Vec<Version> = tags
+        .iter()
+        .filter_map(|tag| kit::parse_version_tag(tag).ok())
+        .filter(|version| version.pre.is_empty() || !minimum.pre.is_empty())
+        .filter(|version| requirement.matches(version))
+        .collect();
+    versions.sort();
+    versions.dedup();
+    versions.reverse();
+    versions
+}
+
+/// The locked version of the SDK and each kit, keyed by name.
+fn versions(lock: &Lock) -> BTreeMap<String, String> {
+    let mut versions = BTreeMap::new();
+    versions.insert(lock.sdk().repo.clone(), lock.sdk().tag.clone());
+    for kit in lock.kits() {
+        versions.insert(kit.repo.clone(), kit.version.to_string());
+    }
+    versions
+}
+
+/// Format the versions before and after the update as a table.
+fn format_table(before: &BTreeMap<String, String>, after: &BTreeMap<String, String>) -> String {
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let mut rows = vec![("NAME", "BEFORE", "AFTER")];
+    for name in names {
+        rows.push((
+            name.as_str(),
+            before.get(name).map(String::as_str).unwrap_or("-"),
+            after.get(name).map(String::as_str).unwrap_or("-"),
+        ));
+    }
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let before_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(name, before, after)| {
+            format!("{name:name_width$}  {before:before_width$}  {after}")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kit::KitMetadata;
+    use std::collections::HashMap;
+
+    const SDK_REPO: &str = "example.com/bottlerocket-sdk-x86_64";
+
+    /// Serves kit metadata from memory, keyed by kit name and version.
+    struct TestSource(HashMap<(String, Version), KitMetadata>);
+
+    impl TestSource {
+        fn new(kits: &[(&str, &str, &str, &[&str])]) -> Self {
+            Self(
+                kits.iter()
+                    .map(|(name, version, sdk, dependencies)| {
+                        let metadata = KitMetadata {
+                            name: name.to_string(),
+                            version: format!("v{version}"),
+                            arch: "x86_64".to_string(),
+                            sdk: format!("{SDK_REPO}:{sdk}"),
+                            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+                        };
+                        (
+                            (name.to_string(), Version::parse(version).unwrap()),
+                            metadata,
+                        )
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    impl KitMetadataSource for TestSource {
+        async fn fetch(&self, kit: &KitDependency, _: &str) -> Result<KitMetadata> {
+            self.0
+                .get(&(kit.repo.clone(), kit.version.clone()))
+                .cloned()
+                .context(format!("No such kit '{kit}'"))
+        }
+    }
+
+    fn dependency(repo: &str, version: &str) -> KitDependency {
+        KitDependency {
+            registry: Some("example.com".to_string()),
+            repo: repo.to_string(),
+            version: Version::parse(version).unwrap(),
+        }
+    }
+
+    fn versions(versions: &[&str]) -> Vec<Version> {
+        versions
+            .iter()
+            .map(|v| Version::parse(v).unwrap())
+            .collect()
+    }
+
+    fn sdk_constraint(exact_tag: Option<&str>) -> SdkConstraint {
+        SdkConstraint {
+            declared: ImageUri::new(
+                Some("example.com".to_string()),
+                "bottlerocket-sdk",
+                "v0.50.0",
+            ),
+            exact_tag: exact_tag.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn tags_are_filtered() {
+        let tags: Vec<String> = [
+            "v1.0.0",
+            "v1.2.0",
+            "v1.2.0-metadata",
+            "v1.3.0-rc1",
+            "v2.0.0",
+            "latest",
+            "v0.9.0",
+        ]
+        .iter()
+        .map(|t| t.to_string())
+        .collect();
+        assert_eq!(
+            compatible_tags(&tags, &Version::new(1, 0, 0)),
+            versions(&["1.2.0", "1.0.0"])
+        );
+    }
+
+    #[tokio::test]
+    async fn select_newest() {
+        let source = TestSource::new(&[
+            ("a-kit", "1.0.0", "v0.50.0", &[]),
+            ("a-kit", "1.1.0", "v0.50.1", &[]),
+            ("a-kit", "1.2.0", "v0.51.0", &[]),
+        ]);
+        let (kits, resolved) = select_versions(
+            &source,
+            &[dependency("a-kit", "1.0.0")],
+            &[versions(&["1.2.0", "1.1.0", "1.0.0"])],
+            &sdk_constraint(None),
+            "x86_64",
+        )
+        .await
+        .unwrap();
+        // 1.2.0 was built with an SDK that does not satisfy v0.50.0.
+        assert_eq!(kits, vec![dependency("a-kit", "1.1.0")]);
+        assert_eq!(resolved.sdk, Some(format!("{SDK_REPO}:v0.50.1")));
+    }
+
+    #[tokio::test]
+    async fn select_shared_dependency() {
+        let source = TestSource::new(&[
+            (
+                "a-kit",
+                "1.0.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.0.0"],
+            ),
+            (
+                "a-kit",
+                "1.1.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.1.0"],
+            ),
+            (
+                "b-kit",
+                "1.0.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.0.0"],
+            ),
+            (
+                "b-kit",
+                "1.1.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.1.0"],
+            ),
+            ("c-kit", "1.0.0", "v0.50.0", &[]),
+            ("c-kit", "1.1.0", "v0.50.0", &[]),
+        ]);
+        let (kits, resolved) = select_versions(
+            &source,
+            &[dependency("a-kit", "1.0.0"), dependency("b-kit", "1.0.0")],
+            &[versions(&["1.1.0", "1.0.0"]), versions(&["1.1.0", "1.0.0"])],
+            &sdk_constraint(None),
+            "x86_64",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            kits,
+            vec![dependency("a-kit", "1.1.0"), dependency("b-kit", "1.1.0")]
+        );
+        assert_eq!(
+            resolved.kits["c-kit"].dependency,
+            dependency("c-kit", "1.1.0")
+        );
+    }
+
+    #[tokio::test]
+    async fn select_backtracks() {
+        let source = TestSource::new(&[
+            (
+                "a-kit",
+                "1.0.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.0.0"],
+            ),
+            (
+                "a-kit",
+                "1.1.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.1.0"],
+            ),
+            (
+                "b-kit",
+                "1.0.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.0.0"],
+            ),
+            ("c-kit", "1.0.0", "v0.50.0", &[]),
+            ("c-kit", "1.1.0", "v0.50.0", &[]),
+        ]);
+        let (kits, _) = select_versions(
+            &source,
+            &[dependency("a-kit", "1.0.0"), dependency("b-kit", "1.0.0")],
+            &[versions(&["1.1.0", "1.0.0"]), versions(&["1.0.0"])],
+            &sdk_constraint(None),
+            "x86_64",
+        )
+        .await
+        .unwrap();
+        // a-kit 1.1.0 needs a newer c-kit than b-kit can use.
+        assert_eq!(
+            kits,
+            vec![dependency("a-kit", "1.0.0"), dependency("b-kit", "1.0.0")]
+        );
+    }
+
+    #[tokio::test]
+    async fn select_impossible() {
+        let source = TestSource::new(&[
+            (
+                "a-kit",
+                "1.0.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.1.0"],
+            ),
+            (
+                "b-kit",
+                "1.0.0",
+                "v0.50.0",
+                &["example.com/c-kit-x86_64:v1.0.0"],
+            ),
+            ("c-kit", "1.0.0", "v0.50.0", &[]),
+            ("c-kit", "1.1.0", "v0.50.0", &[]),
+        ]);
+        assert!(select_versions(
+            &source,
+            &[dependency("a-kit", "1.0.0"), dependency("b-kit", "1.0.0")],
+            &[versions(&["1.0.0"]), versions(&["1.0.0"])],
+            &sdk_constraint(None),
+            "x86_64",
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn select_exact_sdk() {
+        let source = TestSource::new(&[
+            ("a-kit", "1.0.0", "v0.50.0", &[]),
+            ("a-kit", "1.0.1", "v0.50.1", &[]),
+        ]);
+        let (kits, _) = select_versions(
+            &source,
+            &[dependency("a-kit", "1.0.0")],
+            &[versions(&["1.0.1", "1.0.0"])],
+            &sdk_constraint(Some("v0.50.0")),
+            "x86_64",
+        )
+        .await
+        .unwrap();
+        assert_eq!(kits, vec![dependency("a-kit", "1.0.0")]);
+    }
+
+    #[test]
+    fn table() {
+        let before = [("a-kit", "1.0.0"), ("bottlerocket-sdk", "v0.50.0")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let after = [
+            ("a-kit", "1.1.0"),
+            ("b-kit", "2.0.0"),
+            ("bottlerocket-sdk", "v0.50.0"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(
+            format_table(&before, &after),
+            "NAME              BEFORE   AFTER\n\
+             a-kit             1.0.0    1.1.0\n\
+             b-kit             -        2.0.0\n\
+             bottlerocket-sdk  v0.50.0  v0.50.0"
+        );
+    }
+}
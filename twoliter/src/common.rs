@@ -163,6 +163,23 @@ pub(crate) mod fs {
         ))
     }
 
+    /// Point the symlink at `link` to `original`, replacing whatever is at `link`, like `ln -sfn`.
+    pub(crate) async fn replace_symlink(
+        original: impl AsRef<Path>,
+        link: impl AsRef<Path>,
+    ) -> Result<()> {
+        let original = original.as_ref();
+        let link = link.as_ref();
+        if fs::symlink_metadata(link).await.is_ok() {
+            remove_file(link).await?;
+        }
+        fs::symlink(original, link).await.context(format!(
+            "Unable to link '{}' to '{}'",
+            link.display(),
+            original.display()
+        ))
+    }
+
     pub(crate) async fn write<P, C>(path: P, contents: C) -> Result<()>
     where
         P: AsRef<Path>,
@@ -1 +1,135 @@
+use super::runtime::{command, commands};
+use crate::common::{exec, exec_log};
+use crate::docker::ImageUri;
+use anyhow::{Context, Result};
+use container_runtime::Build;
+use log::debug;
+use std::fmt::Display;
+use std::path::Path;
 
+/// Pull an image from its registry with the container runtime. `image` can be anything the runtime
+/// accepts as an image reference, e.g. an [`ImageUri`] or a `repo@sha256:...` string.
+pub(crate) async fn pull(image: impl Display) -> Result<()> {
+    debug!("Pulling docker image '{image}'");
+    exec_log(&mut command(commands().pull(&image.to_string())))
+        .await
+        .context(format!("Unable to pull docker image '{image}'"))
+}
+
+/// Returns the ID of the local image named by `reference`, or `None` if there is no such image.
+pub(crate) async fn image_id(reference: impl Display) -> Result<Option<String>> {
+    let output = exec(
+        &mut command([
+            "image",
+            "inspect",
+            "--format",
+            "{{.Id}}",
+            reference.to_string().as_str(),
+        ]),
+        true,
+    )
+    .await;
+    // `image inspect` fails when the image does not exist.
+    Ok(output.ok().flatten().map(|id| id.trim().to_string()))
+}
+
+/// Pull `image` unless it already exists locally.
+pub(crate) async fn ensure_image(image: &ImageUri) -> Result<()> {
+    if image_id(image).await?.is_none() {
+        pull(image).await?;
+    }
+    Ok(())
+}
+
+/// Returns the manifest digest of the local image `image`. An image that was built locally and has
+/// never been pushed or pulled has no manifest digest, so its image ID is returned instead.
+pub(crate) async fn image_digest(image: &ImageUri) -> Result<String> {
+    let output = exec(
+        &mut command([
+            "image",
+            "inspect",
+            "--format",
+            "{{json .RepoDigests}} {{.Id}}",
+            image.uri().as_str(),
+        ]),
+        true,
+    )
+    .await
+    .context(format!("Unable to inspect docker image '{image}'"))?
+    .unwrap_or_default();
+    let (repo_digests, id) = output
+        .trim()
+        .rsplit_once(' ')
+        .context(format!("Unexpected output inspecting '{image}': {output}"))?;
+    let repo_digests: Vec<String> = serde_json::from_str(repo_digests).context(format!(
+        "Unable to parse the repo digests of docker image '{image}'"
+    ))?;
+    Ok(find_repo_digest(image, &repo_digests).unwrap_or_else(|| id.to_string()))
+}
+
+/// Returns a reference for `image` that is guaranteed to resolve to `digest`, which was recorded by
+/// [`image_digest`]. The image is pulled by digest if it is not already present.
+pub(crate) async fn pinned_image(image: &ImageUri, digest: &str) -> Result<String> {
+    // Images that were built locally are pinned by image ID. They cannot be pulled, so all we can
+    // do is make sure that the tag still refers to the same image.
+    if image_id(image).await?.as_deref() == Some(digest) {
+        return Ok(image.uri());
+    }
+    let reference = image.digest_uri(digest);
+    if image_id(&reference).await?.is_none() {
+        pull(&reference).await.context(format!(
+            "The image '{image}' is pinned to digest '{digest}', but that digest is not available"
+        ))?;
+    }
+    Ok(reference)
+}
+
+/// Build the image `tag` from `dockerfile` with the container runtime, using `context` as the build
+/// context.
+pub(crate) async fn build(
+    context: &Path,
+    dockerfile: &Path,
+    tag: &str,
+    build_args: &[(&str, &str)],
+) -> Result<()> {
+    debug!("Building docker image '{tag}'");
+    let runtime = commands();
+    let args = runtime.build(&Build {
+        context: context.to_path_buf(),
+        dockerfile: dockerfile.to_path_buf(),
+        tag: tag.to_string(),
+        build_args: build_args
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        ..Default::default()
+    });
+    exec_log(command(args).envs(runtime.build_env()))
+        .await
+        .context(format!("Unable to build docker image '{tag}'"))
+}
+
+/// Docker lists the digests of an image as `registry/repo@sha256:...`, one for each repository the
+/// image was pulled from or pushed to. Find the one for `image`'s repository.
+fn find_repo_digest(image: &ImageUri, repo_digests: &[String]) -> Option<String> {
+    let name = image.name();
+    repo_digests
+        .iter()
+        .filter_map(|repo_digest| repo_digest.split_once('@'))
+        .find(|(repo, _)| *repo == name)
+        .map(|(_, digest)| digest.to_string())
+}
+
+#[test]
+fn test_find_repo_digest() {
+    let image = ImageUri::new(Some("example.com/a".to_string()), "sdk", "v0.1.0");
+    let repo_digests = vec![
+        "other.com/a/sdk@sha256:1111".to_string(),
+        "example.com/a/sdk@sha256:2222".to_string(),
+    ];
+    assert_eq!(
+        find_repo_digest(&image, &repo_digests).as_deref(),
+        Some("sha256:2222")
+    );
+    assert_eq!(find_repo_digest(&image, &repo_digests[..1]), None);
+}
@@ -1,15 +1,15 @@
+use super::runtime::{command, commands};
 use crate::common::exec;
 use anyhow::Result;
 use log::{debug, log, Level};
 use std::path::Path;
-use tokio::process::Command;
 
 pub(crate) struct DockerContainer {
     name: String,
 }
 
 impl DockerContainer {
-    /// Create a docker image with the given name from the image by using `docker create`.
+    /// Create a container with the given name from the image with the container runtime.
     pub(crate) async fn new<S1, S2>(container_name: S1, image: S2) -> Result<Self>
     where
         S1: Into<String>,
@@ -23,16 +23,10 @@ impl DockerContainer {
 
         debug!("Creating docker container '{name}' from image '{image}'");
 
-        // Create the new container.
-        let args = vec![
-            "create".to_string(),
-            "--rm".to_string(),
-            "--name".to_string(),
-            name.to_string(),
-            image.to_string(),
-        ];
-
-        exec(Command::new("docker").args(args), true).await?;
+        // Create the new container. It is never started, so the command only needs to satisfy
+        // images that have no default command, such as kits, which are built `FROM scratch`.
+        let args = commands().create(&name, &image, &["true"]);
+        exec(&mut command(args), true).await?;
         Ok(Self { name })
     }
 
@@ -48,10 +42,8 @@ impl DockerContainer {
             self.name,
             dest.as_ref().display()
         );
-        let mut args = vec!["cp".to_string()];
-        args.push(format!("{}:{}", self.name, src.as_ref().display()));
-        args.push(dest.as_ref().display().to_string());
-        exec(Command::new("docker").args(args), true).await?;
+        let args = commands().cp(&self.name, src.as_ref(), dest.as_ref());
+        exec(&mut command(args), true).await?;
         Ok(())
     }
 }
@@ -64,12 +56,10 @@ impl Drop for DockerContainer {
 }
 
 async fn cleanup_container(name: &str, log_level: Level) {
-    let args = vec!["stop".to_string(), name.to_string()];
-    if let Err(e) = exec(Command::new("docker").args(args), true).await {
+    if let Err(e) = exec(&mut command(commands().stop(name)), true).await {
         log!(log_level, "Unable to stop container '{}': {e}", name)
     }
-    let args = vec!["rm".to_string(), name.to_string()];
-    if let Err(e) = exec(Command::new("docker").args(args), true).await {
+    if let Err(e) = exec(&mut command(commands().rm(name)), true).await {
         log!(log_level, "Unable to remove container '{}': {e}", name)
     }
 }
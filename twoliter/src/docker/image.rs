@@ -1,5 +1,7 @@
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 /// Represents a docker image URI such as `public.ecr.aws/myregistry/myrepo:v0.1.0`. The registry is
 /// optional as it is when using `docker`. That is, it will be looked for locally first, then at
@@ -16,7 +18,6 @@ pub(crate) struct ImageUri {
 
 impl ImageUri {
     /// Create a new `ImageUri`.
-    #[allow(unused)]
     pub(crate) fn new<S1, S2>(registry: Option<String>, repo: S1, tag: S2) -> Self
     where
         S1: AsRef<str>,
@@ -36,6 +37,20 @@ impl ImageUri {
             Some(registry) => format!("{}/{}:{}", registry, self.repo, self.tag),
         }
     }
+
+    /// Returns the image name without a tag, e.g. `public.ecr.aws/myregistry/myrepo`
+    pub(crate) fn name(&self) -> String {
+        match &self.registry {
+            None => self.repo.clone(),
+            Some(registry) => format!("{}/{}", registry, self.repo),
+        }
+    }
+
+    /// Returns a reference to this image's repository by digest, e.g.
+    /// `public.ecr.aws/myregistry/myrepo@sha256:...`
+    pub(crate) fn digest_uri(&self, digest: &str) -> String {
+        format!("{}@{}", self.name(), digest)
+    }
 }
 
 impl Display for ImageUri {
@@ -44,6 +59,23 @@ impl Display for ImageUri {
     }
 }
 
+impl FromStr for ImageUri {
+    type Err = anyhow::Error;
+
+    /// Parse an image URI such as `public.ecr.aws/myregistry/myrepo:v0.1.0`. The tag is required.
+    fn from_str(uri: &str) -> Result<Self> {
+        let (name, tag) = uri
+            .rsplit_once(':')
+            .filter(|(_, tag)| !tag.contains('/'))
+            .context(format!("The image '{uri}' does not have a tag"))?;
+        let (registry, repo) = match name.rsplit_once('/') {
+            Some((registry, repo)) => (Some(registry.to_string()), repo),
+            None => (None, name),
+        };
+        Ok(Self::new(registry, repo, tag))
+    }
+}
+
 impl From<ImageUri> for String {
     fn from(value: ImageUri) -> Self {
         value.to_string()
@@ -65,3 +97,25 @@ fn image_uri_with_registry() {
     let expected = "example.com/a/b/c/foo:v1.2.3";
     assert_eq!(expected, formatted);
 }
+
+#[test]
+fn image_uri_digest() {
+    let uri = ImageUri::new(Some("example.com".to_string()), "foo", "v1.2.3");
+    assert_eq!(uri.digest_uri("sha256:abcd"), "example.com/foo@sha256:abcd");
+}
+
+#[test]
+fn image_uri_parse() {
+    let uri: ImageUri = "public.ecr.aws/bottlerocket/bottlerocket-sdk:v0.50.0"
+        .parse()
+        .unwrap();
+    assert_eq!(uri.registry.as_deref(), Some("public.ecr.aws/bottlerocket"));
+    assert_eq!(uri.repo, "bottlerocket-sdk");
+    assert_eq!(uri.tag, "v0.50.0");
+
+    let uri: ImageUri = "localhost:5000/foo:latest".parse().unwrap();
+    assert_eq!(uri.registry.as_deref(), Some("localhost:5000"));
+    assert_eq!(uri.repo, "foo");
+
+    assert!("localhost:5000/foo".parse::<ImageUri>().is_err());
+}
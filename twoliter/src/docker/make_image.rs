@@ -0,0 +1,113 @@
+//! The image that `cargo make` runs in when Twoliter runs it in a container instead of on the host.
+
+use crate::common::fs;
+use crate::docker::commands::{build, image_id};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::env::consts::ARCH;
+use tempfile::TempDir;
+
+const DOCKERFILE: &str = include_str!("Twoliter.dockerfile");
+
+/// The repository that Twoliter tags its `cargo make` images with.
+const MAKE_IMAGE_REPO: &str = "twoliter-make";
+
+/// The version of the static docker CLI that is installed in the image. It talks to the host's
+/// docker daemon through its socket, so it only needs to be new enough for the daemon's API.
+const DOCKER_CLI_VERSION: &str = "27.3.1";
+
+/// The SHA-256 of Docker's static CLI archive of `DOCKER_CLI_VERSION`, for each architecture that
+/// it is pinned for. Each one comes from the archive on Docker's download site, and has to be
+/// updated along with the version.
+const DOCKER_CLI_SHA256: &[(&str, &str)] = &[];
+
+/// Returns the `cargo make` image for `sdk`, building it if it does not exist yet. Images are
+/// tagged by a hash of everything they are built from, so they are only built once for each SDK
+/// and version of Twoliter.
+pub(crate) async fn make_image(sdk: &str) -> Result<String> {
+    let (url, sha256) = docker_cli(ARCH)?;
+    let mut d = Sha256::new();
+    d.update(env!("CARGO_PKG_VERSION"));
+    d.update(sdk);
+    d.update(DOCKERFILE);
+    d.update(&url);
+    d.update(sha256);
+    let tag = format!("{MAKE_IMAGE_REPO}:{}", &hex::encode(d.finalize())[..16]);
+    if image_id(&tag).await?.is_some() {
+        return Ok(tag);
+    }
+
+    let context = TempDir::new().context("Unable to create a tempdir for the cargo make image")?;
+    let dockerfile = context.path().join("Dockerfile");
+    fs::write(&dockerfile, DOCKERFILE).await?;
+    build(
+        context.path(),
+        &dockerfile,
+        &tag,
+        &[
+            ("BASE", sdk),
+            ("DOCKER_CLI_URL", &url),
+            ("DOCKER_CLI_SHA256", sha256),
+        ],
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Unable to install docker CLI {DOCKER_CLI_VERSION} in the cargo make image. It is \
+            downloaded from '{url}' and checked against its pinned SHA-256, so the build needs \
+            network access to download.docker.com"
+        )
+    })?;
+    Ok(tag)
+}
+
+/// The URL and SHA-256 of the static docker CLI archive for `arch`, which is named the way both
+/// Rust and Docker name it.
+fn docker_cli(arch: &str) -> Result<(String, &'static str)> {
+    let (_, sha256) = DOCKER_CLI_SHA256
+        .iter()
+        .find(|(pinned, _)| *pinned == arch)
+        .with_context(|| {
+            format!(
+                "Unable to run cargo make in a container on '{arch}', which has no pinned docker \
+                CLI to install in it"
+            )
+        })?;
+    let url = format!(
+        "https://download.docker.com/linux/static/stable/{arch}/docker-{DOCKER_CLI_VERSION}.tgz"
+    );
+    Ok((url, sha256))
+}
+
+#[test]
+// The digests of the x86_64 and aarch64 archives have not been pinned yet, and `--in-container`
+// fails until they are.
+#[ignore = "the SHA-256 of Docker's static CLI archives is not pinned yet"]
+fn test_docker_cli() {
+    for arch in ["x86_64", "aarch64"] {
+        let (url, sha256) = docker_cli(arch).unwrap();
+        assert_eq!(
+            url,
+            format!(
+                "https://download.docker.com/linux/static/stable/{arch}/docker-{DOCKER_CLI_VERSION}.tgz"
+            )
+        );
+        assert!(
+            sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit()),
+            "{arch}: {sha256}"
+        );
+        assert_eq!(
+            DOCKER_CLI_SHA256
+                .iter()
+                .filter(|(pinned, _)| *pinned == arch)
+                .collect::<Vec<_>>(),
+            [&(arch, sha256)]
+        );
+    }
+    assert_eq!(DOCKER_CLI_SHA256.len(), 2);
+}
+
+#[test]
+fn test_docker_cli_unpinned() {
+    assert!(docker_cli("s390x").is_err());
+}
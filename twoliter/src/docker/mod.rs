@@ -1,6 +1,13 @@
 mod commands;
 mod container;
 mod image;
+mod make_image;
+mod registry;
+mod runtime;
 
+pub(crate) use self::commands::{ensure_image, image_digest, image_id, pinned_image, pull};
 pub(crate) use self::container::DockerContainer;
 pub(crate) use self::image::ImageUri;
+pub(crate) use self::make_image::make_image;
+pub(crate) use self::registry::list_tags;
+pub(crate) use self::runtime::{runtime, use_runtime};
@@ -0,0 +1,185 @@
+//! Just enough of the OCI distribution API to list the tags in an image repository. Registries that
+//! require authentication, even for public images, answer with a `WWW-Authenticate` challenge that
+//! tells us where to get an anonymous token.
+
+use crate::docker::ImageUri;
+use anyhow::{Context, Result};
+use log::debug;
+use reqwest::header::{HeaderMap, AUTHORIZATION, LINK, WWW_AUTHENTICATE};
+use reqwest::{Client, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Docker Hub serves its API from a different host than the one used in image names.
+const DOCKER_HUB: &str = "docker.io";
+const DOCKER_HUB_API: &str = "registry-1.docker.io";
+
+#[derive(Debug, Deserialize)]
+struct TagList {
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// List the tags in the repository of `image`. The tag of `image` itself is ignored.
+pub(crate) async fn list_tags(image: &ImageUri) -> Result<Vec<String>> {
+    let registry = image.registry.as_deref().context(format!(
+        "Unable to list the tags of '{image}' because it does not name a registry"
+    ))?;
+    let (host, name) = match registry.split_once('/') {
+        Some((host, namespace)) => (host, format!("{namespace}/{}", image.repo)),
+        None => (registry, image.repo.clone()),
+    };
+    let host = if host == DOCKER_HUB {
+        DOCKER_HUB_API
+    } else {
+        host
+    };
+
+    let client = Client::new();
+    let mut url = format!("https://{host}/v2/{name}/tags/list");
+    let mut token = None;
+    let mut tags = Vec::new();
+    loop {
+        debug!("Listing tags from '{url}'");
+        let mut response = get(&client, &url, token.as_deref()).await?;
+        if response.status() == StatusCode::UNAUTHORIZED && token.is_none() {
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .context(format!("The registry '{host}' requires authentication"))?;
+            token = Some(anonymous_token(&client, challenge, &name).await?);
+            response = get(&client, &url, token.as_deref()).await?;
+        }
+        let response = response
+            .error_for_status()
+            .context(format!("Unable to list the tags of '{image}'"))?;
+        let next = next_link(response.headers()).map(|path| format!("https://{host}{path}"));
+        let body = response
+            .text()
+            .await
+            .context(format!("Unable to read the tags of '{image}'"))?;
+        let list: TagList = serde_json::from_str(&body)
+            .context(format!("Unable to parse the tags of '{image}'"))?;
+        tags.extend(list.tags.unwrap_or_default());
+        match next {
+            Some(next) => url = next,
+            None => return Ok(tags),
+        }
+    }
+}
+
+async fn get(client: &Client, url: &str, token: Option<&str>) -> Result<Response> {
+    let mut request = client.get(url);
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+    }
+    request
+        .send()
+        .await
+        .context(format!("Unable to send request to '{url}'"))
+}
+
+/// Get a token for pulling from the repository `name`, as directed by a `WWW-Authenticate`
+/// challenge such as `Bearer realm="https://example.com/token",service="example.com"`.
+async fn anonymous_token(client: &Client, challenge: &str, name: &str) -> Result<String> {
+    let mut params = parse_challenge(challenge);
+    let realm = params.remove("realm").context(format!(
+        "Unable to find the token realm in authentication challenge '{challenge}'"
+    ))?;
+    params
+        .entry("scope".to_string())
+        .or_insert_with(|| format!("repository:{name}:pull"));
+
+    let response = client
+        .get(&realm)
+        .query(&params)
+        .send()
+        .await
+        .and_then(Response::error_for_status)
+        .context(format!("Unable to get a registry token from '{realm}'"))?;
+    let body = response
+        .text()
+        .await
+        .context(format!("Unable to read the registry token from '{realm}'"))?;
+    let token: TokenResponse = serde_json::from_str(&body)
+        .context(format!("Unable to parse the registry token from '{realm}'"))?;
+    token
+        .token
+        .or(token.access_token)
+        .context(format!("No registry token was returned by '{realm}'"))
+}
+
+/// Parse the `key="value"` parameters of a `Bearer` authentication challenge.
+fn parse_challenge(challenge: &str) -> HashMap<String, String> {
+    let params = challenge
+        .trim()
+        .strip_prefix("Bearer")
+        .unwrap_or(challenge)
+        .trim();
+    let mut parsed = HashMap::new();
+    let mut rest = params;
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim();
+        let value = value.trim_start();
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, remainder)) => (value, remainder),
+                None => (quoted, ""),
+            },
+            None => match value.split_once(',') {
+                Some((value, remainder)) => (value, remainder),
+                None => (value, ""),
+            },
+        };
+        parsed.insert(key.to_string(), value.to_string());
+        rest = remainder;
+    }
+    parsed
+}
+
+/// Registries paginate long tag lists with a header like `Link: </v2/...?last=x>; rel="next"`.
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(LINK)?.to_str().ok()?;
+    if !link.contains("rel=\"next\"") {
+        return None;
+    }
+    let start = link.find('<')? + 1;
+    let end = link.find('>')?;
+    link.get(start..end).map(str::to_string)
+}
+
+#[test]
+fn test_parse_challenge() {
+    let params = parse_challenge(
+        r#"Bearer realm="https://public.ecr.aws/token/",service="public.ecr.aws",scope="aws""#,
+    );
+    assert_eq!(params["realm"], "https://public.ecr.aws/token/");
+    assert_eq!(params["service"], "public.ecr.aws");
+    assert_eq!(params["scope"], "aws");
+
+    let params = parse_challenge(r#"Bearer realm="https://example.com/token, with comma",x=y"#);
+    assert_eq!(params["realm"], "https://example.com/token, with comma");
+    assert_eq!(params["x"], "y");
+}
+
+#[test]
+fn test_next_link() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(next_link(&headers), None);
+    headers.insert(
+        LINK,
+        r#"</v2/my-kit/tags/list?last=v1.0.0&n=100>; rel="next""#
+            .parse()
+            .unwrap(),
+    );
+    assert_eq!(
+        next_link(&headers).as_deref(),
+        Some("/v2/my-kit/tags/list?last=v1.0.0&n=100")
+    );
+}
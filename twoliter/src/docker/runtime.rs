@@ -0,0 +1,45 @@
+//! The container runtime that Twoliter runs containers with. Projects choose it with the
+//! `container-runtime` build setting, which can be overridden by `TWOLITER_CONTAINER_RUNTIME`.
+
+use container_runtime::{ContainerRuntime, Runtime};
+use log::warn;
+use std::sync::OnceLock;
+use tokio::process::Command;
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Use `runtime` for the containers of this process. The first choice sticks, so that containers
+/// created with one runtime are not cleaned up with another.
+pub(crate) fn use_runtime(runtime: Runtime) {
+    let chosen = *RUNTIME.get_or_init(|| runtime);
+    if chosen != runtime {
+        warn!("Already using container runtime '{chosen}', unable to switch to '{runtime}'");
+    }
+}
+
+/// The container runtime of this process. Commands that do not load a project use the one from
+/// `TWOLITER_CONTAINER_RUNTIME`, or Docker.
+pub(crate) fn runtime() -> Runtime {
+    *RUNTIME.get_or_init(|| {
+        Runtime::from_env().unwrap_or_else(|e| {
+            warn!("{e}, using docker");
+            Runtime::default()
+        })
+    })
+}
+
+/// The commands of the container runtime of this process.
+pub(crate) fn commands() -> &'static dyn ContainerRuntime {
+    runtime().commands()
+}
+
+/// A command that runs the container runtime's program with `args`.
+pub(crate) fn command<I, S>(args: I) -> Command
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let mut command = Command::new(commands().program());
+    command.args(args);
+    command
+}
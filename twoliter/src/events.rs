@@ -0,0 +1,385 @@
+//! Build events. buildsys appends an event for each step of the package, kit and variant builds to
+//! a file as JSON lines, which Twoliter reads to find the builds that failed. For
+//! `--message-format json`, Twoliter forwards them to stdout as they are written, along with events
+//! of its own, so that stdout is a stream of JSON objects, one per line, while everything else goes
+//! to stderr.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// How often the events file is checked for new events.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How the progress of a build is reported.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, ValueEnum)]
+pub(crate) enum MessageFormat {
+    /// Build output and log messages for people to read.
+    #[default]
+    Human,
+    /// JSON build events on stdout, one per line. Everything else is written to stderr.
+    Json,
+}
+
+/// The events that Twoliter reports itself. Their fields follow the ones of buildsys' events.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum Event<'a> {
+    VariantBuildStarted {
+        variant: &'a str,
+        arch: &'a str,
+    },
+    VariantBuildFinished {
+        variant: &'a str,
+        arch: &'a str,
+        success: bool,
+        duration_secs: f64,
+        /// The directory of the images, if the build succeeded.
+        image_dir: Option<&'a Path>,
+        error: Option<String>,
+    },
+    /// A package or kit that cargo found up to date, so it did not run buildsys to build it.
+    BuildFinished {
+        kind: &'a str,
+        name: &'a str,
+        arch: &'a str,
+        success: bool,
+        cached: bool,
+        duration_secs: f64,
+    },
+}
+
+/// Write `event` to stdout as a line of JSON.
+pub(crate) fn emit(event: &Event) {
+    match serde_json::to_string(event) {
+        Ok(line) => println!("{line}"),
+        Err(e) => warn!("Unable to serialize build event {event:?}: {e}"),
+    }
+}
+
+/// The file that buildsys appends its events to.
+#[derive(Debug, Clone)]
+pub(crate) struct BuildEvents {
+    path: PathBuf,
+}
+
+/// The fields of buildsys' events that Twoliter reads.
+#[derive(Debug, Deserialize)]
+struct BuildsysEvent {
+    event: String,
+    kind: Option<String>,
+    name: Option<String>,
+    arch: Option<String>,
+    success: Option<bool>,
+    log: Option<PathBuf>,
+}
+
+/// A package, kit or variant build that failed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct FailedBuild {
+    pub(crate) kind: String,
+    pub(crate) name: String,
+    pub(crate) arch: String,
+    /// The file with the output of the build, if it was written to one.
+    pub(crate) log: Option<PathBuf>,
+}
+
+impl BuildEvents {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Start forwarding events from the file to stdout as they are written.
+    pub(crate) fn forward(&self) -> Forwarder {
+        let (done, mut done_rx) = oneshot::channel();
+        let path = self.path.clone();
+        let task = tokio::spawn(async move {
+            let mut offset = 0;
+            loop {
+                let finished = tokio::select! {
+                    _ = &mut done_rx => true,
+                    _ = tokio::time::sleep(POLL_INTERVAL) => false,
+                };
+                match read_lines(&path, offset).await {
+                    Ok((lines, end)) => {
+                        for line in lines {
+                            println!("{line}");
+                        }
+                        offset = end;
+                    }
+                    Err(e) => warn!("{e:#}"),
+                }
+                if finished {
+                    break;
+                }
+            }
+        });
+        Forwarder { done, task }
+    }
+
+    /// The offset after the last complete event in the file. Passed to `started`, it leaves out
+    /// the events that were written before now.
+    pub(crate) async fn end(&self) -> Result<usize> {
+        Ok(read_lines(&self.path, 0).await?.1)
+    }
+
+    /// The names of the builds for `arch` that buildsys has started since the offset `since`.
+    pub(crate) async fn started(&self, arch: &str, since: usize) -> Result<BTreeSet<String>> {
+        Ok(self
+            .buildsys_events(since)
+            .await?
+            .into_iter()
+            .filter(|e| e.event == "build-started" && e.arch.as_deref() == Some(arch))
+            .filter_map(|e| e.name)
+            .collect())
+    }
+
+    /// The builds that buildsys reported as failed, in the order they finished.
+    pub(crate) async fn failed(&self) -> Result<Vec<FailedBuild>> {
+        Ok(self
+            .buildsys_events(0)
+            .await?
+            .into_iter()
+            .filter(|e| e.event == "build-finished" && e.success == Some(false))
+            .filter_map(|e| {
+                Some(FailedBuild {
+                    kind: e.kind?,
+                    name: e.name?,
+                    arch: e.arch?,
+                    log: e.log,
+                })
+            })
+            .collect())
+    }
+
+    async fn buildsys_events(&self, offset: usize) -> Result<Vec<BuildsysEvent>> {
+        let (lines, _) = read_lines(&self.path, offset).await?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+/// Forwards events until it is finished.
+pub(crate) struct Forwarder {
+    done: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Forwarder {
+    /// Forward the events that have not been forwarded yet, and stop.
+    pub(crate) async fn finish(self) {
+        let _ = self.done.send(());
+        if let Err(e) = self.task.await {
+            warn!("Unable to forward build events: {e}");
+        }
+    }
+}
+
+/// Read the complete lines of `path` after `offset`. Returns the lines and the offset after the
+/// last of them, so that a line that is still being written is read again next time.
+async fn read_lines(path: &Path, offset: usize) -> Result<(Vec<String>, usize)> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        // buildsys creates the file when it reports its first event.
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((Vec::new(), offset)),
+        Err(e) => {
+            return Err(e).context(format!(
+                "Unable to read build events from '{}'",
+                path.display()
+            ))
+        }
+    };
+    let new = data.get(offset..).unwrap_or_default();
+    let complete = match new.iter().rposition(|b| *b == b'\n') {
+        Some(last) => &new[..=last],
+        None => return Ok((Vec::new(), offset)),
+    };
+    let lines = String::from_utf8_lossy(complete)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok((lines, offset + complete.len()))
+}
+
+/// The crates in the workspace of `cargo_lock` that `root` depends on, directly or through other
+/// workspace crates. These are the packages and kits that a variant build needs. Crates from
+/// registries are left out, since they are not built by buildsys.
+pub(crate) fn workspace_dependencies(cargo_lock: &str, root: &str) -> Result<BTreeSet<String>> {
+    #[derive(Deserialize)]
+    struct Lock {
+        #[serde(default)]
+        package: Vec<Package>,
+    }
+    #[derive(Deserialize)]
+    struct Package {
+        name: String,
+        source: Option<String>,
+        #[serde(default)]
+        dependencies: Vec<String>,
+    }
+
+    let lock: Lock = toml::from_str(cargo_lock).context("Unable to parse Cargo.lock")?;
+    let workspace = lock
+        .package
+        .iter()
+        .filter(|package| package.source.is_none())
+        .map(|package| (package.name.as_str(), package))
+        .collect::<HashMap<_, _>>();
+
+    let mut found = BTreeSet::new();
+    let mut queue = vec![root];
+    while let Some(name) = queue.pop() {
+        let Some(package) = workspace.get(name) else {
+            continue;
+        };
+        // Dependencies are listed as `name`, or `name version` when there are several versions.
+        for dependency in &package.dependencies {
+            let dependency = dependency.split(' ').next().unwrap_or_default();
+            if workspace.contains_key(dependency) && found.insert(dependency.to_string()) {
+                queue.push(dependency);
+            }
+        }
+    }
+    found.remove(root);
+    Ok(found)
+}
+
+#[test]
+fn test_workspace_dependencies() {
+    let cargo_lock = r#"
+version = 3
+
+[[package]]
+name = "aws-dev"
+version = "0.1.0"
+dependencies = ["core-kit", "hello"]
+
+[[package]]
+name = "core-kit"
+version = "0.1.0"
+dependencies = ["kernel-6_1", "glibc"]
+
+[[package]]
+name = "glibc"
+version = "0.1.0"
+
+[[package]]
+name = "kernel-6_1"
+version = "0.1.0"
+dependencies = ["glibc", "serde 1.0.197"]
+
+[[package]]
+name = "hello"
+version = "0.1.0"
+
+[[package]]
+name = "metal-dev"
+version = "0.1.0"
+dependencies = ["hello"]
+
+[[package]]
+name = "serde"
+version = "1.0.197"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+    assert_eq!(
+        workspace_dependencies(cargo_lock, "aws-dev")
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        ["core-kit", "glibc", "hello", "kernel-6_1"]
+    );
+    assert!(workspace_dependencies(cargo_lock, "hello")
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_failed() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let events = BuildEvents::new(tempdir.path().join("events.jsonl"));
+    std::fs::write(
+        events.path(),
+        r#"{"event":"build-started","kind":"package","name":"hello","arch":"x86_64"}
+{"event":"build-finished","kind":"package","name":"glibc","arch":"x86_64","success":true,"cached":false,"duration_secs":3.0}
+{"event":"build-finished","kind":"package","name":"hello","arch":"x86_64","success":false,"cached":false,"duration_secs":1.0,"log":"/p/build/logs/x86_64/hello.log"}
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        events.failed().await.unwrap(),
+        [FailedBuild {
+            kind: "package".to_string(),
+            name: "hello".to_string(),
+            arch: "x86_64".to_string(),
+            log: Some(PathBuf::from("/p/build/logs/x86_64/hello.log")),
+        }]
+    );
+    assert_eq!(
+        events.started("x86_64", 0).await.unwrap(),
+        BTreeSet::from(["hello".to_string()])
+    );
+}
+
+#[tokio::test]
+async fn test_started_since() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let events = BuildEvents::new(tempdir.path().join("events.jsonl"));
+    assert_eq!(events.end().await.unwrap(), 0);
+
+    // The packages that the first variant build started are not counted for the second one.
+    let first = r#"{"event":"build-started","kind":"package","name":"glibc","arch":"x86_64"}
+{"event":"build-started","kind":"package","name":"hello","arch":"x86_64"}
+"#;
+    std::fs::write(events.path(), first).unwrap();
+    let since = events.end().await.unwrap();
+    assert_eq!(since, first.len());
+    let second = r#"{"event":"build-started","kind":"package","name":"kernel-6_1","arch":"x86_64"}
+{"event":"build-started","kind":"package","name":"hello","arch":"aarch64"}
+{"event":"build-started","kind":"package","name":"hel"#;
+    std::fs::write(events.path(), format!("{first}{second}")).unwrap();
+    assert_eq!(
+        events.started("x86_64", since).await.unwrap(),
+        BTreeSet::from(["kernel-6_1".to_string()])
+    );
+    assert_eq!(
+        events.started("x86_64", 0).await.unwrap(),
+        BTreeSet::from([
+            "glibc".to_string(),
+            "hello".to_string(),
+            "kernel-6_1".to_string()
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_read_lines() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let path = tempdir.path().join("events.jsonl");
+    assert_eq!(read_lines(&path, 0).await.unwrap(), (Vec::new(), 0));
+
+    // A line that is still being written is left for next time.
+    std::fs::write(&path, "{\"event\":\"a\"}\n{\"eve").unwrap();
+    let (lines, offset) = read_lines(&path, 0).await.unwrap();
+    assert_eq!(lines, ["{\"event\":\"a\"}"]);
+    assert_eq!(offset, 14);
+
+    std::fs::write(&path, "{\"event\":\"a\"}\n{\"event\":\"b\"}\n").unwrap();
+    let (lines, offset) = read_lines(&path, offset).await.unwrap();
+    assert_eq!(lines, ["{\"event\":\"b\"}"]);
+    assert_eq!(offset, 28);
+}
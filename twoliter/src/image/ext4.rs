@@ -0,0 +1,655 @@
+//! A reader for the ext4 filesystems in Bottlerocket images. It handles what `mkfs.ext4` creates
+//! for the image build: files mapped by extents, linear and hashed directories, and symlinks.
+
+use super::Disk;
+use anyhow::{bail, ensure, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const MAGIC: u16 = 0xef53;
+const EXTENT_MAGIC: u16 = 0xf30a;
+const ROOT_INODE: u32 = 2;
+
+const INCOMPAT_META_BG: u32 = 0x10;
+const INCOMPAT_64BIT: u32 = 0x80;
+
+const EXTENTS_FLAG: u32 = 0x80000;
+const INLINE_DATA_FLAG: u32 = 0x1000_0000;
+
+/// Extent trees are at most five levels deep.
+const MAX_EXTENT_DEPTH: u16 = 5;
+/// The most symlinks to follow while resolving a path, like Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// The kind of a file in the filesystem.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    /// A device, FIFO or socket.
+    Other,
+}
+
+impl FileKind {
+    fn from_mode(mode: u16) -> Self {
+        match mode & 0xf000 {
+            0x8000 => FileKind::File,
+            0x4000 => FileKind::Directory,
+            0xa000 => FileKind::Symlink,
+            _ => FileKind::Other,
+        }
+    }
+}
+
+/// A file in the filesystem, as found by [`Ext4::walk`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct FileNode {
+    /// The absolute path of the file.
+    pub(crate) path: String,
+    pub(crate) inode: u32,
+    pub(crate) kind: FileKind,
+    /// The permission bits.
+    pub(crate) mode: u16,
+    pub(crate) size: u64,
+    /// The target of a symlink.
+    pub(crate) target: Option<String>,
+}
+
+struct Inode {
+    mode: u16,
+    size: u64,
+    flags: u32,
+    block: [u8; 60],
+}
+
+impl Inode {
+    fn kind(&self) -> FileKind {
+        FileKind::from_mode(self.mode)
+    }
+}
+
+struct Extent {
+    logical: u64,
+    physical: u64,
+    len: u64,
+    /// Whether the extent is allocated but not written yet, so that it reads as zeros.
+    uninitialized: bool,
+}
+
+/// An ext4 filesystem on `disk`, read-only.
+pub(crate) struct Ext4<D> {
+    disk: D,
+    block_size: u64,
+    inode_count: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    /// The block of the inode table of each block group.
+    inode_tables: Vec<u64>,
+}
+
+impl<D: Disk> Ext4<D> {
+    pub(crate) fn new(disk: D) -> Result<Self> {
+        let superblock = disk.read_vec(SUPERBLOCK_OFFSET, 1024)?;
+        ensure!(
+            u16_at(&superblock, 56) == MAGIC,
+            "There is no ext4 filesystem"
+        );
+        let log_block_size = u32_at(&superblock, 24);
+        ensure!(
+            log_block_size <= 6,
+            "The filesystem has an unexpected block size"
+        );
+        let block_size: u64 = 1024 << log_block_size;
+        let inode_count = u32_at(&superblock, 0);
+        let inodes_per_group = u32_at(&superblock, 40);
+        ensure!(
+            inodes_per_group > 0,
+            "The filesystem has no inodes per group"
+        );
+        let inode_size = match u32_at(&superblock, 76) {
+            0 => 128,
+            _ => u16_at(&superblock, 88) as u64,
+        };
+        ensure!(
+            inode_size >= 128,
+            "The filesystem has an unexpected inode size"
+        );
+        let incompat = u32_at(&superblock, 96);
+        ensure!(
+            incompat & INCOMPAT_META_BG == 0,
+            "The filesystem uses meta block groups, which are not supported"
+        );
+        let desc_size = if incompat & INCOMPAT_64BIT != 0 {
+            (u16_at(&superblock, 254) as u64).max(32)
+        } else {
+            32
+        };
+
+        // The group descriptors follow the block that holds the superblock.
+        let first_data_block = u32_at(&superblock, 20) as u64;
+        let group_count = inode_count.div_ceil(inodes_per_group) as u64;
+        let descriptors_offset = (first_data_block + 1)
+            .checked_mul(block_size)
+            .context("The filesystem has a corrupt superblock")?;
+        let descriptors_len = group_count
+            .checked_mul(desc_size)
+            .filter(|len| *len <= disk.size())
+            .context("The filesystem has more block groups than fit in it")?;
+        let descriptors = disk.read_vec(descriptors_offset, descriptors_len as usize)?;
+        let inode_tables = descriptors
+            .chunks_exact(desc_size as usize)
+            .map(|desc| {
+                let high = if desc_size >= 64 {
+                    u32_at(desc, 0x28) as u64
+                } else {
+                    0
+                };
+                high << 32 | u32_at(desc, 8) as u64
+            })
+            .collect();
+
+        Ok(Self {
+            disk,
+            block_size,
+            inode_count,
+            inodes_per_group,
+            inode_size,
+            inode_tables,
+        })
+    }
+
+    /// Read the whole file at `path`, following symlinks.
+    pub(crate) fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let inode = self.inode(self.resolve(path)?)?;
+        ensure!(
+            inode.kind() == FileKind::File,
+            "'{path}' is not a regular file"
+        );
+        self.data(&inode)
+            .context(format!("Unable to read '{path}'"))
+    }
+
+    /// Read the whole file that `node` refers to.
+    pub(crate) fn read_node(&self, node: &FileNode) -> Result<Vec<u8>> {
+        self.data(&self.inode(node.inode)?)
+            .context(format!("Unable to read '{}'", node.path))
+    }
+
+    /// List every file in the filesystem, sorted by path.
+    pub(crate) fn walk(&self) -> Result<Vec<FileNode>> {
+        let mut nodes = Vec::new();
+        let mut visited = BTreeSet::from([ROOT_INODE]);
+        let mut pending = VecDeque::from([(String::new(), ROOT_INODE)]);
+        while let Some((dir_path, dir)) = pending.pop_front() {
+            for (name, number) in self.read_dir(&self.inode(dir)?)? {
+                let path = format!("{dir_path}/{name}");
+                let inode = self.inode(number)?;
+                let kind = inode.kind();
+                let target = match kind {
+                    FileKind::Symlink => Some(
+                        String::from_utf8_lossy(
+                            &self
+                                .data(&inode)
+                                .context(format!("Unable to read '{path}'"))?,
+                        )
+                        .to_string(),
+                    ),
+                    _ => None,
+                };
+                if kind == FileKind::Directory {
+                    ensure!(
+                        visited.insert(number),
+                        "Directory '{path}' is linked more than once"
+                    );
+                    pending.push_back((path.clone(), number));
+                }
+                nodes.push(FileNode {
+                    path,
+                    inode: number,
+                    kind,
+                    mode: inode.mode & 0o7777,
+                    size: inode.size,
+                    target,
+                });
+            }
+        }
+        nodes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(nodes)
+    }
+
+    /// Find the inode of `path`, following symlinks.
+    fn resolve(&self, path: &str) -> Result<u32> {
+        // The directories from the root to where the lookup is, so that `..` can go back up.
+        let mut dirs = vec![ROOT_INODE];
+        let mut pending: VecDeque<String> = path.split('/').map(str::to_string).collect();
+        let mut symlinks = 0;
+        while let Some(component) = pending.pop_front() {
+            match component.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    if dirs.len() > 1 {
+                        dirs.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let dir = self.inode(*dirs.last().unwrap_or(&ROOT_INODE))?;
+            ensure!(
+                dir.kind() == FileKind::Directory,
+                "Unable to find '{path}', a part of it is not a directory"
+            );
+            let number = self
+                .read_dir(&dir)?
+                .into_iter()
+                .find_map(|(name, number)| (name == component).then_some(number))
+                .context(format!("There is no '{path}'"))?;
+            let inode = self.inode(number)?;
+            if inode.kind() == FileKind::Symlink {
+                symlinks += 1;
+                ensure!(
+                    symlinks <= MAX_SYMLINKS,
+                    "Too many symlinks while resolving '{path}'"
+                );
+                let target = String::from_utf8_lossy(&self.data(&inode)?).to_string();
+                if target.starts_with('/') {
+                    dirs.truncate(1);
+                }
+                for component in target.split('/').rev() {
+                    pending.push_front(component.to_string());
+                }
+            } else {
+                dirs.push(number);
+            }
+        }
+        Ok(*dirs.last().unwrap_or(&ROOT_INODE))
+    }
+
+    /// The entries of a directory, without `.` and `..`.
+    fn read_dir(&self, inode: &Inode) -> Result<Vec<(String, u32)>> {
+        ensure!(
+            inode.kind() == FileKind::Directory,
+            "Unable to list a file that is not a directory"
+        );
+        let data = self.data(inode)?;
+        let mut entries = Vec::new();
+        for block in data.chunks(self.block_size as usize) {
+            let mut offset = 0;
+            while offset + 8 <= block.len() {
+                let number = u32_at(block, offset);
+                let rec_len = u16_at(block, offset + 4) as usize;
+                let name_len = block[offset + 6] as usize;
+                ensure!(
+                    rec_len >= 8 && offset + rec_len <= block.len() && name_len + 8 <= rec_len,
+                    "A directory has a corrupt entry"
+                );
+                // Unused entries, the nodes of hashed directories and the checksums at the end of
+                // each block all have an inode of zero.
+                let name = &block[offset + 8..offset + 8 + name_len];
+                if number != 0 && name != b"." && name != b".." {
+                    entries.push((String::from_utf8_lossy(name).to_string(), number));
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn inode(&self, number: u32) -> Result<Inode> {
+        ensure!(
+            number >= 1 && number <= self.inode_count,
+            "Inode {number} is out of range"
+        );
+        let group = (number - 1) / self.inodes_per_group;
+        let index = ((number - 1) % self.inodes_per_group) as u64;
+        let table = self.inode_tables[group as usize];
+        let offset = self
+            .block_offset(table)?
+            .checked_add(index * self.inode_size)
+            .context(format!("Inode {number} is past the end of the filesystem"))?;
+        let raw = self.disk.read_vec(offset, 128)?;
+        Ok(Inode {
+            mode: u16_at(&raw, 0),
+            size: (u32_at(&raw, 108) as u64) << 32 | u32_at(&raw, 4) as u64,
+            flags: u32_at(&raw, 32),
+            block: raw[40..100].try_into().unwrap(),
+        })
+    }
+
+    fn data(&self, inode: &Inode) -> Result<Vec<u8>> {
+        if inode.flags & INLINE_DATA_FLAG != 0 {
+            bail!("Inline data is not supported");
+        }
+        if inode.flags & EXTENTS_FLAG == 0 {
+            // Short symlink targets are stored in place of the block map.
+            return match inode.size {
+                0 => Ok(Vec::new()),
+                size if inode.kind() == FileKind::Symlink && size < 60 => {
+                    Ok(inode.block[..size as usize].to_vec())
+                }
+                _ => bail!("Files without extents are not supported"),
+            };
+        }
+
+        ensure!(
+            inode.size <= self.disk.size(),
+            "A file is larger than the filesystem"
+        );
+        let mut data = vec![0; inode.size as usize];
+        for extent in self.extents(&inode.block, MAX_EXTENT_DEPTH)? {
+            if extent.uninitialized {
+                continue;
+            }
+            // The logical blocks are 32 bits and the lengths 16 bits, so these cannot overflow.
+            let start = extent.logical * self.block_size;
+            if start >= inode.size {
+                continue;
+            }
+            let end = ((extent.logical + extent.len) * self.block_size).min(inode.size);
+            self.disk.read_at(
+                self.block_offset(extent.physical)?,
+                &mut data[start as usize..end as usize],
+            )?;
+        }
+        Ok(data)
+    }
+
+    /// The offset in bytes of `block`, which is read from the filesystem and may be corrupt.
+    fn block_offset(&self, block: u64) -> Result<u64> {
+        block
+            .checked_mul(self.block_size)
+            .filter(|offset| *offset < self.disk.size())
+            .context(format!("Block {block} is past the end of the filesystem"))
+    }
+
+    /// The extents in the tree under `node`, which is either the block map of an inode or a block
+    /// of the tree.
+    fn extents(&self, node: &[u8], depth_left: u16) -> Result<Vec<Extent>> {
+        ensure!(
+            u16_at(node, 0) == EXTENT_MAGIC,
+            "A file has a corrupt extent tree"
+        );
+        let entries = u16_at(node, 2) as usize;
+        let depth = u16_at(node, 6);
+        ensure!(
+            depth < depth_left && 12 * (entries + 1) <= node.len(),
+            "A file has a corrupt extent tree"
+        );
+        let mut extents = Vec::new();
+        for entry in (0..entries).map(|i| &node[12 * (i + 1)..12 * (i + 2)]) {
+            if depth == 0 {
+                let len = u16_at(entry, 4) as u64;
+                extents.push(Extent {
+                    logical: u32_at(entry, 0) as u64,
+                    physical: (u16_at(entry, 6) as u64) << 32 | u32_at(entry, 8) as u64,
+                    len: if len > 32768 { len - 32768 } else { len },
+                    uninitialized: len > 32768,
+                });
+            } else {
+                let leaf = (u16_at(entry, 8) as u64) << 32 | u32_at(entry, 4) as u64;
+                let block = self
+                    .disk
+                    .read_vec(self.block_offset(leaf)?, self.block_size as usize)?;
+                extents.extend(self.extents(&block, depth)?);
+            }
+        }
+        Ok(extents)
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// A file to put in a filesystem made by [`test_ext4`].
+#[cfg(test)]
+pub(crate) enum TestNode<'a> {
+    File(&'a [u8]),
+    Directory,
+    Symlink(&'a str),
+}
+
+/// Make a small ext4 filesystem with 1 KiB blocks that holds `nodes`, for tests. Each node is
+/// given by its path relative to the root, after its parent directory. Files of more than two
+/// blocks are mapped by an extent tree with a leaf block, and the others directly by the inode.
+#[cfg(test)]
+pub(crate) fn test_ext4(nodes: &[(&str, TestNode<'_>)]) -> Vec<u8> {
+    const BLOCK: usize = 1024;
+    const INODES: u32 = 64;
+    const INODE_TABLE: usize = 3;
+    const FIRST_DATA: usize = INODE_TABLE + INODES as usize * 128 / BLOCK;
+
+    fn put16(image: &mut [u8], offset: usize, value: u16) {
+        image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    fn put32(image: &mut [u8], offset: usize, value: u32) {
+        image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    fn alloc(image: &mut Vec<u8>, data: &[u8]) -> usize {
+        let block = image.len() / BLOCK;
+        let blocks = data.len().div_ceil(BLOCK).max(1);
+        image.extend_from_slice(data);
+        image.resize((block + blocks) * BLOCK, 0);
+        block
+    }
+    fn write_inode(image: &mut [u8], number: u32, mode: u16, size: usize, data_block: usize) {
+        let inode = INODE_TABLE * BLOCK + (number as usize - 1) * 128;
+        put16(image, inode, mode);
+        put32(image, inode + 4, size as u32);
+        if mode & 0xf000 == 0xa000 && size < 60 {
+            return;
+        }
+        put32(image, inode + 32, EXTENTS_FLAG);
+        let blocks = size.div_ceil(BLOCK) as u16;
+        let tree = inode + 40;
+        put16(image, tree, EXTENT_MAGIC);
+        put16(image, tree + 2, 1);
+        put16(image, tree + 4, 4);
+        if blocks > 2 {
+            // An index node in the inode that points to a leaf in the block before the data.
+            put16(image, tree + 6, 1);
+            put32(image, tree + 16, data_block as u32 - 1);
+            let leaf = (data_block - 1) * BLOCK;
+            put16(image, leaf, EXTENT_MAGIC);
+            put16(image, leaf + 2, 1);
+            put16(image, leaf + 4, 84);
+            put16(image, leaf + 16, blocks);
+            put32(image, leaf + 20, data_block as u32);
+        } else {
+            put16(image, tree + 16, blocks);
+            put32(image, tree + 20, data_block as u32);
+        }
+    }
+
+    let mut image = vec![0; FIRST_DATA * BLOCK];
+    let mut numbers = vec![("".to_string(), ROOT_INODE)];
+    let mut children: Vec<Vec<(String, u32)>> = vec![Vec::new(); INODES as usize + 1];
+    for (i, (path, _)) in nodes.iter().enumerate() {
+        let number = 11 + i as u32;
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let parent = numbers.iter().find(|(p, _)| p == parent).unwrap().1;
+        children[parent as usize].push((name.to_string(), number));
+        numbers.push((path.to_string(), number));
+    }
+
+    let mut dirs = vec![(ROOT_INODE, ROOT_INODE)];
+    for (i, (_, node)) in nodes.iter().enumerate() {
+        let number = 11 + i as u32;
+        match node {
+            TestNode::File(data) => {
+                if data.len() > 2 * BLOCK {
+                    alloc(&mut image, &[]);
+                }
+                let block = alloc(&mut image, data);
+                write_inode(&mut image, number, 0o100644, data.len(), block);
+            }
+            TestNode::Symlink(target) if target.len() < 60 => {
+                write_inode(&mut image, number, 0o120777, target.len(), 0);
+                let inode = INODE_TABLE * BLOCK + (number as usize - 1) * 128;
+                image[inode + 40..inode + 40 + target.len()].copy_from_slice(target.as_bytes());
+            }
+            TestNode::Symlink(target) => {
+                let block = alloc(&mut image, target.as_bytes());
+                write_inode(&mut image, number, 0o120777, target.len(), block);
+            }
+            TestNode::Directory => {
+                let (parent, _) = nodes[i].0.rsplit_once('/').unwrap_or(("", ""));
+                let parent = numbers.iter().find(|(p, _)| p == parent).unwrap().1;
+                dirs.push((number, parent));
+            }
+        }
+    }
+    for (number, parent) in dirs {
+        let mut block = vec![0; BLOCK];
+        let mut offset = 0;
+        let entries = [(".".to_string(), number), ("..".to_string(), parent)];
+        let entries = entries.iter().chain(children[number as usize].iter());
+        let mut last = 0;
+        for (name, child) in entries {
+            let rec_len = (8 + name.len()).div_ceil(4) * 4;
+            put32(&mut block, offset, *child);
+            put16(&mut block, offset + 4, rec_len as u16);
+            block[offset + 6] = name.len() as u8;
+            block[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+            last = offset;
+            offset += rec_len;
+        }
+        put16(&mut block, last + 4, (BLOCK - last) as u16);
+        let data_block = alloc(&mut image, &block);
+        write_inode(&mut image, number, 0o040755, BLOCK, data_block);
+    }
+
+    let superblock = SUPERBLOCK_OFFSET as usize;
+    put32(&mut image, superblock, INODES);
+    let block_count = (image.len() / BLOCK) as u32;
+    put32(&mut image, superblock + 4, block_count);
+    put32(&mut image, superblock + 20, 1);
+    put32(&mut image, superblock + 32, 8192);
+    put32(&mut image, superblock + 40, INODES);
+    put16(&mut image, superblock + 56, MAGIC);
+    put32(&mut image, superblock + 76, 1);
+    put16(&mut image, superblock + 88, 128);
+    put32(&mut image, superblock + 96, 0x42);
+    put32(&mut image, 2 * BLOCK + 8, INODE_TABLE as u32);
+    image
+}
+
+#[test]
+fn test_ext4_reader() {
+    use super::MemoryDisk;
+
+    let large = (0..5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let long_target = format!("/{}", "x".repeat(70));
+    let fs = Ext4::new(MemoryDisk(test_ext4(&[
+        ("etc", TestNode::Directory),
+        ("etc/os-release", TestNode::Symlink("../usr/lib/os-release")),
+        ("usr", TestNode::Symlink("sys-root/usr")),
+        ("sys-root", TestNode::Directory),
+        ("sys-root/usr", TestNode::Directory),
+        ("sys-root/usr/lib", TestNode::Directory),
+        (
+            "sys-root/usr/lib/os-release",
+            TestNode::File(b"VERSION_ID=1.0.0\n"),
+        ),
+        ("sys-root/usr/lib/large", TestNode::File(&large)),
+        ("long", TestNode::Symlink(&long_target)),
+        ("empty", TestNode::File(b"")),
+    ])))
+    .unwrap();
+
+    assert_eq!(fs.read("/etc/os-release").unwrap(), b"VERSION_ID=1.0.0\n");
+    assert_eq!(fs.read("usr/lib/./large").unwrap(), large);
+    assert_eq!(fs.read("/etc/../empty").unwrap(), b"");
+    assert!(fs.read("/etc").is_err());
+    assert!(fs.read("/missing").is_err());
+    assert!(fs.read("/long").is_err());
+
+    let nodes = fs.walk().unwrap();
+    assert_eq!(
+        nodes
+            .iter()
+            .map(|node| node.path.as_str())
+            .collect::<Vec<_>>(),
+        [
+            "/empty",
+            "/etc",
+            "/etc/os-release",
+            "/long",
+            "/sys-root",
+            "/sys-root/usr",
+            "/sys-root/usr/lib",
+            "/sys-root/usr/lib/large",
+            "/sys-root/usr/lib/os-release",
+            "/usr",
+        ]
+    );
+    let large_node = &nodes[7];
+    assert_eq!(large_node.kind, FileKind::File);
+    assert_eq!(large_node.mode, 0o644);
+    assert_eq!(large_node.size, 5000);
+    assert_eq!(fs.read_node(large_node).unwrap(), large);
+    assert_eq!(nodes[3].target.as_deref(), Some(long_target.as_str()));
+    assert_eq!(nodes[9].kind, FileKind::Symlink);
+    assert_eq!(nodes[9].target.as_deref(), Some("sys-root/usr"));
+    assert_eq!(nodes[1].kind, FileKind::Directory);
+}
+
+#[test]
+fn test_ext4_corrupt() {
+    use super::MemoryDisk;
+
+    fn put32(image: &mut [u8], offset: usize, value: u32) {
+        image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    fn error(result: Result<impl Sized>) -> String {
+        format!("{:#}", result.err().unwrap())
+    }
+    let image = test_ext4(&[
+        ("dir", TestNode::Directory),
+        ("dir/file", TestNode::File(b"data")),
+    ]);
+    let superblock = SUPERBLOCK_OFFSET as usize;
+    // The inode of the file, and the first extent in its block map.
+    let inode = 3 * 1024 + 11 * 128;
+    let extent = inode + 40 + 12;
+
+    assert!(Ext4::new(MemoryDisk(image[..1536].to_vec())).is_err());
+    let mut corrupt = image.clone();
+    corrupt[superblock + 56] = 0;
+    assert_eq!(
+        error(Ext4::new(MemoryDisk(corrupt))),
+        "There is no ext4 filesystem"
+    );
+
+    // The block groups of this many inodes have descriptors that would not fit in the disk.
+    let mut corrupt = image.clone();
+    put32(&mut corrupt, superblock, u32::MAX);
+    put32(&mut corrupt, superblock + 40, 1);
+    assert_eq!(
+        error(Ext4::new(MemoryDisk(corrupt))),
+        "The filesystem has more block groups than fit in it"
+    );
+
+    let mut corrupt = image.clone();
+    put32(&mut corrupt, inode + 4, u32::MAX);
+    put32(&mut corrupt, inode + 108, u32::MAX);
+    let fs = Ext4::new(MemoryDisk(corrupt)).unwrap();
+    assert!(error(fs.read("/dir/file")).contains("A file is larger than the filesystem"));
+
+    let mut corrupt = image.clone();
+    corrupt[extent + 6..extent + 8].copy_from_slice(&u16::MAX.to_le_bytes());
+    let fs = Ext4::new(MemoryDisk(corrupt)).unwrap();
+    assert!(error(fs.read("/dir/file")).contains("is past the end of the filesystem"));
+
+    // A directory whose inode table is past the end of a truncated disk.
+    let fs = Ext4::new(MemoryDisk(image[..3 * 1024].to_vec())).unwrap();
+    assert!(fs.walk().is_err());
+    assert!(fs.read("/dir/file").is_err());
+}
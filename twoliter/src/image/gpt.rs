@@ -0,0 +1,256 @@
+//! The GUID partition table at the start of a disk.
+
+use super::{Disk, SECTOR_SIZE};
+use anyhow::{ensure, Context, Result};
+use serde::Serialize;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Partition entries are 128 bytes, but may be padded to a larger power of two.
+const MAX_ENTRY_SIZE: usize = 4096;
+const MAX_ENTRIES: u32 = 1024;
+
+/// A partition in the table.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Partition {
+    /// The number of the partition, counting from 1.
+    pub(crate) number: u32,
+    pub(crate) name: String,
+    pub(crate) type_guid: String,
+    pub(crate) guid: String,
+    pub(crate) first_lba: u64,
+    pub(crate) last_lba: u64,
+    pub(crate) attributes: u64,
+}
+
+impl Partition {
+    /// The offset of the partition in bytes.
+    pub(crate) fn offset(&self) -> u64 {
+        self.first_lba * SECTOR_SIZE
+    }
+
+    /// The size of the partition in bytes.
+    pub(crate) fn size(&self) -> u64 {
+        (self.last_lba + 1 - self.first_lba) * SECTOR_SIZE
+    }
+}
+
+/// The partition table of a disk.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Gpt {
+    pub(crate) disk_guid: String,
+    pub(crate) first_usable_lba: u64,
+    pub(crate) last_usable_lba: u64,
+    /// The partitions that are in use, in the order of the table.
+    pub(crate) partitions: Vec<Partition>,
+}
+
+impl Gpt {
+    /// Read the primary partition table of `disk`.
+    pub(crate) fn read(disk: &dyn Disk) -> Result<Self> {
+        let header = disk.read_vec(SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        ensure!(
+            &header[0..8] == SIGNATURE,
+            "There is no GPT partition table"
+        );
+        let first_usable_lba = u64_at(&header, 40);
+        let last_usable_lba = u64_at(&header, 48);
+        let entries_lba = u64_at(&header, 72);
+        let entry_count = u32_at(&header, 80);
+        let entry_size = u32_at(&header, 84) as usize;
+        ensure!(
+            (128..=MAX_ENTRY_SIZE).contains(&entry_size) && entry_count <= MAX_ENTRIES,
+            "The GPT header has unexpected entries: {entry_count} of {entry_size} bytes"
+        );
+
+        let entries_offset = entries_lba
+            .checked_mul(SECTOR_SIZE)
+            .context("The GPT header has a corrupt partition entry location")?;
+        let entries = disk.read_vec(entries_offset, entry_size * entry_count as usize)?;
+        let mut partitions = Vec::new();
+        for (i, entry) in entries.chunks_exact(entry_size).enumerate() {
+            if entry[0..16].iter().all(|byte| *byte == 0) {
+                continue;
+            }
+            let name = entry[56..128]
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .take_while(|unit| *unit != 0)
+                .collect::<Vec<_>>();
+            let partition = Partition {
+                number: i as u32 + 1,
+                name: String::from_utf16_lossy(&name),
+                type_guid: guid(&entry[0..16]),
+                guid: guid(&entry[16..32]),
+                first_lba: u64_at(entry, 32),
+                last_lba: u64_at(entry, 40),
+                attributes: u64_at(entry, 48),
+            };
+            ensure!(
+                partition.first_lba <= partition.last_lba,
+                "Partition {} ends before it starts",
+                partition.number
+            );
+            // Make sure that the offset and size in bytes can be computed.
+            ensure!(
+                partition.last_lba < u64::MAX / SECTOR_SIZE,
+                "Partition {} ends past the largest possible disk",
+                partition.number
+            );
+            partitions.push(partition);
+        }
+
+        Ok(Self {
+            disk_guid: guid(&header[56..72]),
+            first_usable_lba,
+            last_usable_lba,
+            partitions,
+        })
+    }
+
+    /// The partition named `name`.
+    pub(crate) fn partition(&self, name: &str) -> Option<&Partition> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.name == name)
+    }
+}
+
+/// Format a GUID the way it is usually written. The first three fields are stored little-endian.
+fn guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{}-{}",
+        u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+        u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+        u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+        hex::encode(&bytes[8..10]),
+        hex::encode(&bytes[10..16])
+    )
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Write a partition table for `partitions` into `image`, for tests.
+#[cfg(test)]
+pub(crate) fn write_test_gpt(image: &mut [u8], partitions: &[(&str, &str, u64, u64)]) {
+    fn guid_bytes(guid: &str) -> Vec<u8> {
+        let bytes = hex::decode(guid.replace('-', "")).unwrap();
+        let mut out = Vec::new();
+        out.extend(bytes[0..4].iter().rev());
+        out.extend(bytes[4..6].iter().rev());
+        out.extend(bytes[6..8].iter().rev());
+        out.extend(&bytes[8..16]);
+        out
+    }
+
+    let last_lba = image.len() as u64 / SECTOR_SIZE - 1;
+    let header = &mut image[512..1024];
+    header[0..8].copy_from_slice(SIGNATURE);
+    header[40..48].copy_from_slice(&34u64.to_le_bytes());
+    header[48..56].copy_from_slice(&(last_lba - 33).to_le_bytes());
+    header[56..72].copy_from_slice(&guid_bytes("11111111-2222-3333-4444-555555555555"));
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    for (i, (name, type_guid, first_lba, last_lba)) in partitions.iter().enumerate() {
+        let entry = &mut image[1024 + i * 128..1024 + (i + 1) * 128];
+        entry[0..16].copy_from_slice(&guid_bytes(type_guid));
+        entry[16..32].copy_from_slice(&guid_bytes(&format!(
+            "{:08x}-0000-0000-0000-000000000000",
+            i + 1
+        )));
+        entry[32..40].copy_from_slice(&first_lba.to_le_bytes());
+        entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+        for (j, unit) in name.encode_utf16().enumerate() {
+            entry[56 + j * 2..58 + j * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+}
+
+#[test]
+fn test_read_gpt() {
+    use super::MemoryDisk;
+
+    let mut image = vec![0; 4 * 1024 * 1024];
+    write_test_gpt(
+        &mut image,
+        &[
+            (
+                "BOTTLEROCKET-BOOT-A",
+                "6b636168-7420-6568-2070-6c616e657421",
+                2048,
+                4095,
+            ),
+            (
+                "BOTTLEROCKET-ROOT-A",
+                "5526016a-1a97-4ea4-b39a-b7c8c6ca4502",
+                4096,
+                8191,
+            ),
+        ],
+    );
+    let gpt = Gpt::read(&MemoryDisk(image)).unwrap();
+    assert_eq!(gpt.disk_guid, "11111111-2222-3333-4444-555555555555");
+    assert_eq!(gpt.partitions.len(), 2);
+    let root = gpt.partition("BOTTLEROCKET-ROOT-A").unwrap();
+    assert_eq!(root.number, 2);
+    assert_eq!(root.type_guid, "5526016a-1a97-4ea4-b39a-b7c8c6ca4502");
+    assert_eq!(root.guid, "00000002-0000-0000-0000-000000000000");
+    assert_eq!(root.offset(), 2 * 1024 * 1024);
+    assert_eq!(root.size(), 2 * 1024 * 1024);
+    assert!(gpt.partition("BOTTLEROCKET-DATA-A").is_none());
+
+    assert!(Gpt::read(&MemoryDisk(vec![0; 4096])).is_err());
+}
+
+#[test]
+fn test_read_corrupt_gpt() {
+    use super::MemoryDisk;
+
+    let mut image = vec![0; 64 * 1024];
+    write_test_gpt(
+        &mut image,
+        &[("PRIVATE", "440408bb-eb0b-4328-a6e5-a29038fad706", 34, 127)],
+    );
+    let entry = 1024;
+    let read = |change: &dyn Fn(&mut Vec<u8>)| {
+        let mut image = image.clone();
+        change(&mut image);
+        Gpt::read(&MemoryDisk(image)).map_err(|e| e.to_string())
+    };
+    assert!(read(&|_| {}).is_ok());
+
+    // The partition entries are past the end of a truncated disk.
+    assert!(read(&|image| image.truncate(1024)).is_err());
+    assert!(
+        read(&|image| image[512 + 72..512 + 80].copy_from_slice(&u64::MAX.to_le_bytes()))
+            .unwrap_err()
+            .contains("corrupt partition entry location")
+    );
+    // Entries that are too large, or too many of them, are not allocated.
+    for (offset, value) in [(80, 1 << 20), (84, u32::MAX), (84, 64)] {
+        assert!(read(
+            &|image| image[512 + offset..512 + offset + 4].copy_from_slice(&value.to_le_bytes())
+        )
+        .unwrap_err()
+        .contains("unexpected entries"));
+    }
+    assert!(
+        read(&|image| image[entry + 32..entry + 40].copy_from_slice(&200u64.to_le_bytes()))
+            .unwrap_err()
+            .contains("ends before it starts")
+    );
+    // A partition whose offset in bytes would overflow.
+    let partition = read(&|image| {
+        image[entry + 32..entry + 40].copy_from_slice(&(u64::MAX / 1024).to_le_bytes());
+        image[entry + 40..entry + 48].copy_from_slice(&u64::MAX.to_le_bytes());
+    });
+    assert!(partition.unwrap_err().contains("largest possible disk"));
+}
@@ -0,0 +1,137 @@
+//! The GRUB configuration that `rpm2img` writes to the boot partition, which holds the kernel
+//! command line.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+
+/// Where the boot partition keeps the GRUB configuration.
+pub(crate) const GRUB_CFG_PATH: &str = "/grub/grub.cfg";
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct GrubConfig {
+    /// The variables set with `set name="value"`.
+    pub(crate) variables: BTreeMap<String, String>,
+    /// The arguments of the `linux` command after the kernel path, including those after `--`
+    /// that the kernel passes on to init.
+    pub(crate) kernel_parameters: Vec<String>,
+}
+
+impl GrubConfig {
+    pub(crate) fn parse(grub_cfg: &str) -> Result<Self> {
+        let grub_cfg = grub_cfg.replace("\\\n", " ");
+        let mut config = Self::default();
+        let mut found_linux = false;
+        for line in grub_cfg.lines() {
+            let words = split_words(line);
+            match words.first().map(String::as_str) {
+                Some("set") if words.len() == 2 => {
+                    if let Some((name, value)) = words[1].split_once('=') {
+                        config.variables.insert(name.to_string(), value.to_string());
+                    }
+                }
+                Some("linux") if !found_linux => {
+                    found_linux = true;
+                    config.kernel_parameters = words.into_iter().skip(2).collect();
+                }
+                _ => {}
+            }
+        }
+        found_linux
+            .then_some(config)
+            .context("The GRUB configuration has no 'linux' command")
+    }
+}
+
+/// Split a line of GRUB script into words, removing the double quotes around parts of words.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    for c in line.trim().chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+#[test]
+fn test_grub_config() {
+    let config = GrubConfig::parse(
+        r#"set default="0"
+set timeout="0"
+set dm_verity_root="root,,,ro,0 1638400 verity 1 PARTUUID=$boot_uuid/PARTNROFF=1"
+
+menuentry "Bottlerocket OS 1.0.0" --unrestricted {
+   linux ($root)/vmlinuz \
+       console=tty0 console=ttyS0,115200n8 \
+        \
+       root=/dev/dm-0 rootwait ro \
+       dm-mod.create="$dm_verity_root" \
+       -- \
+       systemd.log_color=0
+
+   boot
+}
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.variables["timeout"], "0");
+    assert_eq!(
+        config.variables["dm_verity_root"],
+        "root,,,ro,0 1638400 verity 1 PARTUUID=$boot_uuid/PARTNROFF=1"
+    );
+    assert_eq!(
+        config.kernel_parameters,
+        [
+            "console=tty0",
+            "console=ttyS0,115200n8",
+            "root=/dev/dm-0",
+            "rootwait",
+            "ro",
+            "dm-mod.create=$dm_verity_root",
+            "--",
+            "systemd.log_color=0",
+        ]
+    );
+    assert!(GrubConfig::parse("set default=\"0\"\n").is_err());
+}
+
+#[test]
+fn test_grub_config_malformed() {
+    // A quote that is never closed takes in the rest of the line.
+    let config =
+        GrubConfig::parse("set prompt=\"none\nlinux /vmlinuz \"console=tty0 ro\n").unwrap();
+    assert_eq!(config.variables["prompt"], "none");
+    assert_eq!(config.kernel_parameters, ["console=tty0 ro"]);
+
+    // Only the first `linux` command counts, and `set` without a value is skipped.
+    let config =
+        GrubConfig::parse("set\nset novalue\nlinux /vmlinuz a\nlinux /vmlinuz b\n").unwrap();
+    assert!(config.variables.is_empty());
+    assert_eq!(config.kernel_parameters, ["a"]);
+
+    let config = GrubConfig::parse("linux").unwrap();
+    assert!(config.kernel_parameters.is_empty());
+
+    assert!(GrubConfig::parse("").is_err());
+    assert!(GrubConfig::parse(&String::from_utf8_lossy(&[0xff, 0xfe, 0x00, b'\n'])).is_err());
+    assert!(GrubConfig::parse("\\").is_err());
+}
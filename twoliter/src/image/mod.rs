@@ -0,0 +1,453 @@
+//! Reading Bottlerocket disk images without mounting them or needing root privileges. The images
+//! that the variant build writes to `build/images` are opened as they are, whether they are raw
+//! images compressed with lz4 or qcow2 images, and their partitions and ext4 filesystems are
+//! parsed directly.
+
+mod ext4;
+mod gpt;
+mod grub;
+mod qcow2;
+mod variant;
+mod verity;
+
+pub(crate) use self::ext4::{Ext4, FileKind};
+pub(crate) use self::gpt::{Gpt, Partition};
+pub(crate) use self::grub::GrubConfig;
+pub(crate) use self::variant::{ImageLayout, PlannedPartition, VariantRecord};
+pub(crate) use self::verity::{DmVerity, DM_VERITY_ROOT_VARIABLE};
+#[cfg(test)]
+pub(crate) use self::{ext4::TestNode, test_image::test_image};
+
+use self::qcow2::Qcow2;
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// The size of the sectors that GPT addresses are counted in.
+pub(crate) const SECTOR_SIZE: u64 = 512;
+
+/// The partitions of the active bank, which an image boots from when it is new.
+pub(crate) const BOOT_PARTITION: &str = "BOTTLEROCKET-BOOT-A";
+pub(crate) const ROOT_PARTITION: &str = "BOTTLEROCKET-ROOT-A";
+pub(crate) const HASH_PARTITION: &str = "BOTTLEROCKET-HASH-A";
+
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+const QCOW2_MAGIC: [u8; 4] = *b"QFI\xfb";
+const VMDK_MAGIC: [u8; 4] = *b"KDMV";
+
+/// The image file extensions that the variant build writes, most common first.
+const IMAGE_EXTENSIONS: [&str; 4] = [".img.lz4", ".qcow2", ".img", ".vmdk"];
+
+/// Something that reads like a block device.
+pub(crate) trait Disk {
+    /// The size in bytes.
+    fn size(&self) -> u64;
+
+    /// Fill `buf` from `offset`, failing if that would read past the end.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Read `len` bytes from `offset`. The read is checked against the size first, so that a
+    /// corrupt length in an image fails rather than allocating more than the image holds.
+    fn read_vec(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        check_bounds(self.size(), offset, len)?;
+        let mut buf = vec![0; len];
+        self.read_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// A disk in memory, for tests.
+#[cfg(test)]
+pub(crate) struct MemoryDisk(pub(crate) Vec<u8>);
+
+#[cfg(test)]
+impl Disk for MemoryDisk {
+    fn size(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        check_bounds(self.size(), offset, buf.len())?;
+        buf.copy_from_slice(&self.0[offset as usize..offset as usize + buf.len()]);
+        Ok(())
+    }
+}
+
+/// An uncompressed image in a file.
+struct RawDisk {
+    file: File,
+    size: u64,
+}
+
+impl RawDisk {
+    fn new(file: File) -> Result<Self> {
+        let size = file
+            .metadata()
+            .context("Unable to get the size of the image")?
+            .len();
+        Ok(Self { file, size })
+    }
+}
+
+impl Disk for RawDisk {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        check_bounds(self.size, offset, buf.len())?;
+        self.file
+            .read_exact_at(buf, offset)
+            .context(format!("Unable to read the image at offset {offset}"))
+    }
+}
+
+/// A range of another disk, such as a partition.
+pub(crate) struct Slice<'a> {
+    disk: &'a dyn Disk,
+    offset: u64,
+    size: u64,
+}
+
+impl Disk for Slice<'_> {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        check_bounds(self.size, offset, buf.len())?;
+        self.disk.read_at(self.offset + offset, buf)
+    }
+}
+
+fn check_bounds(size: u64, offset: u64, len: usize) -> Result<()> {
+    ensure!(
+        offset
+            .checked_add(len as u64)
+            .is_some_and(|end| end <= size),
+        "Unable to read {len} bytes at offset {offset}, past the end at {size}"
+    );
+    Ok(())
+}
+
+/// A Bottlerocket OS image and its partition table.
+pub(crate) struct OsImage {
+    path: PathBuf,
+    disk: Box<dyn Disk + Send>,
+    gpt: Gpt,
+}
+
+impl OsImage {
+    /// Open the OS image at `path`. If `path` is a directory of build output, such as
+    /// `build/images/x86_64-aws-dev/latest`, the OS image in it is opened. Images compressed with
+    /// lz4 are decompressed to a temporary file first.
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let path = if path.is_dir() {
+            find_os_image(path)?
+        } else {
+            path.to_path_buf()
+        };
+        let mut file =
+            File::open(&path).context(format!("Unable to open image '{}'", path.display()))?;
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)
+            .context(format!("Unable to read image '{}'", path.display()))?;
+        file.rewind()
+            .context(format!("Unable to read image '{}'", path.display()))?;
+        let disk: Box<dyn Disk + Send> = match magic {
+            LZ4_MAGIC => Box::new(RawDisk::new(decompress_lz4(file).context(format!(
+                "Unable to decompress image '{}'",
+                path.display()
+            ))?)?),
+            QCOW2_MAGIC => Box::new(
+                Qcow2::new(file).context(format!("Unable to open image '{}'", path.display()))?,
+            ),
+            VMDK_MAGIC => bail!(
+                "Image '{}' is a VMDK, which cannot be inspected. Use a raw or qcow2 image instead.",
+                path.display()
+            ),
+            _ => Box::new(RawDisk::new(file)?),
+        };
+        let gpt = Gpt::read(disk.as_ref()).context(format!(
+            "Unable to read the partition table of '{}'",
+            path.display()
+        ))?;
+        Ok(Self { path, disk, gpt })
+    }
+
+    /// The path of the image file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The directory of the image, which holds the other output of its build.
+    pub(crate) fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// The size of the image in bytes, after decompressing it.
+    pub(crate) fn size(&self) -> u64 {
+        self.disk.size()
+    }
+
+    pub(crate) fn gpt(&self) -> &Gpt {
+        &self.gpt
+    }
+
+    /// The contents of the partition named `name`.
+    pub(crate) fn partition(&self, name: &str) -> Result<Slice<'_>> {
+        let partition = self.gpt.partition(name).context(format!(
+            "Image '{}' has no partition '{name}'",
+            self.path.display()
+        ))?;
+        ensure!(
+            partition.offset() + partition.size() <= self.disk.size(),
+            "Partition '{name}' extends past the end of image '{}'",
+            self.path.display()
+        );
+        Ok(Slice {
+            disk: self.disk.as_ref(),
+            offset: partition.offset(),
+            size: partition.size(),
+        })
+    }
+
+    /// The ext4 filesystem in the partition named `name`.
+    pub(crate) fn filesystem(&self, name: &str) -> Result<Ext4<Slice<'_>>> {
+        Ext4::new(self.partition(name)?).context(format!(
+            "Unable to read the filesystem of partition '{name}' in '{}'",
+            self.path.display()
+        ))
+    }
+
+    /// The GRUB configuration in the boot partition.
+    pub(crate) fn grub_config(&self) -> Result<GrubConfig> {
+        let grub_cfg = self.filesystem(BOOT_PARTITION)?.read(grub::GRUB_CFG_PATH)?;
+        GrubConfig::parse(&String::from_utf8_lossy(&grub_cfg)).context(format!(
+            "Unable to parse the GRUB configuration of '{}'",
+            self.path.display()
+        ))
+    }
+
+    /// The fields of `/etc/os-release` in the root filesystem, with the quotes removed from their
+    /// values.
+    pub(crate) fn os_release(&self) -> Result<BTreeMap<String, String>> {
+        let os_release = self.filesystem(ROOT_PARTITION)?.read("/etc/os-release")?;
+        Ok(String::from_utf8_lossy(&os_release)
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (key.trim().to_string(), value.to_string())
+            })
+            .collect())
+    }
+}
+
+/// Find the OS image among the build output in `dir`, skipping the data image and the links to
+/// the image under other names.
+fn find_os_image(dir: &Path) -> Result<PathBuf> {
+    let mut images = Vec::new();
+    for entry in
+        std::fs::read_dir(dir).context(format!("Unable to read directory '{}'", dir.display()))?
+    {
+        let entry = entry.context(format!("Unable to read directory '{}'", dir.display()))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_file = entry
+            .file_type()
+            .context(format!("Unable to read directory '{}'", dir.display()))?
+            .is_file();
+        let Some(stem) = IMAGE_EXTENSIONS
+            .iter()
+            .find_map(|extension| name.strip_suffix(extension))
+        else {
+            continue;
+        };
+        if is_file && !stem.ends_with("-data") {
+            images.push(entry.path());
+        }
+    }
+    images.sort();
+    match images.as_slice() {
+        [image] => Ok(image.clone()),
+        [] => bail!("There is no OS image in '{}'", dir.display()),
+        _ => bail!(
+            "There is more than one OS image in '{}', pass one of them instead: {}",
+            dir.display(),
+            images
+                .iter()
+                .map(|image| image.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Decompress an lz4 image to an unnamed temporary file. Runs of zeros are skipped rather than
+/// written, so that the file is sparse like the image was before it was compressed.
+fn decompress_lz4(file: File) -> Result<File> {
+    const CHUNK_SIZE: usize = 1024 * 1024;
+    let mut decoder = lz4_flex::frame::FrameDecoder::new(std::io::BufReader::new(file));
+    let mut output = tempfile::tempfile().context("Unable to create a temporary file")?;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut size = 0;
+    loop {
+        let mut filled = 0;
+        while filled < CHUNK_SIZE {
+            match decoder.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).context("Unable to decompress the image"),
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        if chunk[..filled].iter().all(|byte| *byte == 0) {
+            output
+                .seek(SeekFrom::Current(filled as i64))
+                .context("Unable to write the decompressed image")?;
+        } else {
+            output
+                .write_all(&chunk[..filled])
+                .context("Unable to write the decompressed image")?;
+        }
+        size += filled as u64;
+    }
+    output
+        .set_len(size)
+        .context("Unable to write the decompressed image")?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test_image {
+    use super::ext4::{test_ext4, TestNode};
+    use super::gpt::write_test_gpt;
+    use super::{BOOT_PARTITION, ROOT_PARTITION, SECTOR_SIZE};
+
+    /// Make a raw image with a boot partition that holds `grub_cfg`, followed by a root partition
+    /// that holds `root`, for tests.
+    pub(crate) fn test_image(grub_cfg: &str, root: &[(&str, TestNode<'_>)]) -> Vec<u8> {
+        const BOOT_TYPE: &str = "6b636168-7420-6568-2070-6c616e657421";
+        const ROOT_TYPE: &str = "5526016a-1a97-4ea4-b39a-b7c8c6ca4502";
+        let boot = test_ext4(&[
+            ("grub", TestNode::Directory),
+            ("grub/grub.cfg", TestNode::File(grub_cfg.as_bytes())),
+        ]);
+        let root = test_ext4(root);
+
+        let boot_start = 2048;
+        let boot_end = boot_start + boot.len() as u64 / SECTOR_SIZE - 1;
+        let root_start = boot_end + 1;
+        let root_end = root_start + root.len() as u64 / SECTOR_SIZE - 1;
+        let mut image = vec![0; ((root_end + 1 + 2048) * SECTOR_SIZE) as usize];
+        write_test_gpt(
+            &mut image,
+            &[
+                (BOOT_PARTITION, BOOT_TYPE, boot_start, boot_end),
+                (ROOT_PARTITION, ROOT_TYPE, root_start, root_end),
+            ],
+        );
+        let boot_offset = (boot_start * SECTOR_SIZE) as usize;
+        image[boot_offset..boot_offset + boot.len()].copy_from_slice(&boot);
+        let root_offset = (root_start * SECTOR_SIZE) as usize;
+        image[root_offset..root_offset + root.len()].copy_from_slice(&root);
+        image
+    }
+}
+
+#[test]
+fn test_open_os_image() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let image = test_image(
+        "set timeout=\"0\"\nlinux ($root)/vmlinuz console=ttyS0 ro\n",
+        &[
+            ("etc", TestNode::Directory),
+            ("etc/os-release", TestNode::Symlink("../usr/lib/os-release")),
+            ("usr", TestNode::Directory),
+            ("usr/lib", TestNode::Directory),
+            (
+                "usr/lib/os-release",
+                TestNode::File(
+                    b"# Bottlerocket\nPRETTY_NAME=\"Bottlerocket OS\"\nVERSION_ID=1.0.0\n",
+                ),
+            ),
+        ],
+    );
+    let path = tempdir
+        .path()
+        .join("bottlerocket-aws-dev-x86_64-1.0.0-abcdef.img.lz4");
+    let mut encoder = lz4_flex::frame::FrameEncoder::new(File::create(&path).unwrap());
+    encoder.write_all(&image).unwrap();
+    encoder.finish().unwrap();
+
+    let os_image = OsImage::open(tempdir.path()).unwrap();
+    assert_eq!(os_image.path(), path);
+    assert_eq!(os_image.dir(), tempdir.path());
+    assert_eq!(os_image.size(), image.len() as u64);
+    assert_eq!(os_image.gpt().partitions.len(), 2);
+    assert_eq!(
+        os_image.grub_config().unwrap().kernel_parameters,
+        ["console=ttyS0", "ro"]
+    );
+    let os_release = os_image.os_release().unwrap();
+    assert_eq!(os_release.len(), 2);
+    assert_eq!(os_release["PRETTY_NAME"], "Bottlerocket OS");
+    assert_eq!(os_release["VERSION_ID"], "1.0.0");
+    assert!(os_image.partition("BOTTLEROCKET-DATA-A").is_err());
+}
+
+#[test]
+fn test_find_os_image() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let dir = tempdir.path();
+    let prefix = "bottlerocket-aws-dev-x86_64-1.0.0-abcdef";
+    for name in [
+        format!("{prefix}.img.lz4"),
+        format!("{prefix}-data.img.lz4"),
+        format!("{prefix}-root.ext4.lz4"),
+        "packages.json".to_string(),
+    ] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    std::os::unix::fs::symlink(
+        format!("{prefix}.img.lz4"),
+        dir.join("bottlerocket-aws-dev-x86_64.img.lz4"),
+    )
+    .unwrap();
+    assert_eq!(
+        find_os_image(dir).unwrap(),
+        dir.join(format!("{prefix}.img.lz4"))
+    );
+
+    std::fs::write(dir.join(format!("{prefix}.qcow2")), "").unwrap();
+    assert!(find_os_image(dir).is_err());
+}
+
+#[test]
+fn test_decompress_lz4() {
+    let mut image = vec![0; 3 * 1024 * 1024];
+    image[1024 * 1024 + 7] = 42;
+    image.extend_from_slice(b"end");
+    let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+    encoder.write_all(&image).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&compressed).unwrap();
+    file.rewind().unwrap();
+    let disk = RawDisk::new(decompress_lz4(file).unwrap()).unwrap();
+    assert_eq!(disk.size(), image.len() as u64);
+    assert_eq!(disk.read_vec(0, image.len()).unwrap(), image);
+    assert!(disk.read_vec(disk.size() - 2, 3).is_err());
+}
@@ -0,0 +1,342 @@
+//! A reader for qcow2 images, as written by `qemu-img convert`. Images with a backing file,
+//! encryption or an external data file are not supported.
+
+use super::Disk;
+use anyhow::{ensure, Context, Result};
+use flate2::read::DeflateDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::sync::{Arc, Mutex};
+
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const COMPRESSED_FLAG: u64 = 1 << 62;
+const ZERO_FLAG: u64 = 1;
+
+/// The incompatible features that do not change how the image is read: the dirty bit and the
+/// compression type, which must be deflate anyway.
+const SUPPORTED_INCOMPATIBLE_FEATURES: u64 = 0b1001;
+
+/// The largest virtual size to accept. Bottlerocket's images are a few GiB, or a few tens of GiB
+/// with the data partition, and files read from a corrupt image are only bounded by this size.
+const MAX_SIZE: u64 = 64 << 30;
+
+pub(super) struct Qcow2 {
+    file: File,
+    size: u64,
+    cluster_bits: u32,
+    l1_table: Vec<u64>,
+    /// The L2 tables read so far, by their offset.
+    l2_tables: Mutex<HashMap<u64, Arc<Vec<u64>>>>,
+}
+
+impl Qcow2 {
+    pub(super) fn new(file: File) -> Result<Self> {
+        let mut header = [0; 104];
+        file.read_exact_at(&mut header, 0)
+            .context("Unable to read the qcow2 header")?;
+        let version = u32_at(&header, 4);
+        ensure!(
+            version == 2 || version == 3,
+            "Unsupported qcow2 version {version}"
+        );
+        ensure!(
+            u64_at(&header, 8) == 0,
+            "Images with a backing file are not supported"
+        );
+        let cluster_bits = u32_at(&header, 20);
+        ensure!(
+            (9..=21).contains(&cluster_bits),
+            "Unexpected qcow2 cluster size"
+        );
+        ensure!(
+            u32_at(&header, 32) == 0,
+            "Encrypted images are not supported"
+        );
+        if version == 3 {
+            let incompatible = u64_at(&header, 72);
+            ensure!(
+                incompatible & !SUPPORTED_INCOMPATIBLE_FEATURES == 0,
+                "The image uses unsupported qcow2 features ({incompatible:#x})"
+            );
+            if incompatible & 0b1000 != 0 {
+                let mut compression = [0; 1];
+                file.read_exact_at(&mut compression, 104)
+                    .context("Unable to read the qcow2 header")?;
+                ensure!(compression[0] == 0, "Only deflate compression is supported");
+            }
+        }
+
+        let size = u64_at(&header, 24);
+        ensure!(
+            size <= MAX_SIZE,
+            "The qcow2 image is {size} bytes, more than the largest supported size of {MAX_SIZE}"
+        );
+        let l1_size = u32_at(&header, 36) as usize;
+        let l1_offset = u64_at(&header, 40);
+        ensure!(l1_size <= 1 << 24, "The qcow2 L1 table is too large");
+        let file_size = file
+            .metadata()
+            .context("Unable to get the size of the image")?
+            .len();
+        ensure!(
+            l1_offset
+                .checked_add(l1_size as u64 * 8)
+                .is_some_and(|end| end <= file_size),
+            "The qcow2 L1 table is past the end of the image"
+        );
+        // Each L1 entry maps an L2 table's worth of clusters.
+        let mapped = (l1_size as u64)
+            .checked_mul(1 << (2 * cluster_bits - 3))
+            .context("The qcow2 L1 table maps more than the largest possible image")?;
+        ensure!(
+            size <= mapped,
+            "The qcow2 image is {size} bytes, but its L1 table only maps {mapped}"
+        );
+        let mut l1 = vec![0; l1_size * 8];
+        file.read_exact_at(&mut l1, l1_offset)
+            .context("Unable to read the qcow2 L1 table")?;
+
+        Ok(Self {
+            file,
+            size,
+            cluster_bits,
+            l1_table: l1.chunks_exact(8).map(|entry| u64_at(entry, 0)).collect(),
+            l2_tables: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    fn l2_table(&self, offset: u64) -> Result<Arc<Vec<u64>>> {
+        let mut tables = self
+            .l2_tables
+            .lock()
+            .expect("The qcow2 L2 table cache is poisoned");
+        if let Some(table) = tables.get(&offset) {
+            return Ok(Arc::clone(table));
+        }
+        let mut raw = vec![0; self.cluster_size() as usize];
+        self.file
+            .read_exact_at(&mut raw, offset)
+            .context("Unable to read a qcow2 L2 table")?;
+        let table = Arc::new(raw.chunks_exact(8).map(|entry| u64_at(entry, 0)).collect());
+        tables.insert(offset, Arc::clone(&table));
+        Ok(table)
+    }
+
+    /// Read the part of the guest cluster at `offset` that `buf` covers. The read must not cross
+    /// into the next cluster.
+    fn read_cluster(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let cluster_size = self.cluster_size();
+        let in_cluster = offset % cluster_size;
+        let l2_entries = cluster_size / 8;
+        let cluster = offset / cluster_size;
+        let l1_entry = self
+            .l1_table
+            .get((cluster / l2_entries) as usize)
+            .copied()
+            .unwrap_or(0);
+        let l2_offset = l1_entry & OFFSET_MASK;
+        if l2_offset == 0 {
+            buf.fill(0);
+            return Ok(());
+        }
+        let entry = self.l2_table(l2_offset)?[(cluster % l2_entries) as usize];
+
+        if entry & COMPRESSED_FLAG != 0 {
+            let offset_bits = 62 - (self.cluster_bits - 8);
+            let host_offset = entry & ((1 << offset_bits) - 1);
+            let sectors = ((entry >> offset_bits) & ((1 << (self.cluster_bits - 8)) - 1)) + 1;
+            let compressed_len = sectors * 512 - (host_offset & 511);
+            let mut compressed = vec![0; compressed_len as usize];
+            // The compressed data may end before the end of the file, where the last sector is.
+            let read = self
+                .file
+                .read_at(&mut compressed, host_offset)
+                .context("Unable to read a compressed qcow2 cluster")?;
+            compressed.truncate(read);
+            let mut data = vec![0; cluster_size as usize];
+            DeflateDecoder::new(compressed.as_slice())
+                .read_exact(&mut data)
+                .context("Unable to decompress a qcow2 cluster")?;
+            let start = in_cluster as usize;
+            buf.copy_from_slice(&data[start..start + buf.len()]);
+            return Ok(());
+        }
+
+        let host_offset = entry & OFFSET_MASK;
+        if host_offset == 0 || entry & ZERO_FLAG != 0 {
+            buf.fill(0);
+            return Ok(());
+        }
+        self.file
+            .read_exact_at(buf, host_offset + in_cluster)
+            .context("Unable to read a qcow2 cluster")
+    }
+}
+
+impl Disk for Qcow2 {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        super::check_bounds(self.size, offset, buf.len())?;
+        let cluster_size = self.cluster_size();
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let len = ((cluster_size - position % cluster_size) as usize).min(buf.len() - done);
+            self.read_cluster(position, &mut buf[done..done + len])
+                .context(format!("Unable to read the image at offset {position}"))?;
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[test]
+fn test_qcow2() {
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // 512 byte clusters, so each L2 table maps 64 clusters. The guest has 128 clusters: the first
+    // is stored as is, the second is compressed, the third is zeroed and the rest are unallocated.
+    const CLUSTER: usize = 512;
+    let mut image = vec![0; 6 * CLUSTER];
+    let put32 = |image: &mut Vec<u8>, offset: usize, value: u32| {
+        image[offset..offset + 4].copy_from_slice(&value.to_be_bytes())
+    };
+    let put64 = |image: &mut Vec<u8>, offset: usize, value: u64| {
+        image[offset..offset + 8].copy_from_slice(&value.to_be_bytes())
+    };
+    image[0..4].copy_from_slice(b"QFI\xfb");
+    put32(&mut image, 4, 3);
+    put32(&mut image, 20, 9);
+    put64(&mut image, 24, 128 * CLUSTER as u64);
+    put32(&mut image, 36, 2);
+    put64(&mut image, 40, CLUSTER as u64);
+    put32(&mut image, 100, 104);
+    // The L1 table in cluster 1 points to the L2 table in cluster 2.
+    put64(&mut image, CLUSTER, (2 * CLUSTER as u64) | (1 << 63));
+    // Guest cluster 0 is in host cluster 3.
+    put64(&mut image, 2 * CLUSTER, (3 * CLUSTER as u64) | (1 << 63));
+    image[3 * CLUSTER..4 * CLUSTER].fill(0xab);
+    // Guest cluster 1 is compressed in host cluster 4.
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&[0xcd; CLUSTER]).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert!(compressed.len() <= CLUSTER);
+    image[4 * CLUSTER..4 * CLUSTER + compressed.len()].copy_from_slice(&compressed);
+    put64(
+        &mut image,
+        2 * CLUSTER + 8,
+        COMPRESSED_FLAG | (4 * CLUSTER as u64),
+    );
+    // Guest cluster 2 reads as zeros, even though it points at data.
+    put64(
+        &mut image,
+        2 * CLUSTER + 16,
+        (3 * CLUSTER as u64) | ZERO_FLAG,
+    );
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&image).unwrap();
+    let disk = Qcow2::new(file).unwrap();
+    assert_eq!(disk.size(), 128 * CLUSTER as u64);
+    let data = disk.read_vec(CLUSTER as u64 - 2, CLUSTER + 4).unwrap();
+    assert_eq!(data[..2], [0xab; 2]);
+    assert_eq!(data[2..CLUSTER + 2], [0xcd; CLUSTER]);
+    assert_eq!(data[CLUSTER + 2..], [0; 2]);
+    assert_eq!(
+        disk.read_vec(100 * CLUSTER as u64, CLUSTER).unwrap(),
+        [0; CLUSTER]
+    );
+    assert!(disk.read_vec(128 * CLUSTER as u64 - 1, 2).is_err());
+}
+
+#[test]
+fn test_qcow2_corrupt() {
+    use std::io::Write;
+
+    const CLUSTER: usize = 512;
+    let mut image = vec![0; 3 * CLUSTER];
+    image[0..4].copy_from_slice(b"QFI\xfb");
+    image[4..8].copy_from_slice(&3u32.to_be_bytes());
+    image[20..24].copy_from_slice(&9u32.to_be_bytes());
+    image[24..32].copy_from_slice(&(64 * CLUSTER as u64).to_be_bytes());
+    image[36..40].copy_from_slice(&1u32.to_be_bytes());
+    image[40..48].copy_from_slice(&(CLUSTER as u64).to_be_bytes());
+    // Guest cluster 0 has an L2 table past the end of the image.
+    image[CLUSTER..CLUSTER + 8].copy_from_slice(&(64 * CLUSTER as u64).to_be_bytes());
+
+    let open = |change: &dyn Fn(&mut Vec<u8>)| {
+        let mut image = image.clone();
+        change(&mut image);
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&image).unwrap();
+        Qcow2::new(file).map_err(|e| e.to_string())
+    };
+    let disk = open(&|_| {}).unwrap();
+    assert!(disk.read_vec(0, 16).is_err());
+    assert!(disk.read_vec(64 * CLUSTER as u64, 1).is_err());
+
+    assert!(open(&|image| image.truncate(64)).is_err());
+    assert!(
+        open(&|image| image[4..8].copy_from_slice(&4u32.to_be_bytes()))
+            .err()
+            .unwrap()
+            .contains("Unsupported qcow2 version")
+    );
+    assert!(
+        open(&|image| image[20..24].copy_from_slice(&40u32.to_be_bytes()))
+            .err()
+            .unwrap()
+            .contains("cluster size")
+    );
+    assert!(
+        open(&|image| image[36..40].copy_from_slice(&(1u32 << 24).to_be_bytes()))
+            .err()
+            .unwrap()
+            .contains("past the end of the image")
+    );
+    assert!(
+        open(&|image| image[40..48].copy_from_slice(&u64::MAX.to_be_bytes()))
+            .err()
+            .unwrap()
+            .contains("past the end of the image")
+    );
+    // A virtual size that the L1 table cannot map would let reads run past the table.
+    assert!(
+        open(&|image| image[24..32].copy_from_slice(&(65 * CLUSTER as u64).to_be_bytes()))
+            .err()
+            .unwrap()
+            .contains("only maps")
+    );
+    // A corrupt virtual size would let the files in the image claim to be as large.
+    assert!(
+        open(&|image| image[24..32].copy_from_slice(&u64::MAX.to_be_bytes()))
+            .err()
+            .unwrap()
+            .contains("largest supported size")
+    );
+    assert!(
+        open(&|image| image[24..32].copy_from_slice(&(MAX_SIZE + 1).to_be_bytes()))
+            .err()
+            .unwrap()
+            .contains("largest supported size")
+    );
+}
@@ -0,0 +1,347 @@
+//! The `variant.json` that buildsys writes next to the images of a variant build, and the
+//! partition layout that an image of that variant should have.
+
+use super::{Gpt, SECTOR_SIZE};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+
+/// How a variant was configured, as recorded by its build.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct VariantRecord {
+    pub(crate) variant: String,
+    pub(crate) image_features: Vec<String>,
+    pub(crate) kernel_parameters: Vec<String>,
+    pub(crate) image_layout: ImageLayout,
+    /// The partitions of the OS image, when the variant lists its own instead of following the
+    /// partition plan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) partitions: Option<Vec<PlannedPartition>>,
+}
+
+impl VariantRecord {
+    /// Load the `variant.json` in `dir`, if the build wrote one. Builds from before Twoliter
+    /// recorded the variant have none.
+    pub(crate) fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join("variant.json");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(&path)
+            .context(format!("Unable to read '{}'", path.display()))?;
+        serde_json::from_str(&json)
+            .map(Some)
+            .context(format!("Unable to parse '{}'", path.display()))
+    }
+
+    /// Describe how an OS image of `size` bytes with the partition table `gpt` differs from the
+    /// image layout and partitions of the variant. Nothing is returned when they match.
+    pub(crate) fn mismatches(&self, size: u64, gpt: &Gpt) -> Vec<String> {
+        let layout = &self.image_layout;
+        let mut mismatches = Vec::new();
+        if size != layout.os_image_size() {
+            mismatches.push(format!(
+                "The image is {} MiB, but os-image-size-gib {} with the {} partition plan makes it \
+                {} MiB",
+                size / MIB,
+                layout.os_image_size_gib,
+                layout.partition_plan,
+                layout.os_image_size() / MIB
+            ));
+        }
+
+        let (planned, source) = match &self.partitions {
+            Some(partitions) => (
+                partitions.clone(),
+                "the variant's partition list".to_string(),
+            ),
+            None => (
+                layout.os_image_partitions(),
+                format!("the {} partition plan", layout.partition_plan),
+            ),
+        };
+        if gpt.partitions.len() != planned.len() {
+            mismatches.push(format!(
+                "The image has {} partitions, but {source} has {}",
+                gpt.partitions.len(),
+                planned.len()
+            ));
+        }
+        for (partition, plan) in gpt.partitions.iter().zip(&planned) {
+            let which = if plan.label.is_empty() {
+                format!("Partition {}", partition.number)
+            } else {
+                format!("Partition {} ({})", partition.number, plan.label)
+            };
+            if partition.name != plan.label {
+                mismatches.push(format!(
+                    "{which} is named '{}' instead of '{}'",
+                    partition.name, plan.label
+                ));
+            }
+            if !partition.type_guid.eq_ignore_ascii_case(&plan.type_guid) {
+                mismatches.push(format!(
+                    "{which} has type {} instead of {}",
+                    partition.type_guid, plan.type_guid
+                ));
+            }
+            let offset = partition.offset();
+            let planned_offset = plan.offset_mib * MIB;
+            if offset != planned_offset {
+                mismatches.push(format!(
+                    "{which} starts at sector {} instead of {}",
+                    offset / SECTOR_SIZE,
+                    planned_offset / SECTOR_SIZE
+                ));
+            }
+            let planned_size = plan.size_mib * MIB;
+            if partition.size() != planned_size {
+                mismatches.push(format!(
+                    "{which} is {} bytes instead of {} MiB",
+                    partition.size(),
+                    plan.size_mib
+                ));
+            }
+        }
+        mismatches
+    }
+}
+
+/// The `image-layout` of the variant's `Cargo.toml`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ImageLayout {
+    pub(crate) os_image_size_gib: u64,
+    pub(crate) data_image_size_gib: u64,
+    pub(crate) partition_plan: PartitionPlan,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PartitionPlan {
+    /// The data partition is in a separate image.
+    Split,
+    /// The data partition is at the end of the OS image.
+    Unified,
+}
+
+impl Display for PartitionPlan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PartitionPlan::Split => write!(f, "split"),
+            PartitionPlan::Unified => write!(f, "unified"),
+        }
+    }
+}
+
+/// A partition of the OS image as `partyplanner` or the variant's own partitions plan it.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PlannedPartition {
+    pub(crate) label: String,
+    pub(crate) type_guid: String,
+    pub(crate) offset_mib: u64,
+    pub(crate) size_mib: u64,
+}
+
+// The type GUIDs that `partyplanner` gives the partitions.
+const BIOS_BOOT_TYPE: &str = "21686148-6449-6e6f-744e-656564454649";
+const EFI_SYSTEM_TYPE: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+const EFI_BACKUP_TYPE: &str = "b39ce39c-0a00-b4ab-2d11-f18f8237a21c";
+const BOOT_TYPE: &str = "6b636168-7420-6568-2070-6c616e657421";
+const ROOT_TYPE: &str = "5526016a-1a97-4ea4-b39a-b7c8c6ca4502";
+const HASH_TYPE: &str = "598f10af-c955-4456-6a99-7720068a6cea";
+const RESERVED_TYPE: &str = "0c5d99a5-d331-4147-baef-08e2b855bdc9";
+const PRIVATE_TYPE: &str = "440408bb-eb0b-4328-a6e5-a29038fad706";
+const DATA_TYPE: &str = "626f7474-6c65-6474-6861-726d61726b73";
+
+impl ImageLayout {
+    /// The size in bytes of the OS image, which holds the data partition too when the partition
+    /// plan is unified.
+    pub(crate) fn os_image_size(&self) -> u64 {
+        match self.partition_plan {
+            PartitionPlan::Split => self.os_image_size_gib * GIB,
+            PartitionPlan::Unified => (self.os_image_size_gib + self.data_image_size_gib) * GIB,
+        }
+    }
+
+    /// The partitions of the OS image in the order `partyplanner` lays them out. This follows the
+    /// sizes in `partyplanner`, which scale with the size of the OS image.
+    pub(crate) fn os_image_partitions(&self) -> Vec<PlannedPartition> {
+        let gib = self.os_image_size_gib;
+        let gpt_mib = 1;
+        let bios_mib = 4;
+        let efi_mib = 5;
+        let data_a_mib = 1;
+        let sizes = [
+            ("BOOT", BOOT_TYPE, gib * 20),
+            ("ROOT", ROOT_TYPE, gib * 460),
+            ("HASH", HASH_TYPE, gib * 5),
+            ("RESERVED", RESERVED_TYPE, gib * 15 - efi_mib),
+        ];
+
+        let mut partitions = Vec::new();
+        let mut offset_mib = 1;
+        let mut add = |label: &str, type_guid: &str, size_mib: u64| {
+            partitions.push(PlannedPartition {
+                label: label.to_string(),
+                type_guid: type_guid.to_string(),
+                offset_mib,
+                size_mib,
+            });
+            offset_mib += size_mib;
+        };
+        add("BIOS-BOOT", BIOS_BOOT_TYPE, bios_mib);
+        for (bank, efi_name, efi_type) in [
+            ("A", "EFI-SYSTEM", EFI_SYSTEM_TYPE),
+            ("B", "EFI-BACKUP", EFI_BACKUP_TYPE),
+        ] {
+            add(efi_name, efi_type, efi_mib);
+            for (part, type_guid, size_mib) in sizes {
+                add(&format!("BOTTLEROCKET-{part}-{bank}"), type_guid, size_mib);
+            }
+        }
+        add(
+            "BOTTLEROCKET-PRIVATE",
+            PRIVATE_TYPE,
+            gib * 24 - (gpt_mib * 2 + bios_mib) - data_a_mib,
+        );
+        // The data partitions have no name, so that they are found by their GUID.
+        match self.partition_plan {
+            PartitionPlan::Split => add("", DATA_TYPE, data_a_mib),
+            PartitionPlan::Unified => add("", DATA_TYPE, self.data_image_size_gib * 1024),
+        }
+        partitions
+    }
+}
+
+#[test]
+fn test_os_image_partitions() {
+    let layout = ImageLayout {
+        os_image_size_gib: 2,
+        data_image_size_gib: 1,
+        partition_plan: PartitionPlan::Split,
+    };
+    let partitions = layout.os_image_partitions();
+    let names = partitions
+        .iter()
+        .map(|partition| partition.label.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "BIOS-BOOT",
+            "EFI-SYSTEM",
+            "BOTTLEROCKET-BOOT-A",
+            "BOTTLEROCKET-ROOT-A",
+            "BOTTLEROCKET-HASH-A",
+            "BOTTLEROCKET-RESERVED-A",
+            "EFI-BACKUP",
+            "BOTTLEROCKET-BOOT-B",
+            "BOTTLEROCKET-ROOT-B",
+            "BOTTLEROCKET-HASH-B",
+            "BOTTLEROCKET-RESERVED-B",
+            "BOTTLEROCKET-PRIVATE",
+            "",
+        ]
+    );
+    // The partitions and the two copies of the partition table fill the image exactly.
+    let last = partitions.last().unwrap();
+    assert_eq!(last.offset_mib + last.size_mib + 1, 2048);
+    assert_eq!(partitions[3].size_mib, 920);
+
+    let unified = ImageLayout {
+        partition_plan: PartitionPlan::Unified,
+        ..layout
+    };
+    assert_eq!(unified.os_image_size(), 3 * GIB);
+    // partyplanner still takes the 1 MiB of the split plan's DATA-A from the private partition,
+    // which leaves it unused.
+    let last = unified.os_image_partitions().pop().unwrap();
+    assert_eq!(last.offset_mib + last.size_mib + 2, 3072);
+}
+
+#[test]
+fn test_layout_mismatches() {
+    use super::gpt::write_test_gpt;
+    use super::MemoryDisk;
+
+    fn test_gpt(planned: &[PlannedPartition]) -> Vec<(&str, &str, u64, u64)> {
+        planned
+            .iter()
+            .map(|plan| {
+                (
+                    plan.label.as_str(),
+                    plan.type_guid.as_str(),
+                    plan.offset_mib * 2048,
+                    (plan.offset_mib + plan.size_mib) * 2048 - 1,
+                )
+            })
+            .collect()
+    }
+    fn read_gpt(table: &[(&str, &str, u64, u64)]) -> Gpt {
+        // The image only needs to be large enough for the partition table.
+        let mut image = vec![0; 64 * 1024];
+        write_test_gpt(&mut image, table);
+        Gpt::read(&MemoryDisk(image)).unwrap()
+    }
+
+    let mut record = VariantRecord {
+        variant: "aws-dev".to_string(),
+        image_features: Vec::new(),
+        kernel_parameters: Vec::new(),
+        image_layout: ImageLayout {
+            os_image_size_gib: 2,
+            data_image_size_gib: 1,
+            partition_plan: PartitionPlan::Split,
+        },
+        partitions: None,
+    };
+    let planned = record.image_layout.os_image_partitions();
+    let mut table = test_gpt(&planned);
+    assert_eq!(
+        record.mismatches(2 * GIB, &read_gpt(&table)),
+        Vec::<String>::new()
+    );
+
+    table[3].3 -= 2048;
+    table.pop();
+    assert_eq!(
+        record.mismatches(GIB, &read_gpt(&table)),
+        [
+            "The image is 1024 MiB, but os-image-size-gib 2 with the split partition plan makes it \
+            2048 MiB",
+            "The image has 12 partitions, but the split partition plan has 13",
+            "Partition 4 (BOTTLEROCKET-ROOT-A) is 963641344 bytes instead of 920 MiB",
+        ]
+    );
+
+    // A variant with its own partitions is checked against those instead.
+    let mut custom = planned.clone();
+    custom[11].size_mib -= 16;
+    custom.insert(
+        12,
+        PlannedPartition {
+            label: "SCRATCH".to_string(),
+            type_guid: "0fc63daf-8483-4772-8e79-3d69d8477de4".to_string(),
+            offset_mib: custom[11].offset_mib + custom[11].size_mib,
+            size_mib: 16,
+        },
+    );
+    let gpt = read_gpt(&test_gpt(&custom));
+    assert_eq!(record.mismatches(2 * GIB, &gpt).len(), 6);
+    record.partitions = Some(custom);
+    assert_eq!(record.mismatches(2 * GIB, &gpt), Vec::<String>::new());
+    assert_eq!(
+        record.mismatches(2 * GIB, &read_gpt(&test_gpt(&planned)))[..2],
+        [
+            "The image has 13 partitions, but the variant's partition list has 14",
+            "Partition 12 (BOTTLEROCKET-PRIVATE) is 42991616 bytes instead of 25 MiB",
+        ]
+    );
+}
@@ -0,0 +1,138 @@
+//! The dm-verity table that GRUB passes to the kernel, and the hash tree in the hash partition
+//! that it refers to.
+
+use super::Disk;
+use anyhow::{bail, ensure, Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// The GRUB variable that holds the dm-verity table of the root filesystem.
+pub(crate) const DM_VERITY_ROOT_VARIABLE: &str = "dm_verity_root";
+
+/// The parameters of a dm-verity target, as in the table given to `dm-mod.create`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DmVerity {
+    pub(crate) version: u32,
+    pub(crate) data_block_size: u64,
+    pub(crate) hash_block_size: u64,
+    pub(crate) data_blocks: u64,
+    /// Where the hash tree starts in the hash device, in hash blocks. The blocks before it hold
+    /// the verity superblock.
+    pub(crate) hash_start_block: u64,
+    pub(crate) hash_algorithm: String,
+    pub(crate) root_hash: String,
+    pub(crate) salt: String,
+}
+
+impl DmVerity {
+    /// Parse a device mapper table, such as `root,,,ro,0 1638400 verity 1 ...`, with a verity
+    /// target.
+    pub(crate) fn parse(table: &str) -> Result<Self> {
+        let words = table.split_whitespace().collect::<Vec<_>>();
+        let target = words
+            .iter()
+            .position(|word| *word == "verity")
+            .context("The device mapper table has no verity target")?;
+        let args = &words[target + 1..];
+        ensure!(
+            args.len() >= 10,
+            "The verity target has {} arguments instead of at least 10",
+            args.len()
+        );
+        let number = |i: usize, what: &str| -> Result<u64> {
+            args[i]
+                .parse()
+                .context(format!("The verity {what} '{}' is not a number", args[i]))
+        };
+        Ok(Self {
+            version: number(0, "version")? as u32,
+            data_block_size: number(3, "data block size")?,
+            hash_block_size: number(4, "hash block size")?,
+            data_blocks: number(5, "number of data blocks")?,
+            hash_start_block: number(6, "hash start block")?,
+            hash_algorithm: args[7].to_string(),
+            root_hash: args[8].to_string(),
+            salt: args[9].to_string(),
+        })
+    }
+
+    /// Check the root hash against the top of the hash tree in `hash_device`, which holds the
+    /// hashes of the hash blocks below it. This does not read the data device, so it shows that
+    /// the hash partition belongs with the kernel command line, not that the root filesystem is
+    /// intact.
+    pub(crate) fn verify_root_hash(&self, hash_device: &dyn Disk) -> Result<bool> {
+        if self.hash_algorithm != "sha256" {
+            bail!(
+                "Unable to check the root hash, the '{}' algorithm is not supported",
+                self.hash_algorithm
+            );
+        }
+        // The "-" salt means that there is none.
+        let salt = match self.salt.as_str() {
+            "-" => Vec::new(),
+            salt => hex::decode(salt).context(format!("The verity salt '{salt}' is not hex"))?,
+        };
+        let top_offset = self
+            .hash_start_block
+            .checked_mul(self.hash_block_size)
+            .context("The verity hash start block is past the end of the hash device")?;
+        let top = hash_device
+            .read_vec(top_offset, self.hash_block_size as usize)
+            .context("Unable to read the top of the verity hash tree")?;
+        let mut hasher = Sha256::new();
+        match self.version {
+            0 => {
+                hasher.update(&top);
+                hasher.update(&salt);
+            }
+            1 => {
+                hasher.update(&salt);
+                hasher.update(&top);
+            }
+            version => bail!("Unable to check the root hash of verity version {version}"),
+        }
+        Ok(hex::encode(hasher.finalize()) == self.root_hash.to_lowercase())
+    }
+}
+
+#[test]
+fn test_dm_verity() {
+    use super::MemoryDisk;
+
+    let salt = [0x5a; 32];
+    let hash = |block: &[u8]| -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(salt);
+        hasher.update(block);
+        hasher.finalize().to_vec()
+    };
+    // Three data blocks fit in one hash block, which is the whole tree.
+    let mut tree = Vec::new();
+    for byte in [1, 2, 3] {
+        tree.extend(hash(&[byte; 4096]));
+    }
+    tree.resize(4096, 0);
+    let root_hash = hex::encode(hash(&tree));
+    let mut hash_device = vec![0; 4096];
+    hash_device.extend(&tree);
+
+    let verity = DmVerity::parse(&format!(
+        "root,,,ro,0 24 verity 1 PARTUUID=$boot_uuid/PARTNROFF=1 PARTUUID=$boot_uuid/PARTNROFF=2 \
+        4096 4096 3 1 sha256 {root_hash} {} 2 restart_on_corruption ignore_zero_blocks",
+        hex::encode(salt)
+    ))
+    .unwrap();
+    assert_eq!(verity.version, 1);
+    assert_eq!(verity.data_blocks, 3);
+    assert_eq!(verity.hash_start_block, 1);
+    assert_eq!(verity.root_hash, root_hash);
+    assert!(verity
+        .verify_root_hash(&MemoryDisk(hash_device.clone()))
+        .unwrap());
+
+    hash_device[4096] ^= 1;
+    assert!(!verity.verify_root_hash(&MemoryDisk(hash_device)).unwrap());
+    assert!(DmVerity::parse("root,,,ro,0 24 linear").is_err());
+    assert!(DmVerity::parse("root,,,ro,0 24 verity 1 a b").is_err());
+}
@@ -0,0 +1,487 @@
+/*!
+
+External kits are kits that a project consumes from a container registry instead of building them
+from source. They are declared in `Twoliter.toml`, and each one is published with a companion
+`<tag>-metadata` image that describes the kit and the kits it depends on. This module resolves the
+full set of external kits by walking those dependencies, and extracts the resolved kits into the
+build directory so that buildsys can find them.
+
+!*/
+use crate::common::fs;
+use crate::docker::{self, DockerContainer, ImageUri};
+use crate::project::Project;
+use anyhow::{bail, ensure, Context, Result};
+use log::{debug, info};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use tempfile::TempDir;
+
+/// The location of the metadata JSON file in a kit's `-metadata` image.
+const KIT_METADATA_PATH: &str = "/metadata.json";
+
+/// The suffix added to a kit's tag to find its metadata image.
+const KIT_METADATA_TAG_SUFFIX: &str = "-metadata";
+
+/// A dependency on an external kit, either declared in `Twoliter.toml` or by another kit.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct KitDependency {
+    /// e.g. public.ecr.aws/bottlerocket
+    pub(crate) registry: Option<String>,
+    /// The name of the kit, e.g. bottlerocket-core-kit. The architecture is appended to this to
+    /// find the kit's image repository.
+    pub(crate) repo: String,
+    /// e.g. 1.15.1
+    pub(crate) version: Version,
+}
+
+impl KitDependency {
+    /// The kit's image for the given architecture, e.g. `registry/my-kit-x86_64:v0.1.0`.
+    pub(crate) fn image(&self, arch: &str) -> ImageUri {
+        ImageUri::new(
+            self.registry.clone(),
+            format!("{}-{}", self.repo, arch),
+            format!("v{}", self.version),
+        )
+    }
+
+    /// The image that holds the kit's metadata, e.g. `registry/my-kit-x86_64:v0.1.0-metadata`.
+    pub(crate) fn metadata_image(&self, arch: &str) -> ImageUri {
+        let mut image = self.image(arch);
+        image.tag.push_str(KIT_METADATA_TAG_SUFFIX);
+        image
+    }
+
+    /// Parse a dependency as it is written in kit metadata, i.e. as the URI of the kit's image for
+    /// the given architecture, e.g. `public.ecr.aws/bottlerocket/bottlerocket-core-kit-x86_64:v1.15.1`.
+    pub(crate) fn from_image_uri(uri: &str, arch: &str) -> Result<Self> {
+        let image: ImageUri = uri.parse()?;
+        let repo = image
+            .repo
+            .strip_suffix(&format!("-{arch}"))
+            .context(format!(
+                "The kit image '{uri}' is not for the '{arch}' architecture"
+            ))?
+            .to_string();
+        let version = parse_version_tag(&image.tag)
+            .context(format!("The kit image '{uri}' does not have a version tag"))?;
+        Ok(Self {
+            registry: image.registry,
+            repo,
+            version,
+        })
+    }
+}
+
+impl Display for KitDependency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.registry {
+            None => write!(f, "{}@{}", self.repo, self.version),
+            Some(registry) => write!(f, "{}/{}@{}", registry, self.repo, self.version),
+        }
+    }
+}
+
+/// Parse a version from a tag such as `v1.2.3`.
+pub(crate) fn parse_version_tag(tag: &str) -> Result<Version> {
+    let version = tag.strip_prefix('v').unwrap_or(tag);
+    Version::parse(version).context(format!("Unable to parse '{tag}' as a version"))
+}
+
+/// The contents of the JSON file found in a kit's `-metadata` image.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct KitMetadataFile {
+    pub(crate) kit: KitMetadata,
+}
+
+/// Describes a kit and the SDK and kits that it was built with.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct KitMetadata {
+    /// e.g. my-awesome-kit
+    pub(crate) name: String,
+    /// e.g. v0.1.0
+    pub(crate) version: String,
+    /// e.g. x86_64
+    pub(crate) arch: String,
+    /// The URI of the SDK image the kit was built with.
+    pub(crate) sdk: String,
+    /// The URIs of the kit images this kit depends on.
+    #[serde(default)]
+    pub(crate) dependencies: Vec<String>,
+}
+
+/// A way of getting the metadata for a kit. This is a trait so that dependency resolution can be
+/// tested without a container registry.
+pub(crate) trait KitMetadataSource {
+    async fn fetch(&self, kit: &KitDependency, arch: &str) -> Result<KitMetadata>;
+}
+
+/// Gets kit metadata by pulling the kit's `-metadata` image and copying the JSON file out of it.
+pub(crate) struct DockerKitMetadataSource {
+    token: String,
+}
+
+impl DockerKitMetadataSource {
+    pub(crate) fn new(project: &Project) -> Self {
+        Self {
+            token: project.token(),
+        }
+    }
+}
+
+impl KitMetadataSource for DockerKitMetadataSource {
+    async fn fetch(&self, kit: &KitDependency, arch: &str) -> Result<KitMetadata> {
+        let image = kit.metadata_image(arch);
+        debug!("Fetching metadata for kit '{kit}' from '{image}'");
+        docker::pull(&image).await?;
+        let container = DockerContainer::new(
+            format!("kit-metadata-{}-{}", kit.repo, self.token),
+            image.uri(),
+        )
+        .await?;
+        let tempdir = TempDir::new().context("Unable to create a tempdir for kit metadata")?;
+        let path = tempdir.path().join("metadata.json");
+        container.cp_out(KIT_METADATA_PATH, &path).await?;
+        let data = fs::read_to_string(&path).await?;
+        let metadata: KitMetadataFile = serde_json::from_str(&data).context(format!(
+            "Unable to parse the kit metadata found in '{image}'"
+        ))?;
+        Ok(metadata.kit)
+    }
+}
+
+/// A kit that was found while resolving the project's external kits.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct ResolvedKit {
+    pub(crate) dependency: KitDependency,
+    pub(crate) metadata: KitMetadata,
+    /// Either `Twoliter.toml` or the name of the kit that depends on this kit.
+    pub(crate) required_by: String,
+}
+
+/// The full set of external kits needed by a project, including transitive dependencies.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct ResolvedKits {
+    /// Resolved kits keyed by name.
+    pub(crate) kits: BTreeMap<String, ResolvedKit>,
+    /// The SDK that all of the kits were built with. This is `None` when there are no kits.
+    pub(crate) sdk: Option<String>,
+}
+
+impl ResolvedKits {
+    /// Walk the dependency graph, starting from `kits`, and make sure that the kits agree with
+    /// each other about the SDK and the versions of the kits they have in common.
+    pub(crate) async fn resolve<S>(source: &S, kits: &[KitDependency], arch: &str) -> Result<Self>
+    where
+        S: KitMetadataSource,
+    {
+        let mut resolved = Self::default();
+        let mut queue: VecDeque<(KitDependency, String)> = kits
+            .iter()
+            .map(|kit| (kit.clone(), "Twoliter.toml".to_string()))
+            .collect();
+
+        while let Some((kit, required_by)) = queue.pop_front() {
+            if let Some(existing) = resolved.kits.get(&kit.repo) {
+                ensure!(
+                    existing.dependency == kit,
+                    "Kit '{}' is required as '{}' by {} but as '{}' by {}",
+                    kit.repo,
+                    existing.dependency,
+                    existing.required_by,
+                    kit,
+                    required_by,
+                );
+                continue;
+            }
+
+            let metadata = source
+                .fetch(&kit, arch)
+                .await
+                .context(format!("Unable to get the metadata for kit '{kit}'"))?;
+            ensure!(
+                metadata.arch == arch,
+                "The metadata for kit '{kit}' is for the '{}' architecture, expected '{arch}'",
+                metadata.arch
+            );
+
+            match &resolved.sdk {
+                None => resolved.sdk = Some(metadata.sdk.clone()),
+                Some(sdk) if sdk != &metadata.sdk => {
+                    let other = resolved
+                        .kits
+                        .values()
+                        .next()
+                        .map(|k| k.dependency.repo.clone())
+                        .unwrap_or_default();
+                    bail!(
+                        "Kit '{}' was built with SDK '{}', but kit '{}' was built with SDK '{}'. \
+                        All kits must be built with the same SDK.",
+                        kit.repo,
+                        metadata.sdk,
+                        other,
+                        sdk,
+                    )
+                }
+                Some(_) => {}
+            }
+
+            for dependency in &metadata.dependencies {
+                let dependency = KitDependency::from_image_uri(dependency, arch)
+                    .context(format!("Kit '{kit}' has an invalid dependency"))?;
+                queue.push_back((dependency, kit.repo.clone()));
+            }
+
+            resolved.kits.insert(
+                kit.repo.clone(),
+                ResolvedKit {
+                    dependency: kit,
+                    metadata,
+                    required_by,
+                },
+            );
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Resolve `kits` and their dependencies for `arch`. The kits must have been built with `sdk`.
+pub(crate) async fn resolve_external_kits(
+    project: &Project,
+    sdk: &ImageUri,
+    kits: &[KitDependency],
+    arch: &str,
+) -> Result<ResolvedKits> {
+    let source = DockerKitMetadataSource::new(project);
+    let resolved = ResolvedKits::resolve(&source, kits, arch).await?;
+    if let Some(kit_sdk) = &resolved.sdk {
+        ensure!(
+            same_sdk(kit_sdk, sdk, arch),
+            "The project uses SDK '{sdk}', but its kits were built with SDK '{kit_sdk}'",
+        );
+    }
+    Ok(resolved)
+}
+
+/// Copy the contents of each kit image into `dir`, which ends up with the same layout as the
+/// `/local` directory of the kit images. `images` maps kit names to image references.
+pub(crate) async fn extract_kits(
+    project: &Project,
+    images: &BTreeMap<String, String>,
+    dir: &Path,
+) -> Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir).await?;
+    }
+    fs::create_dir_all(dir).await?;
+
+    for (name, image) in images {
+        info!("Extracting kit '{name}' from '{image}'");
+        let container =
+            DockerContainer::new(format!("kit-{}-{}", name, project.token()), image).await?;
+        container.cp_out("/local/.", dir).await?;
+    }
+    Ok(())
+}
+
+/// Kit metadata names the SDK image for a specific architecture, whereas the project may name the
+/// multi-arch image. Treat `bottlerocket-sdk-x86_64` as the same SDK as `bottlerocket-sdk`.
+fn same_sdk(kit_sdk: &str, project_sdk: &ImageUri, arch: &str) -> bool {
+    kit_sdk_tag(kit_sdk, project_sdk, arch) == Some(project_sdk.tag.as_str())
+}
+
+/// Returns the tag of the SDK that a kit was built with, if it is the same SDK image repository as
+/// the project's SDK (in either its multi-arch or single-arch form).
+pub(crate) fn kit_sdk_tag<'a>(
+    kit_sdk: &'a str,
+    project_sdk: &ImageUri,
+    arch: &str,
+) -> Option<&'a str> {
+    let (name, tag) = kit_sdk.rsplit_once(':')?;
+    let arch_name = format!("{}-{}", project_sdk.name(), arch);
+    (name == project_sdk.name() || name == arch_name).then_some(tag)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    const SDK: &str = "example.com/bottlerocket-sdk-x86_64:v0.50.0";
+
+    /// Serves kit metadata from memory, keyed by the metadata image URI.
+    struct TestSource(HashMap<String, KitMetadata>);
+
+    impl TestSource {
+        fn new(kits: &[(&str, &str, &str, &[&str])]) -> Self {
+            Self(
+                kits.iter()
+                    .map(|(name, version, sdk, dependencies)| {
+                        let kit = KitDependency {
+                            registry: Some("example.com".to_string()),
+                            repo: name.to_string(),
+                            version: Version::parse(version).unwrap(),
+                        };
+                        let metadata = KitMetadata {
+                            name: name.to_string(),
+                            version: format!("v{version}"),
+                            arch: "x86_64".to_string(),
+                            sdk: sdk.to_string(),
+                            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+                        };
+                        (kit.metadata_image("x86_64").uri(), metadata)
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    impl KitMetadataSource for TestSource {
+        async fn fetch(&self, kit: &KitDependency, arch: &str) -> Result<KitMetadata> {
+            let image = kit.metadata_image(arch).uri();
+            self.0
+                .get(&image)
+                .cloned()
+                .context(format!("No such image '{image}'"))
+        }
+    }
+
+    fn dependency(repo: &str, version: &str) -> KitDependency {
+        KitDependency {
+            registry: Some("example.com".to_string()),
+            repo: repo.to_string(),
+            version: Version::parse(version).unwrap(),
+        }
+    }
+
+    #[test]
+    fn kit_images() {
+        let kit = dependency("my-kit", "0.1.0");
+        assert_eq!(
+            kit.image("x86_64").uri(),
+            "example.com/my-kit-x86_64:v0.1.0"
+        );
+        assert_eq!(
+            kit.metadata_image("aarch64").uri(),
+            "example.com/my-kit-aarch64:v0.1.0-metadata"
+        );
+    }
+
+    #[test]
+    fn parse_dependency_uri() {
+        let kit = KitDependency::from_image_uri(
+            "public.ecr.aws/bottlerocket/bottlerocket-core-kit-x86_64:v1.15.1",
+            "x86_64",
+        )
+        .unwrap();
+        assert_eq!(kit.registry.as_deref(), Some("public.ecr.aws/bottlerocket"));
+        assert_eq!(kit.repo, "bottlerocket-core-kit");
+        assert_eq!(kit.version, Version::new(1, 15, 1));
+
+        let kit =
+            KitDependency::from_image_uri("localhost:5000/my-kit-x86_64:v0.1.0", "x86_64").unwrap();
+        assert_eq!(kit.registry.as_deref(), Some("localhost:5000"));
+        assert_eq!(kit.repo, "my-kit");
+    }
+
+    #[test]
+    fn parse_dependency_uri_wrong_arch() {
+        assert!(
+            KitDependency::from_image_uri("example.com/my-kit-aarch64:v0.1.0", "x86_64").is_err()
+        );
+        assert!(KitDependency::from_image_uri("localhost:5000/my-kit-x86_64", "x86_64").is_err());
+    }
+
+    #[test]
+    fn parse_metadata_json() {
+        let json = r#"{
+          "kit": {
+            "name": "my-awesome-kit",
+            "version": "v0.1.0",
+            "arch": "x86_64",
+            "sdk": "public.ecr.aws/bottlerocket/bottlerocket-sdk-x86_64:v0.50.0",
+            "dependencies": [
+                "public.ecr.aws/bottlerocket/bottlerocket-core-kit-x86_64:v1.15.1"
+            ]
+          }
+        }"#;
+        let metadata: KitMetadataFile = serde_json::from_str(json).unwrap();
+        assert_eq!(metadata.kit.name, "my-awesome-kit");
+        assert_eq!(metadata.kit.dependencies.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn resolve_transitive() {
+        let source = TestSource::new(&[
+            ("a-kit", "1.0.0", SDK, &["example.com/b-kit-x86_64:v2.0.0"]),
+            ("b-kit", "2.0.0", SDK, &["example.com/c-kit-x86_64:v3.0.0"]),
+            ("c-kit", "3.0.0", SDK, &[]),
+        ]);
+        let resolved = ResolvedKits::resolve(&source, &[dependency("a-kit", "1.0.0")], "x86_64")
+            .await
+            .unwrap();
+        assert_eq!(
+            resolved.kits.keys().collect::<Vec<_>>(),
+            vec!["a-kit", "b-kit", "c-kit"]
+        );
+        assert_eq!(resolved.kits["c-kit"].required_by, "b-kit");
+        assert_eq!(resolved.sdk.as_deref(), Some(SDK));
+    }
+
+    #[tokio::test]
+    async fn resolve_sdk_mismatch() {
+        let source = TestSource::new(&[
+            ("a-kit", "1.0.0", SDK, &[]),
+            (
+                "b-kit",
+                "1.0.0",
+                "example.com/bottlerocket-sdk-x86_64:v0.51.0",
+                &[],
+            ),
+        ]);
+        let err = ResolvedKits::resolve(
+            &source,
+            &[dependency("a-kit", "1.0.0"), dependency("b-kit", "1.0.0")],
+            "x86_64",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("same SDK"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn resolve_version_conflict() {
+        let source = TestSource::new(&[
+            ("a-kit", "1.0.0", SDK, &["example.com/c-kit-x86_64:v1.0.0"]),
+            ("b-kit", "1.0.0", SDK, &["example.com/c-kit-x86_64:v2.0.0"]),
+            ("c-kit", "1.0.0", SDK, &[]),
+            ("c-kit", "2.0.0", SDK, &[]),
+        ]);
+        let err = ResolvedKits::resolve(
+            &source,
+            &[dependency("a-kit", "1.0.0"), dependency("b-kit", "1.0.0")],
+            "x86_64",
+        )
+        .await
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("Kit 'c-kit' is required as"), "{message}");
+    }
+
+    #[test]
+    fn sdk_comparison() {
+        let sdk = ImageUri::new(
+            Some("example.com".to_string()),
+            "bottlerocket-sdk",
+            "v0.50.0",
+        );
+        assert!(same_sdk(SDK, &sdk, "x86_64"));
+        assert!(!same_sdk(SDK, &sdk, "aarch64"));
+    }
+}
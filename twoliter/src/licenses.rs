@@ -0,0 +1,600 @@
+//! License attributions for variant images. Each package that a variant includes declares the
+//! licenses of what it is built from in its spec file, and these are checked against the licenses
+//! that the project allows in `sources/deny.toml`. The license texts come from the Rust sources,
+//! the Go modules that packages vendor, and the upstream archives of the packages. They are put
+//! together into one attribution bundle per variant.
+
+use crate::common::fs;
+use crate::events;
+use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// The licenses that packages may use.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AllowList {
+    licenses: BTreeSet<String>,
+}
+
+impl AllowList {
+    /// The licenses allowed by the `[licenses]` table of a cargo-deny config.
+    pub(crate) fn from_deny_toml(deny_toml: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Deny {
+            #[serde(default)]
+            licenses: Licenses,
+        }
+        #[derive(Default, Deserialize)]
+        struct Licenses {
+            #[serde(default)]
+            allow: Vec<String>,
+        }
+
+        let deny: Deny = toml::from_str(deny_toml).context("Unable to parse deny.toml")?;
+        Ok(Self {
+            licenses: deny.licenses.allow.into_iter().collect(),
+        })
+    }
+
+    pub(crate) fn allow(&mut self, license: impl Into<String>) {
+        self.licenses.insert(license.into());
+    }
+
+    /// The licenses in the SPDX `expression` that would need to be allowed for it to be satisfied.
+    /// An `OR` is satisfied by either side, and a license with an exception is allowed when the
+    /// license is.
+    pub(crate) fn disallowed(&self, expression: &str) -> Result<BTreeSet<String>> {
+        let tokens = tokenize(expression);
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            allow_list: self,
+        };
+        let disallowed = parser.or_expression()?;
+        ensure!(
+            parser.position == tokens.len(),
+            "Unexpected '{}' in license expression '{expression}'",
+            tokens[parser.position]
+        );
+        Ok(disallowed)
+    }
+}
+
+fn tokenize(expression: &str) -> Vec<String> {
+    expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+/// A recursive descent parser for SPDX license expressions, which returns the licenses that are not
+/// allowed instead of a syntax tree.
+struct Parser<'a> {
+    tokens: &'a [String],
+    position: usize,
+    allow_list: &'a AllowList,
+}
+
+impl Parser<'_> {
+    fn or_expression(&mut self) -> Result<BTreeSet<String>> {
+        let mut disallowed = self.and_expression()?;
+        while self.operator("OR") {
+            let other = self.and_expression()?;
+            disallowed = if disallowed.is_empty() || other.is_empty() {
+                BTreeSet::new()
+            } else {
+                disallowed.union(&other).cloned().collect()
+            };
+        }
+        Ok(disallowed)
+    }
+
+    fn and_expression(&mut self) -> Result<BTreeSet<String>> {
+        let mut disallowed = self.license()?;
+        while self.operator("AND") {
+            disallowed.extend(self.license()?);
+        }
+        Ok(disallowed)
+    }
+
+    fn license(&mut self) -> Result<BTreeSet<String>> {
+        let Some(token) = self.tokens.get(self.position) else {
+            bail!("Incomplete license expression '{}'", self.tokens.join(" "));
+        };
+        self.position += 1;
+        if token == "(" {
+            let disallowed = self.or_expression()?;
+            ensure!(
+                self.tokens.get(self.position).map(String::as_str) == Some(")"),
+                "Unbalanced parentheses in license expression '{}'",
+                self.tokens.join(" ")
+            );
+            self.position += 1;
+            return Ok(disallowed);
+        }
+        ensure!(
+            token != ")" && !is_operator(token),
+            "Unexpected '{token}' in license expression '{}'",
+            self.tokens.join(" ")
+        );
+        if self.operator("WITH") {
+            ensure!(
+                self.position < self.tokens.len(),
+                "Missing exception in license expression '{}'",
+                self.tokens.join(" ")
+            );
+            self.position += 1;
+        }
+        Ok(if self.allow_list.licenses.contains(token) {
+            BTreeSet::new()
+        } else {
+            BTreeSet::from([token.clone()])
+        })
+    }
+
+    /// Consume the next token if it is `operator`. Spec files often use lowercase operators.
+    fn operator(&mut self, operator: &str) -> bool {
+        let found = self
+            .tokens
+            .get(self.position)
+            .is_some_and(|token| token.eq_ignore_ascii_case(operator));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+}
+
+fn is_operator(token: &str) -> bool {
+    ["AND", "OR", "WITH"]
+        .iter()
+        .any(|operator| token.eq_ignore_ascii_case(operator))
+}
+
+/// The file name extensions of the tarballs that `tar` extracts.
+const TARBALL_EXTENSIONS: [&str; 10] = [
+    ".tar", ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.bz2", ".tbz2", ".tar.zst", ".tzst",
+    ".tar.lz",
+];
+
+/// How the license texts of an archive are found.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ArchiveKind {
+    /// An upstream tarball, whose license files are copied.
+    Upstream,
+    /// A source RPM, whose license files are copied from it and the tarballs it holds.
+    SourceRpm,
+    /// An archive of vendored Go modules, which are scanned for their licenses.
+    Go,
+}
+
+impl ArchiveKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveKind::Upstream => "upstream",
+            ArchiveKind::SourceRpm => "srpm",
+            ArchiveKind::Go => "go",
+        }
+    }
+
+    /// The kind of the upstream file `file_name`, or `None` if it is not an archive that the
+    /// license scan can extract, such as a patch or a single file.
+    fn of_upstream_file(file_name: &Path) -> Option<Self> {
+        let name = file_name.to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".src.rpm") {
+            Some(ArchiveKind::SourceRpm)
+        } else if TARBALL_EXTENSIONS
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        {
+            Some(ArchiveKind::Upstream)
+        } else {
+            None
+        }
+    }
+}
+
+/// An archive that a package is built from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Archive {
+    pub(crate) kind: ArchiveKind,
+    /// The path of the archive, relative to the packages directory.
+    pub(crate) path: PathBuf,
+}
+
+/// What a package is built from, as far as its licenses are concerned.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Package {
+    pub(crate) name: String,
+    /// The `License` tags of the spec file.
+    pub(crate) licenses: Vec<String>,
+    pub(crate) archives: Vec<Archive>,
+    /// The external files that are not archives, relative to the packages directory. Their
+    /// license texts are not scanned.
+    pub(crate) other_files: Vec<PathBuf>,
+    /// Whether the package is built from the project's Rust sources.
+    pub(crate) uses_sources: bool,
+}
+
+impl Package {
+    /// Load the package in `dir`, from its manifest and spec file.
+    pub(crate) async fn load(dir: &Path) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Manifest {
+            package: ManifestPackage,
+        }
+        #[derive(Deserialize)]
+        struct ManifestPackage {
+            name: String,
+            metadata: Option<Metadata>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct Metadata {
+            build_package: Option<BuildPackage>,
+        }
+        #[derive(Default, Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct BuildPackage {
+            package_name: Option<String>,
+            #[serde(default)]
+            source_groups: Vec<PathBuf>,
+            #[serde(default)]
+            external_files: Vec<ExternalFile>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct ExternalFile {
+            path: Option<PathBuf>,
+            url: String,
+            #[serde(default)]
+            bundle_modules: Vec<String>,
+            bundle_output_path: Option<PathBuf>,
+        }
+
+        let manifest_path = dir.join("Cargo.toml");
+        let manifest: Manifest = toml::from_str(&fs::read_to_string(&manifest_path).await?)
+            .context(format!("Unable to parse '{}'", manifest_path.display()))?;
+        let build_package = manifest
+            .package
+            .metadata
+            .and_then(|m| m.build_package)
+            .unwrap_or_default();
+        let name = manifest.package.name;
+        let dir_name = dir
+            .file_name()
+            .context(format!("Invalid package directory '{}'", dir.display()))?;
+
+        // These follow the file names that buildsys uses for external files and Go bundles.
+        let mut archives = Vec::new();
+        let mut other_files = Vec::new();
+        for file in build_package.external_files {
+            let file_name = match file.path {
+                Some(path) => path,
+                None => PathBuf::from(file.url.rsplit('/').next().unwrap_or_default()),
+            };
+            if file.bundle_modules.iter().any(|module| module == "go") {
+                let bundle = file
+                    .bundle_output_path
+                    .unwrap_or_else(|| PathBuf::from(format!("bundled-{}", file_name.display())));
+                archives.push(Archive {
+                    kind: ArchiveKind::Go,
+                    path: Path::new(dir_name).join(bundle),
+                });
+            }
+            let path = Path::new(dir_name).join(&file_name);
+            match ArchiveKind::of_upstream_file(&file_name) {
+                Some(kind) => archives.push(Archive { kind, path }),
+                None => other_files.push(path),
+            }
+        }
+
+        let spec_name = build_package.package_name.as_ref().unwrap_or(&name);
+        let spec_path = dir.join(format!("{spec_name}.spec"));
+        let licenses = fs::read_to_string(&spec_path)
+            .await?
+            .lines()
+            .filter_map(|line| {
+                let (tag, value) = line.split_once(':')?;
+                tag.trim()
+                    .eq_ignore_ascii_case("License")
+                    .then(|| value.trim().to_string())
+            })
+            .collect();
+
+        Ok(Self {
+            name,
+            licenses,
+            archives,
+            other_files,
+            uses_sources: !build_package.source_groups.is_empty(),
+        })
+    }
+}
+
+/// The project's packages that `variant` includes, directly or through the project's kits.
+/// Packages from external kits are already built, so they are not among them.
+pub(crate) async fn variant_packages(project_dir: &Path, variant: &str) -> Result<Vec<Package>> {
+    let cargo_lock = fs::read_to_string(project_dir.join("variants").join("Cargo.lock")).await?;
+    let dependencies = events::workspace_dependencies(&cargo_lock, variant)?;
+
+    let packages_dir = project_dir.join("packages");
+    let mut packages = Vec::new();
+    if !packages_dir.is_dir() {
+        return Ok(packages);
+    }
+    let mut read_dir = tokio::fs::read_dir(&packages_dir)
+        .await
+        .context(format!("Unable to read dir '{}'", packages_dir.display()))?;
+    while let Some(entry) = read_dir.next_entry().await.context(format!(
+        "Error while reading entries in dir '{}'",
+        packages_dir.display()
+    ))? {
+        if !entry.path().join("Cargo.toml").is_file() {
+            continue;
+        }
+        let package = Package::load(&entry.path()).await?;
+        if dependencies.contains(&package.name) {
+            packages.push(package);
+        }
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packages)
+}
+
+/// The licenses of each package that the allow list does not allow, by package.
+pub(crate) fn check_licenses(
+    packages: &[Package],
+    allow_list: &AllowList,
+) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    let mut disallowed = BTreeMap::new();
+    for package in packages {
+        ensure!(
+            !package.licenses.is_empty(),
+            "The spec file of package '{}' has no License tag",
+            package.name
+        );
+        for expression in &package.licenses {
+            let licenses = allow_list
+                .disallowed(expression)
+                .context(format!("Invalid license of package '{}'", package.name))?;
+            if !licenses.is_empty() {
+                disallowed
+                    .entry(package.name.clone())
+                    .or_insert_with(BTreeSet::new)
+                    .extend(licenses);
+            }
+        }
+    }
+    Ok(disallowed)
+}
+
+/// The list of archives that the `scan-licenses` task reads, one `<kind> <package> <path>` per line.
+pub(crate) fn archive_list(packages: &[Package]) -> String {
+    packages
+        .iter()
+        .flat_map(|package| {
+            package.archives.iter().map(move |archive| {
+                format!(
+                    "{} {} {}\n",
+                    archive.kind.as_str(),
+                    package.name,
+                    archive.path.display()
+                )
+            })
+        })
+        .collect()
+}
+
+/// Put the license files found in `dir` together into a single attribution document, after the
+/// licenses that each package declares.
+pub(crate) async fn attribution(variant: &str, packages: &[Package], dir: &Path) -> Result<String> {
+    let mut document = format!("Attributions for the '{variant}' variant\n\n");
+    for package in packages {
+        writeln!(
+            document,
+            "{}: {}",
+            package.name,
+            package.licenses.join(" AND ")
+        )?;
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        let mut read_dir = tokio::fs::read_dir(&current)
+            .await
+            .context(format!("Unable to read dir '{}'", current.display()))?;
+        while let Some(entry) = read_dir.next_entry().await.context(format!(
+            "Error while reading entries in dir '{}'",
+            current.display()
+        ))? {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    for file in files {
+        let name = file
+            .strip_prefix(dir)
+            .unwrap_or(&file)
+            .display()
+            .to_string();
+        let text = String::from_utf8_lossy(&fs::read(&file).await?).into_owned();
+        write!(
+            document,
+            "\n{}\n{}\n\n{}",
+            name,
+            "=".repeat(name.len()),
+            text
+        )?;
+        if !text.ends_with('\n') {
+            document.push('\n');
+        }
+    }
+    Ok(document)
+}
+
+#[test]
+fn test_disallowed() {
+    let mut allow_list = AllowList::from_deny_toml(
+        r#"
+[licenses]
+unlicensed = "deny"
+allow = ["Apache-2.0", "MIT"]
+"#,
+    )
+    .unwrap();
+    let disallowed = |allow_list: &AllowList, expression| {
+        allow_list
+            .disallowed(expression)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    assert!(disallowed(&allow_list, "Apache-2.0 OR MIT").is_empty());
+    assert!(disallowed(&allow_list, "MIT and (GPL-2.0-only or Apache-2.0)").is_empty());
+    assert!(disallowed(&allow_list, "Apache-2.0 WITH LLVM-exception").is_empty());
+    assert_eq!(
+        disallowed(&allow_list, "MIT AND GPL-2.0-only"),
+        ["GPL-2.0-only"]
+    );
+    assert_eq!(
+        disallowed(&allow_list, "(BSD-3-Clause OR ISC) AND MIT"),
+        ["BSD-3-Clause", "ISC"]
+    );
+    assert!(allow_list.disallowed("MIT AND").is_err());
+    assert!(allow_list.disallowed("(MIT").is_err());
+    assert!(allow_list.disallowed("MIT Apache-2.0").is_err());
+
+    allow_list.allow("ISC");
+    assert!(disallowed(&allow_list, "(BSD-3-Clause OR ISC) AND MIT").is_empty());
+}
+
+#[tokio::test]
+async fn test_package_load() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let dir = tempdir.path().join("hello-go");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        r#"
+[package]
+name = "hello-go"
+version = "0.1.0"
+
+[package.metadata.build-package]
+package-name = "hello.go"
+
+[[package.metadata.build-package.external-files]]
+url = "https://example.com/hello-1.0.tar.gz"
+sha512 = "abcdef"
+bundle-modules = ["go"]
+
+[[package.metadata.build-package.external-files]]
+path = "extra.tar.xz"
+url = "https://example.com/download?file=extra"
+sha512 = "123456"
+"#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("hello.go.spec"),
+        "Name: %{_cross_os}hello-go\nLicense: Apache-2.0 OR MIT\n\n%package bin\nLicense: MIT\n",
+    )
+    .unwrap();
+
+    let package = Package::load(&dir).await.unwrap();
+    assert_eq!(package.name, "hello-go");
+    assert_eq!(package.licenses, ["Apache-2.0 OR MIT", "MIT"]);
+    assert!(!package.uses_sources);
+    assert_eq!(
+        archive_list(&[package]),
+        "go hello-go hello-go/bundled-hello-1.0.tar.gz\n\
+         upstream hello-go hello-go/hello-1.0.tar.gz\n\
+         upstream hello-go hello-go/extra.tar.xz\n"
+    );
+}
+
+#[tokio::test]
+async fn test_package_load_mixed_sources() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let dir = tempdir.path().join("kernel-6_1");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut manifest = "[package]\nname = \"kernel-6_1\"\nversion = \"0.1.0\"\n".to_string();
+    for url in [
+        "https://example.com/kernel-6.1.72-1.amzn2023.src.rpm",
+        "https://example.com/firmware-20240101.TAR.XZ",
+        "https://example.com/fix-build.patch",
+        "https://example.com/config-bottlerocket",
+        "https://example.com/tools.zip",
+        "https://example.com/headers.tgz",
+    ] {
+        manifest.push_str(&format!(
+            "\n[[package.metadata.build-package.external-files]]\nurl = \"{url}\"\n\
+            sha512 = \"abcdef\"\n"
+        ));
+    }
+    std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+    std::fs::write(dir.join("kernel-6_1.spec"), "License: GPL-2.0-only\n").unwrap();
+
+    let package = Package::load(&dir).await.unwrap();
+    // Source RPMs and tarballs are extracted, and anything else is left out of the scan.
+    assert_eq!(
+        archive_list(std::slice::from_ref(&package)),
+        "srpm kernel-6_1 kernel-6_1/kernel-6.1.72-1.amzn2023.src.rpm\n\
+         upstream kernel-6_1 kernel-6_1/firmware-20240101.TAR.XZ\n\
+         upstream kernel-6_1 kernel-6_1/headers.tgz\n"
+    );
+    assert_eq!(
+        package.other_files,
+        [
+            PathBuf::from("kernel-6_1/fix-build.patch"),
+            PathBuf::from("kernel-6_1/config-bottlerocket"),
+            PathBuf::from("kernel-6_1/tools.zip"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_attribution() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let dir = tempdir.path();
+    std::fs::create_dir_all(dir.join("packages/hello/hello-1.0")).unwrap();
+    std::fs::create_dir_all(dir.join("go/hello/golang.org/x/sys")).unwrap();
+    std::fs::write(dir.join("packages/hello/hello-1.0/LICENSE"), "MIT text").unwrap();
+    std::fs::write(dir.join("go/hello/golang.org/x/sys/LICENSE"), "BSD text\n").unwrap();
+    let packages = [Package {
+        name: "hello".to_string(),
+        licenses: vec!["MIT".to_string(), "BSD-3-Clause".to_string()],
+        archives: Vec::new(),
+        other_files: Vec::new(),
+        uses_sources: false,
+    }];
+
+    assert_eq!(
+        attribution("hello-ootb", &packages, dir).await.unwrap(),
+        "Attributions for the 'hello-ootb' variant\n\
+         \n\
+         hello: MIT AND BSD-3-Clause\n\
+         \n\
+         go/hello/golang.org/x/sys/LICENSE\n\
+         =================================\n\
+         \n\
+         BSD text\n\
+         \n\
+         packages/hello/hello-1.0/LICENSE\n\
+         ================================\n\
+         \n\
+         MIT text\n"
+    );
+}
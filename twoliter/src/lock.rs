@@ -0,0 +1,487 @@
+/*!
+
+`Twoliter.lock` records the exact SDK and external kit images that a project is built with. Tags
+such as `v0.50.0` or `latest` can be moved to point at different images, so the lock pins each image
+by its manifest digest. The lock is created the first time a project is built, and it is extended
+with the kit images for each new architecture. After that, builds use the pinned images until the
+lock is deleted.
+
+When `Twoliter.toml` changes in a way that the lock no longer satisfies, for example a different
+SDK or a new kit, the lock is stale and builds refuse to run until it is re-created.
+
+!*/
+use crate::common::fs;
+use crate::docker::{self, ImageUri};
+use crate::kit::{self, KitDependency};
+use crate::project::Project;
+use crate::schema_version::SchemaVersion;
+use anyhow::{anyhow, ensure, Context, Result};
+use log::{debug, info};
+use semver::{Comparator, Op, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// The name of the lock file, which lives next to `Twoliter.toml`.
+pub(crate) const LOCK_FILE: &str = "Twoliter.lock";
+
+/// The value used for `required-by` when a kit is declared in `Twoliter.toml`.
+const REQUIRED_BY_PROJECT: &str = "Twoliter.toml";
+
+const LOCK_FILE_HEADER: &str = "# This file is generated by twoliter. Do not edit it by hand.\n";
+
+/// Represents the structure of a `Twoliter.lock` file.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Lock {
+    /// The version of this schema struct.
+    schema_version: SchemaVersion<1>,
+
+    /// The SDK container image.
+    sdk: LockedImage,
+
+    /// The external kits declared in `Twoliter.toml` and the kits they depend on.
+    #[serde(default, rename = "kit", skip_serializing_if = "Vec::is_empty")]
+    kits: Vec<LockedKit>,
+}
+
+/// An image pinned by digest.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LockedImage {
+    #[serde(flatten)]
+    image: ImageUri,
+    /// The manifest digest of the image, or the image ID for an image that was built locally.
+    digest: String,
+}
+
+/// An external kit, pinned by digest for each architecture it has been resolved for.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct LockedKit {
+    #[serde(flatten)]
+    dependency: KitDependency,
+    /// Either `Twoliter.toml` or the name of the kit that depends on this kit.
+    required_by: String,
+    /// The digest of the kit's image for each architecture, keyed by architecture.
+    #[serde(default)]
+    digests: BTreeMap<String, String>,
+}
+
+impl Lock {
+    /// Returns the path to the lock file for `project`.
+    pub(crate) fn path(project: &Project) -> PathBuf {
+        project.project_dir().join(LOCK_FILE)
+    }
+
+    /// Load the lock file from the given path.
+    pub(crate) async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .await
+            .context(format!("Unable to read lock file '{}'", path.display()))?;
+        toml::from_str(&data).context(format!(
+            "Unable to deserialize lock file '{}'",
+            path.display()
+        ))
+    }
+
+    /// Write the lock file to the given path.
+    pub(crate) async fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = toml::to_string(self).context("Unable to serialize the lock file")?;
+        fs::write(path, format!("{LOCK_FILE_HEADER}\n{data}")).await
+    }
+
+    /// Load the project's lock file if there is one, making sure that it is not stale.
+    pub(crate) async fn load_current(project: &Project) -> Result<Option<Self>> {
+        let path = Self::path(project);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let lock = Self::load(&path).await?;
+        lock.check(project)?;
+        Ok(Some(lock))
+    }
+
+    /// Load the project's lock file, or create it if there is none, and make sure it has the kit
+    /// images for `arch`. When `locked` is true, any change to the lock file is an error.
+    pub(crate) async fn load_or_create(
+        project: &Project,
+        arch: &str,
+        locked: bool,
+    ) -> Result<Self> {
+        let path = Self::path(project);
+        let (mut lock, mut changed) = match Self::load_current(project).await? {
+            Some(lock) => (lock, false),
+            None => {
+                ensure!(
+                    !locked,
+                    "'{}' does not exist, but --locked was given",
+                    path.display()
+                );
+                info!("Creating '{}'", path.display());
+                (Self::create(project, arch).await?, true)
+            }
+        };
+
+        if !lock.has_arch(arch) {
+            ensure!(
+                !locked,
+                "'{}' does not have the kits for the '{arch}' architecture, but --locked was given",
+                path.display()
+            );
+            info!("Adding the kits for '{arch}' to '{}'", path.display());
+            lock.add_arch(project, arch).await?;
+            changed = true;
+        }
+
+        if changed {
+            lock.save(&path).await?;
+        }
+        Ok(lock)
+    }
+
+    /// Resolve the project's SDK and kits and pin them by digest.
+    async fn create(project: &Project, arch: &str) -> Result<Self> {
+        let sdk = project.sdk().context(format!(
+            "No SDK defined in {}",
+            project.filepath().display(),
+        ))?;
+        Self::resolve(project, sdk, project.kits(), &[arch.to_string()]).await
+    }
+
+    /// Pin `sdk`, `kits`, and the kits they depend on, by digest for each of `arches`.
+    pub(crate) async fn resolve(
+        project: &Project,
+        sdk: ImageUri,
+        kits: &[KitDependency],
+        arches: &[String],
+    ) -> Result<Self> {
+        docker::ensure_image(&sdk).await?;
+        let digest = docker::image_digest(&sdk).await?;
+        debug!("Locking SDK '{sdk}' to '{digest}'");
+
+        let mut lock = Self {
+            schema_version: SchemaVersion,
+            sdk: LockedImage { image: sdk, digest },
+            kits: Vec::new(),
+        };
+        let Some((first, rest)) = arches.split_first() else {
+            return Ok(lock);
+        };
+
+        let resolved = kit::resolve_external_kits(project, &lock.sdk.image, kits, first).await?;
+        lock.kits = resolved
+            .kits
+            .into_values()
+            .map(|kit| LockedKit {
+                dependency: kit.dependency,
+                required_by: kit.required_by,
+                digests: BTreeMap::new(),
+            })
+            .collect();
+        lock.add_digests(first).await?;
+        for arch in rest {
+            lock.add_arch(project, arch).await?;
+        }
+        Ok(lock)
+    }
+
+    /// Resolve the locked kits for another architecture. The kits are expected to have the same
+    /// dependencies on every architecture.
+    async fn add_arch(&mut self, project: &Project, arch: &str) -> Result<()> {
+        let direct: Vec<KitDependency> = self
+            .kits
+            .iter()
+            .filter(|kit| kit.required_by == REQUIRED_BY_PROJECT)
+            .map(|kit| kit.dependency.clone())
+            .collect();
+        let resolved = kit::resolve_external_kits(project, &self.sdk.image, &direct, arch).await?;
+        let resolved: BTreeSet<&KitDependency> =
+            resolved.kits.values().map(|kit| &kit.dependency).collect();
+        let locked: BTreeSet<&KitDependency> =
+            self.kits.iter().map(|kit| &kit.dependency).collect();
+        ensure!(
+            resolved == locked,
+            "The kits for the '{arch}' architecture have different dependencies than the kits in \
+            '{LOCK_FILE}'. Run `twoliter update` to resolve the kits again."
+        );
+        self.add_digests(arch).await
+    }
+
+    /// Pull each kit image for `arch` and record its digest.
+    async fn add_digests(&mut self, arch: &str) -> Result<()> {
+        for kit in &mut self.kits {
+            let image = kit.dependency.image(arch);
+            docker::ensure_image(&image).await?;
+            let digest = docker::image_digest(&image).await?;
+            debug!("Locking kit '{image}' to '{digest}'");
+            kit.digests.insert(arch.to_string(), digest);
+        }
+        Ok(())
+    }
+
+    /// The locked SDK image.
+    pub(crate) fn sdk(&self) -> &ImageUri {
+        &self.sdk.image
+    }
+
+    /// The locked kits, including the kits that other kits depend on.
+    pub(crate) fn kits(&self) -> impl Iterator<Item = &KitDependency> {
+        self.kits.iter().map(|kit| &kit.dependency)
+    }
+
+    /// The architectures that the kits have been locked for.
+    pub(crate) fn arches(&self) -> BTreeSet<String> {
+        self.kits
+            .iter()
+            .flat_map(|kit| kit.digests.keys().cloned())
+            .collect()
+    }
+
+    /// Returns true if every kit has been pinned for `arch`.
+    fn has_arch(&self, arch: &str) -> bool {
+        self.kits.iter().all(|kit| kit.digests.contains_key(arch))
+    }
+
+    /// Make sure that the lock satisfies what is declared in `Twoliter.toml`. Kit versions in
+    /// `Twoliter.toml` are minimums, so a kit may be locked at any semver-compatible version.
+    fn check(&self, project: &Project) -> Result<()> {
+        match project.sdk() {
+            Some(sdk) if sdk_satisfies(&sdk, &self.sdk.image) => {}
+            Some(sdk) => {
+                return Err(stale(format!(
+                    "the SDK is '{sdk}' in Twoliter.toml, but '{}' in the lock",
+                    self.sdk.image
+                )))
+            }
+            None => return Err(stale("there is no SDK in Twoliter.toml")),
+        }
+
+        let direct: BTreeMap<&str, &KitDependency> = self
+            .kits
+            .iter()
+            .filter(|kit| kit.required_by == REQUIRED_BY_PROJECT)
+            .map(|kit| (kit.dependency.repo.as_str(), &kit.dependency))
+            .collect();
+        for declared in project.kits() {
+            let locked = direct
+                .get(declared.repo.as_str())
+                .ok_or_else(|| stale(format!("the kit '{}' is not in the lock", declared.repo)))?;
+            ensure!(
+                locked.registry == declared.registry
+                    && compatible_versions(&declared.version).matches(&locked.version),
+                stale(format!(
+                    "the kit '{declared}' in Twoliter.toml does not match '{locked}' in the lock"
+                ))
+            );
+        }
+        for name in direct.keys() {
+            ensure!(
+                project.kits().iter().any(|kit| kit.repo == *name),
+                stale(format!("the kit '{name}' is no longer in Twoliter.toml"))
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns a reference to the SDK image that is pinned to the locked digest.
+    pub(crate) async fn sdk_image(&self) -> Result<String> {
+        docker::pinned_image(&self.sdk.image, &self.sdk.digest).await
+    }
+
+    /// Returns references to the kit images for `arch`, keyed by kit name, that are pinned to the
+    /// locked digests.
+    pub(crate) async fn kit_images(&self, arch: &str) -> Result<BTreeMap<String, String>> {
+        let mut images = BTreeMap::new();
+        for kit in &self.kits {
+            let digest = kit.digests.get(arch).context(format!(
+                "The kit '{}' is not locked for the '{arch}' architecture",
+                kit.dependency
+            ))?;
+            let image = docker::pinned_image(&kit.dependency.image(arch), digest).await?;
+            images.insert(kit.dependency.repo.clone(), image);
+        }
+        Ok(images)
+    }
+}
+
+/// The error returned when the lock file does not satisfy `Twoliter.toml`.
+fn stale(reason: impl Display) -> anyhow::Error {
+    anyhow!("'{LOCK_FILE}' is out of date: {reason}. Run `twoliter update` to resolve the SDK and kits again.")
+}
+
+/// Returns true if the `locked` SDK image satisfies the `declared` one. When the SDK tags are
+/// versions, the declared version is a minimum in the same way as it is for kits.
+pub(crate) fn sdk_satisfies(declared: &ImageUri, locked: &ImageUri) -> bool {
+    if declared.name() != locked.name() {
+        return false;
+    }
+    match (
+        kit::parse_version_tag(&declared.tag),
+        kit::parse_version_tag(&locked.tag),
+    ) {
+        (Ok(declared), Ok(locked)) => compatible_versions(&declared).matches(&locked),
+        _ => declared.tag == locked.tag,
+    }
+}
+
+/// The versions that satisfy a kit version declared in `Twoliter.toml`, using the same rules as a
+/// Cargo dependency, i.e. `1.15.1` accepts `>=1.15.1, <2.0.0`.
+pub(crate) fn compatible_versions(version: &semver::Version) -> VersionReq {
+    VersionReq {
+        comparators: vec![Comparator {
+            op: Op::Caret,
+            major: version.major,
+            minor: Some(version.minor),
+            patch: Some(version.patch),
+            pre: version.pre.clone(),
+        }],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::data_dir;
+    use semver::Version;
+    use tempfile::TempDir;
+
+    fn locked_kit(repo: &str, version: &str, required_by: &str) -> LockedKit {
+        LockedKit {
+            dependency: KitDependency {
+                registry: Some("public.ecr.aws/bottlerocket".to_string()),
+                repo: repo.to_string(),
+                version: Version::parse(version).unwrap(),
+            },
+            required_by: required_by.to_string(),
+            digests: [("x86_64".to_string(), format!("sha256:{repo}"))]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// A lock that satisfies `Twoliter-1-kits.toml`.
+    fn test_lock() -> Lock {
+        let mut my_kit = locked_kit("my-kit", "0.1.0", REQUIRED_BY_PROJECT);
+        my_kit.dependency.registry = None;
+        Lock {
+            schema_version: SchemaVersion,
+            sdk: LockedImage {
+                image: ImageUri::new(
+                    Some("public.ecr.aws/bottlerocket".to_string()),
+                    "bottlerocket-sdk",
+                    "v0.50.0",
+                ),
+                digest: "sha256:sdk".to_string(),
+            },
+            kits: vec![
+                locked_kit("bottlerocket-core-kit", "1.15.1", REQUIRED_BY_PROJECT),
+                my_kit,
+                locked_kit("other-kit", "2.0.0", "my-kit"),
+            ],
+        }
+    }
+
+    async fn test_project() -> Project {
+        Project::load(data_dir().join("Twoliter-1-kits.toml"))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join(LOCK_FILE);
+        let lock = test_lock();
+        lock.save(&path).await.unwrap();
+        let data = fs::read_to_string(&path).await.unwrap();
+        assert!(data.starts_with(LOCK_FILE_HEADER));
+        assert!(data.contains("[[kit]]"), "{data}");
+        assert_eq!(Lock::load(&path).await.unwrap(), lock);
+    }
+
+    #[tokio::test]
+    async fn check_current() {
+        let project = test_project().await;
+        test_lock().check(&project).unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_newer_compatible_kit() {
+        let project = test_project().await;
+        let mut lock = test_lock();
+        lock.kits[0].dependency.version = Version::new(1, 16, 0);
+        lock.check(&project).unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_stale_sdk() {
+        let project = test_project().await;
+        let mut lock = test_lock();
+        lock.sdk.image.tag = "v0.49.0".to_string();
+        let err = lock.check(&project).unwrap_err();
+        assert!(err.to_string().contains("the SDK is"), "{err}");
+    }
+
+    #[test]
+    fn sdk_versions() {
+        let sdk = |registry: &str, tag: &str| {
+            ImageUri::new(Some(registry.to_string()), "bottlerocket-sdk", tag)
+        };
+        assert!(sdk_satisfies(
+            &sdk("example.com", "v0.50.0"),
+            &sdk("example.com", "v0.50.1")
+        ));
+        assert!(!sdk_satisfies(
+            &sdk("example.com", "v0.50.0"),
+            &sdk("example.com", "v0.51.0")
+        ));
+        assert!(!sdk_satisfies(
+            &sdk("example.com", "v0.50.0"),
+            &sdk("example.org", "v0.50.0")
+        ));
+        assert!(sdk_satisfies(
+            &sdk("example.com", "latest"),
+            &sdk("example.com", "latest")
+        ));
+    }
+
+    #[tokio::test]
+    async fn check_stale_kit_version() {
+        let project = test_project().await;
+        let mut lock = test_lock();
+        lock.kits[0].dependency.version = Version::new(2, 0, 0);
+        assert!(lock.check(&project).is_err());
+        lock.kits[0].dependency.version = Version::new(1, 15, 0);
+        assert!(lock.check(&project).is_err());
+    }
+
+    #[tokio::test]
+    async fn check_stale_kits() {
+        let project = test_project().await;
+        let mut lock = test_lock();
+        lock.kits.remove(1);
+        let err = lock.check(&project).unwrap_err();
+        assert!(err.to_string().contains("is not in the lock"), "{err}");
+
+        let mut lock = test_lock();
+        lock.kits[2].required_by = REQUIRED_BY_PROJECT.to_string();
+        let err = lock.check(&project).unwrap_err();
+        assert!(
+            err.to_string().contains("no longer in Twoliter.toml"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn has_arch() {
+        let mut lock = test_lock();
+        assert!(lock.has_arch("x86_64"));
+        assert!(!lock.has_arch("aarch64"));
+        lock.kits.clear();
+        assert!(lock.has_arch("aarch64"));
+    }
+}
@@ -2,10 +2,17 @@ use crate::cmd::{init_logger, Args};
 use anyhow::Result;
 use clap::Parser;
 
+mod artifacts;
 mod cargo_make;
 mod cmd;
 mod common;
 mod docker;
+mod events;
+mod image;
+mod kit;
+mod licenses;
+mod lock;
+mod packages;
 mod project;
 mod schema_version;
 mod tools;
@@ -0,0 +1,222 @@
+//! The packages installed in a variant image. The image build writes them to `packages.json` next
+//! to the image, along with the kit that each one was taken from, or `local` for packages built in
+//! the project. Comparing two of these files shows how the contents of an image changed.
+
+use crate::common::fs;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// An RPM installed in a variant image.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub(crate) struct InstalledPackage {
+    pub(crate) name: String,
+    /// The epoch, version and release, as `epoch:version-release` with the epoch left out when it
+    /// is zero.
+    pub(crate) evr: String,
+    pub(crate) arch: String,
+    /// The kit the package was taken from, or `local`.
+    pub(crate) source: String,
+    /// The installed size in bytes.
+    pub(crate) size: u64,
+}
+
+/// Load the packages listed in a `packages.json` file, by name.
+pub(crate) async fn load(path: &Path) -> Result<BTreeMap<String, InstalledPackage>> {
+    parse(&fs::read_to_string(path).await?, path)
+}
+
+/// Parse the contents of the `packages.json` file at `path`, for callers that cannot await.
+pub(crate) fn parse(json: &str, path: &Path) -> Result<BTreeMap<String, InstalledPackage>> {
+    let packages: Vec<InstalledPackage> = serde_json::from_str(json).context(format!(
+        "Unable to parse the installed packages in '{}'",
+        path.display()
+    ))?;
+    Ok(packages
+        .into_iter()
+        .map(|package| (package.name.clone(), package))
+        .collect())
+}
+
+/// A package that is installed in both images, but differs between them.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub(crate) struct ChangedPackage {
+    pub(crate) old: InstalledPackage,
+    pub(crate) new: InstalledPackage,
+}
+
+/// The differences between the packages of two images.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub(crate) struct PackageDiff {
+    pub(crate) added: Vec<InstalledPackage>,
+    pub(crate) removed: Vec<InstalledPackage>,
+    pub(crate) changed: Vec<ChangedPackage>,
+    /// The change in the total installed size, in bytes.
+    pub(crate) size_delta: i64,
+}
+
+impl PackageDiff {
+    pub(crate) fn new(
+        old: &BTreeMap<String, InstalledPackage>,
+        new: &BTreeMap<String, InstalledPackage>,
+    ) -> Self {
+        let mut diff = Self::default();
+        for (name, old_package) in old {
+            match new.get(name) {
+                None => diff.removed.push(old_package.clone()),
+                Some(new_package) if new_package != old_package => {
+                    diff.changed.push(ChangedPackage {
+                        old: old_package.clone(),
+                        new: new_package.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        diff.added = new
+            .iter()
+            .filter(|(name, _)| !old.contains_key(*name))
+            .map(|(_, package)| package.clone())
+            .collect();
+        diff.size_delta = total_size(new) as i64 - total_size(old) as i64;
+        diff
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn total_size(packages: &BTreeMap<String, InstalledPackage>) -> u64 {
+    packages.values().map(|package| package.size).sum()
+}
+
+impl Display for PackageDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "The installed packages are the same");
+        }
+        for package in &self.added {
+            writeln!(
+                f,
+                "+ {} {}.{} from {} ({})",
+                package.name,
+                package.evr,
+                package.arch,
+                package.source,
+                human_size(package.size as i64)
+            )?;
+        }
+        for package in &self.removed {
+            writeln!(
+                f,
+                "- {} {}.{} from {} ({})",
+                package.name,
+                package.evr,
+                package.arch,
+                package.source,
+                human_size(-(package.size as i64))
+            )?;
+        }
+        for ChangedPackage { old, new } in &self.changed {
+            write!(f, "~ {}", new.name)?;
+            if (&old.evr, &old.arch) != (&new.evr, &new.arch) {
+                write!(f, " {}.{} -> {}.{}", old.evr, old.arch, new.evr, new.arch)?;
+            }
+            if old.source != new.source {
+                write!(f, " from {} -> {}", old.source, new.source)?;
+            }
+            writeln!(f, " ({})", human_size(new.size as i64 - old.size as i64))?;
+        }
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed, total size {}",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            human_size(self.size_delta)
+        )
+    }
+}
+
+/// Format a change in size in bytes with a sign and a binary unit, such as `+1.5 MiB`.
+pub(crate) fn human_size(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    let sign = if bytes < 0 { "-" } else { "+" };
+    let mut size = bytes.unsigned_abs() as f64;
+    if size < 1024.0 {
+        return format!("{sign}{size} B");
+    }
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{sign}{size:.1} {unit}")
+}
+
+#[cfg(test)]
+fn package(name: &str, evr: &str, source: &str, size: u64) -> InstalledPackage {
+    InstalledPackage {
+        name: name.to_string(),
+        evr: evr.to_string(),
+        arch: "x86_64".to_string(),
+        source: source.to_string(),
+        size,
+    }
+}
+
+#[test]
+fn test_package_diff() {
+    let by_name = |packages: Vec<InstalledPackage>| {
+        packages
+            .into_iter()
+            .map(|package| (package.name.clone(), package))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let old = by_name(vec![
+        package("kernel", "6.1-1", "core-kit", 1000),
+        package("removed", "1.0-1", "local", 300),
+        package("same", "1:2.0-1", "core-kit", 50),
+        package("moved", "1.0-1", "core-kit", 10),
+    ]);
+    let new = by_name(vec![
+        package("kernel", "6.1-2", "core-kit", 3048),
+        package("added", "0.1-1", "local", 100),
+        package("same", "1:2.0-1", "core-kit", 50),
+        package("moved", "1.0-1", "local", 10),
+    ]);
+    let diff = PackageDiff::new(&old, &new);
+    assert_eq!(diff.added, [new["added"].clone()]);
+    assert_eq!(diff.removed, [old["removed"].clone()]);
+    assert_eq!(
+        diff.changed
+            .iter()
+            .map(|change| change.new.name.as_str())
+            .collect::<Vec<_>>(),
+        ["kernel", "moved"]
+    );
+    assert_eq!(diff.size_delta, 1848);
+    assert_eq!(
+        diff.to_string(),
+        "+ added 0.1-1.x86_64 from local (+100 B)\n\
+         - removed 1.0-1.x86_64 from local (-300 B)\n\
+         ~ kernel 6.1-1.x86_64 -> 6.1-2.x86_64 (+2.0 KiB)\n\
+         ~ moved from core-kit -> local (+0 B)\n\
+         1 added, 1 removed, 2 changed, total size +1.8 KiB\n"
+    );
+    assert!(PackageDiff::new(&old, &old).is_empty());
+}
+
+#[test]
+fn test_human_size() {
+    assert_eq!(human_size(0), "+0 B");
+    assert_eq!(human_size(-1023), "-1023 B");
+    assert_eq!(human_size(1536), "+1.5 KiB");
+    assert_eq!(human_size(-5 * 1024 * 1024 * 1024), "-5.0 GiB");
+}
@@ -1,13 +1,22 @@
+mod build_settings;
+mod v1;
+mod v2;
+
+pub(crate) use self::build_settings::{BuildSettings, SETTINGS_ENV_VARS};
+pub(crate) use self::v2::{sdk_version, vendor_name, ImageDependency};
+
 use crate::common::fs;
-use crate::docker::ImageUri;
-use crate::schema_version::SchemaVersion;
-use anyhow::{ensure, Context, Result};
+use crate::docker::{self, ImageUri};
+use crate::kit::KitDependency;
+use crate::tools;
+use anyhow::{bail, ensure, Context, Result};
 use async_recursion::async_recursion;
 use async_walkdir::WalkDir;
 use futures::stream::StreamExt;
 use log::{debug, info, trace, warn};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use toml::Table;
@@ -21,29 +30,64 @@ pub(crate) async fn load_or_find_project(user_path: Option<PathBuf>) -> Result<P
         Some(p) => Project::load(&p).await?,
     };
     debug!(
-        "Project file loaded from '{}'",
+        "Project '{}' loaded from '{}'",
+        project.name(),
         project.filepath().display()
     );
+    docker::use_runtime(project.build_settings().resolve()?.runtime()?);
     Ok(project)
 }
 
-/// Represents the structure of a `Twoliter.toml` project file.
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
-#[serde(rename_all = "kebab-case")]
+/// The newest version of the `Twoliter.toml` schema, which `twoliter migrate` migrates to.
+pub(crate) const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+/// Make sure that `data` is a valid project file, in any of the supported schema versions, without
+/// loading the project it describes.
+pub(crate) fn check_project_file(data: &str) -> Result<()> {
+    UnvalidatedProject::parse(data).map(|_| ())
+}
+
+/// Represents a `Twoliter.toml` project file, whichever schema version it was written in.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) struct Project {
-    #[serde(skip)]
     filepath: PathBuf,
-    #[serde(skip)]
     project_dir: PathBuf,
 
-    /// The version of this schema struct.
-    schema_version: SchemaVersion<1>,
+    /// The schema version that the project file was written in.
+    schema_version: u32,
+
+    /// The name of the project. Projects written in schema version 1 do not have one.
+    project_name: Option<String>,
 
     /// The version that will be given to released artifacts such as kits and variants.
     release_version: String,
 
     /// The Bottlerocket SDK container image.
     sdk: Option<ImageUri>,
+
+    /// The external kits that the project depends on.
+    kits: Vec<KitDependency>,
+
+    /// Settings for builds of the project.
+    build: BuildSettings,
+
+    /// The data store migrations that each release needs.
+    migrations: Migrations,
+
+    /// Locally built tools to install instead of the ones embedded in Twoliter, keyed by tool
+    /// name.
+    tool_overrides: BTreeMap<String, PathBuf>,
+}
+
+/// Data store migrations, listed for each pair of releases that they migrate between, e.g.
+/// `"(0.1.0, 0.2.0)" = ["migrate_v0.2.0_my-migration.lz4"]`.
+pub(crate) type Migrations = BTreeMap<String, Vec<String>>;
+
+/// The shape of the release config that pubsys reads, which used to be `Release.toml`.
+#[derive(Debug, Serialize)]
+struct ReleaseConfig<'a> {
+    version: &'a str,
+    migrations: &'a Migrations,
 }
 
 impl Project {
@@ -53,7 +97,7 @@ impl Project {
         let data = fs::read_to_string(&path)
             .await
             .context(format!("Unable to read project file '{}'", path.display()))?;
-        let unvalidated: UnvalidatedProject = toml::from_str(&data).context(format!(
+        let unvalidated = UnvalidatedProject::parse(&data).context(format!(
             "Unable to deserialize project file '{}'",
             path.display()
         ))?;
@@ -94,6 +138,21 @@ impl Project {
         self.project_dir.clone()
     }
 
+    pub(crate) fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    /// The name of the project, which defaults to the name of the project directory for projects
+    /// that do not have one.
+    pub(crate) fn name(&self) -> String {
+        self.project_name.clone().unwrap_or_else(|| {
+            self.project_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+    }
+
     pub(crate) fn release_version(&self) -> &str {
         self.release_version.as_str()
     }
@@ -102,6 +161,39 @@ impl Project {
         self.sdk.clone()
     }
 
+    pub(crate) fn kits(&self) -> &[KitDependency] {
+        &self.kits
+    }
+
+    pub(crate) fn build_settings(&self) -> &BuildSettings {
+        &self.build
+    }
+
+    /// The locally built tools that the project installs instead of the embedded ones. Relative
+    /// paths in `Twoliter.toml` have been resolved against the project directory.
+    pub(crate) fn tool_overrides(&self) -> &BTreeMap<String, PathBuf> {
+        &self.tool_overrides
+    }
+
+    /// Returns the path to the release config, with the release version and migrations, that
+    /// publishing a repo needs. Projects that still have a `Release.toml` use it, otherwise it is
+    /// written to the build directory from `Twoliter.toml`.
+    pub(crate) async fn release_config(&self) -> Result<PathBuf> {
+        let release_toml = self.project_dir.join("Release.toml");
+        if release_toml.is_file() {
+            return Ok(release_toml);
+        }
+        let path = self.project_dir.join("build").join("Release.toml");
+        let config = toml::to_string(&ReleaseConfig {
+            version: &self.release_version,
+            migrations: &self.migrations,
+        })
+        .context("Unable to serialize the release config")?;
+        fs::create_dir_all(self.project_dir.join("build")).await?;
+        fs::write(&path, config).await?;
+        Ok(path)
+    }
+
     pub(crate) fn token(&self) -> String {
         let mut d = Sha512::new();
         d.update(self.filepath().display().to_string());
@@ -154,21 +246,73 @@ impl Project {
         modules.sort();
         Ok(modules)
     }
+
+    /// Returns a sorted list of the names of variants, which are the directories in `variants`
+    /// that have a `Cargo.toml`.
+    pub(crate) async fn find_variants(&self) -> Result<Vec<String>> {
+        let root = self.project_dir.join("variants");
+        let mut read_dir = tokio::fs::read_dir(&root)
+            .await
+            .context(format!("Unable to read dir '{}'", root.display()))?;
+        let mut variants = Vec::new();
+        while let Some(entry) = read_dir.next_entry().await.context(format!(
+            "Error while reading entries in dir '{}'",
+            root.display()
+        ))? {
+            if !entry.path().join("Cargo.toml").is_file() {
+                continue;
+            }
+            let name = entry.file_name();
+            let name = name.to_str().context(format!(
+                "Found non-UTF-8 character in file path '{}'",
+                entry.path().display()
+            ))?;
+            variants.push(name.to_string());
+        }
+        variants.sort();
+        Ok(variants)
+    }
 }
 
 /// This is used to `Deserialize` a project, then run validation code before returning a valid
 /// [`Project`]. This is necessary both because there is no post-deserialization serde hook for
 /// validation and, even if there was, we need to know the project directory path in order to check
 /// some things.
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+///
+/// Each schema version has its own struct, which is converted into this one after deserialization.
+#[derive(Debug, Clone)]
 struct UnvalidatedProject {
-    schema_version: SchemaVersion<1>,
+    schema_version: u32,
+    project_name: Option<String>,
     release_version: String,
     sdk: Option<ImageUri>,
+    kits: Vec<KitDependency>,
+    build: BuildSettings,
+    migrations: Migrations,
+    tool_overrides: BTreeMap<String, PathBuf>,
+}
+
+/// Only the schema version of a project file, which says how to deserialize the rest of it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Versioned {
+    schema_version: u32,
 }
 
 impl UnvalidatedProject {
+    /// Deserialize a project file written in any of the supported schema versions.
+    fn parse(data: &str) -> Result<Self> {
+        let versioned: Versioned = toml::from_str(data)?;
+        match versioned.schema_version {
+            1 => Ok(toml::from_str::<v1::ProjectV1>(data)?.into()),
+            2 => toml::from_str::<v2::ProjectV2>(data)?.try_into(),
+            version => bail!(
+                "Incorrect project schema_version: got '{version}', expected a version from 1 to \
+                {CURRENT_SCHEMA_VERSION}"
+            ),
+        }
+    }
+
     /// Constructs a [`Project`] from an [`UnvalidatedProject`] after validating fields.
     async fn validate(self, path: impl AsRef<Path>) -> Result<Project> {
         let filepath: PathBuf = path.as_ref().into();
@@ -181,16 +325,44 @@ impl UnvalidatedProject {
             .to_path_buf();
 
         self.check_release_toml(&project_dir).await?;
+        self.check_kits()?;
+        self.build.validate()?;
+        for name in self.tool_overrides.keys() {
+            tools::check_tool_name(name)?;
+        }
+        let tool_overrides = self
+            .tool_overrides
+            .into_iter()
+            .map(|(name, path)| (name, project_dir.join(path)))
+            .collect();
 
         Ok(Project {
             filepath,
             project_dir,
             schema_version: self.schema_version,
+            project_name: self.project_name,
             release_version: self.release_version,
             sdk: self.sdk,
+            kits: self.kits,
+            build: self.build,
+            migrations: self.migrations,
+            tool_overrides,
         })
     }
 
+    /// Ensures that each external kit is only declared once.
+    fn check_kits(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for kit in &self.kits {
+            ensure!(
+                names.insert(kit.repo.as_str()),
+                "The kit '{}' is declared more than once in Twoliter.toml",
+                kit.repo
+            );
+        }
+        Ok(())
+    }
+
     /// Issues a warning if `Release.toml` is found and, if so, ensures that it contains the same
     /// version (i.e. `release-version`) as the `Twoliter.toml` project file.
     async fn check_release_toml(&self, project_dir: &Path) -> Result<()> {
@@ -201,8 +373,13 @@ impl UnvalidatedProject {
             return Ok(());
         }
         warn!(
-            "A Release.toml file was found. Release.toml is deprecated. Please remove it from \
-             your project."
+            "A Release.toml file was found. Release.toml is deprecated. Run `twoliter migrate` to \
+             move its migrations into Twoliter.toml, then remove it from your project."
+        );
+        ensure!(
+            self.migrations.is_empty(),
+            "Migrations are declared in both Twoliter.toml and '{}', remove Release.toml",
+            path.display()
         );
         let content = fs::read_to_string(&path).await.context(format!(
             "Error while checking Release.toml file at '{}'",
@@ -251,7 +428,7 @@ mod test {
         let deserialized = Project::load(path).await.unwrap();
 
         // Add checks here as desired to validate deserialization.
-        assert_eq!(SchemaVersion::<1>, deserialized.schema_version);
+        assert_eq!(1, deserialized.schema_version());
         let sdk = deserialized.sdk().unwrap();
         assert_eq!("a.com/b", sdk.registry.as_ref().unwrap().as_str());
         assert_eq!(
@@ -295,13 +472,18 @@ mod test {
         let project = Project {
             filepath: Default::default(),
             project_dir: Default::default(),
-            schema_version: Default::default(),
+            schema_version: 1,
+            project_name: None,
             release_version: String::from("1.0.0"),
             sdk: Some(ImageUri {
                 registry: Some("example.com".try_into().unwrap()),
                 repo: "foo-abc".try_into().unwrap(),
                 tag: "version1".try_into().unwrap(),
             }),
+            kits: Vec::new(),
+            build: Default::default(),
+            migrations: Default::default(),
+            tool_overrides: Default::default(),
         };
 
         assert_eq!(
@@ -353,6 +535,122 @@ mod test {
         Project::find_and_load(p).await.unwrap();
     }
 
+    /// Ensure that external kits can be declared in `Twoliter.toml`.
+    #[tokio::test]
+    async fn deserialize_twoliter_1_kits_toml() {
+        let path = data_dir().join("Twoliter-1-kits.toml");
+        let project = Project::load(path).await.unwrap();
+        let kits = project.kits();
+        assert_eq!(kits.len(), 2);
+        assert_eq!(
+            kits[0].registry.as_deref(),
+            Some("public.ecr.aws/bottlerocket")
+        );
+        assert_eq!(kits[0].repo, "bottlerocket-core-kit");
+        assert_eq!(kits[0].version, semver::Version::new(1, 15, 1));
+        assert!(kits[1].registry.is_none());
+    }
+
+    /// Ensure that a kit cannot be declared twice.
+    #[tokio::test]
+    async fn duplicate_kits() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("Twoliter.toml");
+        let mut data = fs::read_to_string(data_dir().join("Twoliter-1-kits.toml"))
+            .await
+            .unwrap();
+        data.push_str("\n[[kit]]\nrepo = \"my-kit\"\nversion = \"0.2.0\"\n");
+        fs::write(&path, data).await.unwrap();
+        assert!(Project::load(path).await.is_err());
+    }
+
+    /// Ensure that a schema version 2 `Twoliter.toml` can be deserialized, and that images are
+    /// found in the registries of their vendors.
+    #[tokio::test]
+    async fn deserialize_twoliter_2_toml() {
+        let project = Project::load(data_dir().join("Twoliter-2.toml"))
+            .await
+            .unwrap();
+        assert_eq!(project.schema_version(), 2);
+        assert_eq!(project.name(), "my-project");
+        assert_eq!(
+            project.sdk().unwrap().to_string(),
+            "public.ecr.aws/bottlerocket/bottlerocket-sdk:v0.50.0"
+        );
+        let kits = project.kits();
+        assert_eq!(
+            kits[0].registry.as_deref(),
+            Some("public.ecr.aws/bottlerocket")
+        );
+        assert_eq!(kits[0].repo, "bottlerocket-core-kit");
+        assert!(kits[1].registry.is_none());
+        assert_eq!(
+            project.build_settings().lookaside_cache.as_deref(),
+            Some("https://cache.example.com")
+        );
+        assert_eq!(
+            project.build_settings().upstream_source_fallback,
+            Some(true)
+        );
+    }
+
+    /// Ensure that images cannot name a vendor that is not declared.
+    #[tokio::test]
+    async fn undeclared_vendor() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("Twoliter.toml");
+        let data = fs::read_to_string(data_dir().join("Twoliter-2.toml"))
+            .await
+            .unwrap()
+            .replace("[vendor.bottlerocket]", "[vendor.someone-else]");
+        fs::write(&path, data).await.unwrap();
+        assert!(Project::load(path).await.is_err());
+    }
+
+    /// Ensure that tool overrides are resolved against the project directory, and that only the
+    /// embedded tools can be overridden.
+    #[tokio::test]
+    async fn tool_overrides() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("Twoliter.toml");
+        let mut data = fs::read_to_string(data_dir().join("Twoliter-2.toml"))
+            .await
+            .unwrap();
+        data.push_str("\n[tool-overrides]\nbuildsys = \"../twoliter/target/debug/buildsys\"\n");
+        fs::write(&path, &data).await.unwrap();
+        let project = Project::load(&path).await.unwrap();
+        assert_eq!(
+            project.tool_overrides()["buildsys"],
+            tempdir.path().join("../twoliter/target/debug/buildsys")
+        );
+
+        data.push_str("cargo = \"/usr/bin/cargo\"\n");
+        fs::write(&path, &data).await.unwrap();
+        assert!(Project::load(&path).await.is_err());
+    }
+
+    /// Ensure that the release config is written from the migrations in `Twoliter.toml`.
+    #[tokio::test]
+    async fn release_config() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("Twoliter.toml");
+        fs::copy(data_dir().join("Twoliter-2.toml"), &path)
+            .await
+            .unwrap();
+        let project = Project::load(&path).await.unwrap();
+        let config: Table = toml::from_str(
+            &fs::read_to_string(project.release_config().await.unwrap())
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(config["version"].as_str(), Some("1.0.0"));
+        assert_eq!(
+            config["migrations"]["(0.9.0, 1.0.0)"][0].as_str(),
+            Some("migrate_v1.0.0_my-migration.lz4")
+        );
+    }
+
     #[tokio::test]
     async fn find_go_modules() {
         let twoliter_toml_path = projects_dir().join("project1").join("Twoliter.toml");
@@ -361,4 +659,12 @@ mod test {
         assert_eq!(go_modules.len(), 1, "Expected to find 1 go module");
         assert_eq!(go_modules.first().unwrap(), "hello-go");
     }
+
+    #[tokio::test]
+    async fn find_variants() {
+        let twoliter_toml_path = projects_dir().join("project1").join("Twoliter.toml");
+        let project = Project::load(twoliter_toml_path).await.unwrap();
+        let variants = project.find_variants().await.unwrap();
+        assert_eq!(variants, ["hello-ootb"]);
+    }
 }
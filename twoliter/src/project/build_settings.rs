@@ -0,0 +1,259 @@
+//! The `[build]` table of `Twoliter.toml`. Each setting is passed to `cargo make` as an environment
+//! variable, and can be overridden by setting that variable, so that one-off changes do not need
+//! an edit to the project file.
+
+use anyhow::{ensure, Context, Result};
+use container_runtime::Runtime;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Settings for builds of the project.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct BuildSettings {
+    /// The URL to the lookaside cache where sources are stored to avoid pulling them from
+    /// upstream. Overridden by `BUILDSYS_LOOKASIDE_CACHE`.
+    pub(crate) lookaside_cache: Option<String>,
+
+    /// Whether to pull sources from their upstream URL when they are not in the lookaside cache.
+    /// Overridden by `BUILDSYS_UPSTREAM_SOURCE_FALLBACK`.
+    pub(crate) upstream_source_fallback: Option<bool>,
+
+    /// The proxy for HTTP requests made by the build. Overridden by `HTTP_PROXY`.
+    pub(crate) http_proxy: Option<String>,
+
+    /// The proxy for HTTPS requests made by the build. Overridden by `HTTPS_PROXY`.
+    pub(crate) https_proxy: Option<String>,
+
+    /// Hosts that are reached without a proxy. Overridden by `NO_PROXY`, a comma-separated list.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) no_proxy: Vec<String>,
+
+    /// The Go module proxy. Overridden by `GOPROXY`.
+    pub(crate) go_proxy: Option<String>,
+
+    /// The Go checksum database. Overridden by `GOSUMDB`.
+    pub(crate) go_sumdb: Option<String>,
+
+    /// Go modules that are private, and fetched without the proxy or checksum database.
+    /// Overridden by `GOPRIVATE`.
+    pub(crate) go_private: Option<String>,
+
+    /// Go modules that are fetched without the proxy. Overridden by `GONOPROXY`.
+    pub(crate) go_no_proxy: Option<String>,
+
+    /// The name used for image files and directories, e.g. `bottlerocket`. Overridden by
+    /// `BUILDSYS_NAME`.
+    pub(crate) image_name: Option<String>,
+
+    /// The name that identifies the OS in os-release, the bootloader, etc., e.g.
+    /// `Bottlerocket OS`. Overridden by `BUILDSYS_PRETTY_NAME`.
+    pub(crate) pretty_name: Option<String>,
+
+    /// The container runtime that runs the build, `docker` or `podman`. Overridden by
+    /// `TWOLITER_CONTAINER_RUNTIME`.
+    pub(crate) container_runtime: Option<String>,
+}
+
+const LOOKASIDE_CACHE: &str = "BUILDSYS_LOOKASIDE_CACHE";
+const UPSTREAM_SOURCE_FALLBACK: &str = "BUILDSYS_UPSTREAM_SOURCE_FALLBACK";
+const HTTP_PROXY: &str = "HTTP_PROXY";
+const HTTPS_PROXY: &str = "HTTPS_PROXY";
+const NO_PROXY: &str = "NO_PROXY";
+const GO_PROXY: &str = "GOPROXY";
+const GO_SUMDB: &str = "GOSUMDB";
+const GO_PRIVATE: &str = "GOPRIVATE";
+const GO_NO_PROXY: &str = "GONOPROXY";
+const IMAGE_NAME: &str = "BUILDSYS_NAME";
+const PRETTY_NAME: &str = "BUILDSYS_PRETTY_NAME";
+const CONTAINER_RUNTIME: &str = container_runtime::RUNTIME_ENV;
+
+/// The environment variables that the settings are passed to `cargo make` as.
+pub(crate) const SETTINGS_ENV_VARS: [&str; 12] = [
+    LOOKASIDE_CACHE,
+    UPSTREAM_SOURCE_FALLBACK,
+    HTTP_PROXY,
+    HTTPS_PROXY,
+    NO_PROXY,
+    GO_PROXY,
+    GO_SUMDB,
+    GO_PRIVATE,
+    GO_NO_PROXY,
+    IMAGE_NAME,
+    PRETTY_NAME,
+    CONTAINER_RUNTIME,
+];
+
+impl BuildSettings {
+    /// The settings from `Twoliter.toml`, overridden by any of their environment variables that are
+    /// set.
+    pub(crate) fn resolve(&self) -> Result<Self> {
+        let mut settings = self.clone();
+        settings.apply_env(|key| env::var(key).ok())?;
+        Ok(settings)
+    }
+
+    /// The settings as environment variables for `cargo make`. Settings that are not set are left
+    /// out so that the defaults in `Makefile.toml` apply. The container runtime is left out too,
+    /// because `CargoMake` passes the one that Twoliter uses to every task.
+    pub(crate) fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = Vec::new();
+        let mut push = |key, value: Option<&String>| {
+            if let Some(value) = value {
+                vars.push((key, value.clone()));
+            }
+        };
+        push(LOOKASIDE_CACHE, self.lookaside_cache.as_ref());
+        push(HTTP_PROXY, self.http_proxy.as_ref());
+        push(HTTPS_PROXY, self.https_proxy.as_ref());
+        push(GO_PROXY, self.go_proxy.as_ref());
+        push(GO_SUMDB, self.go_sumdb.as_ref());
+        push(GO_PRIVATE, self.go_private.as_ref());
+        push(GO_NO_PROXY, self.go_no_proxy.as_ref());
+        push(IMAGE_NAME, self.image_name.as_ref());
+        push(PRETTY_NAME, self.pretty_name.as_ref());
+        if let Some(fallback) = self.upstream_source_fallback {
+            vars.push((UPSTREAM_SOURCE_FALLBACK, fallback.to_string()));
+        }
+        if !self.no_proxy.is_empty() {
+            vars.push((NO_PROXY, self.no_proxy.join(",")));
+        }
+        vars
+    }
+
+    /// Make sure that the settings have values that the build can use.
+    pub(crate) fn validate(&self) -> Result<()> {
+        for (name, url) in [
+            ("lookaside-cache", &self.lookaside_cache),
+            ("http-proxy", &self.http_proxy),
+            ("https-proxy", &self.https_proxy),
+        ] {
+            if let Some(url) = url {
+                Url::parse(url).context(format!(
+                    "The build setting {name} '{url}' is not a valid URL"
+                ))?;
+            }
+        }
+        if let Some(name) = &self.image_name {
+            ensure!(
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')),
+                "The build setting image-name '{name}' is used in file names, use only letters, \
+                numbers, '-', '_' and '.'"
+            );
+        }
+        if let Some(name) = &self.pretty_name {
+            ensure!(
+                !name.trim().is_empty() && !name.contains(['"', '\n']),
+                "The build setting pretty-name '{name}' must not be empty or contain quotes or \
+                newlines"
+            );
+        }
+        self.runtime()?;
+        Ok(())
+    }
+
+    /// The container runtime that the settings choose, Docker unless `container-runtime` is set.
+    pub(crate) fn runtime(&self) -> Result<Runtime> {
+        match &self.container_runtime {
+            Some(name) => name
+                .parse()
+                .context("The build setting container-runtime is invalid"),
+            None => Ok(Runtime::default()),
+        }
+    }
+
+    /// Override settings with the environment variables that `env` returns values for.
+    fn apply_env<F>(&mut self, env: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let set = |setting: &mut Option<String>, key| {
+            if let Some(value) = env(key) {
+                *setting = Some(value);
+            }
+        };
+        set(&mut self.lookaside_cache, LOOKASIDE_CACHE);
+        set(&mut self.http_proxy, HTTP_PROXY);
+        set(&mut self.https_proxy, HTTPS_PROXY);
+        set(&mut self.go_proxy, GO_PROXY);
+        set(&mut self.go_sumdb, GO_SUMDB);
+        set(&mut self.go_private, GO_PRIVATE);
+        set(&mut self.go_no_proxy, GO_NO_PROXY);
+        set(&mut self.image_name, IMAGE_NAME);
+        set(&mut self.pretty_name, PRETTY_NAME);
+        set(&mut self.container_runtime, CONTAINER_RUNTIME);
+        if let Some(fallback) = env(UPSTREAM_SOURCE_FALLBACK) {
+            self.upstream_source_fallback = Some(fallback.parse().context(format!(
+                "{UPSTREAM_SOURCE_FALLBACK} must be 'true' or 'false', not '{fallback}'"
+            ))?);
+        }
+        if let Some(no_proxy) = env(NO_PROXY) {
+            self.no_proxy = no_proxy
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        self.validate()
+            .context("The build settings are invalid after applying environment variables")
+    }
+}
+
+#[test]
+fn test_env_overrides_file() {
+    let mut settings: BuildSettings = toml::from_str(
+        r#"
+lookaside-cache = "https://cache.example.com"
+upstream-source-fallback = false
+no-proxy = ["localhost", "example.com"]
+image-name = "my-os"
+container-runtime = "docker"
+"#,
+    )
+    .unwrap();
+    settings
+        .apply_env(|key| match key {
+            UPSTREAM_SOURCE_FALLBACK => Some("true".to_string()),
+            GO_PROXY => Some("direct".to_string()),
+            CONTAINER_RUNTIME => Some("podman".to_string()),
+            _ => None,
+        })
+        .unwrap();
+
+    let vars = settings.env_vars();
+    let var = |key| {
+        vars.iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    assert_eq!(var(LOOKASIDE_CACHE), Some("https://cache.example.com"));
+    assert_eq!(var(UPSTREAM_SOURCE_FALLBACK), Some("true"));
+    assert_eq!(var(GO_PROXY), Some("direct"));
+    assert_eq!(var(NO_PROXY), Some("localhost,example.com"));
+    assert_eq!(var(IMAGE_NAME), Some("my-os"));
+    assert_eq!(var(HTTP_PROXY), None);
+    assert_eq!(settings.runtime().unwrap(), Runtime::Podman);
+}
+
+#[test]
+fn test_invalid_settings() {
+    // Typos are errors rather than being ignored.
+    assert!(toml::from_str::<BuildSettings>(r#"lookasid-cache = "https://a.com""#).is_err());
+
+    let settings: BuildSettings = toml::from_str(r#"lookaside-cache = "not a url""#).unwrap();
+    assert!(settings.validate().is_err());
+    let settings: BuildSettings = toml::from_str(r#"image-name = "my os""#).unwrap();
+    assert!(settings.validate().is_err());
+    let settings: BuildSettings = toml::from_str(r#"container-runtime = "lxc""#).unwrap();
+    assert!(settings.validate().is_err());
+
+    let mut settings = BuildSettings::default();
+    assert!(settings
+        .apply_env(|key| (key == UPSTREAM_SOURCE_FALLBACK).then(|| "yes".to_string()))
+        .is_err());
+}
@@ -0,0 +1,33 @@
+//! The first version of `Twoliter.toml`, which names images by their registry, repository and tag.
+//! Release migrations are not part of it, they are in the deprecated `Release.toml`.
+
+use super::{BuildSettings, UnvalidatedProject};
+use crate::docker::ImageUri;
+use crate::kit::KitDependency;
+use crate::schema_version::SchemaVersion;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct ProjectV1 {
+    schema_version: SchemaVersion<1>,
+    release_version: String,
+    sdk: Option<ImageUri>,
+    #[serde(default, rename = "kit")]
+    kits: Vec<KitDependency>,
+}
+
+impl From<ProjectV1> for UnvalidatedProject {
+    fn from(project: ProjectV1) -> Self {
+        UnvalidatedProject {
+            schema_version: project.schema_version.get(),
+            project_name: None,
+            release_version: project.release_version,
+            sdk: project.sdk,
+            kits: project.kits,
+            build: BuildSettings::default(),
+            migrations: Default::default(),
+            tool_overrides: Default::default(),
+        }
+    }
+}
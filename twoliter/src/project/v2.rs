@@ -0,0 +1,144 @@
+//! The second version of `Twoliter.toml`. It names the project, declares the vendors that images
+//! come from, refers to the SDK and kits by name and version, and holds the build settings and
+//! release migrations that used to be spread across environment variables and `Release.toml`.
+
+use super::{BuildSettings, Migrations, UnvalidatedProject};
+use crate::docker::ImageUri;
+use crate::kit::KitDependency;
+use crate::schema_version::SchemaVersion;
+use anyhow::{Context, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct ProjectV2 {
+    schema_version: SchemaVersion<2>,
+    project_name: String,
+    release_version: String,
+    #[serde(default, rename = "vendor")]
+    vendors: BTreeMap<String, Vendor>,
+    sdk: Option<ImageDependency>,
+    #[serde(default, rename = "kit")]
+    kits: Vec<ImageDependency>,
+    #[serde(default)]
+    build: BuildSettings,
+    #[serde(default)]
+    migrations: Migrations,
+    #[serde(default)]
+    tool_overrides: BTreeMap<String, PathBuf>,
+}
+
+/// A vendor publishes images, such as the SDK and kits, to a container registry.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Vendor {
+    /// e.g. public.ecr.aws/bottlerocket
+    pub(crate) registry: String,
+}
+
+/// The SDK or a kit, named by the vendor that publishes it. Images without a vendor are looked for
+/// locally.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ImageDependency {
+    /// e.g. bottlerocket-sdk
+    pub(crate) name: String,
+    /// e.g. 0.50.0
+    pub(crate) version: Version,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) vendor: Option<String>,
+}
+
+impl ProjectV2 {
+    /// Find the registry of an image's vendor.
+    fn registry(&self, image: &ImageDependency) -> Result<Option<String>> {
+        image
+            .vendor
+            .as_ref()
+            .map(|vendor| {
+                self.vendors
+                    .get(vendor)
+                    .map(|vendor| vendor.registry.clone())
+                    .context(format!(
+                        "The vendor '{vendor}' of '{}' is not declared in Twoliter.toml, add a \
+                        [vendor.{vendor}] table with its registry",
+                        image.name
+                    ))
+            })
+            .transpose()
+    }
+}
+
+impl TryFrom<ProjectV2> for UnvalidatedProject {
+    type Error = anyhow::Error;
+
+    fn try_from(project: ProjectV2) -> Result<Self> {
+        let sdk = project
+            .sdk
+            .as_ref()
+            .map(|sdk| -> Result<ImageUri> {
+                Ok(ImageUri::new(
+                    project.registry(sdk)?,
+                    &sdk.name,
+                    format!("v{}", sdk.version),
+                ))
+            })
+            .transpose()?;
+        let kits = project
+            .kits
+            .iter()
+            .map(|kit| {
+                Ok(KitDependency {
+                    registry: project.registry(kit)?,
+                    repo: kit.name.clone(),
+                    version: kit.version.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(UnvalidatedProject {
+            schema_version: project.schema_version.get(),
+            project_name: Some(project.project_name),
+            release_version: project.release_version,
+            sdk,
+            kits,
+            build: project.build,
+            migrations: project.migrations,
+            tool_overrides: project.tool_overrides,
+        })
+    }
+}
+
+/// The name given to the vendor of `registry` when a project is created or migrated from a version
+/// that named registries directly, e.g. `bottlerocket` for `public.ecr.aws/bottlerocket`.
+pub(crate) fn vendor_name(registry: &str) -> String {
+    registry
+        .rsplit('/')
+        .next()
+        .unwrap_or(registry)
+        .replace('.', "-")
+}
+
+/// The version of an SDK from its image tag, e.g. `0.50.0` from `v0.50.0`.
+pub(crate) fn sdk_version(tag: &str) -> Result<Version> {
+    tag.strip_prefix('v')
+        .unwrap_or(tag)
+        .parse()
+        .context(format!(
+            "The SDK tag '{tag}' is not a version, SDK tags must look like 'v0.50.0'"
+        ))
+}
+
+#[test]
+fn test_vendor_name() {
+    assert_eq!(vendor_name("public.ecr.aws/bottlerocket"), "bottlerocket");
+    assert_eq!(vendor_name("example.com"), "example-com");
+}
+
+#[test]
+fn test_sdk_version() {
+    assert_eq!(sdk_version("v0.50.0").unwrap(), Version::new(0, 50, 0));
+    assert!(sdk_version("latest").is_err());
+}
@@ -0,0 +1,89 @@
+//! Tests for the `check-migrations` task of the embedded `Makefile.toml`, which checks the
+//! migrations of the release config against the migrations in the sources.
+
+use crate::project::Project;
+use crate::test::data_dir;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// The script of the `check-migrations` task.
+fn check_migrations_script() -> String {
+    let makefile: toml::Table =
+        toml::from_str(include_str!("../../embedded/Makefile.toml")).unwrap();
+    makefile["tasks"]["check-migrations"]["script"][0]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+fn check_migrations(project_dir: &Path, release_config: &Path) -> Output {
+    Command::new("bash")
+        .arg("-c")
+        .arg(check_migrations_script())
+        .current_dir(project_dir)
+        .env("BUILDSYS_RELEASE_CONFIG_PATH", release_config)
+        .output()
+        .unwrap()
+}
+
+/// Write the sources of the migration `name` of version 1.0.0.
+fn write_migration(project_dir: &Path, name: &str) {
+    let dir = project_dir
+        .join("sources/api/migration/migrations/v1.0.0")
+        .join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        format!("[package]\nname = \"{name}\"\n"),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_check_migrations() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let project_dir = tempdir.path();
+    // The project has no Release.toml, so the release config is generated from Twoliter.toml.
+    let project_path = project_dir.join("Twoliter.toml");
+    std::fs::copy(data_dir().join("Twoliter-2.toml"), &project_path).unwrap();
+    let project = Project::load(&project_path).await.unwrap();
+    let release_config = project.release_config().await.unwrap();
+    assert!(!project_dir.join("Release.toml").exists());
+
+    write_migration(project_dir, "my-migration");
+    std::fs::write(
+        project_dir.join("sources/Cargo.toml"),
+        "[workspace]\nmembers = [\"api/migration/migrations/v1.0.0/my-migration\"]\n",
+    )
+    .unwrap();
+    std::fs::write(
+        project_dir.join("sources/Cargo.lock"),
+        "[[package]]\nname = \"my-migration\"\n",
+    )
+    .unwrap();
+    let output = check_migrations(project_dir, &release_config);
+    assert!(output.status.success(), "{output:?}");
+
+    // Migrations in the sources must be in the release config too.
+    write_migration(project_dir, "other-migration");
+    let output = check_migrations(project_dir, &release_config);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("Migration 'other-migration' is missing a declaration in Release.toml"));
+}
+
+#[test]
+fn test_check_migrations_missing_release_config() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let release_config = tempdir.path().join("Release.toml");
+    let output = check_migrations(tempdir.path(), &release_config);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "Cannot check migrations: release config '{}' does not exist.\n",
+            release_config.display()
+        )
+    );
+}
@@ -5,6 +5,8 @@ be compiled for `cfg(test)`, which is accomplished at its declaration in `main.r
 
 !*/
 mod cargo_make;
+mod check_migrations;
+mod rootfs_budget;
 
 use std::path::PathBuf;
 
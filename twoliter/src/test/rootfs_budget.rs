@@ -0,0 +1,144 @@
+//! Tests for the `rootfs-budget` functions that `rpm2img` uses to check the size of the root
+//! filesystem.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn rootfs_budget() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("embedded")
+        .join("rootfs-budget")
+}
+
+/// Run `script` in bash after sourcing `rootfs-budget`, with `stdin` as its input.
+fn run(script: &str, stdin: &str) -> Output {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new("bash")
+        .arg("-c")
+        .arg(format!(
+            "set -eu -o pipefail; . '{}'; {script}",
+            rootfs_budget().display()
+        ))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+const MIB: u64 = 1024 * 1024;
+
+/// Installed packages as `rpm -qa` lists them for the report.
+fn packages() -> String {
+    let mut packages = String::new();
+    for (size, name) in [(10, "kernel"), (60, "containerd"), (30, "glibc")] {
+        packages.push_str(&format!("{}\t{name}\n", size * MIB));
+    }
+    for i in 0..25 {
+        packages.push_str(&format!("{}\tsmall-{i:02}\n", (i + 1) * 1024));
+    }
+    packages
+}
+
+fn write_report(dir: &Path, max_rootfs_size: &str) -> String {
+    let report = dir.join("rootfs-size.txt");
+    let output = run(
+        &format!(
+            "write_rootfs_size_report {} '{max_rootfs_size}' '{}'",
+            100 * MIB,
+            report.display()
+        ),
+        &packages(),
+    );
+    assert!(output.status.success(), "{output:?}");
+    std::fs::read_to_string(report).unwrap()
+}
+
+#[test]
+fn test_rootfs_size_report() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let report = write_report(tempdir.path(), &(120 * MIB).to_string());
+    let lines = report.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "root filesystem: 100 MiB");
+    assert_eq!(lines[1], "max-rootfs-size: 120 MiB");
+    // The packages are listed largest first, with their share of the root filesystem.
+    assert_eq!(lines[2], "      60.0 MiB  60.0%  containerd");
+    assert_eq!(lines[3], "      30.0 MiB  30.0%  glibc");
+    assert_eq!(lines[4], "      10.0 MiB  10.0%  kernel");
+    assert_eq!(lines.len(), 2 + 28);
+
+    // Without a budget, the report has no budget line.
+    let report = write_report(tempdir.path(), "");
+    assert_eq!(
+        report.lines().nth(1),
+        Some("      60.0 MiB  60.0%  containerd")
+    );
+}
+
+#[test]
+fn test_rootfs_size_budget() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let report = tempdir.path().join("rootfs-size.txt");
+    write_report(tempdir.path(), &(80 * MIB).to_string());
+    let check = |max_rootfs_size: &str| {
+        run(
+            &format!(
+                "check_rootfs_size {} '{max_rootfs_size}' '{}'",
+                100 * MIB,
+                report.display()
+            ),
+            "",
+        )
+    };
+
+    // Within the budget, or without one, the check passes quietly.
+    for max_rootfs_size in [(100 * MIB).to_string(), String::new()] {
+        let output = check(&max_rootfs_size);
+        assert!(output.status.success(), "{output:?}");
+        assert!(output.stderr.is_empty());
+    }
+
+    // Over the budget, the check fails and lists the 20 largest packages.
+    let output = check(&(80 * MIB).to_string());
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "largest installed packages:");
+    assert_eq!(lines[1], "      60.0 MiB  60.0%  containerd");
+    assert_eq!(lines.len(), 1 + 20 + 1);
+    assert!(lines[20].ends_with("small-08"));
+    assert_eq!(
+        lines[21],
+        "root filesystem is 100 MiB, over the max-rootfs-size of 80 MiB"
+    );
+}
+
+#[test]
+fn test_measure_rootfs() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let root = tempdir.path();
+    // Everything but /usr/bin is left out of the root filesystem image.
+    for dir in ["boot", "var/lib/rpm", "usr/share/licenses/glibc", "usr/bin"] {
+        let dir = root.join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file"), vec![1; 4 * MIB as usize]).unwrap();
+    }
+
+    let output = run(&format!("measure_rootfs '{}'", root.display()), "");
+    assert!(output.status.success(), "{output:?}");
+    let size: u64 = String::from_utf8(output.stdout)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert!((4 * MIB..8 * MIB).contains(&size), "measured {size} bytes");
+}
@@ -1,13 +1,21 @@
 use crate::common::fs;
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use filetime::{set_file_handle_times, set_file_mtime, FileTime};
 use flate2::read::ZlibDecoder;
-use log::debug;
-use std::path::Path;
+use log::{debug, info};
+use nix::fcntl::{Flock, FlockArg};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use tar::Archive;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
 use tokio::runtime::Handle;
+use tokio::task::spawn_blocking;
 
 const TAR_GZ_DATA: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/tools.tar.gz"));
 const BOTTLEROCKET_VARIANT: &[u8] =
@@ -18,18 +26,295 @@ const PUBSYS_SETUP: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_PUBSYS_SETUP"));
 const TESTSYS: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_TESTSYS"));
 const TUFTOOL: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_TUFTOOL"));
 
-/// Install tools into the given `tools_dir`. If you use a `TempDir` object, make sure to pass it by
-/// reference and hold on to it until you no longer need the tools to still be installed (it will
-/// auto delete when it goes out of scope).
-pub(crate) async fn install_tools(tools_dir: impl AsRef<Path>) -> Result<()> {
+/// The embedded binaries and the names they are installed as.
+const BINARIES: [(&str, &[u8]); 6] = [
+    ("bottlerocket-variant", BOTTLEROCKET_VARIANT),
+    ("buildsys", BUILDSYS),
+    ("pubsys", PUBSYS),
+    ("pubsys-setup", PUBSYS_SETUP),
+    ("testsys", TESTSYS),
+    ("tuftool", TUFTOOL),
+];
+
+// The SHA-256 digests of `TAR_GZ_DATA` and `BINARIES`, which the build script computes so that
+// they are not computed each time that Twoliter runs.
+include!(concat!(env!("OUT_DIR"), "/sha256.rs"));
+
+/// The file in the tools directory that records which tools are installed and where they came
+/// from. It is written last, so a partial install is never mistaken for a complete one.
+const MANIFEST_FILE: &str = "installed-tools.toml";
+
+/// The prefix of the environment variables that override a tool, e.g.
+/// `TWOLITER_TOOL_OVERRIDE_PUBSYS_SETUP` for `pubsys-setup`.
+const OVERRIDE_ENV_PREFIX: &str = "TWOLITER_TOOL_OVERRIDE_";
+
+/// The file in the tools directory that `twoliter` processes lock while they use the tools. It is
+/// never removed, so that every process locks the same file.
+const LOCK_FILE: &str = ".lock";
+
+static FORCE_REINSTALL: AtomicBool = AtomicBool::new(false);
+
+/// Reinstall the tools even if the installed ones are up to date, e.g. to repair a tools
+/// directory that was changed by hand.
+pub(crate) fn force_reinstall() {
+    FORCE_REINSTALL.store(true, Ordering::Relaxed);
+}
+
+/// Ensure that `name` is one of the binaries that Twoliter embeds, and so can be overridden.
+pub(crate) fn check_tool_name(name: &str) -> Result<()> {
+    ensure!(
+        BINARIES.iter().any(|(binary, _)| *binary == name),
+        "Unable to override unknown tool '{name}', expected one of: {}",
+        BINARIES.map(|(binary, _)| binary).join(", ")
+    );
+    Ok(())
+}
+
+/// Locally built binaries to install instead of the embedded ones, keyed by tool name. This lets
+/// you try out a change to e.g. `buildsys` without rebuilding Twoliter.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct ToolOverrides {
+    paths: BTreeMap<String, PathBuf>,
+}
+
+impl ToolOverrides {
+    /// Combine the overrides configured in `Twoliter.toml` with the `TWOLITER_TOOL_OVERRIDE_<NAME>`
+    /// environment variables. A variable takes precedence over the project, and an empty one turns
+    /// off the project's override of that tool.
+    pub(crate) fn resolve(configured: &BTreeMap<String, PathBuf>) -> Result<Self> {
+        Self::from_vars(configured, std::env::vars_os())
+    }
+
+    fn from_vars<I, K, V>(configured: &BTreeMap<String, PathBuf>, vars: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<std::ffi::OsString>,
+        V: Into<std::ffi::OsString>,
+    {
+        let mut paths = configured.clone();
+        for (key, value) in vars {
+            let key = key.into();
+            let Some(var_name) = key
+                .to_str()
+                .and_then(|k| k.strip_prefix(OVERRIDE_ENV_PREFIX))
+            else {
+                continue;
+            };
+            let name = var_name.to_lowercase().replace('_', "-");
+            if check_tool_name(&name).is_err() {
+                bail!(
+                    "Unknown tool in environment variable '{}', expected one of: {}",
+                    key.to_string_lossy(),
+                    BINARIES
+                        .map(|(binary, _)| format!(
+                            "{OVERRIDE_ENV_PREFIX}{}",
+                            binary.to_uppercase().replace('-', "_")
+                        ))
+                        .join(", ")
+                );
+            }
+            let value = value.into();
+            if value.is_empty() {
+                paths.remove(&name);
+            } else {
+                let path = std::env::current_dir()
+                    .context("Unable to get the current directory")?
+                    .join(value);
+                paths.insert(name, path);
+            }
+        }
+        for name in paths.keys() {
+            check_tool_name(name)?;
+        }
+        Ok(Self { paths })
+    }
+}
+
+/// Installed tools. While this is held, other `twoliter` processes wait to reinstall the tools
+/// rather than replace them in the middle of a build.
+#[derive(Debug)]
+pub(crate) struct InstalledTools {
+    _lock: Flock<File>,
+    manifest: Manifest,
+}
+
+impl InstalledTools {
+    /// The installed binaries and where each came from, keyed by tool name.
+    pub(crate) fn binaries(&self) -> &BTreeMap<String, InstalledBinary> {
+        &self.manifest.binaries
+    }
+}
+
+/// The record of an install, kept in the tools directory.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Manifest {
+    /// A hash of everything that was installed, used to tell whether the tools are up to date.
+    hash: String,
+    #[serde(rename = "binary")]
+    binaries: BTreeMap<String, InstalledBinary>,
+}
+
+/// An installed binary: either the one embedded in a version of Twoliter, or an override.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct InstalledBinary {
+    /// The version of Twoliter that embeds the binary, if it was not overridden.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    twoliter_version: Option<String>,
+    /// The locally built binary that was installed instead of the embedded one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    override_path: Option<PathBuf>,
+    sha256: String,
+}
+
+impl InstalledBinary {
+    pub(crate) fn override_path(&self) -> Option<&Path> {
+        self.override_path.as_deref()
+    }
+
+    pub(crate) fn sha256(&self) -> &str {
+        &self.sha256
+    }
+}
+
+/// Install tools into the given `tools_dir`, unless the same tools are already installed there.
+/// Hold on to the returned value until you no longer need the tools. If you use a `TempDir` object,
+/// make sure to pass it by reference and hold on to it too (it will auto delete when it goes out
+/// of scope).
+pub(crate) async fn install_tools(
+    tools_dir: impl AsRef<Path>,
+    overrides: &ToolOverrides,
+) -> Result<InstalledTools> {
     let dir = tools_dir.as_ref();
-    debug!("Installing tools to '{}'", dir.display());
-    fs::remove_dir_all(dir)
-        .await
-        .context("Unable to remove tools directory before installing")?;
+    let (manifest, binaries) = prepare(overrides).await?;
+    for (name, binary) in &manifest.binaries {
+        if let Some(path) = binary.override_path() {
+            info!("Using '{name}' from '{}'", path.display());
+        }
+    }
     fs::create_dir_all(dir)
         .await
         .context("Unable to create directory for tools")?;
+    let lock_path = dir.join(LOCK_FILE);
+    let lock_file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .context(format!("Unable to open '{}'", lock_path.display()))?;
+    let lock = flock(lock_file, FlockArg::LockShared, &lock_path).await?;
+
+    let force = FORCE_REINSTALL.load(Ordering::Relaxed);
+    if !force && installed_hash(dir).await.as_ref() == Some(&manifest.hash) {
+        debug!("Tools in '{}' are up to date", dir.display());
+        return Ok(InstalledTools {
+            _lock: lock,
+            manifest,
+        });
+    }
+
+    // Wait for other processes to finish with the tools before replacing them.
+    let lock = match lock.relock(FlockArg::LockExclusiveNonblock) {
+        Ok(()) => lock,
+        Err(_) => {
+            info!(
+                "Waiting for other twoliter commands to finish with the tools in '{}'",
+                dir.display()
+            );
+            relock(lock, FlockArg::LockExclusive, &lock_path).await?
+        }
+    };
+    // Another process may have installed the same tools while we waited.
+    if force || installed_hash(dir).await.as_ref() != Some(&manifest.hash) {
+        install(dir, &binaries).await?;
+        let manifest_toml =
+            toml::to_string(&manifest).context("Unable to serialize the installed tools")?;
+        fs::write(dir.join(MANIFEST_FILE), manifest_toml).await?;
+    }
+    let lock = relock(lock, FlockArg::LockShared, &lock_path).await?;
+    Ok(InstalledTools {
+        _lock: lock,
+        manifest,
+    })
+}
+
+/// A binary to install, with the mtime of an override so that changes to it can be seen.
+struct Binary {
+    name: &'static str,
+    data: Cow<'static, [u8]>,
+    mtime: Option<FileTime>,
+    /// The SHA-256 of an embedded binary, which was computed when Twoliter was built.
+    sha256: Option<&'static str>,
+}
+
+/// Read and hash the overrides, and hash them together with the embedded tools.
+async fn prepare(overrides: &ToolOverrides) -> Result<(Manifest, Vec<Binary>)> {
+    let mut binaries = Vec::with_capacity(BINARIES.len());
+    let mut override_paths = BTreeMap::new();
+    for ((name, data), (_, sha256)) in BINARIES.into_iter().zip(BINARY_SHA256) {
+        match overrides.paths.get(name) {
+            Some(path) => {
+                let data = fs::read(path)
+                    .await
+                    .context(format!("Unable to read the override of '{name}'"))?;
+                let metadata = fs::metadata(path).await?;
+                let mtime = FileTime::from_last_modification_time(&metadata);
+                override_paths.insert(name, path.clone());
+                binaries.push(Binary {
+                    name,
+                    data: Cow::Owned(data),
+                    mtime: Some(mtime),
+                    sha256: None,
+                });
+            }
+            None => binaries.push(Binary {
+                name,
+                data: Cow::Borrowed(data),
+                mtime: None,
+                sha256: Some(sha256),
+            }),
+        }
+    }
+
+    spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+        hasher.update(TAR_GZ_SHA256);
+        let mut installed = BTreeMap::new();
+        for binary in &binaries {
+            let sha256 = match binary.sha256 {
+                Some(sha256) => sha256.to_string(),
+                None => hex::encode(Sha256::digest(&binary.data)),
+            };
+            hasher.update(binary.name);
+            hasher.update(&sha256);
+            let override_path = override_paths.remove(binary.name);
+            installed.insert(
+                binary.name.to_string(),
+                InstalledBinary {
+                    twoliter_version: override_path
+                        .is_none()
+                        .then(|| env!("CARGO_PKG_VERSION").to_string()),
+                    override_path,
+                    sha256,
+                },
+            );
+        }
+        let manifest = Manifest {
+            hash: hex::encode(hasher.finalize()),
+            binaries: installed,
+        };
+        (manifest, binaries)
+    })
+    .await
+    .context("Unable to run and join async task for hashing the tool overrides")
+}
+
+async fn install(dir: &Path, binaries: &[Binary]) -> Result<()> {
+    debug!("Installing tools to '{}'", dir.display());
+    clear_dir(dir)
+        .await
+        .context("Unable to remove old tools before installing")?;
 
     // Write out the embedded tools and scripts.
     unpack_tarball(dir)
@@ -42,12 +327,10 @@ pub(crate) async fn install_tools(tools_dir: impl AsRef<Path>) -> Result<()> {
         .context("Unable to get Dockerfile metadata")?;
     let mtime = FileTime::from_last_modification_time(&metadata);
 
-    write_bin("bottlerocket-variant", BOTTLEROCKET_VARIANT, &dir, mtime).await?;
-    write_bin("buildsys", BUILDSYS, &dir, mtime).await?;
-    write_bin("pubsys", PUBSYS, &dir, mtime).await?;
-    write_bin("pubsys-setup", PUBSYS_SETUP, &dir, mtime).await?;
-    write_bin("testsys", TESTSYS, &dir, mtime).await?;
-    write_bin("tuftool", TUFTOOL, &dir, mtime).await?;
+    for binary in binaries {
+        let mtime = binary.mtime.unwrap_or(mtime);
+        write_bin(binary.name, &binary.data, &dir, mtime).await?;
+    }
 
     // Apply the mtime to the directory now that the writes are done.
     set_file_mtime(dir, mtime).context(format!("Unable to set mtime for '{}'", dir.display()))?;
@@ -55,6 +338,61 @@ pub(crate) async fn install_tools(tools_dir: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
+/// Remove everything in the tools directory but the lock file.
+async fn clear_dir(dir: &Path) -> Result<()> {
+    let mut read_dir = tokio::fs::read_dir(dir)
+        .await
+        .context(format!("Unable to read dir '{}'", dir.display()))?;
+    while let Some(entry) = read_dir.next_entry().await.context(format!(
+        "Error while reading entries in dir '{}'",
+        dir.display()
+    ))? {
+        if entry.file_name() == LOCK_FILE {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(&path).await?;
+        } else {
+            fs::remove_file(&path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The hash of the installed tools, if they were completely installed. The tools are reinstalled
+/// whenever a different `twoliter` or different overrides are used in the project.
+async fn installed_hash(dir: &Path) -> Option<String> {
+    let manifest = tokio::fs::read_to_string(dir.join(MANIFEST_FILE))
+        .await
+        .ok()?;
+    toml::from_str::<Manifest>(&manifest)
+        .ok()
+        .map(|manifest| manifest.hash)
+}
+
+async fn flock(file: File, arg: FlockArg, path: &Path) -> Result<Flock<File>> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || {
+        Flock::lock(file, arg)
+            .map_err(|(_, e)| e)
+            .context(format!("Unable to lock '{}'", path.display()))
+    })
+    .await
+    .context("Unable to run and join async task for locking the tools")?
+}
+
+async fn relock(lock: Flock<File>, arg: FlockArg, path: &Path) -> Result<Flock<File>> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || {
+        lock.relock(arg)
+            .context(format!("Unable to lock '{}'", path.display()))?;
+        Ok(lock)
+    })
+    .await
+    .context("Unable to run and join async task for locking the tools")?
+}
+
 async fn write_bin(name: &str, data: &[u8], dir: impl AsRef<Path>, mtime: FileTime) -> Result<()> {
     let path = dir.as_ref().join(name);
     let mut f = OpenOptions::new()
@@ -99,7 +437,9 @@ async fn unpack_tarball(tools_dir: impl AsRef<Path>) -> Result<()> {
 async fn test_install_tools() {
     let tempdir = tempfile::TempDir::new().unwrap();
     let toolsdir = tempdir.path().join("tools");
-    install_tools(&toolsdir).await.unwrap();
+    let _tools = install_tools(&toolsdir, &ToolOverrides::default())
+        .await
+        .unwrap();
 
     // Assert that the expected files exist in the tools directory.
 
@@ -111,6 +451,7 @@ async fn test_install_tools() {
     assert!(toolsdir.join("rpm2img").is_file());
     assert!(toolsdir.join("rpm2kmodkit").is_file());
     assert!(toolsdir.join("rpm2migrations").is_file());
+    assert!(toolsdir.join("rootfs-budget").is_file());
     assert!(toolsdir.join("metadata.spec").is_file());
 
     // Check that binaries were copied.
@@ -129,3 +470,117 @@ async fn test_install_tools() {
 
     assert_eq!(dockerfile_mtime, buildsys_mtime);
 }
+
+#[tokio::test]
+async fn test_install_tools_once() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let toolsdir = tempdir.path().join("tools");
+    let makefile = toolsdir.join("Makefile.toml");
+    drop(
+        install_tools(&toolsdir, &ToolOverrides::default())
+            .await
+            .unwrap(),
+    );
+
+    // Tools that are up to date are left alone.
+    fs::write(&makefile, "changed").await.unwrap();
+    drop(
+        install_tools(&toolsdir, &ToolOverrides::default())
+            .await
+            .unwrap(),
+    );
+    assert_eq!(fs::read_to_string(&makefile).await.unwrap(), "changed");
+
+    // Tools without a manifest, e.g. from an install that was interrupted, are reinstalled.
+    fs::remove_file(toolsdir.join(MANIFEST_FILE)).await.unwrap();
+    let _tools = install_tools(&toolsdir, &ToolOverrides::default())
+        .await
+        .unwrap();
+    assert_ne!(fs::read_to_string(&makefile).await.unwrap(), "changed");
+    assert!(toolsdir.join(LOCK_FILE).is_file());
+}
+
+#[tokio::test]
+async fn test_install_tools_override() {
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let toolsdir = tempdir.path().join("tools");
+    let local_buildsys = tempdir.path().join("buildsys");
+    fs::write(&local_buildsys, "local buildsys").await.unwrap();
+    let overrides = ToolOverrides {
+        paths: [("buildsys".to_string(), local_buildsys.clone())].into(),
+    };
+
+    let tools = install_tools(&toolsdir, &overrides).await.unwrap();
+    assert_eq!(
+        fs::read_to_string(toolsdir.join("buildsys")).await.unwrap(),
+        "local buildsys"
+    );
+    let buildsys = &tools.binaries()["buildsys"];
+    assert_eq!(buildsys.override_path(), Some(local_buildsys.as_path()));
+    assert_eq!(
+        buildsys.sha256(),
+        hex::encode(Sha256::digest("local buildsys"))
+    );
+    assert_eq!(tools.binaries()["pubsys"].override_path(), None);
+    let manifest = fs::read_to_string(toolsdir.join(MANIFEST_FILE))
+        .await
+        .unwrap();
+    assert_eq!(
+        toml::from_str::<Manifest>(&manifest).unwrap(),
+        tools.manifest
+    );
+    drop(tools);
+
+    // A change to the override is installed.
+    fs::write(&local_buildsys, "changed buildsys")
+        .await
+        .unwrap();
+    let _tools = install_tools(&toolsdir, &overrides).await.unwrap();
+    assert_eq!(
+        fs::read_to_string(toolsdir.join("buildsys")).await.unwrap(),
+        "changed buildsys"
+    );
+}
+
+#[test]
+fn test_embedded_sha256() {
+    assert_eq!(TAR_GZ_SHA256, hex::encode(Sha256::digest(TAR_GZ_DATA)));
+    for ((name, data), (sha256_name, sha256)) in BINARIES.into_iter().zip(BINARY_SHA256) {
+        assert_eq!(name, sha256_name);
+        assert_eq!(sha256, hex::encode(Sha256::digest(data)), "{name}");
+    }
+}
+
+#[test]
+fn test_tool_overrides_from_vars() {
+    let configured = BTreeMap::from([
+        ("buildsys".to_string(), PathBuf::from("/project/buildsys")),
+        ("pubsys".to_string(), PathBuf::from("/project/pubsys")),
+    ]);
+    let overrides = ToolOverrides::from_vars(
+        &configured,
+        [
+            ("PATH", "/usr/bin"),
+            ("TWOLITER_TOOL_OVERRIDE_BUILDSYS", "/env/buildsys"),
+            ("TWOLITER_TOOL_OVERRIDE_PUBSYS", ""),
+            ("TWOLITER_TOOL_OVERRIDE_PUBSYS_SETUP", "/env/pubsys-setup"),
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        overrides.paths,
+        BTreeMap::from([
+            ("buildsys".to_string(), PathBuf::from("/env/buildsys")),
+            (
+                "pubsys-setup".to_string(),
+                PathBuf::from("/env/pubsys-setup")
+            ),
+        ])
+    );
+
+    assert!(ToolOverrides::from_vars(
+        &BTreeMap::new(),
+        [("TWOLITER_TOOL_OVERRIDE_BUILDSIS", "/env/buildsys")]
+    )
+    .is_err());
+}